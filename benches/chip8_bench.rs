@@ -0,0 +1,63 @@
+//! The `--profile-run` workloads under criterion, to compare changes to the core:
+//!
+//! ```text
+//! cargo bench --bench chip8_bench
+//! ```
+
+use std::sync::mpsc;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use wheat::chip8::{Chip8, NoInput};
+use wheat::graphics::Graphics;
+use wheat::{demo, DebugOptions, Quirks};
+
+/// Instructions each workload runs per iteration
+const CYCLES: u64 = 100_000;
+
+/// Draws a sprite all over the screen, doing some arithmetic and a BCD in between, forever.
+///
+/// ```text
+/// 0x200: LD I, 0x220; LD V0, 0; LD V1, 0
+/// 0x206: DRW V0, V1, 5; ADD V0, 7; ADD V1, 3; LD V2, V0; XOR V2, V1; SHR V2; F2 33; JP 0x206
+/// ```
+const DRAW_LOOP: [u8; 37] = [
+    0xA2, 0x20, 0x60, 0x00, 0x61, 0x00, 0xD0, 0x15, 0x70, 0x07, 0x71, 0x03, 0x82, 0x00, 0x82, 0x13, 0x82,
+    0x26, 0xF2, 0x33, 0x12, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xF0, 0x90,
+    0x90, 0x90, 0xF0,
+];
+
+fn profile_run(c: &mut Criterion) {
+    let mut group = c.benchmark_group("profile_run");
+    group.throughput(Throughput::Elements(CYCLES));
+
+    let rom = demo::rom();
+    group.bench_function("demo", |b| {
+        b.iter(|| Chip8::profile_run(&rom, Quirks::default(), CYCLES))
+    });
+    group.bench_function("draw_loop", |b| {
+        b.iter(|| Chip8::profile_run(&DRAW_LOOP, Quirks::default(), CYCLES))
+    });
+    group.finish();
+}
+
+fn benchmark_cycles(c: &mut Criterion) {
+    let mut group = c.benchmark_group("benchmark_cycles");
+    group.throughput(Throughput::Elements(CYCLES));
+
+    // The ROM loops forever, so one emulator runs every iteration
+    let (_timer_tx, timer_rx) = mpsc::channel();
+    let mut chip8 = Chip8::new(
+        Graphics::new(),
+        timer_rx,
+        Quirks::default(),
+        DebugOptions::default(),
+    );
+    chip8.load_rom(&DRAW_LOOP.to_vec()).unwrap();
+    group.bench_function("draw_loop", |b| {
+        b.iter(|| chip8.benchmark_cycles(&NoInput, CYCLES).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, profile_run, benchmark_cycles);
+criterion_main!(benches);
@@ -0,0 +1,209 @@
+//! A frontend on top of minifb instead of SDL2, to show what a frontend has to provide: a
+//! [`Display`] to draw on, an [`Input`] for the keys, something to make the buzzer heard, and a
+//! loop that runs the emulator a frame at a time.
+//!
+//! ```text
+//! cargo run --example minifb_frontend --no-default-features --features frontend-minifb rom.ch8
+//! ```
+//!
+//! The keys are laid out like in the `wheat` binary. F5 restarts the ROM, F6 saves its state and
+//! F7 goes back to the saved state. Escape quits. There's no sound: the window title shows when
+//! the buzzer is on instead.
+
+use std::sync::mpsc::{self, Sender};
+use std::{env, fs, process};
+
+use minifb::{Key as MinifbKey, KeyRepeat, Scale, Window, WindowOptions};
+use wheat::chip8::{Chip8Builder, DefaultChip8};
+use wheat::graphics::Graphics;
+use wheat::state::Chip8State;
+use wheat::timer::TimerOperation;
+use wheat::traits::{Display, GraphicsBuffer, Input};
+use wheat::{cycles_per_frame, Key, FRAMES_PER_SECOND, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+const TITLE: &str = "Chip 8";
+/// Window pixels per CHIP-8 pixel, on each side
+const SCALE: usize = 10;
+const WIDTH: usize = SCREEN_WIDTH as usize * SCALE;
+const HEIGHT: usize = SCREEN_HEIGHT as usize * SCALE;
+
+/// The CHIP-8 key each keyboard key stands for, see [`Input`].
+const KEYMAP: [(MinifbKey, Key); 16] = [
+    (MinifbKey::Key1, Key::Num1),
+    (MinifbKey::Key2, Key::Num2),
+    (MinifbKey::Key3, Key::Num3),
+    (MinifbKey::Key4, Key::C),
+    (MinifbKey::Q, Key::Num4),
+    (MinifbKey::W, Key::Num5),
+    (MinifbKey::E, Key::Num6),
+    (MinifbKey::R, Key::D),
+    (MinifbKey::A, Key::Num7),
+    (MinifbKey::S, Key::Num8),
+    (MinifbKey::D, Key::Num9),
+    (MinifbKey::F, Key::E),
+    (MinifbKey::Z, Key::A),
+    (MinifbKey::X, Key::Num0),
+    (MinifbKey::C, Key::B),
+    (MinifbKey::V, Key::F),
+];
+
+/// Draws on a minifb window, each CHIP-8 pixel as a `SCALE` by `SCALE` square.
+struct MinifbDisplay {
+    window: Window,
+    /// What the window shows, as `0RGB` words, row by row
+    pixels: Vec<u32>,
+    /// The color of each pixel value
+    palette: [u32; 16],
+}
+
+impl MinifbDisplay {
+    fn new() -> Result<Self, String> {
+        let options = WindowOptions {
+            scale: Scale::X1,
+            ..WindowOptions::default()
+        };
+        let mut window = Window::new(TITLE, WIDTH, HEIGHT, options).map_err(|e| e.to_string())?;
+        // minifb waits between two frames to keep to it
+        window.set_target_fps(FRAMES_PER_SECOND as usize);
+
+        let mut palette = [0xFF_FF_FF; 16];
+        palette[0] = 0;
+        Ok(Self {
+            window,
+            pixels: vec![0; WIDTH * HEIGHT],
+            palette,
+        })
+    }
+
+    /// Shows the last screen drawn, and reads the keyboard. Waits for the next frame first.
+    fn update(&mut self) -> Result<(), String> {
+        self.window
+            .update_with_buffer(&self.pixels, WIDTH, HEIGHT)
+            .map_err(|e| e.to_string())
+    }
+
+    fn pressed(&self, key: MinifbKey) -> bool {
+        self.window.is_key_pressed(key, KeyRepeat::No)
+    }
+}
+
+impl Display for MinifbDisplay {
+    fn draw(&mut self, buffer: &[Vec<u8>]) {
+        for (y, row) in self.pixels.chunks_mut(WIDTH).enumerate() {
+            let source = &buffer[y / SCALE];
+            for (x, pixel) in row.iter_mut().enumerate() {
+                *pixel = self.palette[source[x / SCALE] as usize & 0xF];
+            }
+        }
+    }
+
+    fn set_palette(&mut self, colors: &[[u8; 3]; 16]) {
+        for (color, &[r, g, b]) in self.palette.iter_mut().zip(colors) {
+            *color = u32::from_be_bytes([0, r, g, b]);
+        }
+    }
+}
+
+/// The keys held down in a minifb window, as of its last update.
+struct MinifbInput<'a>(&'a Window);
+
+impl Input for MinifbInput<'_> {
+    fn is_pressed(&self, key: Key) -> bool {
+        KEYMAP
+            .iter()
+            .any(|&(keyboard, chip8)| chip8 == key && self.0.is_key_down(keyboard))
+    }
+}
+
+/// Stands in for an audio backend: shows in the window title whether the buzzer is on.
+struct TitleBuzzer {
+    on: bool,
+}
+
+impl TitleBuzzer {
+    fn set(&mut self, on: bool, window: &mut Window) {
+        if on != self.on {
+            self.on = on;
+            window.set_title(if on { "Chip 8 - BEEP" } else { TITLE });
+        }
+    }
+}
+
+/// Ticks the timers at the emulator's timer frequency, a frame's worth of ticks at a time.
+struct FrameTimer {
+    timer_tx: Sender<TimerOperation>,
+    per_frame: f64,
+    /// The part of a tick carried over to the next frame
+    owed: f64,
+}
+
+impl FrameTimer {
+    fn tick(&mut self) {
+        self.owed += self.per_frame;
+        let ticks = self.owed.floor();
+        self.owed -= ticks;
+        if ticks > 0.0 {
+            // The emulator owns the other end, so this only fails once it's gone
+            let _ = self.timer_tx.send(TimerOperation::Decrement(ticks as u8));
+        }
+    }
+}
+
+fn start(rom: Vec<u8>) -> Result<(DefaultChip8, FrameTimer), String> {
+    let (timer_tx, timer_rx) = mpsc::channel();
+    let mut chip8 = Chip8Builder::new(Graphics::new(), timer_rx)
+        .build()
+        .map_err(|e| e.to_string())?;
+    chip8.load_rom(&rom).map_err(|e| e.to_string())?;
+
+    let timer = FrameTimer {
+        timer_tx,
+        per_frame: chip8.timer_frequency() / FRAMES_PER_SECOND,
+        owed: 0.0,
+    };
+    Ok((chip8, timer))
+}
+
+fn run(path: &str) -> Result<(), String> {
+    let rom = fs::read(path).map_err(|e| format!("{path}: {e}"))?;
+    let (mut chip8, mut timer) = start(rom)?;
+    let cycles_per_frame = cycles_per_frame(chip8.cpu_frequency());
+
+    let mut display = MinifbDisplay::new()?;
+    let mut buzzer = TitleBuzzer { on: false };
+    let mut saved: Option<Chip8State> = None;
+
+    while display.window.is_open() && !display.window.is_key_down(MinifbKey::Escape) {
+        if display.pressed(MinifbKey::F5) {
+            chip8.reset();
+        }
+        if display.pressed(MinifbKey::F6) {
+            saved = Some(chip8.save_state());
+        }
+        if let (true, Some(state)) = (display.pressed(MinifbKey::F7), &saved) {
+            chip8.load_state(state.clone()).map_err(|e| e.to_string())?;
+            display.draw(chip8.graphics().buffer());
+        }
+
+        timer.tick();
+        let output = chip8
+            .run_cycles(cycles_per_frame, &MinifbInput(&display.window))
+            .map_err(|e| e.to_string())?;
+        buzzer.set(output.sound_on, &mut display.window);
+        output.present(&mut display);
+
+        display.update()?;
+    }
+    Ok(())
+}
+
+fn main() {
+    let Some(path) = env::args().nth(1) else {
+        eprintln!("usage: minifb_frontend <rom.ch8>");
+        process::exit(2);
+    };
+    if let Err(e) = run(&path) {
+        eprintln!("{e}");
+        process::exit(1);
+    }
+}
@@ -0,0 +1,248 @@
+use std::future::Future;
+use std::sync::mpsc::{self, Sender};
+use std::time::Duration;
+
+use crate::chip8::{Chip8, Chip8Builder, FrameSnapshot, NoInput};
+use crate::graphics::Graphics;
+use crate::timer::TimerOperation;
+use crate::traits::Rom;
+use crate::{Chip8Error, Key, Quirks, FRAMES_PER_SECOND};
+
+/// The clock an [`AsyncEmulator`] runs on, implemented on top of the async runtime of the
+/// frontend, e.g. with `tokio::time::sleep`, or with a fake clock in tests.
+pub trait Clock {
+    type Sleep: Future<Output = ()>;
+
+    /// Time since an arbitrary but fixed point, e.g. when the clock was created.
+    fn now(&self) -> Duration;
+
+    /// Returns a future that completes once `duration` has passed.
+    fn sleep(&self, duration: Duration) -> Self::Sleep;
+}
+
+/// Runs a [`Chip8`] from an async frontend, one frame at a time, instead of the usual loop that
+/// sleeps between instructions and gets timer ticks from another thread. The timers count down
+/// with the [`Clock`], so they keep time even if frames are late.
+pub struct AsyncEmulator<C: Clock> {
+    chip8: Chip8<Graphics>,
+    clock: C,
+    timer_tx: Sender<TimerOperation>,
+    start: Duration,
+    frames: u64,
+    cycles: u64,
+    ticks: u64,
+}
+
+impl<C: Clock> AsyncEmulator<C> {
+    /// Loads `rom` into a new emulator with `quirks`, whose first frame is due one frame after now.
+    pub fn new(rom: &impl Rom, quirks: Quirks, clock: C) -> Result<Self, Chip8Error> {
+        let (timer_tx, timer_rx) = mpsc::channel();
+        let mut chip8 = Chip8Builder::new(Graphics::new(), timer_rx)
+            .quirks(quirks)
+            .build()?;
+        chip8.load_rom(rom)?;
+
+        Ok(Self {
+            chip8,
+            start: clock.now(),
+            clock,
+            timer_tx,
+            frames: 0,
+            cycles: 0,
+            ticks: 0,
+        })
+    }
+
+    pub fn chip8(&self) -> &Chip8<Graphics> {
+        &self.chip8
+    }
+
+    /// Presses `key` as of the next frame. See [`Chip8::queue_key_event`]: a key pressed and
+    /// released before the next frame is still seen by it.
+    pub fn press(&mut self, key: Key) {
+        self.chip8.queue_key_event(key, true);
+    }
+
+    pub fn release(&mut self, key: Key) {
+        self.chip8.queue_key_event(key, false);
+    }
+
+    /// Waits until the next frame is due, then runs the instructions and timer ticks that are owed
+    /// by then at [`Chip8::cpu_frequency`] and [`Chip8::timer_frequency`]. Both are counted from
+    /// the time since the start, so a late frame catches up on both together; the frames that
+    /// were missed altogether are skipped rather than run back to back.
+    pub async fn next_frame(&mut self) -> Result<FrameSnapshot, Chip8Error> {
+        self.frames += 1;
+        let due = self.start + Duration::from_secs_f64(self.frames as f64 / FRAMES_PER_SECOND);
+        let now = self.clock.now();
+        if due > now {
+            self.clock.sleep(due - now).await;
+        }
+
+        let elapsed = self.clock.now().saturating_sub(self.start).as_secs_f64();
+        self.frames = self.frames.max((elapsed * FRAMES_PER_SECOND) as u64);
+        // Without the nudge, float error can round a whole number of ticks or cycles down
+        let owed = |hertz: f64| (elapsed * hertz + 1e-6) as u64;
+
+        let ticks = owed(self.chip8.timer_frequency());
+        for chunk in (self.ticks..ticks).step_by(u8::MAX as usize) {
+            let decrement = (ticks - chunk).min(u8::MAX as u64) as u8;
+            // The receiver lives as long as `self.chip8`
+            let _ = self.timer_tx.send(TimerOperation::Decrement(decrement));
+        }
+        self.ticks = self.ticks.max(ticks);
+
+        let cycles = owed(self.chip8.cpu_frequency()).max(self.cycles);
+        let batch = (cycles - self.cycles).min(u32::MAX as u64) as u32;
+        let output = self.chip8.emulate_frame(batch, &NoInput)?;
+        self.cycles += batch as u64;
+        Ok(output.to_owned_snapshot())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::future::{self, Future, Ready};
+    use std::pin::pin;
+    use std::rc::Rc;
+    use std::task::{Context, Poll, Waker};
+    use std::time::Duration;
+
+    use crate::test_helpers::rom_bytes;
+    use crate::{Key, Quirks};
+
+    use super::{AsyncEmulator, Clock};
+
+    /// Virtual time, which only moves when something sleeps.
+    #[derive(Clone, Default)]
+    struct TestClock(Rc<Cell<Duration>>);
+
+    impl TestClock {
+        /// Moves the time on, like a host stall would.
+        fn advance(&self, duration: Duration) {
+            self.0.set(self.0.get() + duration);
+        }
+    }
+
+    impl Clock for TestClock {
+        type Sleep = Ready<()>;
+
+        fn now(&self) -> Duration {
+            self.0.get()
+        }
+
+        fn sleep(&self, duration: Duration) -> Ready<()> {
+            self.advance(duration);
+            future::ready(())
+        }
+    }
+
+    /// Polls `future` until it's done.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut context = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                return output;
+            }
+        }
+    }
+
+    fn emulator(program: &[u16], clock: TestClock) -> AsyncEmulator<TestClock> {
+        AsyncEmulator::new(&rom_bytes(program), Quirks::default(), clock).unwrap()
+    }
+
+    #[test]
+    fn test_frame_cadence() {
+        let clock = TestClock::default();
+        // JP 0x200
+        let mut emulator = emulator(&[0x1200], clock.clone());
+
+        for frame in 1..=90 {
+            block_on(emulator.next_frame()).unwrap();
+            let expected = frame as f64 / 60.0;
+            assert!((clock.now().as_secs_f64() - expected).abs() < 1e-6);
+        }
+        // 1.5s at the default 800 Hz
+        assert_eq!(emulator.chip8().statistics().total_cycles, 1200);
+    }
+
+    #[test]
+    fn test_late_frames_run_immediately() {
+        let clock = TestClock::default();
+        let mut emulator = emulator(&[0x1200], clock.clone());
+
+        clock.advance(Duration::from_millis(100));
+        block_on(emulator.next_frame()).unwrap();
+        assert_eq!(clock.now(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_timers_follow_virtual_time() {
+        let clock = TestClock::default();
+        // LD V0, 200; LD DT, V0; JP 0x204
+        let mut emulator = emulator(&[0x60C8, 0xF015, 0x1204], clock.clone());
+
+        // The first tick is applied after `LD V0`, before the delay timer is set
+        block_on(emulator.next_frame()).unwrap();
+        assert_eq!(emulator.chip8().state().delay_timer, 200);
+
+        for _ in 0..59 {
+            block_on(emulator.next_frame()).unwrap();
+        }
+        assert_eq!(emulator.chip8().state().delay_timer, 141);
+
+        // A stall is caught up on the next frame
+        clock.advance(Duration::from_secs(1));
+        block_on(emulator.next_frame()).unwrap();
+        assert_eq!(emulator.chip8().state().delay_timer, 81);
+    }
+
+    #[test]
+    fn test_stall_catches_up_cycles_and_timers_together() {
+        let clock = TestClock::default();
+        let mut emulator = emulator(&[0x1200], clock.clone());
+        block_on(emulator.next_frame()).unwrap();
+
+        // A second's worth of both, in the one frame after the stall
+        clock.advance(Duration::from_secs(1));
+        block_on(emulator.next_frame()).unwrap();
+        let statistics = emulator.chip8().statistics();
+        assert_eq!(statistics.total_cycles, 813);
+        assert_eq!(statistics.timer_decrements, 61);
+
+        // Then the frames keep their cadence from there, instead of running the missed ones
+        let before = clock.now();
+        block_on(emulator.next_frame()).unwrap();
+        assert!(clock.now() > before);
+        assert_eq!(emulator.chip8().statistics().total_cycles, 826);
+    }
+
+    #[test]
+    fn test_pushed_keys() {
+        let clock = TestClock::default();
+        // LD V0, 0x5; SKNP V0; LD V1, 1; JP 0x202
+        let mut emulator = emulator(&[0x6005, 0xE0A1, 0x6101, 0x1202], clock);
+
+        block_on(emulator.next_frame()).unwrap();
+        assert_eq!(emulator.chip8().state().registers[1], 0);
+
+        emulator.press(Key::Num5);
+        block_on(emulator.next_frame()).unwrap();
+        assert_eq!(emulator.chip8().state().registers[1], 1);
+    }
+
+    #[test]
+    fn test_tap_between_frames() {
+        let clock = TestClock::default();
+        // LD V0, 0x5; SKP V0; JP 0x202; LD V1, 1; JP 0x208
+        let mut emulator = emulator(&[0x6005, 0xE09E, 0x1202, 0x6101, 0x1208], clock);
+        block_on(emulator.next_frame()).unwrap();
+
+        emulator.press(Key::Num5);
+        emulator.release(Key::Num5);
+        block_on(emulator.next_frame()).unwrap();
+        assert_eq!(emulator.chip8().state().registers[1], 1);
+    }
+}
@@ -0,0 +1,167 @@
+//! Conditions that make a breakpoint only stop the program when the machine is in a given state,
+//! see [`crate::chip8::Chip8::add_conditional_breakpoint`].
+
+use crate::chip8::NUM_REGISTERS;
+use crate::Chip8Error;
+
+/// How [`BreakCondition::CompareRegisters`] compares two registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    fn holds(self, a: u8, b: u8) -> bool {
+        match self {
+            Comparison::Equal => a == b,
+            Comparison::NotEqual => a != b,
+            Comparison::Less => a < b,
+            Comparison::LessOrEqual => a <= b,
+            Comparison::Greater => a > b,
+            Comparison::GreaterOrEqual => a >= b,
+        }
+    }
+}
+
+/// When a conditional breakpoint stops the program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BreakCondition {
+    /// `(register, value)`: the register holds the value
+    RegisterEquals(u8, u8),
+    /// Two registers compare as the [`Comparison`] says, e.g.
+    /// `CompareRegisters(3, Comparison::Less, 4)` for `V3 < V4`
+    CompareRegisters(u8, Comparison, u8),
+    /// `I` holds this address
+    IrEquals(u16),
+    DelayTimerEquals(u8),
+    /// Every one of the conditions holds
+    All(Vec<BreakCondition>),
+}
+
+impl BreakCondition {
+    /// A condition that holds when both `self` and `other` do.
+    pub fn and(self, other: BreakCondition) -> Self {
+        match self {
+            BreakCondition::All(mut conditions) => {
+                conditions.push(other);
+                BreakCondition::All(conditions)
+            }
+            condition => BreakCondition::All(vec![condition, other]),
+        }
+    }
+
+    /// Fails with [`Chip8Error::InvalidRegister`] if the condition names a register that
+    /// doesn't exist.
+    pub(crate) fn validate(&self) -> Result<(), Chip8Error> {
+        let check = |register: u8| match register as usize {
+            0..NUM_REGISTERS => Ok(()),
+            _ => Err(Chip8Error::InvalidRegister(register)),
+        };
+        match self {
+            BreakCondition::RegisterEquals(register, _) => check(*register),
+            BreakCondition::CompareRegisters(a, _, b) => check(*a).and(check(*b)),
+            BreakCondition::IrEquals(_) | BreakCondition::DelayTimerEquals(_) => Ok(()),
+            BreakCondition::All(conditions) => conditions.iter().try_for_each(BreakCondition::validate),
+        }
+    }
+
+    pub(crate) fn holds(&self, registers: &[u8], ir: u16, delay_timer: u8) -> bool {
+        match self {
+            BreakCondition::RegisterEquals(register, value) => registers[*register as usize] == *value,
+            BreakCondition::CompareRegisters(a, comparison, b) => {
+                comparison.holds(registers[*a as usize], registers[*b as usize])
+            }
+            BreakCondition::IrEquals(addr) => ir == *addr,
+            BreakCondition::DelayTimerEquals(value) => delay_timer == *value,
+            BreakCondition::All(conditions) => conditions
+                .iter()
+                .all(|condition| condition.holds(registers, ir, delay_timer)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BreakCondition, Comparison};
+    use crate::chip8::{Chip8, NoInput};
+    use crate::graphics::Graphics;
+    use crate::test_helpers::chip8_with_program;
+    use crate::Chip8Error;
+
+    /// LD V3, 0; LD V4, 0x10; 0x204: ADD V3, 1; JP 0x204
+    const COUNTING_LOOP: [u16; 4] = [0x6300, 0x6410, 0x7301, 0x1204];
+
+    fn chip8() -> Chip8<Graphics> {
+        chip8_with_program(&COUNTING_LOOP, Default::default())
+    }
+
+    /// Runs until the next breakpoint, and returns the cycles it took.
+    fn run_to_break(chip8: &mut Chip8<Graphics>) -> u32 {
+        for cycles in 0..10_000 {
+            match chip8.emulate_cycle(&NoInput) {
+                Ok(_) => (),
+                Err(Chip8Error::BreakpointHit(addr)) => {
+                    assert_eq!(addr, chip8.pc());
+                    return cycles;
+                }
+                Err(e) => panic!("{e}"),
+            }
+        }
+        panic!("no breakpoint hit");
+    }
+
+    #[test]
+    fn test_breaks_at_one_iteration() {
+        let mut chip8 = chip8();
+        chip8
+            .add_conditional_breakpoint(0x204, BreakCondition::RegisterEquals(3, 0x1F))
+            .unwrap();
+
+        // The two loads, then two instructions per iteration
+        assert_eq!(run_to_break(&mut chip8), 2 + 2 * 0x1F);
+        assert_eq!(chip8.registers()[3], 0x1F);
+        assert_eq!(chip8.pc(), 0x204);
+
+        // Going on runs the instruction, and breaks again once V3 wrapped around
+        assert_eq!(run_to_break(&mut chip8), 2 * 256);
+        assert_eq!(chip8.registers()[3], 0x1F);
+    }
+
+    #[test]
+    fn test_combined_conditions() {
+        let mut chip8 = chip8();
+        let condition = BreakCondition::CompareRegisters(3, Comparison::GreaterOrEqual, 4)
+            .and(BreakCondition::IrEquals(0))
+            .and(BreakCondition::DelayTimerEquals(0));
+        chip8.add_conditional_breakpoint(0x206, condition).unwrap();
+
+        run_to_break(&mut chip8);
+        assert_eq!(chip8.registers()[3], 0x10);
+        assert_eq!(chip8.pc(), 0x206);
+
+        chip8.clear_conditional_breakpoints();
+        chip8
+            .add_conditional_breakpoint(0x206, BreakCondition::IrEquals(0x300))
+            .unwrap();
+        assert!(chip8.emulate_frame(1000, &NoInput).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_register() {
+        let mut chip8 = chip8();
+        let condition = BreakCondition::RegisterEquals(0, 1).and(BreakCondition::CompareRegisters(
+            2,
+            Comparison::Less,
+            0x10,
+        ));
+        assert_eq!(
+            chip8.add_conditional_breakpoint(0x204, condition),
+            Err(Chip8Error::InvalidRegister(0x10))
+        );
+    }
+}
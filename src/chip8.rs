@@ -1,12 +1,47 @@
-use std::sync::mpsc::Receiver;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::io::{ErrorKind, Read};
+use std::ops::Range;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::time::{Duration, Instant};
 
 use rand::Rng;
 
+use crate::breakpoint::BreakCondition;
+use crate::disasm;
+use crate::graphics::{self, Coord, Graphics};
+use crate::opcode_overrides::{OpcodeOverrides, Resolved};
+use crate::profile::{CallProfile, OpcodeProfile, OpcodeProfileReport};
+use crate::state::{Chip8State, KeyWait, StateMask};
+#[cfg(any(test, feature = "test-helpers"))]
+use crate::test_helpers::{self, DisplayMismatch};
 use crate::timer::TimerOperation;
-use crate::traits::{GraphicsBuffer, Input, Rom};
-use crate::{Chip8Error, DebugOptions, Key, Quirks};
+use crate::trace::{TraceBuffer, TraceEntry};
+use crate::traits::{Display, GraphicsBuffer, Input, Rom};
+use crate::{
+    Chip8Error, DebugOptions, InputSampling, Key, LoadStoreBehavior, Quirks, SCREEN_HEIGHT, SCREEN_WIDTH,
+    SPRITE_WIDTH,
+};
+
+/// See [`Chip8::set_machine_call_handler`].
+pub type MachineCallHandler = Box<dyn Fn(u16, &mut Chip8State)>;
+
+/// See [`Chip8::set_pre_execute_hook`] and [`Chip8::set_post_execute_hook`].
+pub type ExecuteHook = Box<dyn FnMut(&HookContext)>;
+
+/// See [`Chip8::set_unknown_opcode_handler`].
+pub type UnknownOpcodeHandler = Box<dyn FnMut(u16, &mut Chip8Context) -> OpcodeOutcome>;
+
+/// See [`Chip8::register_sound_callback`].
+pub type SoundCallback = Box<dyn Fn(SoundEvent)>;
+
+/// A [`Chip8`] drawing on the built-in [`Graphics`], for code that doesn't need another screen.
+pub type DefaultChip8 = Chip8<Graphics>;
+
+/// A [`Chip8`] whose screen is picked at runtime, so machines with different screens can be
+/// stored and passed around as the same type. See [`Chip8::new_boxed`].
+pub type BoxedChip8 = Chip8<Box<dyn GraphicsBuffer>>;
 
-#[derive(Debug)]
 pub struct Chip8<G> {
     /// Current opcode
     opcode: u16,
@@ -25,27 +60,150 @@ pub struct Chip8<G> {
     stack: Vec<u16>,
     /// The stack pointer
     sp: u8,
+    /// The deepest the stack pointer has been
+    max_sp: u8,
     /// Screen that sprites get drawn on. 64x32 pixels
     graphics: G,
     timer_rx: Receiver<TimerOperation>,
-    draw_on_screen: bool,
+    /// Cleared once every sender for `timer_rx` is gone, e.g. in headless runs where the timers
+    /// never tick, so that the channel stops being polled
+    timer_connected: bool,
+    /// What the instructions executed since the last returned output did
+    outcome: CycleOutcome,
+    /// Whether the display is on. Only legacy opcodes can turn it off.
+    display_enabled: bool,
     wait_for_keypress_register: u8,
     wait_for_key_state: WaitForKeyState,
+    /// Key transitions from [`Chip8::queue_key_event`] that no cycle has seen yet, oldest first
+    key_events: VecDeque<(Key, bool)>,
+    /// The keys pressed through [`Chip8::queue_key_event`], as a bitmask
+    queued_keys: u16,
     quirks: Quirks,
     dbg_options: DebugOptions,
+    /// Highest memory address the program has read from, including opcode fetches
+    read_high_water: u16,
+    /// Highest memory address the program has written to
+    write_high_water: u16,
+    /// Reads of each address, if `DebugOptions::track_memory_hotspots` is set
+    memory_read_counts: Box<[u64; MEMORY_SIZE]>,
+    /// Writes to each address, if `DebugOptions::track_memory_hotspots` is set
+    memory_write_counts: Box<[u64; MEMORY_SIZE]>,
+    /// Instructions executed so far, by [`get_opcode_category`]. The only count of the
+    /// instructions: the other ones, like [`Chip8::cycles_executed`] and the statistics, are
+    /// worked out from it
+    executed: [u64; OpcodeCategory::ALL.len()],
+    /// `executed` when [`Chip8::reset_statistics`] was last called
+    statistics_from: [u64; OpcodeCategory::ALL.len()],
+    /// `cycles` when the last output was returned
+    output_cycles: u64,
+    /// `cycles` when [`Chip8::reset_counters`] was last called
+    counted_from: u64,
+    /// Counters since the emulator started or [`Chip8::reset_counters`]
+    frames_drawn: u64,
+    timer_ticks_received: u64,
+    /// Every draw opcode executed, if `DebugOptions::log_draw_calls` is set
+    draw_call_log: Vec<DrawCall>,
+    /// The subroutines each subroutine called, if `DebugOptions::track_call_graph` is set
+    call_graph: HashMap<u16, HashSet<u16>>,
+    /// The subroutines on the way to the current instruction, starting at the entry point
+    call_frames: Vec<u16>,
+    /// Calls and instructions per subroutine, if `DebugOptions::profile_calls` is set
+    call_profile: CallProfile,
+    /// The last instructions executed, if `DebugOptions::trace_depth` is set
+    trace: TraceBuffer,
+    /// Instructions executed per address and opcode family, if `DebugOptions::profile_opcodes`
+    /// is set
+    opcode_profile: OpcodeProfile,
+    /// The statistics other than the instructions executed, which [`Chip8::statistics`] fills in
+    statistics: Chip8Statistics,
+    /// Timer ticks received but not applied yet because of `Quirks::ticks_per_cycle_cap`
+    owed_timer_ticks: u32,
+    /// Emulates the machine code routines called by `0nnn`, see `Quirks::handle_0x0nnn_syscalls`
+    machine_call_handler: Option<MachineCallHandler>,
+    /// Told when the sound timer starts and stops
+    sound_callback: Option<SoundCallback>,
+    /// Called around every instruction executed. `DebugOptions::print_opcodes` sets the pre one
+    pre_execute_hook: Option<ExecuteHook>,
+    post_execute_hook: Option<ExecuteHook>,
+    /// Runs the opcodes the interpreter doesn't know, see [`Chip8::set_unknown_opcode_handler`]
+    unknown_opcode_handler: Option<UnknownOpcodeHandler>,
+    /// Frequencies (in Hz) the frontend drives the CPU and the timers at
+    cpu_frequency: f64,
+    timer_frequency: f64,
+    /// Where `load_rom` puts the ROM
+    load_address: u16,
+    /// Where the program counter starts, see [`Chip8Builder::entry_point`]
+    entry_point: u16,
+    /// The ROM last given to `load_rom`, which `reset` restores
+    rom: Vec<u8>,
+    /// Where the font starts, see [`Chip8Builder::font_base`]
+    font_base: u16,
+    /// Consulted before every opcode is interpreted, see [`Chip8Builder::opcode_overrides`]
+    opcode_overrides: OpcodeOverrides,
+    /// Memory ranges that report the instructions' accesses to them, see [`Chip8::add_watchpoint`]
+    watchpoints: Vec<(Range<u16>, WatchKind)>,
+    /// Checked when the program counter reaches their address, see
+    /// [`Chip8::add_conditional_breakpoint`]
+    conditional_breakpoints: Vec<(u16, BreakCondition)>,
+    /// The breakpoint that stopped the program last, which lets the next instruction run
+    resumed_breakpoint: Option<u16>,
 }
 
-// The default address at which the application is loaded at
-const APP_LOCATION: u16 = 0x200;
+/// Shows the CPU state, the used part of the stack, and the first 16 bytes of memory. The rest of
+/// memory, the graphics buffer and the timer channel are left out.
+impl<G: GraphicsBuffer + fmt::Debug> fmt::Debug for Chip8<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Chip8")
+            .field("opcode", &format_args!("{:#06x}", self.opcode))
+            .field("pc", &format_args!("{:#05x}", self.pc))
+            .field("ir", &format_args!("{:#05x}", self.ir))
+            .field("sp", &self.sp)
+            .field("registers", &self.registers)
+            .field("stack", &&self.stack[..self.sp as usize])
+            .field("delay_timer", &self.delay_timer)
+            .field("sound_timer", &self.sound_timer)
+            .field("wait_for_key_state", &self.wait_for_key_state)
+            .field("cycles_executed", &self.cycles_executed())
+            .field("frames_drawn", &self.frames_drawn)
+            .field("timer_ticks_received", &self.timer_ticks_received)
+            .field("memory[..16]", &&self.memory[..16])
+            .finish_non_exhaustive()
+    }
+}
+
+/// The default address at which the application is loaded at
+pub const APP_LOCATION: u16 = 0x200;
+
+/// The default address of the first [`HEX_DIGITS`] sprite. The interpreter area below
+/// [`APP_LOCATION`] isn't specified further; 0x050 is where most references and modern
+/// interpreters put the font, so ROMs that hardcode its address expect it there.
+pub const FONT_BASE: u16 = 0x050;
+
+/// With [`Quirks::vip_memory_layout`], the address of the first stack entry. Each entry is the
+/// big-endian return address, and the stack grows down.
+pub const VIP_STACK_ADDRESS: u16 = 0xECE;
+
+/// With [`Quirks::vip_memory_layout`], the address the display is stored from, 8 pixels per byte
+/// with the leftmost in the highest bit, row by row.
+pub const VIP_DISPLAY_ADDRESS: u16 = 0xF00;
+
+/// Bytes taken up by every [`HEX_DIGITS`] sprite, i.e. the distance between two digits.
+pub const FONT_GLYPH_SIZE: u16 = 5;
+
+/// Frequency (in Hz) the CPU is assumed to run at unless the builder is told otherwise.
+pub const DEFAULT_CPU_FREQUENCY: f64 = 800.0;
 
-// Total memory available to Chip8
-pub(crate) const MEMORY_SIZE: usize = 4096;
+/// Frequency (in Hz) the timers are assumed to count down at unless the builder is told otherwise.
+pub const DEFAULT_TIMER_FREQUENCY: f64 = 60.0;
+
+/// Total memory available to Chip8, in bytes
+pub const MEMORY_SIZE: usize = 4096;
 
 // Total size of the stock
-const STACK_SIZE: usize = 16;
+pub(crate) const STACK_SIZE: usize = 16;
 
 // Number of registers available
-const NUM_REGISTERS: usize = 16;
+pub(crate) const NUM_REGISTERS: usize = 16;
 
 // Register size in bytes.
 const REG_SIZE: u16 = 1;
@@ -57,8 +215,9 @@ const FLAG_REGISTER: usize = 0xF;
 /// Used for keycode `0xFX0A` (wait for keypress). This opcode
 /// requires halting the whole emulator until a key is pressed
 /// and released. This is part of a state machine that achieves that.
-#[derive(PartialEq, Debug)]
-enum WaitForKeyState {
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub(crate) enum WaitForKeyState {
+    #[default]
     None,
     WaitForNoKeyPressed,
     CheckForKeyPressed,
@@ -74,9 +233,30 @@ enum ProgramCounter {
     Pause,
 }
 
-// Chip8 provides hexadecimal digit sprites stored in memory from 0x000 to
-// 0x1FF.
-const HEX_DIGITS: [u8; 80] = [
+/// What a tolerated legacy opcode does. See [`Quirks::legacy_opcodes`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LegacyOpcode {
+    /// Does nothing.
+    Nop,
+    /// Clears the screen, like `00E0`.
+    ClearScreen,
+    /// Turns the display off; the frontend should show a blank screen.
+    DisplayOff,
+    /// Turns the display back on.
+    DisplayOn,
+}
+
+/// The low-level display opcodes used by a handful of COSMAC VIP-era ROMs.
+pub const VIP_LEGACY_OPCODES: [(u16, LegacyOpcode); 3] = [
+    (0x00E1, LegacyOpcode::DisplayOff),
+    (0x00E2, LegacyOpcode::DisplayOn),
+    (0x00E3, LegacyOpcode::Nop),
+];
+
+/// The hexadecimal digit sprites Chip8 provides, stored in memory from [`FONT_BASE`] unless the
+/// builder is told otherwise. Each digit is 5 rows of 4 pixels, kept in the high nibble of each
+/// byte.
+pub const HEX_DIGITS: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // Number: 0
     0x20, 0x60, 0x20, 0x20, 0x70, // Number: 1
     0xF0, 0x10, 0xF0, 0x80, 0xF0, // Number: 2
@@ -95,24 +275,763 @@ const HEX_DIGITS: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // Letter: F
 ];
 
+/// The result of one call to [`Chip8::emulate_cycle`].
+///
+/// `draw_on_screen` is `true` if an instruction changed the screen since the previous output was
+/// returned. Every draw is reported on exactly one output: cycles that don't execute an instruction
+/// (e.g. while `Fx0A` waits for a key) report `false`, and a draw is never lost because a later
+/// cycle paused or failed before producing an output.
+///
+/// `display_enabled` is `false` while a legacy opcode has turned the display off, in which case the
+/// frontend should show a blank screen instead of `graphics`.
 pub struct Chip8OutputState<'a> {
     pub sound_on: bool,
     pub draw_on_screen: bool,
+    pub display_enabled: bool,
     pub graphics: &'a dyn GraphicsBuffer,
+    /// Timer writes since the last output, oldest first
+    pub events: Vec<Chip8Event>,
+    /// Instructions executed since the last output, not counting the cycles spent waiting on
+    /// an `Fx0A`
+    pub instructions_executed: u32,
 }
 
 impl<'a> Chip8OutputState<'a> {
-    pub fn new(sound_on: bool, draw_on_screen: bool, graphics_buffer: &'a dyn GraphicsBuffer) -> Self {
+    pub fn new(
+        sound_on: bool,
+        draw_on_screen: bool,
+        display_enabled: bool,
+        graphics_buffer: &'a dyn GraphicsBuffer,
+    ) -> Self {
         Self {
             sound_on,
             draw_on_screen,
+            display_enabled,
             graphics: graphics_buffer,
+            events: Vec::new(),
+            instructions_executed: 0,
+        }
+    }
+
+    /// Draws the screen on `display` if it changed, showing a blank screen while the display is
+    /// turned off. Returns whether anything was drawn.
+    pub fn present(&self, display: &mut impl Display) -> bool {
+        if !self.draw_on_screen {
+            return false;
+        }
+
+        if self.display_enabled {
+            display.draw(self.graphics.buffer());
+        } else {
+            display.draw(&vec![vec![0; SCREEN_WIDTH as usize]; SCREEN_HEIGHT as usize]);
+        }
+        true
+    }
+
+    /// Copies the output, so it can be kept past the next cycle or sent to another thread.
+    pub fn to_owned_snapshot(&self) -> FrameSnapshot {
+        let mut rows = [0; SCREEN_HEIGHT as usize];
+        for (packed, row) in rows.iter_mut().zip(self.graphics.buffer()) {
+            *packed = row
+                .iter()
+                .enumerate()
+                .filter(|(_, &pixel)| pixel != 0)
+                .fold(0, |packed, (x, _)| packed | FrameSnapshot::LEFTMOST_PIXEL >> x);
+        }
+
+        FrameSnapshot {
+            sound_on: self.sound_on,
+            draw_on_screen: self.draw_on_screen,
+            display_enabled: self.display_enabled,
+            rows,
+            events: self.events.clone(),
+            instructions_executed: self.instructions_executed,
+        }
+    }
+}
+
+/// An owned copy of a [`Chip8OutputState`]. The screen is packed to one bit per pixel, so pixel
+/// values other than 0 and 1 all read back as 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameSnapshot {
+    pub sound_on: bool,
+    pub draw_on_screen: bool,
+    pub display_enabled: bool,
+    /// One word per row, with the leftmost pixel in the highest bit
+    pub rows: [u64; SCREEN_HEIGHT as usize],
+    /// Timer writes since the last output, oldest first
+    pub events: Vec<Chip8Event>,
+    pub instructions_executed: u32,
+}
+
+impl FrameSnapshot {
+    const LEFTMOST_PIXEL: u64 = 1 << (SCREEN_WIDTH - 1);
+
+    pub fn pixel(&self, pos: Coord) -> u8 {
+        (self.rows[pos.y as usize] & Self::LEFTMOST_PIXEL >> pos.x != 0) as u8
+    }
+
+    /// Unpacks the screen into the layout of [`GraphicsBuffer::buffer`].
+    pub fn buffer(&self) -> Vec<Vec<u8>> {
+        (0..SCREEN_HEIGHT)
+            .map(|y| (0..SCREEN_WIDTH).map(|x| self.pixel(Coord::new(x, y))).collect())
+            .collect()
+    }
+
+    /// Same as [`Chip8OutputState::present`].
+    pub fn present(&self, display: &mut impl Display) -> bool {
+        if !self.draw_on_screen {
+            return false;
+        }
+
+        if self.display_enabled {
+            display.draw(&self.buffer());
+        } else {
+            display.draw(&vec![vec![0; SCREEN_WIDTH as usize]; SCREEN_HEIGHT as usize]);
+        }
+        true
+    }
+}
+
+/// Passed to the [`Chip8::register_sound_callback`] callback.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SoundEvent {
+    /// The buzzer started, and is due to stop after this many seconds at the timer frequency,
+    /// unless the program changes the sound timer again.
+    On(f32),
+    Off,
+}
+
+/// Something an instruction did, reported with the output so the frontend can react to it more
+/// precisely than once per output, e.g. timer writes to schedule sound.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum Chip8Event {
+    /// `Fx15` set the delay timer to `value`. `cycle` counts the instructions executed so far,
+    /// including this one.
+    DelayTimerSet { value: u8, cycle: u64 },
+    /// `Fx18` set the sound timer to `value`.
+    SoundTimerSet { value: u8, cycle: u64 },
+    /// An instruction touched memory a [`Chip8::add_watchpoint`] watches.
+    Watchpoint(WatchHit),
+}
+
+/// Which accesses a watchpoint reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    fn matches(self, access: WatchKind) -> bool {
+        self == WatchKind::ReadWrite || self == access
+    }
+}
+
+/// A memory access that hit a watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub addr: u16,
+    /// The byte read, or written
+    pub value: u8,
+    /// [`WatchKind::Read`] or [`WatchKind::Write`]
+    pub access: WatchKind,
+    /// The instruction that made the access, and its address
+    pub opcode: u16,
+    pub pc: u16,
+}
+
+/// A single `Dxyn` opcode, as recorded when `DebugOptions::log_draw_calls` is set.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DrawCall {
+    /// Number of instructions executed so far, including this one
+    pub cycle: u64,
+    /// Coordinates the sprite was drawn at, before wrapping
+    pub x: u8,
+    pub y: u8,
+    pub num_rows: u8,
+    /// Address the sprite was read from
+    pub ir: u16,
+    /// Whether a pixel was turned off, i.e. whether `VF` was set
+    pub flipped: bool,
+}
+
+/// Counters accumulated while the emulator runs, since it started or since
+/// [`Chip8::reset_statistics`].
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Chip8Statistics {
+    /// Instructions executed
+    pub total_cycles: u64,
+    /// `Dxyn` opcodes executed
+    pub total_draw_calls: u64,
+    /// `2nnn` opcodes executed
+    pub total_subroutine_calls: u64,
+    /// Deepest the stack has been
+    pub max_stack_depth: u8,
+    /// Draws that turned a pixel off, i.e. set `VF` to 1
+    pub total_collisions: u32,
+    /// Ticks taken off the timers
+    pub timer_decrements: u64,
+    /// Instructions executed, by [`get_opcode_category`]. Indexed in
+    /// [`OpcodeCategory::ALL`] order; see [`Chip8Statistics::cycles_in`]. This used to be a
+    /// `HashMap`, which [`Chip8Statistics::cycles_by_category_map`] still builds.
+    pub cycles_by_category: [u64; OpcodeCategory::ALL.len()],
+}
+
+impl Chip8Statistics {
+    /// Instructions executed that were in `category`.
+    pub fn cycles_in(&self, category: OpcodeCategory) -> u64 {
+        self.cycles_by_category[category as usize]
+    }
+
+    /// The categories that executed any instructions, and how many.
+    pub fn cycles_by_category_map(&self) -> HashMap<OpcodeCategory, u64> {
+        OpcodeCategory::ALL
+            .into_iter()
+            .map(|category| (category, self.cycles_in(category)))
+            .filter(|&(_, cycles)| cycles > 0)
+            .collect()
+    }
+}
+
+impl fmt::Display for Chip8Statistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "cycles:           {}", self.total_cycles)?;
+        writeln!(f, "draw calls:       {}", self.total_draw_calls)?;
+        writeln!(f, "collisions:       {}", self.total_collisions)?;
+        writeln!(f, "subroutine calls: {}", self.total_subroutine_calls)?;
+        writeln!(f, "max stack depth:  {}", self.max_stack_depth)?;
+        write!(f, "timer decrements: {}", self.timer_decrements)?;
+
+        if self.cycles_by_category.iter().any(|&cycles| cycles > 0) {
+            write!(f, "\ncycles by category:")?;
+        }
+        for category in OpcodeCategory::ALL {
+            let cycles = self.cycles_in(category);
+            if cycles > 0 {
+                write!(f, "\n  {:<16}{cycles}", format!("{category}:"))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What kind of work an opcode does. See [`get_opcode_category`]. The variants are in
+/// [`OpcodeCategory::ALL`] order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OpcodeCategory {
+    /// Jumps, calls, returns and skips
+    ControlFlow,
+    /// Register loads and arithmetic, including `Cxkk` (random)
+    Arithmetic,
+    /// Anything that reads or writes memory or the index register
+    Memory,
+    /// Clearing, drawing and scrolling the screen
+    Graphics,
+    /// Reading and waiting for keys
+    Input,
+    /// Reading and setting the delay and sound timers
+    Timer,
+    /// Opcodes that aren't part of the instruction set
+    Unknown,
+}
+
+impl OpcodeCategory {
+    pub const ALL: [OpcodeCategory; 7] = [
+        OpcodeCategory::ControlFlow,
+        OpcodeCategory::Arithmetic,
+        OpcodeCategory::Memory,
+        OpcodeCategory::Graphics,
+        OpcodeCategory::Input,
+        OpcodeCategory::Timer,
+        OpcodeCategory::Unknown,
+    ];
+}
+
+impl fmt::Display for OpcodeCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            OpcodeCategory::ControlFlow => "control flow",
+            OpcodeCategory::Arithmetic => "arithmetic",
+            OpcodeCategory::Memory => "memory",
+            OpcodeCategory::Graphics => "graphics",
+            OpcodeCategory::Input => "input",
+            OpcodeCategory::Timer => "timer",
+            OpcodeCategory::Unknown => "unknown",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Collects the side effects of the instructions executed since the last output was returned,
+/// and turns them into the next [`Chip8OutputState`]. It is only reset once it has been reported,
+/// which guarantees each draw is delivered exactly once.
+#[derive(Debug, Default)]
+struct CycleOutcome {
+    draw_on_screen: bool,
+    events: Vec<Chip8Event>,
+}
+
+/// The most events kept for the next output. Callers that only [`Chip8::step`] never take an
+/// output, so past this the oldest half is dropped.
+const MAX_PENDING_EVENTS: usize = 1024;
+
+impl CycleOutcome {
+    fn push_event(&mut self, event: Chip8Event) {
+        if self.events.len() >= MAX_PENDING_EVENTS {
+            self.events.drain(..MAX_PENDING_EVENTS / 2);
         }
+        self.events.push(event);
+    }
+
+    /// Records that the screen changed.
+    fn drew(&mut self) {
+        self.draw_on_screen = true;
+    }
+
+    fn into_output(
+        self,
+        sound_on: bool,
+        display_enabled: bool,
+        graphics: &dyn GraphicsBuffer,
+    ) -> Chip8OutputState<'_> {
+        let mut output = Chip8OutputState::new(sound_on, self.draw_on_screen, display_enabled, graphics);
+        output.events = self.events;
+        output
     }
 }
 
 type OpcodeResult = Result<ProgramCounter, Chip8Error>;
 
+/// The instruction passed to the hooks set with [`Chip8::set_pre_execute_hook`] and
+/// [`Chip8::set_post_execute_hook`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HookContext<'a> {
+    /// Where the instruction is stored
+    pub pc: u16,
+    pub opcode: u16,
+    /// `V0` to `VF`, before the instruction ran for the pre hook and after it for the post one
+    pub registers: &'a [u8],
+    pub ir: u16,
+}
+
+/// `0200: 6120   LD V1, 0x20`, like [`Chip8::disassemble_with_bytes`].
+impl fmt::Display for HookContext<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mnemonic =
+            disasm::disassemble(self.opcode).unwrap_or_else(|_| format!("DB {:#06x}", self.opcode));
+        write!(f, "{:04X}: {:04X}   {mnemonic}", self.pc, self.opcode)
+    }
+}
+
+/// The part of the emulator the [`Chip8::set_unknown_opcode_handler`] handler can change.
+/// The program counter is changed through the [`OpcodeOutcome`] it returns.
+#[derive(Debug)]
+pub struct Chip8Context<'a> {
+    registers: &'a mut [u8],
+    memory: &'a mut [u8],
+    ir: &'a mut u16,
+    pc: u16,
+}
+
+impl Chip8Context<'_> {
+    /// `V0` to `VF`
+    pub fn registers(&self) -> &[u8] {
+        self.registers
+    }
+
+    pub fn registers_mut(&mut self) -> &mut [u8] {
+        self.registers
+    }
+
+    /// The whole memory. Writes made through it aren't watched or tracked.
+    pub fn memory(&self) -> &[u8] {
+        self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        self.memory
+    }
+
+    pub fn ir(&self) -> u16 {
+        *self.ir
+    }
+
+    pub fn set_ir(&mut self, ir: u16) {
+        *self.ir = ir;
+    }
+
+    /// Where the opcode is stored
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+}
+
+/// Where the program goes on after the [`Chip8::set_unknown_opcode_handler`] handler ran.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpcodeOutcome {
+    /// The next instruction
+    Next,
+    /// The instruction after the next one
+    Skip,
+    /// This address
+    Set(u16),
+    /// Fails with `Chip8Error::UnsupportedOpcode`, as if there was no handler
+    Unsupported,
+}
+
+/// What [`Chip8::step`] did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepResult {
+    /// The instruction executed, or the `Fx0A` still waiting for a key
+    pub opcode: u16,
+    pub pc_before: u16,
+    pub pc_after: u16,
+    /// Whether the instruction changed the screen
+    pub drew: bool,
+    /// Whether the program is stopped on an `Fx0A` after the step
+    pub waiting_for_key: bool,
+}
+
+/// The outcome of [`Chip8::run_test_rom`].
+#[derive(Debug, PartialEq)]
+pub struct TestResult {
+    pub passed: bool,
+    /// Every register that didn't hold the expected value, as `(register, expected, actual)`.
+    pub failed_registers: Vec<(u8, u8, u8)>,
+    /// The error that stopped the ROM before it ran for all of its cycles, if any.
+    pub error: Option<Chip8Error>,
+}
+
+/// The outcome of [`Chip8::profile_run`].
+#[derive(Debug, PartialEq)]
+pub struct ProfileResult {
+    /// Instructions executed, including the one that failed if the ROM stopped early.
+    pub instructions: u64,
+    pub elapsed: Duration,
+    /// The error that stopped the ROM before it ran for all of its cycles, if any.
+    pub error: Option<Chip8Error>,
+}
+
+impl ProfileResult {
+    /// Millions of instructions executed per second of wall time.
+    pub fn mips(&self) -> f64 {
+        self.instructions as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON) / 1_000_000.0
+    }
+}
+
+/// The outcome of [`Chip8::benchmark_cycles`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BenchmarkResult {
+    pub cycles: u64,
+    pub elapsed: Duration,
+    /// Millions of cycles per second
+    pub mhz: f64,
+}
+
+/// The keys as they were when the snapshot was taken. See [`Input::pressed_mask`].
+pub(crate) struct KeySnapshot(pub(crate) u16);
+
+impl Input for KeySnapshot {
+    fn is_pressed(&self, key: Key) -> bool {
+        self.0 & (1 << u8::from(key)) != 0
+    }
+
+    fn pressed_mask(&self) -> u16 {
+        self.0
+    }
+}
+
+/// The input passed to a cycle, with the keys pressed through [`Chip8::queue_key_event`] on top.
+struct WithQueuedKeys<'a, I> {
+    input: &'a I,
+    queued: u16,
+}
+
+impl<I: Input> Input for WithQueuedKeys<'_, I> {
+    fn is_pressed(&self, key: Key) -> bool {
+        self.queued & (1 << u8::from(key)) != 0 || self.input.is_pressed(key)
+    }
+
+    fn pressed_mask(&self) -> u16 {
+        self.queued | self.input.pressed_mask()
+    }
+}
+
+/// Input with no keys pressed, for running ROMs without a frontend.
+pub struct NoInput;
+
+impl Input for NoInput {
+    fn is_pressed(&self, _key: Key) -> bool {
+        false
+    }
+}
+
+impl BoxedChip8 {
+    /// Like [`Chip8::new`], boxing `graphics`.
+    pub fn new_boxed(
+        graphics: impl GraphicsBuffer + 'static,
+        timer_rx: Receiver<TimerOperation>,
+        quirks: Quirks,
+        options: DebugOptions,
+    ) -> Self {
+        Chip8::new(Box::new(graphics), timer_rx, quirks, options)
+    }
+}
+
+impl Chip8<Graphics> {
+    /// Runs a test ROM for `cycles` cycles with no keys pressed and the default quirks, then
+    /// checks that every `(register, value)` in `expected` holds. Many published test ROMs report
+    /// their result through the registers this way.
+    pub fn run_test_rom(rom: &[u8], expected: &[(u8, u8)], cycles: u64) -> TestResult {
+        // The timers never tick
+        let (_, timer_rx) = mpsc::channel();
+        let mut chip8 = Chip8::new(
+            Graphics::new(),
+            timer_rx,
+            Quirks::default(),
+            DebugOptions::default(),
+        );
+
+        let run = |chip8: &mut Chip8<Graphics>| -> Result<(), Chip8Error> {
+            chip8.load_rom(&rom.to_vec())?;
+            for _ in 0..cycles {
+                chip8.emulate_cycle(&NoInput)?;
+            }
+            Ok(())
+        };
+        let error = run(&mut chip8).err();
+
+        let failed_registers: Vec<_> = expected
+            .iter()
+            .filter_map(|&(reg, value)| {
+                let actual = chip8.registers.get(reg as usize).copied().unwrap_or(0);
+                (actual != value).then_some((reg, value, actual))
+            })
+            .collect();
+
+        TestResult {
+            passed: error.is_none() && failed_registers.is_empty(),
+            failed_registers,
+            error,
+        }
+    }
+
+    /// Runs `rom` for `cycles` cycles as fast as possible, and times it. Nothing is printed, no
+    /// keys are pressed, and the timers never tick, so the run is deterministic and only the
+    /// emulation itself shows up when it runs under a profiler.
+    pub fn profile_run(rom: &[u8], quirks: Quirks, cycles: u64) -> ProfileResult {
+        /// Cycles per [`Chip8::emulate_frame`] call
+        const BATCH: u64 = 10_000;
+
+        let (_, timer_rx) = mpsc::channel();
+        let mut chip8 = Chip8::new(Graphics::new(), timer_rx, quirks, DebugOptions::default());
+
+        let start = Instant::now();
+        let run = |chip8: &mut Chip8<Graphics>| -> Result<(), Chip8Error> {
+            chip8.load_rom(&rom.to_vec())?;
+            let mut remaining = cycles;
+            while remaining > 0 {
+                let batch = remaining.min(BATCH);
+                chip8.emulate_frame(batch as u32, &NoInput)?;
+                remaining -= batch;
+            }
+            Ok(())
+        };
+        let error = run(&mut chip8).err();
+
+        ProfileResult {
+            instructions: chip8.cycles(),
+            elapsed: start.elapsed(),
+            error,
+        }
+    }
+}
+
+/// Busy-waits until `display` is ready for the next frame. Returns `false` if `timeout`
+/// expired first.
+pub fn wait_for_vsync(display: &mut impl Display, timeout: Duration) -> bool {
+    let start = Instant::now();
+
+    while !display.is_vsync_ready() {
+        if start.elapsed() >= timeout {
+            return false;
+        }
+        std::hint::spin_loop();
+    }
+    true
+}
+
+/// Returns `true` if the two screens are the same size and every pixel matches.
+pub fn compare_graphics_snapshot(a: &[Vec<u8>], b: &[Vec<u8>]) -> bool {
+    a == b
+}
+
+/// Classifies `opcode` by the kind of work it does. Opcodes that are only valid with some
+/// quirks, such as `00C0`, are categorised as if the quirk were on.
+pub fn get_opcode_category(opcode: u16) -> OpcodeCategory {
+    match (opcode & 0xF000, opcode & 0x000F, opcode & 0x00FF) {
+        (0x0000, _, 0xE0 | 0xC0 | 0xC1) => OpcodeCategory::Graphics,
+        (0x0000, _, 0xEE) => OpcodeCategory::ControlFlow,
+        (0x1000 | 0x2000 | 0x3000 | 0x4000 | 0xB000, _, _) => OpcodeCategory::ControlFlow,
+        (0x5000 | 0x9000, 0x0, _) => OpcodeCategory::ControlFlow,
+        (0x6000 | 0x7000 | 0xC000, _, _) => OpcodeCategory::Arithmetic,
+        (0x8000, 0x0..=0x7 | 0xE, _) => OpcodeCategory::Arithmetic,
+        (0xA000, _, _) => OpcodeCategory::Memory,
+        (0xD000, _, _) => OpcodeCategory::Graphics,
+        (0xE000, _, 0x9E | 0xA1) => OpcodeCategory::Input,
+        (0xF000, _, 0x07 | 0x15 | 0x18) => OpcodeCategory::Timer,
+        (0xF000, _, 0x0A) => OpcodeCategory::Input,
+        (0xF000, _, 0x1E | 0x29 | 0x33 | 0x55 | 0x65) => OpcodeCategory::Memory,
+        _ => OpcodeCategory::Unknown,
+    }
+}
+
+/// Returns every `JP nnn` and `CALL nnn` target in `rom`, plus [`APP_LOCATION`] where it
+/// starts, sorted and without duplicates. Every aligned word is treated as an opcode, so data
+/// that happens to look like a jump or a call is included as well.
+pub fn get_program_entry_points(rom: &[u8]) -> Vec<u16> {
+    let mut entry_points = BTreeSet::from([APP_LOCATION]);
+    for word in rom.chunks_exact(2) {
+        let opcode = u16::from_be_bytes([word[0], word[1]]);
+        if matches!(opcode & 0xF000, 0x1000 | 0x2000) {
+            entry_points.insert(opcode & 0x0FFF);
+        }
+    }
+    entry_points.into_iter().collect()
+}
+
+/// Builds a [`Chip8`], checking the configuration before it starts.
+pub struct Chip8Builder<G> {
+    graphics: G,
+    timer_rx: Receiver<TimerOperation>,
+    quirks: Quirks,
+    options: DebugOptions,
+    cpu_frequency: f64,
+    timer_frequency: f64,
+    entry_point: u16,
+    load_address: u16,
+    font_base: u16,
+    opcode_overrides: OpcodeOverrides,
+    allow_core_override: bool,
+}
+
+impl<G> Chip8Builder<G>
+where
+    G: GraphicsBuffer,
+{
+    /// Starts with the default quirks and debug options.
+    pub fn new(graphics: G, timer_rx: Receiver<TimerOperation>) -> Self {
+        Chip8Builder {
+            graphics,
+            timer_rx,
+            quirks: Quirks::default(),
+            options: DebugOptions::default(),
+            cpu_frequency: DEFAULT_CPU_FREQUENCY,
+            timer_frequency: DEFAULT_TIMER_FREQUENCY,
+            entry_point: APP_LOCATION,
+            load_address: APP_LOCATION,
+            font_base: FONT_BASE,
+            opcode_overrides: OpcodeOverrides::default(),
+            allow_core_override: false,
+        }
+    }
+
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = quirks;
+        self
+    }
+
+    pub fn debug_options(mut self, options: DebugOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// The frequency (in Hz) the frontend runs instructions at. The emulator doesn't pace
+    /// itself, this is only reported back by [`Chip8::cpu_frequency`].
+    pub fn cpu_frequency(mut self, hertz: f64) -> Self {
+        self.cpu_frequency = hertz;
+        self
+    }
+
+    /// The frequency (in Hz) the frontend sends timer ticks at. See [`Chip8::timer_frequency`].
+    pub fn timer_frequency(mut self, hertz: f64) -> Self {
+        self.timer_frequency = hertz;
+        self
+    }
+
+    /// Where the program counter starts. Defaults to [`APP_LOCATION`].
+    pub fn entry_point(mut self, addr: u16) -> Self {
+        self.entry_point = addr;
+        self
+    }
+
+    /// Where [`Chip8::load_rom`] copies the ROM to, e.g. `0x600` for ETI-660 programs. Defaults
+    /// to [`APP_LOCATION`].
+    pub fn load_address(mut self, addr: u16) -> Self {
+        self.load_address = addr;
+        self
+    }
+
+    /// Where the font is copied to, and where `Fx29` points to. Defaults to [`FONT_BASE`]; some
+    /// ROMs expect the font at 0x000 instead, like older versions of Wheat put it.
+    pub fn font_base(mut self, addr: u16) -> Self {
+        self.font_base = addr;
+        self
+    }
+
+    /// Opcodes to handle differently from the interpreter, e.g. for homebrew extensions. Empty by
+    /// default.
+    pub fn opcode_overrides(mut self, overrides: OpcodeOverrides) -> Self {
+        self.opcode_overrides = overrides;
+        self
+    }
+
+    /// Lets the [`Chip8Builder::opcode_overrides`] change opcodes the interpreter already has a
+    /// meaning for. Off by default.
+    pub fn allow_core_override(mut self, allow: bool) -> Self {
+        self.allow_core_override = allow;
+        self
+    }
+
+    /// Fails with [`Chip8Error::InvalidQuirksConfiguration`] if the quirks don't pass
+    /// [`Quirks::validate`], with [`Chip8Error::InvalidProgramAddress`] if the entry point or
+    /// the load address is below [`APP_LOCATION`] or outside of memory, with
+    /// [`Chip8Error::InvalidFontAddress`] if the font doesn't fit below [`APP_LOCATION`], and with
+    /// [`Chip8Error::InvalidOpcodeOverrides`] if the overrides don't pass
+    /// [`OpcodeOverrides::validate`].
+    pub fn build(self) -> Result<Chip8<G>, Chip8Error> {
+        self.quirks
+            .validate()
+            .map_err(Chip8Error::InvalidQuirksConfiguration)?;
+        for addr in [self.entry_point, self.load_address] {
+            if addr < APP_LOCATION || addr as usize >= MEMORY_SIZE {
+                return Err(Chip8Error::InvalidProgramAddress(addr));
+            }
+        }
+        if self.font_base as usize + HEX_DIGITS.len() > APP_LOCATION as usize {
+            return Err(Chip8Error::InvalidFontAddress(self.font_base));
+        }
+        self.opcode_overrides
+            .validate(self.allow_core_override)
+            .map_err(Chip8Error::InvalidOpcodeOverrides)?;
+
+        let mut chip8 = Chip8::new(self.graphics, self.timer_rx, self.quirks, self.options);
+        chip8.cpu_frequency = self.cpu_frequency;
+        chip8.timer_frequency = self.timer_frequency;
+        chip8.pc = self.entry_point;
+        chip8.entry_point = self.entry_point;
+        chip8.call_frames = vec![self.entry_point];
+        chip8.call_profile = CallProfile::new(self.entry_point);
+        chip8.load_address = self.load_address;
+        chip8.move_font(self.font_base);
+        chip8.opcode_overrides = self.opcode_overrides;
+        Ok(chip8)
+    }
+}
+
 // Throughout the code, Vx refers to the general purpose registers. There are
 // 15 general purpose registers from V0 to VE. The 16th register is used to
 // represent the carry flag.
@@ -129,7 +1048,12 @@ where
     ) -> Self {
         let mut memory = vec![0; MEMORY_SIZE];
 
-        memory[..HEX_DIGITS.len()].copy_from_slice(&HEX_DIGITS[..]);
+        let font = FONT_BASE as usize;
+        memory[font..font + HEX_DIGITS.len()].copy_from_slice(&HEX_DIGITS[..]);
+        let trace = TraceBuffer::new(options.trace_depth);
+        let pre_execute_hook = options
+            .print_opcodes
+            .then(|| Box::new(|context: &HookContext| log::info!("{context}")) as ExecuteHook);
 
         Chip8 {
             opcode: 0,
@@ -142,866 +1066,4563 @@ where
             sound_timer: 0,
             stack: vec![0; STACK_SIZE],
             sp: 0,
+            max_sp: 0,
             timer_rx,
-            draw_on_screen: false,
+            timer_connected: true,
+            outcome: CycleOutcome::default(),
+            display_enabled: true,
             wait_for_keypress_register: 0,
             wait_for_key_state: WaitForKeyState::None,
+            key_events: VecDeque::new(),
+            queued_keys: 0,
             quirks,
             dbg_options: options,
+            read_high_water: 0,
+            write_high_water: 0,
+            memory_read_counts: Box::new([0; MEMORY_SIZE]),
+            memory_write_counts: Box::new([0; MEMORY_SIZE]),
+            executed: [0; OpcodeCategory::ALL.len()],
+            statistics_from: [0; OpcodeCategory::ALL.len()],
+            output_cycles: 0,
+            counted_from: 0,
+            frames_drawn: 0,
+            timer_ticks_received: 0,
+            draw_call_log: Vec::new(),
+            call_graph: HashMap::new(),
+            call_frames: vec![APP_LOCATION],
+            call_profile: CallProfile::new(APP_LOCATION),
+            trace,
+            opcode_profile: OpcodeProfile::default(),
+            statistics: Chip8Statistics::default(),
+            owed_timer_ticks: 0,
+            machine_call_handler: None,
+            sound_callback: None,
+            pre_execute_hook,
+            post_execute_hook: None,
+            unknown_opcode_handler: None,
+            cpu_frequency: DEFAULT_CPU_FREQUENCY,
+            timer_frequency: DEFAULT_TIMER_FREQUENCY,
+            load_address: APP_LOCATION,
+            entry_point: APP_LOCATION,
+            rom: Vec::new(),
+            font_base: FONT_BASE,
+            opcode_overrides: OpcodeOverrides::default(),
+            watchpoints: Vec::new(),
+            conditional_breakpoints: Vec::new(),
+            resumed_breakpoint: None,
         }
     }
 
+    /// Copies `rom` into memory at the load address, [`APP_LOCATION`] unless the builder set
+    /// another one.
     pub fn load_rom(&mut self, rom: &impl Rom) -> Result<(), Chip8Error> {
         for (i, rom_data) in rom.data().iter().enumerate() {
-            let addr = APP_LOCATION as usize + i;
-            if i < MEMORY_SIZE {
+            let addr = self.load_address as usize + i;
+            if addr < MEMORY_SIZE {
                 self.memory[addr] = *rom_data;
             } else {
                 return Err(Chip8Error::RomTooBig(addr as u16));
             }
         }
 
+        self.rom.clone_from(rom.data());
         Ok(())
     }
 
-    pub fn emulate_cycle(&mut self, input: &impl Input) -> Result<Chip8OutputState, Chip8Error> {
-        self.draw_on_screen = false;
-
-        let input_result = self.check_and_process_0xfx0a(input)?;
-        let mut stack_operation = ProgramCounter::None;
+    /// Restarts the program, as if the emulator was built again with the same settings and given
+    /// the same ROM. The registers, stack, timers, screen, key wait and debug records are
+    /// cleared, memory is set back to the font and the last ROM given to [`Chip8::load_rom`],
+    /// and `pc` goes back to the entry point. Timer ticks that weren't applied yet are dropped.
+    ///
+    /// Memory written any other way, e.g. with [`Chip8::patch_memory_region`], isn't restored.
+    pub fn reset(&mut self) {
+        self.memory.fill(0);
+        let font = self.font_base as usize;
+        self.memory[font..font + HEX_DIGITS.len()].copy_from_slice(&HEX_DIGITS);
+        let load = self.load_address as usize;
+        self.memory[load..load + self.rom.len()].copy_from_slice(&self.rom);
+
+        self.opcode = 0;
+        self.ir = 0;
+        self.pc = self.entry_point;
+        self.registers.fill(0);
+        self.stack.fill(0);
+        self.sp = 0;
+        self.max_sp = 0;
+        self.delay_timer = 0;
+        self.set_sound_timer(0);
+        self.graphics.clear();
+        self.outcome = CycleOutcome::default();
+        self.display_enabled = true;
+        self.wait_for_keypress_register = 0;
+        self.wait_for_key_state = WaitForKeyState::None;
+        self.key_events.clear();
+        self.queued_keys = 0;
+        self.resumed_breakpoint = None;
+
+        self.read_high_water = 0;
+        self.write_high_water = 0;
+        self.memory_read_counts.fill(0);
+        self.memory_write_counts.fill(0);
+        self.executed = [0; OpcodeCategory::ALL.len()];
+        self.statistics_from = self.executed;
+        self.output_cycles = 0;
+        self.reset_counters();
+        self.draw_call_log.clear();
+        self.call_graph.clear();
+        self.call_frames = vec![self.entry_point];
+        self.call_profile = CallProfile::new(self.entry_point);
+        self.trace.clear();
+        self.opcode_profile.reset();
+        self.statistics = Chip8Statistics::default();
+
+        while self.timer_rx.try_recv().is_ok() {}
+        self.owed_timer_ticks = 0;
+    }
 
-        if input_result != ProgramCounter::Pause {
-            stack_operation = self.emulate_instruction(input)?;
-        }
+    /// Like [`Chip8::reset`], but also forgets the ROM, so that only the font is left in memory
+    /// for the next [`Chip8::load_rom`].
+    pub fn hard_reset(&mut self) {
+        self.rom.clear();
+        self.reset();
+    }
 
-        match stack_operation {
-            ProgramCounter::Next => self.pc += OPCODE_SIZE,
-            ProgramCounter::Skip => self.pc += OPCODE_SIZE * 2,
-            ProgramCounter::Set(addr) => self.pc = addr,
-            ProgramCounter::None | ProgramCounter::Pause => (),
+    /// Reads up to `len` bytes from `reader` straight into memory starting at `start`, without
+    /// buffering the whole input first. Stops early when the reader runs out of data, and returns
+    /// the number of bytes written.
+    pub fn import_memory_from_reader<R: Read>(
+        &mut self,
+        mut reader: R,
+        start: u16,
+        len: u16,
+    ) -> Result<usize, Chip8Error> {
+        let end = start as usize + len as usize;
+        if end > self.memory.len() {
+            return Err(Chip8Error::InvalidMemoryAccess(end as u16));
         }
 
-        // If there's a timer message, update the timers
-        while let Ok(timer_operation) = self.timer_rx.try_recv() {
-            match timer_operation {
-                TimerOperation::Decrement(val) => {
-                    self.sound_timer = self.sound_timer.saturating_sub(val);
-                    self.delay_timer = self.delay_timer.saturating_sub(val);
-                }
+        let mut written = 0;
+        let region = &mut self.memory[start as usize..end];
+        while written < region.len() {
+            match reader.read(&mut region[written..]) {
+                Ok(0) => break,
+                Ok(n) => written += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(Chip8Error::Io(e.to_string())),
             }
         }
 
-        let sound_on = self.sound_timer > 0;
-        Ok(Chip8OutputState::new(
-            sound_on,
-            self.draw_on_screen,
-            &self.graphics,
-        ))
+        Ok(written)
     }
 
-    fn emulate_instruction(&mut self, input: &impl Input) -> OpcodeResult {
-        self.opcode =
-            ((self.memory[self.pc as usize] as u16) << 8) | self.memory[self.pc as usize + 1] as u16;
+    /// Applies `transform` to every byte of memory in `[start, end)`, and returns how many bytes
+    /// were changed by it.
+    pub fn patch_memory_region(
+        &mut self,
+        start: u16,
+        end: u16,
+        transform: impl Fn(u8) -> u8,
+    ) -> Result<usize, Chip8Error> {
+        if end as usize > self.memory.len() {
+            return Err(Chip8Error::InvalidMemoryAccess(end));
+        }
+        if start > end {
+            return Err(Chip8Error::InvalidMemoryAccess(start));
+        }
 
-        if self.dbg_options.print_opcodes {
-            println!("opcode is {:#06X}", self.opcode);
+        let mut modified = 0;
+        for byte in &mut self.memory[start as usize..end as usize] {
+            let patched = transform(*byte);
+            if patched != *byte {
+                *byte = patched;
+                modified += 1;
+            }
         }
 
-        match self.opcode & 0xF000 {
-            // Opcode starts with 0x0
-            0x0000 => self.opcode_0x0yyy(),
+        Ok(modified)
+    }
 
-            // Opcode starts with 0x1
-            0x1000 => self.opcode_0x1yyy(),
+    /// Reads the byte at `addr`.
+    pub fn read_memory(&self, addr: u16) -> Result<u8, Chip8Error> {
+        Ok(self.read_memory_range(addr, 1)?[0])
+    }
 
-            // Opcode starts with 0x2
-            0x2000 => self.opcode_0x2yyy(),
+    /// Writes `value` at `addr`. See [`Chip8::write_memory_range`].
+    pub fn write_memory(&mut self, addr: u16, value: u8) -> Result<(), Chip8Error> {
+        self.write_memory_range(addr, &[value])
+    }
 
-            // 3xkk - SE Vx, byte
-            // Skip next instruction if Vx == kk
-            0x3000 => self.opcode_0x3yyy(),
-
-            // Opcodes that start with 0x4
-            0x4000 => self.opcode_0x4yyy(),
+    /// Reads the `len` bytes starting at `start`.
+    pub fn read_memory_range(&self, start: u16, len: usize) -> Result<&[u8], Chip8Error> {
+        let range = Self::memory_range(start, len)?;
+        Ok(&self.memory[range])
+    }
 
-            // Opcodes that start with 0x5
-            0x5000 => self.opcode_0x5yyy(),
+    /// Writes `bytes` to memory starting at `start`, the same way the program's own writes are
+    /// made: the screen follows writes to the display with [`Quirks::vip_memory_layout`], and
+    /// write watchpoints see them, reporting the last instruction executed. Writes below
+    /// [`APP_LOCATION`], where the font is, are allowed, and logged if
+    /// `DebugOptions::print_opcodes` is set.
+    pub fn write_memory_range(&mut self, start: u16, bytes: &[u8]) -> Result<(), Chip8Error> {
+        let range = Self::memory_range(start, bytes.len())?;
+        if self.dbg_options.print_opcodes && range.start < APP_LOCATION as usize && !bytes.is_empty() {
+            log::info!(
+                "{start:#05x}: {} bytes written below {APP_LOCATION:#05x}",
+                bytes.len()
+            );
+        }
+        for (addr, &value) in range.zip(bytes) {
+            self.store(addr as u16, value)?;
+        }
+        Ok(())
+    }
 
-            // Opcodes that start with 0x6
-            0x6000 => self.opcode_0x6yyy(),
+    /// The indices of the `len` bytes of memory from `start`, or the first address past the end
+    /// of memory in a [`Chip8Error::InvalidMemoryAccess`].
+    fn memory_range(start: u16, len: usize) -> Result<Range<usize>, Chip8Error> {
+        let start = start as usize;
+        let end = start + len;
+        if end > MEMORY_SIZE {
+            return Err(Chip8Error::InvalidMemoryAccess(start.max(MEMORY_SIZE) as u16));
+        }
+        Ok(start..end)
+    }
 
-            // Opcodes that start with 0x7
-            0x7000 => self.opcode_0x7yyy(),
+    /// Writes `opcode` at `addr`, high byte first, e.g. to patch a ROM once it's loaded.
+    pub fn write_opcode_at(&mut self, addr: u16, opcode: u16) -> Result<(), Chip8Error> {
+        self.check_opcode_address(addr)?;
+        let [high, low] = opcode.to_be_bytes();
+        self.memory[addr as usize] = high;
+        self.memory[addr as usize + 1] = low;
+        Ok(())
+    }
 
-            // Opcodes that start with 0x8
-            0x8000 => self.opcode_0x8yyy(),
+    /// Reads the opcode stored at `addr`. See [`Chip8::write_opcode_at`].
+    pub fn read_opcode_at(&self, addr: u16) -> Result<u16, Chip8Error> {
+        self.check_opcode_address(addr)?;
+        Ok(self.opcode_at(addr))
+    }
 
-            // Opcodes that start with 0x9
-            0x9000 => self.opcode_0x9yyy(),
+    /// Checks that an opcode at `addr` is aligned and fits in memory.
+    fn check_opcode_address(&self, addr: u16) -> Result<(), Chip8Error> {
+        if !addr.is_multiple_of(2) {
+            return Err(Chip8Error::MemoryAlignment(addr));
+        }
+        if addr as usize + 1 >= self.memory.len() {
+            return Err(Chip8Error::InvalidMemoryAccess(addr));
+        }
+        Ok(())
+    }
 
-            // Opcodes that start with 0xA
-            0xA000 => self.opcode_0xayyy(),
+    /// Disassembles the opcode stored at `addr`.
+    pub fn disassemble_at(&self, addr: u16) -> Result<String, Chip8Error> {
+        if addr as usize + 1 >= self.memory.len() {
+            return Err(Chip8Error::InvalidMemoryAccess(addr));
+        }
 
-            // Opcodes that start with 0xB
-            0xB000 => self.opcode_0xbyyy(),
+        disasm::disassemble(self.opcode_at(addr))
+    }
 
-            // Cxkk - RND, byte
-            // Set Vx = random byte AND kk
-            // Interpreter generates a random number between 0 and 255, which
-            // is then ANDed with kk and the result is stored in Vx.
-            0xC000 => self.opcode_0xcyyy(),
+    /// Disassembles the instruction the program counter points at. Data that doesn't decode to an
+    /// instruction is shown as `DB 0x....`.
+    pub fn disassemble_current(&self) -> String {
+        self.disassemble_at(self.pc)
+            .unwrap_or_else(|_| format!("DB {:#06x}", self.opcode_at(self.pc)))
+    }
 
-            0xD000 => self.opcode_0xdyyy(),
+    /// Disassembles the opcode stored at `addr`, prefixed with its address and raw bytes, e.g.
+    /// `0200: 6120   LD V1, 0x20`.
+    pub fn disassemble_with_bytes(&self, addr: u16) -> String {
+        let opcode = self.opcode_at(addr);
+        let mnemonic = self
+            .disassemble_at(addr)
+            .unwrap_or_else(|_| format!("DB {:#06x}", opcode));
 
-            0xE000 => self.opcode_0xeyyy(input),
+        format!("{addr:04X}: {opcode:04X}   {mnemonic}")
+    }
 
-            0xF000 => self.opcode_0xfyyy(),
+    /// Reads the opcode at `addr` without affecting the access tracking. Bytes past the end of
+    /// memory read as 0.
+    fn opcode_at(&self, addr: u16) -> u16 {
+        let byte = |addr: u16| self.memory.get(addr as usize).copied().unwrap_or(0) as u16;
+        (byte(addr) << 8) | addr.checked_add(1).map_or(0, byte)
+    }
 
-            _ => self.unknown_opcode(),
+    /// Copies `values` into the registers, starting at `V0`. Registers past the end of `values`
+    /// are left unchanged. Fails without changing anything if there are more values than registers.
+    pub fn set_registers_from_slice(&mut self, values: &[u8]) -> Result<(), Chip8Error> {
+        if values.len() > NUM_REGISTERS {
+            return Err(Chip8Error::InvalidRegister(NUM_REGISTERS as u8));
         }
-    }
 
-    // Utility function to return the number of registers x and y.
-    fn get_regs_x_y(&self) -> (usize, usize) {
-        (
-            ((self.opcode & 0x0F00) >> 8) as usize,
-            ((self.opcode & 0x00F0) >> 4) as usize,
-        )
+        self.registers[..values.len()].copy_from_slice(values);
+        Ok(())
     }
 
-    fn unknown_opcode(&mut self) -> OpcodeResult {
-        println!("unknown opcode: {:X}", self.opcode);
-        Err(Chip8Error::UnsupportedOpcode(self.opcode))
+    /// Returns the values of registers `V0` to `VF`.
+    #[deprecated(note = "use `Chip8::registers` instead")]
+    pub fn get_registers_as_slice(&self) -> &[u8] {
+        &self.registers
     }
 
-    /// Takes care of opcodes that start with 0x0.
-    fn opcode_0x0yyy(&mut self) -> OpcodeResult {
-        match self.opcode & 0x00FF {
-            // Clear the screen
-            0x00E0 => {
-                self.graphics.clear();
-                self.draw_on_screen = true;
-                Ok(ProgramCounter::Next)
-            }
-            // Return from subroutine
-            0x00EE => {
-                // Restore program counter to previous location on stack
-                // before subroutine was called
-                self.sp -= 1;
-                Ok(ProgramCounter::Set(self.stack[self.sp as usize]))
-            }
+    /// Registers `V0` to `VF`.
+    pub fn registers(&self) -> &[u8; 16] {
+        self.registers[..].try_into().unwrap()
+    }
 
-            // No other opcodes start with 0x0
-            _ => self.unknown_opcode(),
-        }
+    /// The address of the next instruction to execute.
+    pub fn pc(&self) -> u16 {
+        self.pc
     }
 
-    /// Takes care of opcodes that start with 0x1.
-    fn opcode_0x1yyy(&mut self) -> OpcodeResult {
-        // Only 1 opcode that starts with 0x1: 0x1nnn
-        // 0x1nnn - Jump to location nnn
-        let addr = self.opcode & 0x0FFF;
-        Ok(ProgramCounter::Set(addr))
+    /// The index register `I`.
+    pub fn ir(&self) -> u16 {
+        self.ir
     }
 
-    /// Takes care of opcodes that start with 0x2.
-    fn opcode_0x2yyy(&mut self) -> OpcodeResult {
-        // 0x2adr - Call subroutine at adr
-        // Put instruction after program counter on stack and then jump to subroutine
-        // location. This prevents the VM from entering into an endless loop.
-        self.stack[self.sp as usize] = self.pc + OPCODE_SIZE;
-        self.sp += 1;
-        let addr = self.opcode & 0x0FFF;
-        Ok(ProgramCounter::Set(addr))
+    /// The stack pointer, i.e. the number of subroutine calls on the stack.
+    pub fn sp(&self) -> u8 {
+        self.sp
     }
 
-    /// Takes care of opcodes that start with 0x3.
-    fn opcode_0x3yyy(&mut self) -> OpcodeResult {
-        // 3xkk - SE Vx, byte
-        // Skip next instruction if Vx == kk
+    /// The return addresses of the subroutine calls on the stack, outermost first.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.sp as usize]
+    }
 
-        // Get register value and constant
-        let (x, _) = self.get_regs_x_y();
-        let register_val = self.registers[x];
-        let comp_val = (self.opcode & 0x00FF) as u8;
+    /// The subroutine call stack, as the return address of every running call, outermost
+    /// first. The same as [`Chip8::stack`], for reporting where an error happened.
+    #[deprecated(note = "use `Chip8::stack` instead")]
+    pub fn call_stack(&self) -> &[u16] {
+        self.stack()
+    }
 
-        // If equal, skip next instruction (increment program
-        // counter by 2)
-        if register_val == comp_val {
-            return Ok(ProgramCounter::Skip);
-        }
+    /// The [`Chip8::stack`] as a backtrace, e.g. `#0 0x0202 -> #1 0x0345`. Empty outside of any
+    /// subroutine.
+    pub fn format_call_stack(&self) -> String {
+        self.stack()
+            .iter()
+            .enumerate()
+            .map(|(depth, addr)| format!("#{depth} {addr:#06x}"))
+            .collect::<Vec<_>>()
+            .join(" -> ")
+    }
 
-        Ok(ProgramCounter::Next)
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
     }
 
-    /// Takes care of opcodes that start with 0x4.
-    fn opcode_0x4yyy(&mut self) -> OpcodeResult {
-        // 4xkk - SNE Vx, byte
-        // Skip next instruction if Vx != kk
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
 
-        // Get register value and constant
-        let (x, _) = self.get_regs_x_y();
-        let register_val = self.registers[x];
-        let comp_val = (self.opcode & 0x00FF) as u8;
+    /// The last instruction fetched, i.e. the one the last cycle executed. Only meaningful after
+    /// at least one [`Chip8::emulate_cycle`]; `0` before that, and after [`Chip8::reset`].
+    pub fn current_opcode(&self) -> u16 {
+        self.opcode
+    }
 
-        // If not equal, skip next instruction (increment program
-        // counter by 2)
-        if register_val != comp_val {
-            return Ok(ProgramCounter::Skip);
-        }
+    /// Returns the number of subroutine calls currently on the stack.
+    #[deprecated(note = "use `Chip8::sp` instead")]
+    pub fn get_stack_depth(&self) -> u8 {
+        self.sp
+    }
 
-        Ok(ProgramCounter::Next)
+    /// Returns the deepest the stack has been so far.
+    pub fn get_max_stack_depth(&self) -> u8 {
+        self.max_sp
     }
 
-    /// Takes care of opcodes that start with 0x5.
-    fn opcode_0x5yyy(&mut self) -> OpcodeResult {
-        // 5xy0 - SE Vx, Vy
-        // Skip next instruction if Vx == Vy
-        let (x, y) = self.get_regs_x_y();
-        let vx_val = self.registers[x];
-        let vy_val = self.registers[y];
+    /// Returns the highest memory address the program has read from so far, including opcode
+    /// fetches and sprite data.
+    pub fn get_read_high_water_mark(&self) -> u16 {
+        self.read_high_water
+    }
 
-        // If values are equal, skip next instruction (increment
-        // program counter by 2)
-        if vx_val == vy_val {
-            return Ok(ProgramCounter::Skip);
-        }
+    /// Returns the highest memory address the program has written to so far.
+    pub fn get_write_high_water_mark(&self) -> u16 {
+        self.write_high_water
+    }
 
-        Ok(ProgramCounter::Next)
+    /// Returns the `top_n` most read addresses with their read counts, most read first. Opcode
+    /// fetches and sprite data count as reads. Empty unless `DebugOptions::track_memory_hotspots`
+    /// is set.
+    pub fn trace_memory_hotspots(&self, top_n: usize) -> Vec<(u16, u64)> {
+        Self::hotspots(&self.memory_read_counts, top_n)
     }
 
-    /// Takes care of opcodes that start with 0x6.
-    fn opcode_0x6yyy(&mut self) -> OpcodeResult {
-        // 6xkk - LD Vx, byte
-        // Set Vx = kk
-        let val = (self.opcode & 0x00FF) as u8;
-        let (x, _) = self.get_regs_x_y();
+    /// Same as [`Chip8::trace_memory_hotspots`], for writes.
+    pub fn trace_memory_write_hotspots(&self, top_n: usize) -> Vec<(u16, u64)> {
+        Self::hotspots(&self.memory_write_counts, top_n)
+    }
 
-        // Set register to value
-        self.registers[x] = val;
-        Ok(ProgramCounter::Next)
+    fn hotspots(counts: &[u64; MEMORY_SIZE], top_n: usize) -> Vec<(u16, u64)> {
+        let mut hotspots: Vec<_> = (0..)
+            .zip(counts.iter().copied())
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        // Lower addresses first among equal counts
+        hotspots.sort_by_key(|&(addr, count)| (std::cmp::Reverse(count), addr));
+        hotspots.truncate(top_n);
+        hotspots
     }
 
-    /// Takes care of opcodes that start with 0x7.
-    fn opcode_0x7yyy(&mut self) -> OpcodeResult {
-        // 7xkk - ADD Vx, byte
-        // Set Vx = Vx + kk
-        // Get value and register
-        let val = (self.opcode & 0x00FF) as u8;
-        let x = ((self.opcode & 0x0F00) >> 8) as usize;
+    pub fn emulate_cycle(&mut self, input: &impl Input) -> Result<Chip8OutputState<'_>, Chip8Error> {
+        self.cycle(input)?;
+        Ok(self.take_output())
+    }
 
-        self.registers[x] = self.registers[x].wrapping_add(val);
-        Ok(ProgramCounter::Next)
+    /// Same as [`Chip8::emulate_cycle`], but returns an owned copy of the output, which doesn't
+    /// keep `self` borrowed.
+    pub fn emulate_cycle_snapshot(&mut self, input: &impl Input) -> Result<FrameSnapshot, Chip8Error> {
+        Ok(self.emulate_cycle(input)?.to_owned_snapshot())
     }
 
-    /// Takes care of opcodes that start with 0x8.
-    fn opcode_0x8yyy(&mut self) -> OpcodeResult {
-        macro_rules! reset_vf {
-            () => {
-                if self.quirks.reset_vf {
-                    self.registers[FLAG_REGISTER] = 0;
+    /// Runs `cycles` instructions as one frame, and reports everything they did in a single
+    /// output. The `input_sampling` quirk decides whether the keys are read once for the whole
+    /// frame, or every time a key opcode runs.
+    pub fn emulate_frame(
+        &mut self,
+        cycles: u32,
+        input: &impl Input,
+    ) -> Result<Chip8OutputState<'_>, Chip8Error> {
+        match self.quirks.input_sampling {
+            InputSampling::PerFrame => {
+                let snapshot = KeySnapshot(input.pressed_mask());
+                for _ in 0..cycles {
+                    self.cycle(&snapshot)?;
                 }
-            };
+            }
+            InputSampling::PerCycle => {
+                for _ in 0..cycles {
+                    self.cycle(input)?;
+                }
+            }
         }
 
-        macro_rules! set_vx_to_vy_for_shift {
-            ($x: ident, $y: ident) => {
-                if self.quirks.use_vy_in_shift {
-                    self.registers[$x] = self.registers[$y];
-                }
+        Ok(self.take_output())
+    }
+
+    /// Runs up to `n` cycles like [`Chip8::emulate_frame`], and stops early once the program waits
+    /// on an `Fx0A`. A breakpoint stops it with [`Chip8Error::BreakpointHit`], and what ran before
+    /// it is reported with the next output. `instructions_executed` says how many instructions
+    /// actually ran.
+    pub fn run_cycles(&mut self, n: u32, input: &impl Input) -> Result<Chip8OutputState<'_>, Chip8Error> {
+        self.run_until(n, input, |_| false)
+    }
+
+    /// Same as [`Chip8::run_cycles`], but also stops after the first instruction that changes the
+    /// screen, e.g. `Dxyn` or `00E0`.
+    pub fn run_until_draw(
+        &mut self,
+        max: u32,
+        input: &impl Input,
+    ) -> Result<Chip8OutputState<'_>, Chip8Error> {
+        self.run_until(max, input, |step| step.drew)
+    }
+
+    /// Runs up to `max` cycles, until the program waits for a key or `done` holds for a step.
+    fn run_until(
+        &mut self,
+        max: u32,
+        input: &impl Input,
+        done: impl Fn(&StepResult) -> bool,
+    ) -> Result<Chip8OutputState<'_>, Chip8Error> {
+        let snapshot = KeySnapshot(input.pressed_mask());
+        for _ in 0..max {
+            let step = match self.quirks.input_sampling {
+                InputSampling::PerFrame => self.cycle(&snapshot)?,
+                InputSampling::PerCycle => self.cycle(input)?,
             };
+            if step.waiting_for_key || done(&step) {
+                break;
+            }
         }
 
-        // Last nibble identifies what the opcode does
-        match self.opcode & 0x000F {
-            // 8xy0 - LD Vx, Vy
-            // Set Vx = Vy
-            0x0000 => {
-                let (x, y) = self.get_regs_x_y();
+        Ok(self.take_output())
+    }
 
-                self.registers[x] = self.registers[y];
-                Ok(ProgramCounter::Next)
+    /// Runs `n` cycles as fast as possible, and measures how long they took. The debug options and
+    /// the execute hooks are turned off while it runs, so that printing doesn't dominate the
+    /// measurement.
+    pub fn benchmark_cycles(&mut self, input: &impl Input, n: u64) -> Result<BenchmarkResult, Chip8Error> {
+        let options = std::mem::take(&mut self.dbg_options);
+        let hooks = (self.pre_execute_hook.take(), self.post_execute_hook.take());
+
+        let start = Instant::now();
+        let result = (0..n).try_for_each(|_| self.cycle(input).map(drop));
+        let elapsed = start.elapsed();
+
+        self.dbg_options = options;
+        (self.pre_execute_hook, self.post_execute_hook) = hooks;
+        result?;
+
+        Ok(BenchmarkResult {
+            cycles: n,
+            elapsed,
+            mhz: n as f64 / elapsed.as_secs_f64().max(f64::EPSILON) / 1_000_000.0,
+        })
+    }
+
+    /// Queues a press (`pressed`) or release of `key`, for frontends that get keys as events
+    /// rather than polling them, like macros and replays. Every queued transition is seen by at
+    /// least one cycle, in the order they were queued: each cycle applies the queued transitions
+    /// up to the second one for the same key, which waits for the next cycle. So a press and a
+    /// release queued back to back are seen as a press for one cycle.
+    ///
+    /// The queued keys are added to the keys of the [`Input`] passed to each cycle.
+    pub fn queue_key_event(&mut self, key: Key, pressed: bool) {
+        self.key_events.push_back((key, pressed));
+    }
+
+    /// Applies the queued key transitions that are due this cycle.
+    fn apply_key_events(&mut self) {
+        let mut changed = 0u16;
+        while let Some(&(key, pressed)) = self.key_events.front() {
+            let bit = 1 << u8::from(key);
+            if changed & bit != 0 {
+                break;
+            }
+            changed |= bit;
+            if pressed {
+                self.queued_keys |= bit;
+            } else {
+                self.queued_keys &= !bit;
             }
+            self.key_events.pop_front();
+        }
+    }
 
-            // 8xy1 - OR Vx, Vy
-            // Perform bitwise OR on Vx and Vy and store result in Vx.
-            0x0001 => {
-                let (x, y) = self.get_regs_x_y();
+    /// Executes one instruction (or one step of an `Fx0A` wait) and updates the timers.
+    fn cycle(&mut self, input: &impl Input) -> Result<StepResult, Chip8Error> {
+        let step = self.step(input)?;
+        self.drain_timer_queue();
+        for &(reg, expected) in &self.dbg_options.register_assertions {
+            self.assert_register_equals(reg, expected)?;
+        }
+        Ok(step)
+    }
 
-                self.registers[x] |= self.registers[y];
-                reset_vf!();
+    /// Executes exactly one instruction, for debuggers. Unlike [`Chip8::emulate_cycle`], the
+    /// timers are left alone: the timer channel isn't read, and [`Chip8::tick_timers`] advances
+    /// them instead. What the instruction did is still part of the next cycle's or frame's output.
+    pub fn step(&mut self, input: &impl Input) -> Result<StepResult, Chip8Error> {
+        let pc_before = self.pc;
+        let drew_before = std::mem::take(&mut self.outcome.draw_on_screen);
+        let result = self.execute(input);
+        let drew = self.outcome.draw_on_screen;
+        self.outcome.draw_on_screen |= drew_before;
+        result?;
+
+        Ok(StepResult {
+            opcode: self.opcode,
+            pc_before,
+            pc_after: self.pc,
+            drew,
+            waiting_for_key: self.is_waiting_for_key(),
+        })
+    }
 
-                Ok(ProgramCounter::Next)
-            }
+    /// Executes the instruction at `pc`, or waits on the current `Fx0A`.
+    fn execute(&mut self, input: &impl Input) -> Result<(), Chip8Error> {
+        self.apply_key_events();
+        let input = &WithQueuedKeys {
+            input,
+            queued: self.queued_keys,
+        };
+
+        // While `Fx0A` is waiting it decides what happens to the program counter: it pauses, and
+        // moves past the `Fx0A` once the key is released.
+        let stack_operation = match self.check_and_process_0xfx0a(input)? {
+            ProgramCounter::None => self.emulate_instruction(input)?,
+            key_wait => key_wait,
+        };
+
+        if self.quirks.vip_memory_layout && get_opcode_category(self.opcode) == OpcodeCategory::Graphics {
+            self.store_vip_display();
+        }
 
-            // 8xy2 - AND Vx, Vy
-            // Perform bitwise AND on Vx and Vy and store result in Vx.
-            0x0002 => {
-                let (x, y) = self.get_regs_x_y();
+        match stack_operation {
+            ProgramCounter::Next => self.pc += OPCODE_SIZE,
+            ProgramCounter::Skip => self.pc += OPCODE_SIZE * 2,
+            ProgramCounter::Set(addr) => self.pc = addr,
+            ProgramCounter::None | ProgramCounter::Pause => (),
+        }
+        Ok(())
+    }
 
-                self.registers[x] &= self.registers[y];
-                reset_vf!();
+    /// Counts both timers down by `ticks`, as if the timer channel delivered them.
+    pub fn tick_timers(&mut self, ticks: u8) {
+        self.statistics.timer_decrements += ticks as u64;
+        self.set_sound_timer(self.sound_timer.saturating_sub(ticks));
+        self.delay_timer = self.delay_timer.saturating_sub(ticks);
+    }
 
-                Ok(ProgramCounter::Next)
-            }
+    /// Reports what happened since the last output.
+    fn take_output(&mut self) -> Chip8OutputState<'_> {
+        let sound_on = self.sound_timer > 0;
+        let outcome = std::mem::take(&mut self.outcome);
+        self.frames_drawn += outcome.draw_on_screen as u64;
+        let cycles = self.cycles();
+        let instructions = (cycles - std::mem::replace(&mut self.output_cycles, cycles)) as u32;
+        let mut output = outcome.into_output(sound_on, self.display_enabled, &self.graphics);
+        output.instructions_executed = instructions;
+        output
+    }
 
-            // 8xy3 - XOR Vx, Vy
-            // Performs bitwise XOR on Vx and Vy and stores result in Vx.
-            0x0003 => {
-                let (x, y) = self.get_regs_x_y();
+    /// Returns whether the program is stopped on an `Fx0A`, waiting for a key press.
+    pub fn is_waiting_for_key(&self) -> bool {
+        self.wait_for_key_state != WaitForKeyState::None
+    }
 
-                self.registers[x] ^= self.registers[y];
-                reset_vf!();
+    /// While waiting for a key, returns the keys the program looks like it accepts. See
+    /// [`disasm::expected_keys`]. `None` means any key might be accepted, or there's no wait.
+    pub fn expected_keys(&self) -> Option<Vec<u8>> {
+        if !self.is_waiting_for_key() {
+            return None;
+        }
+        disasm::expected_keys(&self.memory, self.pc)
+    }
 
-                Ok(ProgramCounter::Next)
+    /// Collects the pending timer messages and applies as many ticks as
+    /// `Quirks::ticks_per_cycle_cap` allows, keeping the rest for later calls. Every cycle does
+    /// this, but frontends that drive the timers themselves can also call it between cycles, e.g.
+    /// while the emulator is paused.
+    pub fn drain_timer_queue(&mut self) {
+        while self.timer_connected {
+            match self.timer_rx.try_recv() {
+                Ok(TimerOperation::Decrement(val)) => {
+                    self.timer_ticks_received += val as u64;
+                    self.owed_timer_ticks = self.owed_timer_ticks.saturating_add(val.into());
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => self.timer_connected = false,
             }
+        }
 
-            // 8xy4 - ADD Vx, Vy
-            // Vx = Vx + Vy, set VF = carry
-            // If the result of Vx and Vy is greater than 8 bits (255)
-            // VF is set to 1, otherwise it's set to 0
-            0x0004 => {
-                let (x, y) = self.get_regs_x_y();
-                let (val, overflow) = self.registers[x].overflowing_add(self.registers[y]);
+        let ticks = match self.quirks.ticks_per_cycle_cap {
+            Some(cap) => self.owed_timer_ticks.min(cap),
+            None => self.owed_timer_ticks,
+        };
+        self.owed_timer_ticks -= ticks;
 
-                let flag = if overflow { 1 } else { 0 };
+        let val = ticks.min(u8::MAX.into()) as u8;
+        self.tick_timers(val);
+        // Every tick counts, even past the 255 a timer can hold
+        self.statistics.timer_decrements += (ticks - val as u32) as u64;
+    }
 
-                self.registers[x] = val;
-                self.registers[FLAG_REGISTER] = flag;
+    /// Returns the timer ticks that have been received but are still waiting to be applied
+    /// because of `Quirks::ticks_per_cycle_cap`.
+    pub fn get_owed_timer_ticks(&self) -> u32 {
+        self.owed_timer_ticks
+    }
 
-                Ok(ProgramCounter::Next)
-            }
+    /// Returns the whole of memory, e.g. to snapshot it for a [`crate::memsearch::MemorySearch`].
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
 
-            // 8xy5 - SUB Vx, Vy
-            // Vx= Vx - Vy, set VF = NOT borrow
-            // If Vx >= Vy, then VF is set to 1, otherwise 0
-            0x0005 => {
-                let (x, y) = self.get_regs_x_y();
+    pub fn statistics(&self) -> Chip8Statistics {
+        let mut statistics = self.statistics.clone();
+        for (cycles, (executed, from)) in statistics
+            .cycles_by_category
+            .iter_mut()
+            .zip(self.executed.iter().zip(&self.statistics_from))
+        {
+            *cycles = executed - from;
+        }
+        statistics.total_cycles = statistics.cycles_by_category.iter().sum();
+        statistics
+    }
 
-                let flag = if self.registers[x] >= self.registers[y] {
-                    1
-                } else {
-                    0
-                };
+    /// Instructions executed since the emulator started.
+    fn cycles(&self) -> u64 {
+        self.executed.iter().sum()
+    }
 
-                let (val, _) = self.registers[x].overflowing_sub(self.registers[y]);
+    /// Instructions executed, not counting the cycles spent waiting on an `Fx0A`.
+    pub fn cycles_executed(&self) -> u64 {
+        self.cycles() - self.counted_from
+    }
 
-                self.registers[x] = val;
-                self.registers[FLAG_REGISTER] = flag;
+    /// Outputs that reported a change to the screen, i.e. with `draw_on_screen` set.
+    pub fn frames_drawn(&self) -> u64 {
+        self.frames_drawn
+    }
 
-                Ok(ProgramCounter::Next)
-            }
+    /// Timer ticks received from the timer channel, including the ones not applied yet.
+    pub fn timer_ticks_received(&self) -> u64 {
+        self.timer_ticks_received
+    }
 
-            // 8xy6 - SHR Vx {, Vy}
-            // Set Vx = Vx SHR 1
-            // If least significant bit of Vx is 1, then VF is set to 1,
-            // otherwise 0. Then Vx is divided by 2
-            0x0006 => {
-                let (x, y) = self.get_regs_x_y();
+    /// Zeroes [`Chip8::cycles_executed`], [`Chip8::frames_drawn`] and
+    /// [`Chip8::timer_ticks_received`].
+    pub fn reset_counters(&mut self) {
+        self.counted_from = self.cycles();
+        self.frames_drawn = 0;
+        self.timer_ticks_received = 0;
+    }
 
-                set_vx_to_vy_for_shift!(x, y);
+    /// Zeroes the statistics. The emulator itself is left untouched.
+    pub fn reset_statistics(&mut self) {
+        self.statistics = Chip8Statistics::default();
+        self.statistics_from = self.executed;
+    }
 
-                let flag = self.registers[x] & 0x1;
-                self.registers[x] >>= 1;
+    /// Returns the draw opcodes executed since the log was last cleared. Empty unless
+    /// `DebugOptions::log_draw_calls` is set.
+    pub fn get_draw_call_log(&self) -> &[DrawCall] {
+        &self.draw_call_log
+    }
 
-                self.registers[FLAG_REGISTER] = flag;
-                Ok(ProgramCounter::Next)
+    pub fn clear_draw_call_log(&mut self) {
+        self.draw_call_log.clear();
+    }
+
+    /// Renders the subroutine calls seen so far as a Graphviz graph, with an edge from each
+    /// subroutine to every subroutine it called. The entry point stands for the code outside of
+    /// any subroutine. Empty unless `DebugOptions::track_call_graph` is set.
+    pub fn export_call_graph_dot(&self) -> String {
+        let mut nodes = BTreeSet::new();
+        let mut edges = BTreeSet::new();
+        for (&caller, callees) in &self.call_graph {
+            nodes.insert(caller);
+            for &callee in callees {
+                nodes.insert(callee);
+                edges.insert((caller, callee));
             }
+        }
 
-            // 8xy7 - SUBN Vx, Vy
-            // Set Vx = Vy - Vx, set VF = NOT borrow
-            // If Vy >= Vx, then VF = 1, otherwise VF = 0.
-            0x0007 => {
-                let (x, y) = self.get_regs_x_y();
+        let mut dot = String::from("digraph calls {\n");
+        for node in nodes {
+            dot += &format!("    \"{node:#05x}\";\n");
+        }
+        for (caller, callee) in edges {
+            dot += &format!("    \"{caller:#05x}\" -> \"{callee:#05x}\";\n");
+        }
+        dot += "}\n";
+        dot
+    }
 
-                let flag = if self.registers[y] >= self.registers[x] {
-                    1
-                } else {
-                    0
-                };
+    /// The last instructions executed, oldest first. Empty unless `DebugOptions::trace_depth` is
+    /// set.
+    pub fn trace(&self) -> impl Iterator<Item = &TraceEntry> {
+        self.trace.iter()
+    }
 
-                let (val, _) = self.registers[y].overflowing_sub(self.registers[x]);
+    /// The `top_n` addresses the most instructions were executed at, and how many instructions
+    /// of each opcode family were executed. Empty unless `DebugOptions::profile_opcodes` is set.
+    pub fn profile_report(&self, top_n: usize) -> OpcodeProfileReport {
+        self.opcode_profile.report(top_n, &self.memory)
+    }
 
-                self.registers[x] = val;
-                self.registers[FLAG_REGISTER] = flag;
+    /// Zeroes the counts of [`Chip8::profile_report`].
+    pub fn reset_profile(&mut self) {
+        self.opcode_profile.reset();
+    }
 
-                Ok(ProgramCounter::Next)
-            }
+    /// The subroutines called so far, with the instructions executed in each. Empty unless
+    /// `DebugOptions::profile_calls` is set.
+    pub fn call_profile(&self) -> CallProfile {
+        let mut profile = self.call_profile.clone();
+        if self.dbg_options.profile_calls {
+            profile.run_until(self.cycles());
+        }
+        profile
+    }
 
-            // 8xyE - SHL Vx {, Vy}
-            // Set Vx = Vx SHL 1
-            // If most significant bit of Vx is 1, set VF to 1, otherwise 0.
-            0x000E => {
-                let (x, y) = self.get_regs_x_y();
-                set_vx_to_vy_for_shift!(x, y);
+    /// Returns the graphics buffer the emulator draws on.
+    pub fn graphics(&self) -> &G {
+        &self.graphics
+    }
 
-                let flag = (self.registers[x] >> 7) & 0x1;
+    /// Turns [`Quirks::handle_0x0nnn_syscalls`] on or off.
+    pub fn enable_legacy_0x0nnn_machine_calls(&mut self, enabled: bool) {
+        self.quirks.handle_0x0nnn_syscalls = enabled;
+    }
 
-                self.registers[x] <<= 1;
-                self.registers[FLAG_REGISTER] = flag;
+    /// Sets the routine that emulates the machine code called by `0nnn`. It gets `nnn` and a copy
+    /// of the emulator's state, and the changes it makes to the state are applied once it returns.
+    /// Execution resumes after the instruction at `pc`. A stack pointer past the end of the stack
+    /// fails the call with [`Chip8Error::StackOverflow`], and none of the changes are applied.
+    pub fn set_machine_call_handler(&mut self, handler: impl Fn(u16, &mut Chip8State) + 'static) {
+        self.machine_call_handler = Some(Box::new(handler));
+    }
 
-                Ok(ProgramCounter::Next)
-            }
+    /// Sets a callback that is told when the buzzer starts and stops, i.e. when the sound timer
+    /// goes from 0 to non-zero and back, so frontends don't have to poll
+    /// [`Chip8OutputState::sound_on`].
+    pub fn register_sound_callback(&mut self, callback: impl Fn(SoundEvent) + 'static) {
+        self.sound_callback = Some(Box::new(callback));
+    }
 
-            // No other opcodes start with 0x8
-            _ => self.unknown_opcode(),
-        }
+    /// Sets a hook that is called with every instruction after it's fetched, before it runs.
+    /// Replaces the one `DebugOptions::print_opcodes` sets.
+    pub fn set_pre_execute_hook(&mut self, hook: impl FnMut(&HookContext) + 'static) {
+        self.pre_execute_hook = Some(Box::new(hook));
     }
 
-    /// Takes care of opcodes that start with 0x9
-    fn opcode_0x9yyy(&mut self) -> OpcodeResult {
-        // 9xy0 - SNE Vx, Vy
-        // Skip next instruction if Vx != Vy
-        let (x, y) = self.get_regs_x_y();
+    /// Sets a hook that is called with every instruction after it ran, unless it failed.
+    pub fn set_post_execute_hook(&mut self, hook: impl FnMut(&HookContext) + 'static) {
+        self.post_execute_hook = Some(Box::new(hook));
+    }
 
-        if self.registers[x] != self.registers[y] {
-            return Ok(ProgramCounter::Skip);
-        }
+    /// Sets the handler for the opcodes the interpreter doesn't know, e.g. vendor extensions.
+    /// It gets the opcode and the [`Chip8Context`] to change, instead of the opcode failing with
+    /// [`Chip8Error::UnsupportedOpcode`]. Opcodes the interpreter runs itself, including the
+    /// tolerated legacy opcodes and the machine calls, never get to it.
+    pub fn set_unknown_opcode_handler(
+        &mut self,
+        handler: impl FnMut(u16, &mut Chip8Context) -> OpcodeOutcome + 'static,
+    ) {
+        self.unknown_opcode_handler = Some(Box::new(handler));
+    }
 
-        Ok(ProgramCounter::Next)
+    /// Removes the hooks set with [`Chip8::set_pre_execute_hook`] and
+    /// [`Chip8::set_post_execute_hook`], including the `DebugOptions::print_opcodes` one.
+    pub fn clear_execute_hooks(&mut self) {
+        self.pre_execute_hook = None;
+        self.post_execute_hook = None;
     }
 
-    /// Takes care of opcodes that start with 0xA
-    fn opcode_0xayyy(&mut self) -> OpcodeResult {
-        // Annn - LD I, addr
-        // Set I = nnn
-        // Get address and set index register
-        let val = self.opcode & 0x0FFF;
-        self.ir = val;
-        Ok(ProgramCounter::Next)
+    /// Returns a copy of the emulator's state, e.g. to [`Chip8State::diff`] it against another
+    /// emulator's.
+    /// Same as [`Chip8::save_state`].
+    pub fn state(&self) -> Chip8State {
+        self.save_state()
     }
 
-    /// Takes care of opcodes that start with 0xB
-    fn opcode_0xbyyy(&mut self) -> OpcodeResult {
-        // Bnnn - JP V0, nnn
-        // Jump to location nnn + V0 (set pc = nnn + V0)
-        // With quirk `use_vx_in_jump`, it is:
-        // Bxnn - JP Vx, nn (set pc = Vx + nn)
-        if !self.quirks.use_vx_in_jump {
-            let val = self.opcode & 0x0FFF;
-            Ok(ProgramCounter::Set(val + self.registers[0x0] as u16))
-        } else {
-            let (x, _) = self.get_regs_x_y();
-            let val = self.opcode & 0x00FF;
-            Ok(ProgramCounter::Set(val + self.registers[x] as u16))
+    /// Copies everything the program can see: memory, registers, stack, timers, screen, and how far
+    /// a running `Fx0A` got. Restore it with [`Chip8::load_state`].
+    pub fn save_state(&self) -> Chip8State {
+        Chip8State {
+            memory: self.memory.clone(),
+            registers: self.registers[..].try_into().unwrap(),
+            stack: self.stack[..].try_into().unwrap(),
+            pc: self.pc,
+            ir: self.ir,
+            sp: self.sp,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            screen: self.graphics.buffer().clone(),
+            key_wait: KeyWait {
+                state: self.wait_for_key_state,
+                register: self.wait_for_keypress_register,
+            },
         }
     }
 
-    /// Takes care of opcodes that start with 0xC
-    fn opcode_0xcyyy(&mut self) -> OpcodeResult {
-        // Cxkk - RND, byte
-        // Set Vx = random byte AND kk
-        // Interpreter generates a random number between 0 and 255, which
-        // is then ANDed with kk and the result is stored in Vx.
-        let kk: u8 = (self.opcode & 0x00FF) as u8;
-        let (x, _) = self.get_regs_x_y();
+    /// Restores a state from [`Chip8::save_state`], so that the program carries on from where it
+    /// was saved, including in the middle of an `Fx0A` wait. Fails with
+    /// [`Chip8Error::DeserializationError`], changing nothing, if the memory, the screen, the
+    /// stack pointer or the register the key wait stores into don't fit this emulator.
+    ///
+    /// The sound callback hears the buzzer start or stop if the state's sound timer does. Whether
+    /// a legacy opcode turned the display off isn't saved, so that stays as it is.
+    pub fn load_state(&mut self, state: Chip8State) -> Result<(), Chip8Error> {
+        if state.memory.len() != self.memory.len() {
+            return Err(Chip8Error::DeserializationError(format!(
+                "expected {} bytes of memory, got {}",
+                self.memory.len(),
+                state.memory.len()
+            )));
+        }
+        let buffer = self.graphics.buffer();
+        let same_size = state.screen.len() == buffer.len()
+            && state
+                .screen
+                .iter()
+                .zip(buffer)
+                .all(|(ours, theirs)| ours.len() == theirs.len());
+        if !same_size {
+            return Err(Chip8Error::DeserializationError(
+                "the screen has a different size".to_string(),
+            ));
+        }
+        if state.sp as usize > STACK_SIZE {
+            return Err(Chip8Error::DeserializationError(format!(
+                "stack pointer {} is past the end of the stack",
+                state.sp
+            )));
+        }
+        if state.key_wait.register as usize >= NUM_REGISTERS {
+            return Err(Chip8Error::DeserializationError(format!(
+                "the key wait stores into V{:X}, which doesn't exist",
+                state.key_wait.register
+            )));
+        }
 
-        let rand_val = rand::thread_rng().gen_range(0..256) as u8;
+        self.memory = state.memory;
+        self.registers = state.registers.to_vec();
+        self.stack = state.stack.to_vec();
+        self.pc = state.pc;
+        self.ir = state.ir;
+        self.sp = state.sp;
+        self.delay_timer = state.delay_timer;
+        self.set_sound_timer(state.sound_timer);
+        self.graphics.load_buffer(&state.screen);
+        self.wait_for_key_state = state.key_wait.state;
+        self.wait_for_keypress_register = state.key_wait.register;
+        Ok(())
+    }
 
-        self.registers[x] = rand_val & kk;
-        Ok(ProgramCounter::Next)
+    pub fn quirks(&self) -> &Quirks {
+        &self.quirks
     }
 
-    /// Takes care of opcodes that start with 0xD
-    fn opcode_0xdyyy(&mut self) -> OpcodeResult {
-        // Dxyn - DRW Vx, Vy, nibble
-        // Display n-byte sprite starting at memory location I at (Vx, Vy),
-        // set VF = collision
-        let (x_reg, y_reg) = self.get_regs_x_y();
-        let num_rows = (self.opcode & 0x000F) as u8;
+    /// Returns the address of the first font sprite. The font takes up the [`HEX_DIGITS`] bytes
+    /// from there.
+    pub fn font_base(&self) -> u16 {
+        self.font_base
+    }
 
-        let x = self.registers[x_reg];
-        let y = self.registers[y_reg];
+    /// Moves the font from where it is to `addr`, clearing its old location.
+    fn move_font(&mut self, addr: u16) {
+        let old = self.font_base as usize;
+        self.memory[old..old + HEX_DIGITS.len()].fill(0);
+        let new = addr as usize;
+        self.memory[new..new + HEX_DIGITS.len()].copy_from_slice(&HEX_DIGITS);
+        self.font_base = addr;
+    }
 
-        let flipped = self
-            .graphics
-            .draw(x, y, num_rows, self.ir, &self.memory, self.quirks.clipping);
-        self.draw_on_screen = true;
+    /// Returns `true` if the emulator's state matches `other`, apart from the fields `mask`
+    /// ignores.
+    pub fn state_eq_ignoring(&self, other: &Chip8State, mask: StateMask) -> bool {
+        self.state().diff(other).is_empty_ignoring(mask)
+    }
 
-        if flipped {
-            self.registers[FLAG_REGISTER] = 1;
-        } else {
-            self.registers[FLAG_REGISTER] = 0;
-        }
+    /// Returns a copy of the screen, without the rest of the emulator state.
+    pub fn snapshot_graphics(&self) -> Vec<Vec<u8>> {
+        self.graphics.buffer().clone()
+    }
 
-        if self.dbg_options.dump_graphics {
-            self.dump_graphics();
-        }
+    pub fn cpu_frequency(&self) -> f64 {
+        self.cpu_frequency
+    }
 
-        Ok(ProgramCounter::Next)
+    pub fn timer_frequency(&self) -> f64 {
+        self.timer_frequency
     }
 
-    /// Takes care of opcodes that are related to input such as checking whether
-    /// a key is pressed or not pressed, and waiting until a key is pressed.
-    fn opcode_0xeyyy(&mut self, input: &impl Input) -> OpcodeResult {
-        match self.opcode & 0xFF {
-            // Ex9E - SKP Vx
-            // Skips the next instruction if the key with the value of Vx is
-            // pressed. If the key corresponding to the value of Vx is currently
-            // in the down position, PC is increased by 2.
-            0x9E => {
-                let (x, _) = self.get_regs_x_y();
+    /// Returns how long (in ms) until the delay timer reaches 0 if the timers count down at
+    /// `timer_freq_hz`. The countdown doesn't depend on `cpu_freq_hz`, which is taken so that
+    /// frontends can pass both of [`Chip8::cpu_frequency`] and [`Chip8::timer_frequency`].
+    pub fn delay_timer_remaining_ms(&self, _cpu_freq_hz: f64, timer_freq_hz: f64) -> f64 {
+        self.delay_timer as f64 / timer_freq_hz * 1000.0
+    }
 
-                if input.is_pressed((self.registers[x]).try_into()?) {
-                    return Ok(ProgramCounter::Skip);
-                }
+    /// Like [`Chip8::delay_timer_remaining_ms`], for the sound timer.
+    pub fn sound_timer_remaining_ms(&self, _cpu_freq_hz: f64, timer_freq_hz: f64) -> f64 {
+        self.sound_timer as f64 / timer_freq_hz * 1000.0
+    }
 
-                Ok(ProgramCounter::Next)
-            }
+    /// Returns `true` if every pixel on the screen is off, e.g. while a game is loading.
+    pub fn is_display_blank(&self) -> bool {
+        self.graphics.buffer().iter().flatten().all(|&pixel| pixel == 0)
+    }
 
-            // ExA1 - SKNP Vx
-            // Skip next instruction if key with value Vx is not pressed. If the
-            // key with value Vx is not pressed, the program counter is incremented
-            // by 2.
-            0xA1 => {
-                let (x, _) = self.get_regs_x_y();
+    /// Returns `true` if every pixel on the screen is on.
+    pub fn is_display_full(&self) -> bool {
+        self.graphics.buffer().iter().flatten().all(|&pixel| pixel != 0)
+    }
 
-                if !input.is_pressed((self.registers[x]).try_into()?) {
-                    return Ok(ProgramCounter::Skip);
-                }
+    /// Renders the screen as text, two pixel rows per line, using Unicode block elements. See
+    /// [`graphics::to_ascii`].
+    pub fn screen_to_ascii(&self) -> String {
+        graphics::to_ascii(self.graphics.buffer())
+    }
 
-                Ok(ProgramCounter::Next)
-            }
+    /// Renders the screen as text, one pixel row per line, using `'#'` and `'.'`. See
+    /// [`graphics::to_ascii_1bpp`].
+    pub fn screen_to_ascii_1bpp(&self) -> String {
+        graphics::to_ascii_1bpp(self.graphics.buffer())
+    }
 
-            _ => self.unknown_opcode(),
-        }
+    /// Compares the screen against `expected`, listing every pixel that differs.
+    #[cfg(any(test, feature = "test-helpers"))]
+    pub fn assert_display_equals(&self, expected: &[Vec<u8>]) -> Result<(), DisplayMismatch> {
+        test_helpers::compare_display(expected, self.graphics.buffer())
     }
 
-    fn opcode_0xfyyy(&mut self) -> OpcodeResult {
-        macro_rules! increment_ir {
-            () => {
-                if self.quirks.increment_ir {
-                    self.ir += 1;
-                }
-            };
+    /// Fails with [`Chip8Error::AssertionFailed`] if register `V{reg}` doesn't hold `expected`.
+    pub fn assert_register_equals(&self, reg: u8, expected: u8) -> Result<(), Chip8Error> {
+        let actual = *self
+            .registers
+            .get(reg as usize)
+            .ok_or(Chip8Error::InvalidRegister(reg))?;
+        if actual != expected {
+            return Err(Chip8Error::AssertionFailed {
+                register: reg,
+                expected,
+                actual,
+            });
         }
+        Ok(())
+    }
 
-        match self.opcode & 0xFF {
-            // Fx07 - LD Vx, DT
-            // Set Vx = delay timer value.
-            // The value of DT is placed into Vx.
-            0x07 => {
-                let (x, _) = self.get_regs_x_y();
-                self.registers[x] = self.delay_timer;
-                Ok(ProgramCounter::Next)
-            }
+    /// Same as [`Chip8::assert_register_equals`] for the flag register.
+    pub fn assert_vf_equals(&self, expected: u8) -> Result<(), Chip8Error> {
+        self.assert_register_equals(FLAG_REGISTER as u8, expected)
+    }
 
-            // Fx0A - LD Vx, K
-            // Wait for a key press, store the value of the key in Vx.
-            // All execution stops until a key is pressed, then the value
-            // of that key is stored in Vx.
-            0x0A => {
-                let (x, _) = self.get_regs_x_y();
+    /// Checks that every pixel on the screen is off, listing the ones that aren't.
+    #[cfg(any(test, feature = "test-helpers"))]
+    pub fn assert_display_clear(&self) -> Result<(), DisplayMismatch> {
+        let blank = vec![vec![0; SCREEN_WIDTH as usize]; SCREEN_HEIGHT as usize];
+        self.assert_display_equals(&blank)
+    }
 
-                if self.wait_for_key_state == WaitForKeyState::None {
-                    self.wait_for_keypress_register = x as u8;
-                    self.wait_for_key_state = WaitForKeyState::WaitForNoKeyPressed;
-                }
+    fn emulate_instruction(&mut self, input: &impl Input) -> OpcodeResult {
+        if !self.conditional_breakpoints.is_empty() {
+            self.check_conditional_breakpoints()?;
+        }
+        self.opcode = ((self.fetch(self.pc)? as u16) << 8) | self.fetch(self.pc.wrapping_add(1))? as u16;
+        if self.dbg_options.profile_opcodes {
+            self.opcode_profile.record(self.pc);
+        }
+        if self.dbg_options.trace_depth > 0 {
+            self.trace.push(TraceEntry {
+                pc: self.pc,
+                opcode: self.opcode,
+                registers: *self.registers(),
+            });
+        }
+        self.executed[get_opcode_category(self.opcode) as usize] += 1;
+
+        if let Some(hook) = &mut self.pre_execute_hook {
+            hook(&HookContext {
+                pc: self.pc,
+                opcode: self.opcode,
+                registers: &self.registers,
+                ir: self.ir,
+            });
+        }
 
-                Ok(ProgramCounter::Pause)
-            }
+        let result = self.interpret(input);
+        if let (Some(hook), Ok(_)) = (&mut self.post_execute_hook, &result) {
+            hook(&HookContext {
+                pc: self.pc,
+                opcode: self.opcode,
+                registers: &self.registers,
+                ir: self.ir,
+            });
+        }
+        result
+    }
 
-            // Fx15 - LD DT, Vx
-            // Set delay timer = Vx
-            // DT is set equal to the value of Vx.
-            0x15 => {
-                let (x, _) = self.get_regs_x_y();
-                self.delay_timer = self.registers[x];
-                Ok(ProgramCounter::Next)
+    /// Runs the fetched opcode.
+    fn interpret(&mut self, input: &impl Input) -> OpcodeResult {
+        if !self.opcode_overrides.is_empty() {
+            match self.opcode_overrides.resolve(self.opcode) {
+                Resolved::Execute(opcode) => self.opcode = opcode,
+                Resolved::Nop => return Ok(ProgramCounter::Next),
+                Resolved::Halt => return Err(Chip8Error::Halted(self.pc)),
+                Resolved::Break => {
+                    let addr = self.pc;
+                    self.pc += OPCODE_SIZE;
+                    return Err(Chip8Error::BreakpointHit(addr));
+                }
             }
+        }
 
-            // Fx18 - LD ST, Vx
-            // Set sound timer = Vx
-            // ST is set equal to the value of Vx.
-            0x18 => {
-                let (x, _) = self.get_regs_x_y();
-                self.sound_timer = self.registers[x];
-                Ok(ProgramCounter::Next)
-            }
+        match self.opcode & 0xF000 {
+            // Opcode starts with 0x0
+            0x0000 => self.opcode_0x0yyy(),
 
-            // Fx1E - ADD I, Vx
-            // Set I = I + Vx
-            // The values of I and Vx are added, and the results are stored in I.
-            0x1E => {
-                let (x, _) = self.get_regs_x_y();
-                self.ir += self.registers[x] as u16;
-                Ok(ProgramCounter::Next)
-            }
+            // Opcode starts with 0x1
+            0x1000 => self.opcode_0x1yyy(),
 
-            // Fx29 - LD F, Vx
-            // Set I = location of sprite for digit Vx.
-            // The value of I is set to the location for the hexadecimal sprite
-            // corresponding to the value of Vx.
-            0x29 => {
-                let (x, _) = self.get_regs_x_y();
-                // Each hex sprite takes up 5 bytes, and they start at address
-                // 0x0, so multiplying the value in Vx by 5 will get us the
-                // address of the sprite
-                self.ir = self.registers[x] as u16 * 5;
+            // Opcode starts with 0x2
+            0x2000 => self.opcode_0x2yyy(),
+
+            // 3xkk - SE Vx, byte
+            // Skip next instruction if Vx == kk
+            0x3000 => self.opcode_0x3yyy(),
+
+            // Opcodes that start with 0x4
+            0x4000 => self.opcode_0x4yyy(),
+
+            // Opcodes that start with 0x5
+            0x5000 => self.opcode_0x5yyy(),
+
+            // Opcodes that start with 0x6
+            0x6000 => self.opcode_0x6yyy(),
+
+            // Opcodes that start with 0x7
+            0x7000 => self.opcode_0x7yyy(),
+
+            // Opcodes that start with 0x8
+            0x8000 => self.opcode_0x8yyy(),
+
+            // Opcodes that start with 0x9
+            0x9000 => self.opcode_0x9yyy(),
+
+            // Opcodes that start with 0xA
+            0xA000 => self.opcode_0xayyy(),
+
+            // Opcodes that start with 0xB
+            0xB000 => self.opcode_0xbyyy(),
+
+            // Cxkk - RND, byte
+            // Set Vx = random byte AND kk
+            // Interpreter generates a random number between 0 and 255, which
+            // is then ANDed with kk and the result is stored in Vx.
+            0xC000 => self.opcode_0xcyyy(),
+
+            0xD000 => self.opcode_0xdyyy(),
+
+            0xE000 => self.opcode_0xeyyy(input),
+
+            0xF000 => self.opcode_0xfyyy(),
+
+            _ => self.unknown_opcode(),
+        }
+    }
+
+    /// Reports the instruction's memory accesses to `range` as [`Chip8Event::Watchpoint`]s in
+    /// the output, for the kinds of access `kind` covers. The accesses are the reads of `Dxyn`
+    /// and `Fx65`, and the writes of `Fx33` and `Fx55`; fetching opcodes isn't reported.
+    pub fn add_watchpoint(&mut self, range: Range<u16>, kind: WatchKind) {
+        self.watchpoints.push((range, kind));
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    /// Stops the program with [`Chip8Error::BreakpointHit`] when it's about to execute the
+    /// instruction at `addr` while `condition` holds. The instruction runs once the program is
+    /// resumed. Fails with [`Chip8Error::InvalidRegister`] if `condition` names a register that
+    /// doesn't exist.
+    pub fn add_conditional_breakpoint(
+        &mut self,
+        addr: u16,
+        condition: BreakCondition,
+    ) -> Result<(), Chip8Error> {
+        condition.validate()?;
+        self.conditional_breakpoints.push((addr, condition));
+        Ok(())
+    }
+
+    pub fn clear_conditional_breakpoints(&mut self) {
+        self.conditional_breakpoints.clear();
+    }
+
+    fn check_conditional_breakpoints(&mut self) -> Result<(), Chip8Error> {
+        if self.resumed_breakpoint.take() == Some(self.pc) {
+            return Ok(());
+        }
+        let hit = self.conditional_breakpoints.iter().any(|(addr, condition)| {
+            *addr == self.pc && condition.holds(&self.registers, self.ir, self.delay_timer)
+        });
+        if hit {
+            self.resumed_breakpoint = Some(self.pc);
+            return Err(Chip8Error::BreakpointHit(self.pc));
+        }
+        Ok(())
+    }
+
+    fn check_watchpoints(&mut self, addr: u16, value: u8, access: WatchKind) {
+        let hit = self
+            .watchpoints
+            .iter()
+            .any(|(range, kind)| range.contains(&addr) && kind.matches(access));
+        if hit {
+            self.outcome.push_event(Chip8Event::Watchpoint(WatchHit {
+                addr,
+                value,
+                access,
+                opcode: self.opcode,
+                pc: self.pc,
+            }));
+        }
+    }
+
+    /// Reads a byte of memory on behalf of the running program.
+    fn load(&mut self, addr: u16) -> Result<u8, Chip8Error> {
+        let value = self.fetch(addr)?;
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(self.wrap_address(addr), value, WatchKind::Read);
+        }
+        Ok(value)
+    }
+
+    /// Same as [`Chip8::load`], but not reported to watchpoints, to fetch opcodes.
+    fn fetch(&mut self, addr: u16) -> Result<u8, Chip8Error> {
+        let addr = self.checked_address(addr)?;
+        self.read_high_water = self.read_high_water.max(addr);
+        if self.dbg_options.track_memory_hotspots {
+            self.memory_read_counts[addr as usize] += 1;
+        }
+        Ok(self.memory[addr as usize])
+    }
+
+    /// Writes a byte of memory on behalf of the running program.
+    fn store(&mut self, addr: u16, value: u8) -> Result<(), Chip8Error> {
+        let addr = self.checked_address(addr)?;
+        self.write_high_water = self.write_high_water.max(addr);
+        if self.dbg_options.track_memory_hotspots {
+            self.memory_write_counts[addr as usize] += 1;
+        }
+        self.memory[addr as usize] = value;
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(addr, value, WatchKind::Write);
+        }
+
+        if self.quirks.vip_memory_layout && addr >= VIP_DISPLAY_ADDRESS {
+            let pixel = (addr - VIP_DISPLAY_ADDRESS) * SPRITE_WIDTH as u16;
+            let (x, y) = (pixel % SCREEN_WIDTH, pixel / SCREEN_WIDTH);
+            for dx in 0..SPRITE_WIDTH as u16 {
+                let on = value & (0x80 >> dx) != 0;
+                self.graphics.set_pixel(Coord::new(x + dx, y), on as u8);
+            }
+            self.outcome.drew();
+        }
+        Ok(())
+    }
+
+    /// Address of the stack entry at `sp` with [`Quirks::vip_memory_layout`].
+    fn vip_stack_entry(&self) -> u16 {
+        VIP_STACK_ADDRESS - self.sp as u16 * 2
+    }
+
+    /// Copies the screen to [`VIP_DISPLAY_ADDRESS`] with [`Quirks::vip_memory_layout`].
+    fn store_vip_display(&mut self) {
+        let display = &mut self.memory[VIP_DISPLAY_ADDRESS as usize..];
+        for (byte, pixels) in display
+            .iter_mut()
+            .zip(self.graphics.buffer().iter().flat_map(|row| row.chunks(8)))
+        {
+            *byte = pixels
+                .iter()
+                .fold(0, |byte, &pixel| byte << 1 | (pixel == graphics::PIXEL_ON) as u8);
+        }
+    }
+
+    /// Wraps `addr` around to the start of memory if `Quirks::wrap_memory` is set.
+    fn wrap_address(&self, addr: u16) -> u16 {
+        if self.quirks.wrap_memory {
+            addr % self.memory.len() as u16
+        } else {
+            addr
+        }
+    }
+
+    /// Same as [`Chip8::wrap_address`], but fails with [`Chip8Error::InvalidMemoryAccess`] if the
+    /// address is still past the end of memory.
+    fn checked_address(&self, addr: u16) -> Result<u16, Chip8Error> {
+        let addr = self.wrap_address(addr);
+        if addr as usize >= self.memory.len() {
+            return Err(Chip8Error::InvalidMemoryAccess(addr));
+        }
+        Ok(addr)
+    }
+
+    /// Reads the `num_rows` bytes of the sprite at I into `sprite`, and returns the rows that were
+    /// read. Without `Quirks::wrap_memory`, rows past the end of memory are left out, and a warning
+    /// is logged.
+    fn read_sprite<'s>(&mut self, num_rows: u8, sprite: &'s mut [u8; 15]) -> Result<&'s [u8], Chip8Error> {
+        let mut num_rows = num_rows as usize;
+        let available = self.memory.len().saturating_sub(self.ir as usize);
+        if !self.quirks.wrap_memory && num_rows > available {
+            log::warn!(
+                "{num_rows}-row sprite at {:#05x} reads past the end of memory, drawing {available} rows",
+                self.ir
+            );
+            num_rows = available;
+        }
+
+        for (row, byte) in sprite.iter_mut().take(num_rows).enumerate() {
+            *byte = self.load(self.ir.wrapping_add(row as u16))?;
+        }
+        Ok(&sprite[..num_rows])
+    }
+
+    // Utility function to return the number of registers x and y.
+    fn get_regs_x_y(&self) -> (usize, usize) {
+        (
+            ((self.opcode & 0x0F00) >> 8) as usize,
+            ((self.opcode & 0x00F0) >> 4) as usize,
+        )
+    }
+
+    fn unknown_opcode(&mut self) -> OpcodeResult {
+        if let Some(handler) = &mut self.unknown_opcode_handler {
+            let mut context = Chip8Context {
+                registers: &mut self.registers,
+                memory: &mut self.memory,
+                ir: &mut self.ir,
+                pc: self.pc,
+            };
+            match handler(self.opcode, &mut context) {
+                OpcodeOutcome::Next => return Ok(ProgramCounter::Next),
+                OpcodeOutcome::Skip => return Ok(ProgramCounter::Skip),
+                OpcodeOutcome::Set(addr) => return Ok(ProgramCounter::Set(addr)),
+                OpcodeOutcome::Unsupported => (),
+            }
+        }
+        println!("unknown opcode: {:X}", self.opcode);
+        Err(Chip8Error::UnsupportedOpcode(self.opcode))
+    }
+
+    /// Takes care of opcodes that start with 0x0.
+    fn opcode_0x0yyy(&mut self) -> OpcodeResult {
+        match self.opcode & 0x00FF {
+            // Clear the screen
+            0x00E0 => {
+                self.graphics.clear();
+                self.outcome.drew();
+                Ok(ProgramCounter::Next)
+            }
+            // Return from subroutine
+            0x00EE => {
+                // Restore program counter to previous location on stack
+                // before subroutine was called
+                if self.sp == 0 {
+                    return Err(Chip8Error::StackUnderflow(self.pc));
+                }
+                self.sp -= 1;
+                if self.dbg_options.track_call_graph && self.call_frames.len() > 1 {
+                    self.call_frames.pop();
+                }
+                if self.dbg_options.profile_calls {
+                    self.call_profile.ret(self.cycles());
+                }
+                if self.quirks.vip_memory_layout {
+                    // The program might have changed the return address
+                    return Ok(ProgramCounter::Set(self.opcode_at(self.vip_stack_entry())));
+                }
+                Ok(ProgramCounter::Set(self.stack[self.sp as usize]))
+            }
+            // Scroll the screen left/right by 1 pixel
+            0x00C0 if self.quirks.extended_scrolling => {
+                self.graphics.scroll_left(1);
+                self.outcome.drew();
+                Ok(ProgramCounter::Next)
+            }
+            0x00C1 if self.quirks.extended_scrolling => {
+                self.graphics.scroll_right(1);
+                self.outcome.drew();
                 Ok(ProgramCounter::Next)
             }
 
-            // Fx33 - LD B, Vx
-            // Store BCD representation of Vx in memory locations I, I+1, and I+2.
-            // The interpreter takes the decimal value of Vx, and places the hundreds digit
-            // in memory at location in I, the tens digit at location I+1, and the ones digit at location I+2.
-            0x33 => {
-                let (x, _) = self.get_regs_x_y();
-                let val = self.registers[x];
+            // No other opcodes start with 0x0
+            _ => self.legacy_opcode(),
+        }
+    }
 
-                let hundreds = val / 100;
-                let tens = (val / 10) % 10;
-                let ones = val % 10;
+    /// Executes the current opcode if it's one of the tolerated legacy opcodes, and fails
+    /// otherwise.
+    fn legacy_opcode(&mut self) -> OpcodeResult {
+        let legacy = self
+            .quirks
+            .legacy_opcodes
+            .iter()
+            .find(|(opcode, _)| *opcode == self.opcode)
+            .map(|(_, legacy)| *legacy);
+
+        match legacy {
+            Some(LegacyOpcode::Nop) => (),
+            Some(LegacyOpcode::ClearScreen) => {
+                self.graphics.clear();
+                self.outcome.drew();
+            }
+            Some(LegacyOpcode::DisplayOff) => {
+                self.display_enabled = false;
+                self.outcome.drew();
+            }
+            Some(LegacyOpcode::DisplayOn) => {
+                self.display_enabled = true;
+                self.outcome.drew();
+            }
+            None if self.quirks.handle_0x0nnn_syscalls => return self.machine_call(),
+            None => return self.unknown_opcode(),
+        }
+
+        Ok(ProgramCounter::Next)
+    }
+
+    /// Hands `0nnn` to the machine call handler, or skips it if there is none.
+    fn machine_call(&mut self) -> OpcodeResult {
+        let addr = self.opcode & 0x0FFF;
+        let Some(handler) = &self.machine_call_handler else {
+            log::warn!("no handler for machine code call to {addr:#05x}, skipping it");
+            return Ok(ProgramCounter::Next);
+        };
+
+        let mut state = self.state();
+        handler(addr, &mut state);
+        self.apply_state(state)?;
+
+        Ok(ProgramCounter::Next)
+    }
+
+    /// Writes `state` back into the emulator. The screen is left untouched. Fails with
+    /// [`Chip8Error::StackOverflow`], without changing anything, if the stack pointer is past the
+    /// end of the stack.
+    fn apply_state(&mut self, state: Chip8State) -> Result<(), Chip8Error> {
+        if state.sp as usize > STACK_SIZE {
+            return Err(Chip8Error::StackOverflow(self.pc));
+        }
+
+        let len = self.memory.len().min(state.memory.len());
+        self.memory[..len].copy_from_slice(&state.memory[..len]);
+        self.registers.copy_from_slice(&state.registers);
+        self.stack.copy_from_slice(&state.stack);
+        self.pc = state.pc;
+        self.ir = state.ir;
+        self.sp = state.sp;
+        self.delay_timer = state.delay_timer;
+        self.set_sound_timer(state.sound_timer);
+        Ok(())
+    }
+
+    /// Sets the sound timer, telling the sound callback if the buzzer starts or stops.
+    fn set_sound_timer(&mut self, value: u8) {
+        let was_on = self.sound_timer > 0;
+        self.sound_timer = value;
+
+        let Some(callback) = &self.sound_callback else {
+            return;
+        };
+        match (was_on, value > 0) {
+            (false, true) => callback(SoundEvent::On(value as f32 / self.timer_frequency as f32)),
+            (true, false) => callback(SoundEvent::Off),
+            _ => (),
+        }
+    }
+
+    /// Takes care of opcodes that start with 0x1.
+    fn opcode_0x1yyy(&mut self) -> OpcodeResult {
+        // Only 1 opcode that starts with 0x1: 0x1nnn
+        // 0x1nnn - Jump to location nnn
+        let addr = self.opcode & 0x0FFF;
+        Ok(ProgramCounter::Set(addr))
+    }
+
+    /// Takes care of opcodes that start with 0x2.
+    fn opcode_0x2yyy(&mut self) -> OpcodeResult {
+        // 0x2adr - Call subroutine at adr
+        // Put instruction after program counter on stack and then jump to subroutine
+        // location. This prevents the VM from entering into an endless loop.
+        if self.sp as usize == STACK_SIZE {
+            return Err(Chip8Error::StackOverflow(self.pc));
+        }
+        self.stack[self.sp as usize] = self.pc + OPCODE_SIZE;
+        if self.quirks.vip_memory_layout {
+            let entry = self.vip_stack_entry() as usize;
+            self.memory[entry..entry + 2].copy_from_slice(&(self.pc + OPCODE_SIZE).to_be_bytes());
+        }
+        self.sp += 1;
+        self.max_sp = self.max_sp.max(self.sp);
+        self.statistics.total_subroutine_calls += 1;
+        self.statistics.max_stack_depth = self.statistics.max_stack_depth.max(self.sp);
+        let addr = self.opcode & 0x0FFF;
+        if self.dbg_options.track_call_graph {
+            let caller = self.call_frames[self.call_frames.len() - 1];
+            self.call_graph.entry(caller).or_default().insert(addr);
+            self.call_frames.push(addr);
+        }
+        if self.dbg_options.profile_calls {
+            self.call_profile.call(addr, self.sp as usize - 1, self.cycles());
+        }
+        Ok(ProgramCounter::Set(addr))
+    }
+
+    /// Takes care of opcodes that start with 0x3.
+    fn opcode_0x3yyy(&mut self) -> OpcodeResult {
+        // 3xkk - SE Vx, byte
+        // Skip next instruction if Vx == kk
+
+        // Get register value and constant
+        let (x, _) = self.get_regs_x_y();
+        let register_val = self.registers[x];
+        let comp_val = (self.opcode & 0x00FF) as u8;
+
+        // If equal, skip next instruction (increment program
+        // counter by 2)
+        if register_val == comp_val {
+            return Ok(ProgramCounter::Skip);
+        }
+
+        Ok(ProgramCounter::Next)
+    }
+
+    /// Takes care of opcodes that start with 0x4.
+    fn opcode_0x4yyy(&mut self) -> OpcodeResult {
+        // 4xkk - SNE Vx, byte
+        // Skip next instruction if Vx != kk
+
+        // Get register value and constant
+        let (x, _) = self.get_regs_x_y();
+        let register_val = self.registers[x];
+        let comp_val = (self.opcode & 0x00FF) as u8;
+
+        // If not equal, skip next instruction (increment program
+        // counter by 2)
+        if register_val != comp_val {
+            return Ok(ProgramCounter::Skip);
+        }
+
+        Ok(ProgramCounter::Next)
+    }
+
+    /// Takes care of opcodes that start with 0x5.
+    fn opcode_0x5yyy(&mut self) -> OpcodeResult {
+        // 5xy0 - SE Vx, Vy
+        // Skip next instruction if Vx == Vy
+        let (x, y) = self.get_regs_x_y();
+        let vx_val = self.registers[x];
+        let vy_val = self.registers[y];
+
+        // If values are equal, skip next instruction (increment
+        // program counter by 2)
+        if vx_val == vy_val {
+            return Ok(ProgramCounter::Skip);
+        }
+
+        Ok(ProgramCounter::Next)
+    }
+
+    /// Takes care of opcodes that start with 0x6.
+    fn opcode_0x6yyy(&mut self) -> OpcodeResult {
+        // 6xkk - LD Vx, byte
+        // Set Vx = kk
+        let val = (self.opcode & 0x00FF) as u8;
+        let (x, _) = self.get_regs_x_y();
+
+        // Set register to value
+        self.registers[x] = val;
+        Ok(ProgramCounter::Next)
+    }
+
+    /// Takes care of opcodes that start with 0x7.
+    fn opcode_0x7yyy(&mut self) -> OpcodeResult {
+        // 7xkk - ADD Vx, byte
+        // Set Vx = Vx + kk
+        // Get value and register
+        let val = (self.opcode & 0x00FF) as u8;
+        let x = ((self.opcode & 0x0F00) >> 8) as usize;
+
+        self.registers[x] = self.registers[x].wrapping_add(val);
+        Ok(ProgramCounter::Next)
+    }
+
+    /// Takes care of opcodes that start with 0x8.
+    fn opcode_0x8yyy(&mut self) -> OpcodeResult {
+        macro_rules! reset_vf {
+            () => {
+                if self.quirks.reset_vf {
+                    self.registers[FLAG_REGISTER] = 0;
+                }
+            };
+        }
+
+        macro_rules! set_vx_to_vy_for_shift {
+            ($x: ident, $y: ident) => {
+                if self.quirks.use_vy_in_shift {
+                    self.registers[$x] = self.registers[$y];
+                }
+            };
+        }
+
+        // Last nibble identifies what the opcode does
+        match self.opcode & 0x000F {
+            // 8xy0 - LD Vx, Vy
+            // Set Vx = Vy
+            0x0000 => {
+                let (x, y) = self.get_regs_x_y();
+
+                self.registers[x] = self.registers[y];
+                Ok(ProgramCounter::Next)
+            }
+
+            // 8xy1 - OR Vx, Vy
+            // Perform bitwise OR on Vx and Vy and store result in Vx.
+            0x0001 => {
+                let (x, y) = self.get_regs_x_y();
+
+                self.registers[x] |= self.registers[y];
+                reset_vf!();
+
+                Ok(ProgramCounter::Next)
+            }
+
+            // 8xy2 - AND Vx, Vy
+            // Perform bitwise AND on Vx and Vy and store result in Vx.
+            0x0002 => {
+                let (x, y) = self.get_regs_x_y();
+
+                self.registers[x] &= self.registers[y];
+                reset_vf!();
+
+                Ok(ProgramCounter::Next)
+            }
+
+            // 8xy3 - XOR Vx, Vy
+            // Performs bitwise XOR on Vx and Vy and stores result in Vx.
+            0x0003 => {
+                let (x, y) = self.get_regs_x_y();
+
+                self.registers[x] ^= self.registers[y];
+                reset_vf!();
+
+                Ok(ProgramCounter::Next)
+            }
+
+            // 8xy4 - ADD Vx, Vy
+            // Vx = Vx + Vy, set VF = carry
+            // If the result of Vx and Vy is greater than 8 bits (255)
+            // VF is set to 1, otherwise it's set to 0
+            0x0004 => {
+                let (x, y) = self.get_regs_x_y();
+                let (val, overflow) = self.registers[x].overflowing_add(self.registers[y]);
+
+                let flag = if overflow { 1 } else { 0 };
+
+                self.registers[x] = val;
+                self.registers[FLAG_REGISTER] = flag;
+
+                Ok(ProgramCounter::Next)
+            }
+
+            // 8xy5 - SUB Vx, Vy
+            // Vx= Vx - Vy, set VF = NOT borrow
+            // If Vx >= Vy, then VF is set to 1, otherwise 0
+            0x0005 => {
+                let (x, y) = self.get_regs_x_y();
+
+                let flag = if self.registers[x] >= self.registers[y] {
+                    1
+                } else {
+                    0
+                };
+
+                let (val, _) = self.registers[x].overflowing_sub(self.registers[y]);
+
+                self.registers[x] = val;
+                self.registers[FLAG_REGISTER] = flag;
+
+                Ok(ProgramCounter::Next)
+            }
+
+            // 8xy6 - SHR Vx {, Vy}
+            // Set Vx = Vx SHR 1
+            // If least significant bit of Vx is 1, then VF is set to 1,
+            // otherwise 0. Then Vx is divided by 2
+            0x0006 => {
+                let (x, y) = self.get_regs_x_y();
+
+                set_vx_to_vy_for_shift!(x, y);
+
+                let flag = self.registers[x] & 0x1;
+                self.registers[x] >>= 1;
+
+                self.registers[FLAG_REGISTER] = flag;
+                Ok(ProgramCounter::Next)
+            }
+
+            // 8xy7 - SUBN Vx, Vy
+            // Set Vx = Vy - Vx, set VF = NOT borrow
+            // If Vy >= Vx, then VF = 1, otherwise VF = 0.
+            0x0007 => {
+                let (x, y) = self.get_regs_x_y();
+
+                let flag = if self.registers[y] >= self.registers[x] {
+                    1
+                } else {
+                    0
+                };
+
+                let (val, _) = self.registers[y].overflowing_sub(self.registers[x]);
+
+                self.registers[x] = val;
+                self.registers[FLAG_REGISTER] = flag;
+
+                Ok(ProgramCounter::Next)
+            }
+
+            // 8xyE - SHL Vx {, Vy}
+            // Set Vx = Vx SHL 1
+            // If most significant bit of Vx is 1, set VF to 1, otherwise 0.
+            0x000E => {
+                let (x, y) = self.get_regs_x_y();
+                set_vx_to_vy_for_shift!(x, y);
+
+                let flag = (self.registers[x] >> 7) & 0x1;
+
+                self.registers[x] <<= 1;
+                self.registers[FLAG_REGISTER] = flag;
+
+                Ok(ProgramCounter::Next)
+            }
+
+            // No other opcodes start with 0x8
+            _ => self.unknown_opcode(),
+        }
+    }
+
+    /// Takes care of opcodes that start with 0x9
+    fn opcode_0x9yyy(&mut self) -> OpcodeResult {
+        // 9xy0 - SNE Vx, Vy
+        // Skip next instruction if Vx != Vy
+        let (x, y) = self.get_regs_x_y();
+
+        if self.registers[x] != self.registers[y] {
+            return Ok(ProgramCounter::Skip);
+        }
+
+        Ok(ProgramCounter::Next)
+    }
+
+    /// Takes care of opcodes that start with 0xA
+    fn opcode_0xayyy(&mut self) -> OpcodeResult {
+        // Annn - LD I, addr
+        // Set I = nnn
+        // Get address and set index register
+        let val = self.opcode & 0x0FFF;
+        self.ir = val;
+        Ok(ProgramCounter::Next)
+    }
+
+    /// Takes care of opcodes that start with 0xB
+    fn opcode_0xbyyy(&mut self) -> OpcodeResult {
+        // Bnnn - JP V0, nnn
+        // Jump to location nnn + V0 (set pc = nnn + V0)
+        // With quirk `use_vx_in_jump`, it is:
+        // Bxnn - JP Vx, nn (set pc = Vx + nn)
+        if !self.quirks.use_vx_in_jump {
+            let val = self.opcode & 0x0FFF;
+            Ok(ProgramCounter::Set(val + self.registers[0x0] as u16))
+        } else {
+            let (x, _) = self.get_regs_x_y();
+            let val = self.opcode & 0x00FF;
+            Ok(ProgramCounter::Set(val + self.registers[x] as u16))
+        }
+    }
+
+    /// Takes care of opcodes that start with 0xC
+    fn opcode_0xcyyy(&mut self) -> OpcodeResult {
+        // Cxkk - RND, byte
+        // Set Vx = random byte AND kk
+        // Interpreter generates a random number between 0 and 255, which
+        // is then ANDed with kk and the result is stored in Vx.
+        let kk: u8 = (self.opcode & 0x00FF) as u8;
+        let (x, _) = self.get_regs_x_y();
+
+        let rand_val = rand::thread_rng().gen_range(0..256) as u8;
+
+        self.registers[x] = rand_val & kk;
+        Ok(ProgramCounter::Next)
+    }
+
+    /// Takes care of opcodes that start with 0xD
+    fn opcode_0xdyyy(&mut self) -> OpcodeResult {
+        // Dxyn - DRW Vx, Vy, nibble
+        // Display n-byte sprite starting at memory location I at (Vx, Vy),
+        // set VF = collision
+        let (x_reg, y_reg) = self.get_regs_x_y();
+        let num_rows = (self.opcode & 0x000F) as u8;
+
+        let (x, y) = (self.registers[x_reg], self.registers[y_reg]);
+        let pos = Coord::new(x.into(), y.into());
+
+        let mut sprite = [0; 15];
+        let rows = self.read_sprite(num_rows, &mut sprite)?;
+
+        let flipped = self.graphics.draw_sprite(pos, rows, self.quirks.clipping);
+        self.outcome.drew();
+        self.statistics.total_draw_calls += 1;
+        if flipped {
+            self.statistics.total_collisions += 1;
+        }
+
+        if self.dbg_options.log_draw_calls {
+            self.draw_call_log.push(DrawCall {
+                cycle: self.cycles(),
+                x,
+                y,
+                num_rows,
+                ir: self.ir,
+                flipped,
+            });
+        }
+
+        if flipped {
+            self.registers[FLAG_REGISTER] = 1;
+        } else {
+            self.registers[FLAG_REGISTER] = 0;
+        }
+
+        if self.dbg_options.dump_graphics {
+            self.dump_graphics();
+        }
+
+        Ok(ProgramCounter::Next)
+    }
+
+    /// Takes care of opcodes that are related to input such as checking whether
+    /// a key is pressed or not pressed, and waiting until a key is pressed.
+    fn opcode_0xeyyy(&mut self, input: &impl Input) -> OpcodeResult {
+        match self.opcode & 0xFF {
+            // Ex9E - SKP Vx
+            // Skips the next instruction if the key with the value of Vx is
+            // pressed. If the key corresponding to the value of Vx is currently
+            // in the down position, PC is increased by 2.
+            0x9E => {
+                let (x, _) = self.get_regs_x_y();
+
+                if input.is_pressed((self.registers[x]).try_into()?) {
+                    return Ok(ProgramCounter::Skip);
+                }
+
+                Ok(ProgramCounter::Next)
+            }
+
+            // ExA1 - SKNP Vx
+            // Skip next instruction if key with value Vx is not pressed. If the
+            // key with value Vx is not pressed, the program counter is incremented
+            // by 2.
+            0xA1 => {
+                let (x, _) = self.get_regs_x_y();
+
+                if !input.is_pressed((self.registers[x]).try_into()?) {
+                    return Ok(ProgramCounter::Skip);
+                }
+
+                Ok(ProgramCounter::Next)
+            }
+
+            _ => self.unknown_opcode(),
+        }
+    }
+
+    /// Moves the index register past `V0` through `Vx` after `Fx55`/`Fx65`, as far as the
+    /// `increment_ir` quirk says.
+    fn increment_ir_after_load_store(&mut self, x: usize) {
+        self.ir += match self.quirks.increment_ir {
+            LoadStoreBehavior::IncrementByXPlusOne => x as u16 + 1,
+            LoadStoreBehavior::IncrementByX => x as u16,
+            LoadStoreBehavior::Unchanged => 0,
+        };
+    }
+
+    fn opcode_0xfyyy(&mut self) -> OpcodeResult {
+        match self.opcode & 0xFF {
+            // Fx07 - LD Vx, DT
+            // Set Vx = delay timer value.
+            // The value of DT is placed into Vx.
+            0x07 => {
+                let (x, _) = self.get_regs_x_y();
+                self.registers[x] = self.delay_timer;
+                Ok(ProgramCounter::Next)
+            }
+
+            // Fx0A - LD Vx, K
+            // Wait for a key press, store the value of the key in Vx.
+            // All execution stops until a key is pressed, then the value
+            // of that key is stored in Vx.
+            0x0A => {
+                let (x, _) = self.get_regs_x_y();
+
+                if self.wait_for_key_state == WaitForKeyState::None {
+                    self.wait_for_keypress_register = x as u8;
+                    self.wait_for_key_state = WaitForKeyState::WaitForNoKeyPressed;
+                }
+
+                Ok(ProgramCounter::Pause)
+            }
+
+            // Fx15 - LD DT, Vx
+            // Set delay timer = Vx
+            // DT is set equal to the value of Vx.
+            0x15 => {
+                let (x, _) = self.get_regs_x_y();
+                self.delay_timer = self.registers[x];
+                self.outcome.push_event(Chip8Event::DelayTimerSet {
+                    value: self.delay_timer,
+                    cycle: self.cycles(),
+                });
+                Ok(ProgramCounter::Next)
+            }
+
+            // Fx18 - LD ST, Vx
+            // Set sound timer = Vx
+            // ST is set equal to the value of Vx.
+            0x18 => {
+                let (x, _) = self.get_regs_x_y();
+                self.set_sound_timer(self.registers[x]);
+                self.outcome.push_event(Chip8Event::SoundTimerSet {
+                    value: self.sound_timer,
+                    cycle: self.cycles(),
+                });
+                Ok(ProgramCounter::Next)
+            }
+
+            // Fx1E - ADD I, Vx
+            // Set I = I + Vx
+            // The values of I and Vx are added, and the results are stored in I.
+            0x1E => {
+                let (x, _) = self.get_regs_x_y();
+                self.ir += self.registers[x] as u16;
+                Ok(ProgramCounter::Next)
+            }
+
+            // Fx29 - LD F, Vx
+            // Set I = location of sprite for digit Vx.
+            // The value of I is set to the location for the hexadecimal sprite
+            // corresponding to the value of Vx.
+            0x29 => {
+                let (x, _) = self.get_regs_x_y();
+                // Each hex sprite takes up 5 bytes, and they start at the font
+                // base, so multiplying the value in Vx by 5 will get us the
+                // offset of the sprite
+                self.ir = self.font_base + self.registers[x] as u16 * FONT_GLYPH_SIZE;
+                Ok(ProgramCounter::Next)
+            }
+
+            // Fx33 - LD B, Vx
+            // Store BCD representation of Vx in memory locations I, I+1, and I+2.
+            // The interpreter takes the decimal value of Vx, and places the hundreds digit
+            // in memory at location in I, the tens digit at location I+1, and the ones digit at location I+2.
+            0x33 => {
+                let (x, _) = self.get_regs_x_y();
+                let val = self.registers[x];
+
+                let hundreds = val / 100;
+                let tens = (val / 10) % 10;
+                let ones = val % 10;
+
+                self.store(self.ir, hundreds)?;
+                self.store(self.ir.wrapping_add(1), tens)?;
+                self.store(self.ir.wrapping_add(2), ones)?;
+
+                Ok(ProgramCounter::Next)
+            }
+
+            // Fx55 - LD [I], Vx
+            // Store registers V0 through Vx in memory starting at location I.
+            // The interpreter copies the values of registers V0 through Vx into memory, starting at the address in I.
+            0x55 => {
+                let (x, _) = self.get_regs_x_y();
+                let start = self.ir;
+
+                for i in 0..=x {
+                    let addr = start.wrapping_add(i as u16 * REG_SIZE);
+                    self.store(addr, self.registers[i])?;
+                }
+                self.increment_ir_after_load_store(x);
+
+                Ok(ProgramCounter::Next)
+            }
+
+            // Fx65 - LD Vx, [I]
+            // Read registers V0 through Vx from memory starting at location I.
+            // The interpreter reads values from memory starting at location I into registers V0 through Vx.
+            0x65 => {
+                let (x, _) = self.get_regs_x_y();
+                let start = self.ir;
+
+                for i in 0..=x {
+                    let addr = start.wrapping_add(i as u16 * REG_SIZE);
+                    self.registers[i] = self.load(addr)?;
+                }
+                self.increment_ir_after_load_store(x);
+
+                Ok(ProgramCounter::Next)
+            }
+
+            _ => self.unknown_opcode(),
+        }
+    }
+
+    // 0xFX0A requires special handling. It has to wait for the key
+    // to be released before registering the key pressed. It also
+    // needs to halt the whole emulator, except for timers.
+    // Timers need to continue to decrement.
+    fn check_and_process_0xfx0a(&mut self, input: &impl Input) -> OpcodeResult {
+        if self.wait_for_key_state != WaitForKeyState::None {
+            match self.wait_for_key_state {
+                WaitForKeyState::WaitForNoKeyPressed => {
+                    if input.pressed_mask() == 0 {
+                        self.wait_for_key_state = WaitForKeyState::CheckForKeyPressed;
+                    }
+                    Ok(ProgramCounter::Pause)
+                }
+                WaitForKeyState::CheckForKeyPressed => {
+                    let pressed = input.pressed_mask();
+                    if pressed != 0 {
+                        // The lowest key wins if several are pressed
+                        self.registers[self.wait_for_keypress_register as usize] =
+                            pressed.trailing_zeros() as u8;
+                        self.wait_for_key_state = WaitForKeyState::WaitForKeyRelease;
+                    }
+                    Ok(ProgramCounter::Pause)
+                }
+                WaitForKeyState::WaitForKeyRelease => {
+                    if input.pressed_mask() == 0 {
+                        self.wait_for_key_state = WaitForKeyState::None;
+                        Ok(ProgramCounter::Next)
+                    } else {
+                        Ok(ProgramCounter::Pause)
+                    }
+                }
+                WaitForKeyState::None => Ok(ProgramCounter::Next),
+            }
+        } else {
+            Ok(ProgramCounter::None)
+        }
+    }
+
+    fn dump_graphics(&self) {
+        let screen = self.graphics.buffer();
+
+        for row in screen {
+            for pixel in row {
+                print!("{} ", pixel);
+            }
+
+            println!();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::io::Cursor;
+    use std::rc::Rc;
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
+
+    use crate::breakpoint::BreakCondition;
+    use crate::graphics::{Coord, Graphics, MirroredGraphics};
+    use crate::opcode_overrides::{OpcodeOverrides, OverrideError};
+    use crate::state::{Chip8State, StateMask};
+    use crate::test_helpers::chip8_with_program;
+    use crate::timer::TimerOperation;
+    use crate::traits::{Display, GraphicsBuffer};
+    use crate::{
+        Chip8Error, DebugOptions, InputSampling, LoadStoreBehavior, Quirks, QuirksBuilder, SCREEN_HEIGHT,
+        SCREEN_WIDTH,
+    };
+
+    use super::{
+        compare_graphics_snapshot, get_opcode_category, get_program_entry_points, wait_for_vsync, BoxedChip8,
+        Chip8, Chip8Builder, Chip8Event, DefaultChip8, HookContext, OpcodeCategory, OpcodeOutcome,
+        ProgramCounter, SoundEvent, StepResult, TestResult, WatchKind,
+    };
+    use super::{
+        LegacyOpcode, APP_LOCATION, FLAG_REGISTER, FONT_BASE, HEX_DIGITS, MEMORY_SIZE, VIP_LEGACY_OPCODES,
+    };
+    use super::{MAX_PENDING_EVENTS, STACK_SIZE, VIP_DISPLAY_ADDRESS, VIP_STACK_ADDRESS};
+    use crate::traits::Input;
+    use crate::Key;
+
+    fn create_chip8(opcode: u16) -> Chip8<Graphics> {
+        let graphics = Graphics::new();
+        let (_, timer_rx) = mpsc::channel();
+        let mut chip8 = Chip8::new(graphics, timer_rx, Quirks::default(), DebugOptions::default());
+        chip8.opcode = opcode;
+        chip8
+    }
+
+    /// Input whose key states are set directly by the test.
+    #[derive(Default)]
+    struct MockInput {
+        keys: [bool; 16],
+    }
+
+    impl Input for MockInput {
+        fn is_pressed(&self, key: Key) -> bool {
+            self.keys[key as usize]
+        }
+    }
+
+    /// Creates a Chip8 with `program` loaded at the start of the application area.
+    fn create_chip8_with_program(program: &[u16]) -> Chip8<Graphics> {
+        chip8_with_program(program, DebugOptions::default())
+    }
+
+    /// Counts the frames drawn on it. Never waits for vsync unless `vsync_ready` is false.
+    struct NullDisplay {
+        draws: usize,
+        vsync_ready: bool,
+    }
+
+    impl NullDisplay {
+        fn new() -> Self {
+            NullDisplay {
+                draws: 0,
+                vsync_ready: true,
+            }
+        }
+    }
+
+    impl Display for NullDisplay {
+        fn draw(&mut self, _buffer: &[Vec<u8>]) {
+            self.draws += 1;
+        }
+
+        fn is_vsync_ready(&mut self) -> bool {
+            self.vsync_ready
+        }
+    }
+
+    /// Runs a cycle and returns whether it reported a draw.
+    fn cycle_draws(chip8: &mut Chip8<Graphics>, input: &MockInput) -> bool {
+        chip8.emulate_cycle(input).unwrap().draw_on_screen
+    }
+
+    fn create_chip8_with_quirks(opcode: u16, quirks: Quirks) -> Chip8<Graphics> {
+        let graphics = Graphics::new();
+
+        let (_, timer_rx) = mpsc::channel();
+        let mut chip8 = Chip8::new(graphics, timer_rx, quirks, DebugOptions::default());
+        chip8.opcode = opcode;
+        chip8
+    }
+
+    #[test]
+    fn test_0x00e0() {
+        let mut chip8 = create_chip8(0x00e0);
+        // Draw the first sprite digit - digits are all 5 bytes tall
+        chip8
+            .graphics
+            .draw_sprite(Coord::new(0, 0), &HEX_DIGITS[..5], chip8.quirks.clipping);
+
+        assert!(chip8.assert_display_clear().is_err());
+
+        let pc_op = chip8.opcode_0x0yyy();
+
+        assert_eq!(pc_op, Ok(ProgramCounter::Next));
+        assert_eq!(chip8.assert_display_clear(), Ok(()));
+    }
+
+    #[test]
+    fn test_assert_display_equals() {
+        let mut chip8 = create_chip8(0x0000);
+        chip8
+            .graphics
+            .draw_sprite(Coord::new(0, 0), &HEX_DIGITS[..1], chip8.quirks.clipping);
+
+        let mut expected = vec![vec![0; SCREEN_WIDTH as usize]; SCREEN_HEIGHT as usize];
+        expected[0][..4].copy_from_slice(&[1, 1, 1, 1]);
+        assert_eq!(chip8.assert_display_equals(&expected), Ok(()));
+
+        expected[0][3] = 0;
+        expected[1][0] = 1;
+        let mismatch = chip8.assert_display_equals(&expected).unwrap_err();
+        assert_eq!(mismatch.diff, vec![(3, 0, 0, 1), (0, 1, 1, 0)]);
+    }
+
+    #[test]
+    fn test_screen_to_ascii() {
+        let mut chip8 = create_chip8(0x0000);
+        // The `0` digit sprite lives at 0x0: rows 0xF0, 0x90, 0x90, 0x90, 0xF0
+        chip8
+            .graphics
+            .draw_sprite(Coord::new(0, 0), &HEX_DIGITS[..5], chip8.quirks.clipping);
+
+        let ascii = chip8.screen_to_ascii();
+        let lines: Vec<_> = ascii.lines().collect();
+
+        assert_eq!(lines.len(), SCREEN_HEIGHT as usize / 2);
+        assert!(lines[0].starts_with("█▀▀█ "));
+        assert!(lines[1].starts_with("█  █ "));
+        assert!(lines[2].starts_with("▀▀▀▀ "));
+        assert!(lines[3].trim().is_empty());
+    }
+
+    #[test]
+    fn test_screen_to_ascii_1bpp() {
+        let mut chip8 = create_chip8(0x0000);
+        chip8
+            .graphics
+            .draw_sprite(Coord::new(0, 0), &HEX_DIGITS[..5], chip8.quirks.clipping);
+
+        let ascii = chip8.screen_to_ascii_1bpp();
+        let lines: Vec<_> = ascii.lines().collect();
+
+        assert_eq!(lines.len(), SCREEN_HEIGHT as usize);
+        assert_eq!(lines[0].len(), SCREEN_WIDTH as usize);
+        assert!(lines[0].starts_with("####."));
+        assert!(lines[1].starts_with("#..#."));
+        assert!(lines[4].starts_with("####."));
+        assert!(lines[5].chars().all(|c| c == '.'));
+    }
+
+    #[test]
+    fn test_disassemble_current() {
+        let mut chip8 = create_chip8(0x0000);
+        chip8.memory[APP_LOCATION as usize] = 0x61;
+        chip8.memory[APP_LOCATION as usize + 1] = 0x20;
+
+        let mnemonic = chip8.disassemble_current();
+        assert!(mnemonic.contains("LD"));
+        assert!(mnemonic.contains("V1"));
+        assert_eq!(
+            chip8.disassemble_with_bytes(APP_LOCATION),
+            "0200: 6120   LD V1, 0x20"
+        );
+        // Past the end of memory, up to the last address
+        assert_eq!(chip8.disassemble_with_bytes(0xFFFF), "FFFF: 0000   DB 0x0000");
+    }
+
+    #[test]
+    fn test_disassemble_current_data() {
+        let mut chip8 = create_chip8(0x0000);
+        chip8.memory[APP_LOCATION as usize] = 0xFF;
+        chip8.memory[APP_LOCATION as usize + 1] = 0xFF;
+
+        assert_eq!(chip8.disassemble_current(), "DB 0xffff");
+        assert_eq!(
+            chip8.disassemble_at(0xFFF),
+            Err(Chip8Error::InvalidMemoryAccess(0xFFF))
+        );
+    }
+
+    #[test]
+    fn test_draw_reported_once() {
+        // DRW V0, V0, 5; CLS; JP 0x204
+        let mut chip8 = create_chip8_with_program(&[0xD005, 0x00E0, 0x1204]);
+        let input = MockInput::default();
+
+        assert!(cycle_draws(&mut chip8, &input));
+        assert!(cycle_draws(&mut chip8, &input));
+        assert!(!cycle_draws(&mut chip8, &input));
+        assert!(!cycle_draws(&mut chip8, &input));
+    }
+
+    #[test]
+    fn test_draw_then_key_wait() {
+        // DRW V0, V0, 5; LD V1, K; DRW V0, V0, 5; JP 0x206
+        let mut chip8 = create_chip8_with_program(&[0xD005, 0xF10A, 0xD005, 0x1206]);
+        let mut input = MockInput::default();
+
+        assert!(cycle_draws(&mut chip8, &input));
+
+        // Fx0A starts waiting; no draws are reported while paused
+        for _ in 0..3 {
+            assert!(!cycle_draws(&mut chip8, &input));
+        }
+
+        // Press and release a key to finish the wait
+        input.keys[Key::A as usize] = true;
+        assert!(!cycle_draws(&mut chip8, &input));
+        input.keys[Key::A as usize] = false;
+        assert!(!cycle_draws(&mut chip8, &input));
+        assert_eq!(chip8.registers[1], u8::from(Key::A));
+
+        // The draw after the wait is reported exactly once
+        assert!(cycle_draws(&mut chip8, &input));
+        assert!(!cycle_draws(&mut chip8, &input));
+    }
+
+    #[test]
+    fn test_pending_draw_survives_failed_cycle() {
+        // DRW V0, V0, 5; JP 0x202; SKP V2 (with an invalid key in V2)
+        let mut chip8 = create_chip8_with_program(&[0xD005, 0x1202, 0xE29E]);
+        chip8.registers[2] = 0x10;
+        let input = MockInput::default();
+
+        // A draw that wasn't reported yet is still delivered after a failed cycle
+        chip8.emulate_instruction(&input).unwrap();
+        chip8.pc = APP_LOCATION + 4;
+        assert!(chip8.emulate_cycle(&input).is_err());
+
+        chip8.pc = APP_LOCATION + 2;
+        assert!(cycle_draws(&mut chip8, &input));
+        assert!(!cycle_draws(&mut chip8, &input));
+    }
+
+    #[test]
+    fn test_legacy_opcodes_rejected_by_default() {
+        for opcode in [0x00E1, 0x00E2, 0x00E3] {
+            let mut chip8 = create_chip8(opcode);
+            assert_eq!(chip8.opcode_0x0yyy(), Err(Chip8Error::UnsupportedOpcode(opcode)));
+        }
+    }
+
+    #[test]
+    fn test_legacy_display_off_on() {
+        let quirks = QuirksBuilder::default()
+            .legacy_opcodes(VIP_LEGACY_OPCODES.to_vec())
+            .build()
+            .unwrap();
+        // DISPLAY OFF; NOP; DISPLAY ON; 0x00E4 (not in the table)
+        let mut chip8 = create_chip8_with_program(&[0x00E1, 0x00E3, 0x00E2, 0x00E4]);
+        chip8.quirks = quirks;
+        let input = MockInput::default();
+
+        let output = chip8.emulate_cycle(&input).unwrap();
+        assert!(!output.display_enabled);
+        assert!(output.draw_on_screen);
+
+        let output = chip8.emulate_cycle(&input).unwrap();
+        assert!(!output.display_enabled);
+        assert!(!output.draw_on_screen);
+
+        let output = chip8.emulate_cycle(&input).unwrap();
+        assert!(output.display_enabled);
+        assert!(output.draw_on_screen);
+
+        assert!(matches!(
+            chip8.emulate_cycle(&input),
+            Err(Chip8Error::UnsupportedOpcode(0x00E4))
+        ));
+    }
+
+    #[test]
+    fn test_legacy_clear_screen() {
+        let quirks = QuirksBuilder::default()
+            .legacy_opcodes(vec![(0x00E5, LegacyOpcode::ClearScreen)])
+            .build()
+            .unwrap();
+        let mut chip8 = create_chip8_with_quirks(0x00E5, quirks);
+        chip8
+            .graphics
+            .draw_sprite(Coord::new(0, 0), &HEX_DIGITS[..5], chip8.quirks.clipping);
+
+        assert_eq!(chip8.opcode_0x0yyy(), Ok(ProgramCounter::Next));
+        assert_eq!(chip8.assert_display_clear(), Ok(()));
+    }
+
+    #[test]
+    fn test_extended_scrolling() {
+        let quirks = QuirksBuilder::default().extended_scrolling(true).build().unwrap();
+        let mut chip8 = create_chip8_with_quirks(0x00C0, quirks);
+        chip8.graphics.set_pixel(Coord::new(10, 3), 1);
+
+        assert_eq!(chip8.opcode_0x0yyy(), Ok(ProgramCounter::Next));
+        assert_eq!(chip8.graphics.buffer()[3][9], 1);
+        assert_eq!(chip8.graphics.buffer()[3][10], 0);
+
+        chip8.opcode = 0x00C1;
+        chip8.opcode_0x0yyy().unwrap();
+        chip8.opcode_0x0yyy().unwrap();
+        assert_eq!(chip8.graphics.buffer()[3][11], 1);
+        assert_eq!(chip8.graphics.buffer()[3][9], 0);
+    }
+
+    #[test]
+    fn test_extended_scrolling_draws_sprite_shifted() {
+        // LD I, 0x050; DRW V0, V0, 5; 00C0
+        let mut chip8 = create_chip8_with_program(&[0xA050, 0xD005, 0x00C0]);
+        chip8.quirks.extended_scrolling = true;
+        chip8.registers[0] = 8;
+        let input = MockInput::default();
+
+        chip8.emulate_cycle(&input).unwrap();
+        chip8.emulate_cycle(&input).unwrap();
+        let before = chip8.snapshot_graphics();
+        assert!(chip8.emulate_cycle(&input).unwrap().draw_on_screen);
+
+        for (row, before) in chip8.graphics.buffer().iter().zip(&before) {
+            assert_eq!(row[..63], before[1..]);
+        }
+    }
+
+    #[test]
+    fn test_extended_scrolling_disabled_by_default() {
+        for opcode in [0x00C0, 0x00C1] {
+            let mut chip8 = create_chip8(opcode);
+            assert_eq!(chip8.opcode_0x0yyy(), Err(Chip8Error::UnsupportedOpcode(opcode)));
+        }
+    }
+
+    #[test]
+    fn test_machine_call() {
+        let calls = Rc::new(Cell::new(None));
+        let mut chip8 = create_chip8_with_program(&[0x0123]);
+        chip8.enable_legacy_0x0nnn_machine_calls(true);
+        chip8.set_machine_call_handler({
+            let calls = Rc::clone(&calls);
+            move |addr, state| {
+                calls.set(Some(addr));
+                state.registers[0] = 0x42;
+            }
+        });
+
+        chip8.emulate_cycle(&MockInput::default()).unwrap();
+
+        assert_eq!(calls.get(), Some(0x123));
+        assert_eq!(chip8.registers[0], 0x42);
+        assert_eq!(chip8.pc, APP_LOCATION + 2);
+    }
+
+    #[test]
+    fn test_machine_call_with_a_corrupt_stack_pointer() {
+        let mut chip8 = create_chip8_with_program(&[0x0123]);
+        chip8.enable_legacy_0x0nnn_machine_calls(true);
+        chip8.set_machine_call_handler(|_, state| {
+            state.registers[0] = 0x42;
+            state.sp = 20;
+        });
+
+        assert_eq!(
+            chip8.emulate_cycle(&MockInput::default()).err(),
+            Some(Chip8Error::StackOverflow(APP_LOCATION))
+        );
+        assert_eq!(chip8.sp, 0);
+        assert_eq!(chip8.registers[0], 0);
+        assert!(chip8.stack().is_empty());
+    }
+
+    #[test]
+    fn test_state_eq_ignoring() {
+        let mut chip8 = create_chip8_with_program(&[0x6005, 0xC0FF]);
+        chip8.emulate_cycle(&MockInput::default()).unwrap();
+        let before = chip8.state();
+        assert!(chip8.state_eq_ignoring(&before, StateMask::default()));
+
+        chip8.emulate_cycle(&MockInput::default()).unwrap();
+        let registers_and_pc = StateMask {
+            registers: true,
+            pc: true,
+            ..Default::default()
+        };
+        assert!(!chip8.state_eq_ignoring(&before, StateMask::default()));
+        assert!(chip8.state_eq_ignoring(&before, registers_and_pc));
+    }
+
+    /// LD F, V1; DRW V0, V3, 5; ADD V0, 1; ADD V1, 1; LD V4, 0x0F; AND V1, V4; CALL 0x210;
+    /// JP 0x200; 0x210: ADD V5, 1; LD DT, V5; RET
+    const COUNTING_PROGRAM: [u16; 11] = [
+        0xF129, 0xD035, 0x7001, 0x7101, 0x640F, 0x8142, 0x2210, 0x1200, 0x7501, 0xF515, 0x00EE,
+    ];
+
+    fn states_after(chip8: &mut Chip8<Graphics>, cycles: usize) -> Vec<Chip8State> {
+        (0..cycles)
+            .map(|_| {
+                chip8.emulate_cycle(&MockInput::default()).unwrap();
+                chip8.save_state()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_save_and_load_state() {
+        let mut chip8 = create_chip8_with_program(&COUNTING_PROGRAM);
+        states_after(&mut chip8, 37);
+
+        let saved = chip8.save_state();
+        let first_run = states_after(&mut chip8, 100);
+        chip8.load_state(saved.clone()).unwrap();
+        assert_eq!(chip8.save_state(), saved);
+        assert_eq!(states_after(&mut chip8, 100), first_run);
+
+        // Into an emulator that hasn't run at all
+        let mut other = create_chip8(0x0000);
+        other.load_state(saved).unwrap();
+        assert_eq!(states_after(&mut other, 100), first_run);
+    }
+
+    #[test]
+    fn test_load_state_resumes_key_wait() {
+        // LD V1, K; JP 0x202
+        let mut chip8 = create_chip8_with_program(&[0xF10A, 0x1202]);
+        // Started the wait, and saw no key pressed
+        chip8.emulate_frame(2, &MockInput::default()).unwrap();
+        let saved = chip8.save_state();
+        assert!(saved.key_wait.is_waiting());
+
+        let mut restored = create_chip8_with_program(&[0xF10A, 0x1202]);
+        restored.load_state(saved).unwrap();
+        let mut input = MockInput::default();
+        input.keys[0x7] = true;
+        restored.emulate_cycle(&input).unwrap();
+        assert_eq!(restored.registers[1], 0x7);
+        assert!(restored.is_waiting_for_key());
+
+        restored.emulate_cycle(&MockInput::default()).unwrap();
+        assert!(!restored.is_waiting_for_key());
+        assert_eq!(restored.pc, 0x202);
+    }
+
+    #[test]
+    fn test_cpu_state_accessors() {
+        // LD V3, 0x42; LD I, 0x300; LD DT, V3; LD ST, V3; CALL 0x20C; JP 0x20A; 0x20C: JP 0x20C
+        let mut chip8 = create_chip8_with_program(&[0x6342, 0xA300, 0xF315, 0xF318, 0x220C, 0x120A, 0x120C]);
+        assert_eq!(chip8.current_opcode(), 0);
+        assert_eq!(chip8.pc(), APP_LOCATION);
+
+        chip8.emulate_frame(6, &MockInput::default()).unwrap();
+        let mut registers = [0; 16];
+        registers[3] = 0x42;
+        assert_eq!(chip8.registers(), &registers);
+        assert_eq!(chip8.ir(), 0x300);
+        assert_eq!((chip8.delay_timer(), chip8.sound_timer()), (0x42, 0x42));
+        assert_eq!(chip8.sp(), 1);
+        assert_eq!(chip8.stack(), [0x20A]);
+        assert_eq!(chip8.pc(), 0x20C);
+        assert_eq!(chip8.current_opcode(), 0x120C);
+    }
+
+    #[test]
+    fn test_memory_peek_and_poke() {
+        let mut chip8 = create_chip8(0x0000);
+        chip8.write_memory(0x0FFF, 0x12).unwrap();
+        assert_eq!(chip8.read_memory(0x0FFF), Ok(0x12));
+        assert_eq!(
+            chip8.read_memory(0x1000),
+            Err(Chip8Error::InvalidMemoryAccess(0x1000))
+        );
+        assert_eq!(
+            chip8.write_memory(0x1000, 0x12),
+            Err(Chip8Error::InvalidMemoryAccess(0x1000))
+        );
+
+        chip8.write_memory_range(0x0FFE, &[0xAB, 0xCD]).unwrap();
+        assert_eq!(chip8.read_memory_range(0x0FFE, 2), Ok(&[0xAB, 0xCD][..]));
+        assert_eq!(chip8.read_memory_range(0x1000, 0), Ok(&[][..]));
+        assert_eq!(
+            chip8.read_memory_range(0x0FFF, 2),
+            Err(Chip8Error::InvalidMemoryAccess(0x1000))
+        );
+        assert_eq!(
+            chip8.write_memory_range(0x0FFF, &[1, 2]),
+            Err(Chip8Error::InvalidMemoryAccess(0x1000))
+        );
+        assert_eq!(chip8.read_memory(0x0FFF), Ok(0xCD));
+
+        // The font can be changed too
+        chip8.write_memory(FONT_BASE, 0xFF).unwrap();
+        assert_eq!(chip8.read_memory(FONT_BASE), Ok(0xFF));
+    }
+
+    #[test]
+    fn test_write_memory_updates_vip_display() {
+        let mut chip8 = create_chip8(0x0000);
+        chip8.quirks.vip_memory_layout = true;
+        chip8.add_watchpoint(VIP_DISPLAY_ADDRESS..VIP_DISPLAY_ADDRESS + 1, WatchKind::Write);
+
+        chip8.write_memory(VIP_DISPLAY_ADDRESS, 0x81).unwrap();
+        assert_eq!(chip8.graphics().buffer()[0][..8], [1, 0, 0, 0, 0, 0, 0, 1]);
+        let output = chip8.emulate_frame(0, &MockInput::default()).unwrap();
+        assert!(output.draw_on_screen);
+        assert!(matches!(output.events[..], [Chip8Event::Watchpoint(_)]));
+    }
+
+    #[test]
+    fn test_call_stack() {
+        // CALL 0x206; JP 0x202; RET; 0x206: CALL 0x20C; RET; 0x20A: RET; 0x20C: CALL 0x20A; RET
+        let mut chip8 =
+            create_chip8_with_program(&[0x2206, 0x1202, 0x00EE, 0x220C, 0x00EE, 0x00EE, 0x220A, 0x00EE]);
+        assert_eq!(chip8.stack(), []);
+        assert_eq!(chip8.format_call_stack(), "");
+
+        let mut stacks = Vec::new();
+        for _ in 0..6 {
+            chip8.emulate_cycle(&MockInput::default()).unwrap();
+            stacks.push(chip8.stack().to_vec());
+        }
+        assert_eq!(
+            stacks,
+            [
+                vec![0x202],
+                vec![0x202, 0x208],
+                vec![0x202, 0x208, 0x20E],
+                vec![0x202, 0x208],
+                vec![0x202],
+                vec![],
+            ]
+        );
+
+        chip8.reset();
+        chip8.emulate_frame(3, &MockInput::default()).unwrap();
+        assert_eq!(chip8.format_call_stack(), "#0 0x0202 -> #1 0x0208 -> #2 0x020e");
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_deprecated_accessors() {
+        // CALL 0x202; LD V0, 0x05
+        let mut chip8 = create_chip8_with_program(&[0x2202, 0x6005]);
+        chip8.emulate_frame(2, &MockInput::default()).unwrap();
+
+        assert_eq!(chip8.get_registers_as_slice(), chip8.registers());
+        assert_eq!(chip8.get_stack_depth(), chip8.sp());
+        assert_eq!(chip8.call_stack(), chip8.stack());
+    }
+
+    #[test]
+    fn test_step() {
+        // LD V0, 5; DRW V1, V1, 5; LD V2, K; JP 0x206
+        let mut chip8 = create_chip8_with_program(&[0x6005, 0xD115, 0xF20A, 0x1206]);
+        let step = |chip8: &mut Chip8<Graphics>| chip8.step(&MockInput::default()).unwrap();
+
+        let load = step(&mut chip8);
+        assert_eq!(
+            load,
+            StepResult {
+                opcode: 0x6005,
+                pc_before: 0x200,
+                pc_after: 0x202,
+                drew: false,
+                waiting_for_key: false,
+            }
+        );
+        assert!(step(&mut chip8).drew);
+
+        let wait = step(&mut chip8);
+        assert!(wait.waiting_for_key);
+        assert_eq!(
+            (wait.opcode, wait.pc_before, wait.pc_after),
+            (0xF20A, 0x204, 0x204)
+        );
+        let still_waiting = step(&mut chip8);
+        assert!(still_waiting.waiting_for_key && !still_waiting.drew);
+
+        // The draw is still reported to the next output
+        assert!(chip8.emulate_cycle(&MockInput::default()).unwrap().draw_on_screen);
+    }
+
+    #[test]
+    fn test_step_leaves_the_timers_alone() {
+        let (timer_tx, timer_rx) = mpsc::channel();
+        let mut chip8 = Chip8::new(
+            Graphics::new(),
+            timer_rx,
+            Quirks::default(),
+            DebugOptions::default(),
+        );
+        // LD V0, 10; LD DT, V0; LD ST, V0; JP 0x206
+        chip8
+            .load_rom(&vec![0x60, 0x0A, 0xF0, 0x15, 0xF0, 0x18, 0x12, 0x06])
+            .unwrap();
+        for _ in 0..3 {
+            chip8.step(&MockInput::default()).unwrap();
+        }
+
+        timer_tx.send(TimerOperation::Decrement(4)).unwrap();
+        chip8.step(&MockInput::default()).unwrap();
+        assert_eq!((chip8.delay_timer(), chip8.sound_timer()), (10, 10));
+
+        chip8.tick_timers(3);
+        assert_eq!((chip8.delay_timer(), chip8.sound_timer()), (7, 7));
+        chip8.tick_timers(u8::MAX);
+        assert_eq!((chip8.delay_timer(), chip8.sound_timer()), (0, 0));
+
+        // The ticks that were sent are only applied by a cycle
+        chip8
+            .load_state(Chip8State {
+                delay_timer: 10,
+                ..chip8.save_state()
+            })
+            .unwrap();
+        chip8.emulate_cycle(&MockInput::default()).unwrap();
+        assert_eq!(chip8.delay_timer(), 6);
+    }
+
+    fn watch_hits(events: &[Chip8Event]) -> Vec<(u16, u8, WatchKind)> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                Chip8Event::Watchpoint(hit) => Some((hit.addr, hit.value, hit.access)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_write_watchpoint_boundary() {
+        // LD V0, 1; LD V1, 2; LD V2, 3; LD V3, 4; LD I, 0x300; LD [I], V3
+        let mut chip8 = create_chip8_with_program(&[0x6001, 0x6102, 0x6203, 0x6304, 0xA300, 0xF355]);
+        // Watches the last two of the four bytes Fx55 writes, and the byte after them. The first
+        // byte is only watched for reads.
+        chip8.add_watchpoint(0x302..0x305, WatchKind::Write);
+        chip8.add_watchpoint(0x300..0x301, WatchKind::Read);
+
+        let output = chip8.emulate_frame(6, &MockInput::default()).unwrap();
+        assert_eq!(
+            watch_hits(&output.events),
+            [(0x302, 3, WatchKind::Write), (0x303, 4, WatchKind::Write)]
+        );
+        let Chip8Event::Watchpoint(hit) = output.events[0] else {
+            panic!("{:?}", output.events);
+        };
+        assert_eq!((hit.opcode, hit.pc), (0xF355, 0x20A));
+    }
+
+    #[test]
+    fn test_read_watchpoints() {
+        // LD I, 0x200; LD V1, [I]; DRW V0, V0, 3; JP 0x206
+        let mut chip8 = create_chip8_with_program(&[0xA200, 0xF165, 0xD003, 0x1206]);
+        chip8.add_watchpoint(0x201..0x203, WatchKind::ReadWrite);
+
+        let output = chip8.emulate_frame(4, &MockInput::default()).unwrap();
+        assert_eq!(
+            watch_hits(&output.events),
+            // V1 from Fx65, then the first sprite row, as Fx65 moved I to 0x202
+            [(0x201, 0x00, WatchKind::Read), (0x202, 0xF1, WatchKind::Read)]
+        );
+
+        chip8.clear_watchpoints();
+        chip8.reset();
+        assert!(chip8
+            .emulate_frame(4, &MockInput::default())
+            .unwrap()
+            .events
+            .is_empty());
+    }
+
+    #[test]
+    fn test_run_until_draw() {
+        // LD V0, 0; ADD V0, 1; ADD V0, 1; DRW V0, V0, 1; CLS; JP 0x20A
+        let mut chip8 = create_chip8_with_program(&[0x6000, 0x7001, 0x7001, 0xD001, 0x00E0, 0x120A]);
+
+        let output = chip8.run_until_draw(100, &MockInput::default()).unwrap();
+        assert!(output.draw_on_screen);
+        assert_eq!(output.instructions_executed, 4);
+        assert_eq!(chip8.pc(), 0x208);
+
+        let output = chip8.run_until_draw(100, &MockInput::default()).unwrap();
+        assert_eq!(output.instructions_executed, 1);
+        assert_eq!(chip8.pc(), 0x20A);
+
+        // Nothing draws anymore
+        let output = chip8.run_until_draw(100, &MockInput::default()).unwrap();
+        assert!(!output.draw_on_screen);
+        assert_eq!(output.instructions_executed, 100);
+        assert_eq!(
+            chip8
+                .emulate_frame(7, &MockInput::default())
+                .unwrap()
+                .instructions_executed,
+            7
+        );
+    }
+
+    #[test]
+    fn test_run_cycles_stops_on_key_wait() {
+        // LD V0, 1; LD V1, K; 0x204: ADD V0, 1; JP 0x204
+        let mut chip8 = create_chip8_with_program(&[0x6001, 0xF10A, 0x7001, 0x1204]);
+
+        let output = chip8.run_cycles(14, &MockInput::default()).unwrap();
+        assert_eq!(output.instructions_executed, 2);
+        assert!(chip8.is_waiting_for_key());
+        let output = chip8.run_cycles(14, &MockInput::default()).unwrap();
+        assert_eq!(output.instructions_executed, 0);
+
+        let mut pressed = MockInput::default();
+        pressed.keys[0xA] = true;
+        chip8.run_cycles(14, &pressed).unwrap();
+        // The first cycle finishes the Fx0A on the release
+        let output = chip8.run_cycles(14, &MockInput::default()).unwrap();
+        assert_eq!(output.instructions_executed, 13);
+        assert_eq!(chip8.registers()[1], 0xA);
+
+        chip8
+            .add_conditional_breakpoint(0x206, BreakCondition::IrEquals(0))
+            .unwrap();
+        assert_eq!(
+            chip8.run_cycles(14, &MockInput::default()).err(),
+            Some(Chip8Error::BreakpointHit(0x206))
+        );
+    }
+
+    #[test]
+    fn test_unknown_opcode_handler() {
+        // 0x0F00 adds V1 to V0 and stores it at I, then LD V2, 0x33; 0x0F01; 0xFFFF
+        let program = [0x6005, 0x6107, 0xA300, 0x0F00, 0x6233, 0x0F01, 0xFFFF];
+        let mut chip8 = create_chip8_with_program(&program);
+        chip8.set_unknown_opcode_handler(|opcode, context| match opcode {
+            0x0F00 => {
+                let sum = context.registers()[0] + context.registers()[1];
+                let ir = context.ir() as usize;
+                context.memory_mut()[ir] = sum;
+                context.set_ir(context.ir() + 1);
+                OpcodeOutcome::Next
+            }
+            0x0F01 => OpcodeOutcome::Set(context.pc() - 2),
+            _ => OpcodeOutcome::Unsupported,
+        });
+
+        for _ in 0..6 {
+            chip8.emulate_cycle(&MockInput::default()).unwrap();
+        }
+        assert_eq!(chip8.read_memory(0x300), Ok(12));
+        assert_eq!(chip8.ir(), 0x301);
+        assert_eq!(chip8.registers()[2], 0x33);
+        // 0x0F01 jumped back to LD V2
+        assert_eq!(chip8.pc(), 0x208);
+
+        let mut chip8 = create_chip8_with_program(&[0x0F01, 0xFFFF]);
+        chip8.set_unknown_opcode_handler(|_, _| OpcodeOutcome::Skip);
+        chip8.emulate_cycle(&MockInput::default()).unwrap();
+        assert_eq!(chip8.pc(), 0x204);
+
+        let mut chip8 = create_chip8_with_program(&[0x0F00]);
+        assert_eq!(
+            chip8.emulate_cycle(&MockInput::default()).err(),
+            Some(Chip8Error::UnsupportedOpcode(0x0F00))
+        );
+    }
+
+    #[test]
+    fn test_execute_hooks() {
+        // LD V0, 2; 0x202: ADD V0, 0xFF; SE V0, 0; JP 0x202; JP 0x208
+        let mut chip8 = create_chip8_with_program(&[0x6002, 0x70FF, 0x3000, 0x1202, 0x1208]);
+        let pcs = Rc::new(RefCell::new(Vec::new()));
+        chip8.set_pre_execute_hook({
+            let pcs = Rc::clone(&pcs);
+            move |context| pcs.borrow_mut().push(context.pc)
+        });
+        let after = Rc::new(RefCell::new(Vec::new()));
+        chip8.set_post_execute_hook({
+            let after = Rc::clone(&after);
+            move |context| after.borrow_mut().push((context.opcode, context.registers[0]))
+        });
+
+        for _ in 0..9 {
+            chip8.emulate_cycle(&MockInput::default()).unwrap();
+        }
+        assert_eq!(
+            *pcs.borrow(),
+            [0x200, 0x202, 0x204, 0x206, 0x202, 0x204, 0x208, 0x208, 0x208]
+        );
+        assert_eq!(after.borrow()[..3], [(0x6002, 2), (0x70FF, 1), (0x3000, 1)]);
+
+        let context = HookContext {
+            pc: 0x200,
+            opcode: 0x6120,
+            registers: &[0; 16],
+            ir: 0,
+        };
+        assert_eq!(context.to_string(), "0200: 6120   LD V1, 0x20");
+
+        chip8.clear_execute_hooks();
+        chip8.emulate_cycle(&MockInput::default()).unwrap();
+        assert_eq!(pcs.borrow().len(), 9);
+    }
+
+    #[test]
+    fn test_counters() {
+        let (timer_tx, timer_rx) = mpsc::channel();
+        let mut chip8 = Chip8::new(
+            Graphics::new(),
+            timer_rx,
+            Quirks::default(),
+            DebugOptions::default(),
+        );
+        // CLS; LD V0, 1; LD V1, K; DRW V0, V0, 1; JP 0x206
+        chip8
+            .load_rom(&vec![0x00, 0xE0, 0x60, 0x01, 0xF1, 0x0A, 0xD0, 0x01, 0x12, 0x06])
+            .unwrap();
+        let mut pressed = MockInput::default();
+        pressed.keys[4] = true;
+
+        timer_tx.send(TimerOperation::Decrement(3)).unwrap();
+        for _ in 0..10 {
+            chip8.emulate_cycle(&MockInput::default()).unwrap();
+        }
+        // Every cycle after the first Fx0A waited
+        assert_eq!(chip8.cycles_executed(), 3);
+        assert_eq!(chip8.frames_drawn(), 1);
+        assert_eq!(chip8.timer_ticks_received(), 3);
+
+        // Press, release, then draw and jump
+        chip8.emulate_cycle(&pressed).unwrap();
+        for _ in 0..3 {
+            chip8.emulate_cycle(&MockInput::default()).unwrap();
+        }
+        assert_eq!(chip8.cycles_executed(), 5);
+        assert_eq!(chip8.frames_drawn(), 2);
+
+        let debug = format!("{chip8:?}");
+        assert!(debug.contains("cycles_executed: 5, frames_drawn: 2, timer_ticks_received: 3"));
+
+        chip8.reset_counters();
+        assert_eq!(
+            (
+                chip8.cycles_executed(),
+                chip8.frames_drawn(),
+                chip8.timer_ticks_received()
+            ),
+            (0, 0, 0)
+        );
+        chip8.emulate_cycle(&MockInput::default()).unwrap();
+        assert_eq!(chip8.cycles_executed(), 1);
+        // The statistics are zeroed separately
+        assert_eq!(chip8.statistics().total_cycles, 6);
+    }
+
+    #[test]
+    fn test_reset_runs_like_a_new_emulator() {
+        let mut fresh = create_chip8_with_program(&COUNTING_PROGRAM);
+        let expected = states_after(&mut fresh, 100);
+
+        let mut chip8 = create_chip8_with_program(&COUNTING_PROGRAM);
+        states_after(&mut chip8, 57);
+        chip8.reset();
+        assert_eq!(
+            chip8.save_state(),
+            create_chip8_with_program(&COUNTING_PROGRAM).save_state()
+        );
+        assert!(chip8.is_display_blank());
+        assert_eq!(chip8.statistics().total_cycles, 0);
+        assert_eq!(states_after(&mut chip8, 100), expected);
+    }
+
+    #[test]
+    fn test_reset_restores_the_rom() {
+        // LD I, 0x200; LD V0, 0xAB; LD [I], V0; JP 0x206
+        let program = [0xA200, 0x60AB, 0xF055, 0x1206];
+        let mut chip8 = create_chip8_with_program(&program);
+        chip8.emulate_frame(4, &MockInput::default()).unwrap();
+        assert_eq!(chip8.memory[0x200], 0xAB);
+
+        chip8.reset();
+        assert_eq!(chip8.read_opcode_at(APP_LOCATION).unwrap(), 0xA200);
+        assert_eq!(chip8.pc, APP_LOCATION);
+    }
+
+    #[test]
+    fn test_hard_reset_wipes_the_rom() {
+        let mut chip8 = create_chip8_with_program(&COUNTING_PROGRAM);
+        chip8.emulate_frame(20, &MockInput::default()).unwrap();
+
+        chip8.hard_reset();
+        assert!(chip8.memory[APP_LOCATION as usize..]
+            .iter()
+            .all(|&byte| byte == 0));
+        assert_eq!(chip8.save_state(), create_chip8(0x0000).save_state());
+
+        chip8.load_rom(&vec![0x12, 0x00]).unwrap();
+        chip8.reset();
+        assert_eq!(chip8.read_opcode_at(APP_LOCATION).unwrap(), 0x1200);
+        assert_eq!(chip8.memory[APP_LOCATION as usize + 2], 0);
+    }
+
+    #[test]
+    fn test_load_state_checks_sizes() {
+        let mut chip8 = create_chip8_with_program(&COUNTING_PROGRAM);
+        let before = chip8.save_state();
+
+        let mut small_memory = before.clone();
+        small_memory.memory.truncate(0x800);
+        let mut big_screen = before.clone();
+        big_screen.screen.push(vec![0; SCREEN_WIDTH as usize]);
+        let mut deep_stack = before.clone();
+        deep_stack.sp = STACK_SIZE as u8 + 1;
+        let mut bad_key_register = before.clone();
+        bad_key_register.key_wait.register = 200;
+        for state in [small_memory, big_screen, deep_stack, bad_key_register] {
+            assert!(matches!(
+                chip8.load_state(state),
+                Err(Chip8Error::DeserializationError(_))
+            ));
+        }
+        assert_eq!(chip8.save_state(), before);
+    }
+
+    #[test]
+    fn test_machine_call_without_handler_is_skipped() {
+        let mut chip8 = create_chip8_with_program(&[0x0123]);
+        chip8.enable_legacy_0x0nnn_machine_calls(true);
+
+        chip8.emulate_cycle(&MockInput::default()).unwrap();
+        assert_eq!(chip8.pc, APP_LOCATION + 2);
+    }
+
+    #[test]
+    fn test_machine_calls_disabled_by_default() {
+        let mut chip8 = create_chip8(0x0123);
+        chip8.set_machine_call_handler(|_, _| panic!("machine calls are off"));
+
+        assert_eq!(chip8.opcode_0x0yyy(), Err(Chip8Error::UnsupportedOpcode(0x0123)));
+    }
+
+    #[test]
+    fn test_regs_x_y() {
+        let chip8 = create_chip8(0x0FA0);
+
+        let (x, y) = chip8.get_regs_x_y();
+        assert_eq!(x, 0xF);
+        assert_eq!(y, 0xA);
+    }
+
+    #[test]
+    fn test_bcd() {
+        let mut chip8 = create_chip8(0xF133);
+        let (x, _) = chip8.get_regs_x_y();
+
+        chip8.registers[x] = 123;
+        chip8.ir = 0x500;
+
+        let result = chip8.opcode_0xfyyy();
+
+        assert_eq!(result, Ok(ProgramCounter::Next));
+        assert_eq!(chip8.memory[chip8.ir as usize], 1);
+        assert_eq!(chip8.memory[chip8.ir as usize + 1], 2);
+        assert_eq!(chip8.memory[chip8.ir as usize + 2], 3);
+    }
+
+    fn test_copy_to_mem_impl(quirks: Quirks, starting_ir: usize, ending_ir: u16) {
+        let mut chip8 = create_chip8_with_quirks(0xF555, quirks);
+
+        for i in 0..=5 {
+            chip8.registers[i] = (i + 1) as u8;
+        }
+
+        chip8.ir = starting_ir as u16;
+
+        let result = chip8.opcode_0xfyyy();
+
+        assert_eq!(result, Ok(ProgramCounter::Next));
+        assert_eq!(chip8.memory[starting_ir], 1);
+        assert_eq!(chip8.memory[starting_ir + 1], 2);
+        assert_eq!(chip8.memory[starting_ir + 2], 3);
+        assert_eq!(chip8.memory[starting_ir + 3], 4);
+        assert_eq!(chip8.memory[starting_ir + 4], 5);
+        assert_eq!(chip8.memory[starting_ir + 5], 6);
+        assert_eq!(chip8.ir, ending_ir);
+    }
+
+    #[test]
+    fn test_copy_to_mem() {
+        let quirks = QuirksBuilder::default().increment_ir(true).build().unwrap();
+        test_copy_to_mem_impl(quirks, 0x500, 0x506);
+    }
+
+    #[test]
+    fn test_copy_to_mem_no_increment_ir() {
+        let quirks = QuirksBuilder::default().increment_ir(false).build().unwrap();
+        test_copy_to_mem_impl(quirks, 0x500, 0x500);
+    }
+
+    #[test]
+    fn test_write_high_water_mark() {
+        let mut chip8 = create_chip8(0xF455);
+        chip8.ir = 0x500;
+
+        let result = chip8.opcode_0xfyyy();
+
+        assert_eq!(result, Ok(ProgramCounter::Next));
+        assert_eq!(chip8.get_write_high_water_mark(), 0x504);
+        assert_eq!(chip8.get_read_high_water_mark(), 0);
+    }
+
+    #[test]
+    fn test_vip_display_in_memory() {
+        // LD V0, 0xF0; LD V1, 0x81; LD I, 0xF00; LD [I], V1
+        let program = [0x60F0, 0x6181, 0xAF00, 0xF155];
+        let mut chip8 = create_chip8_with_program(&program);
+        chip8.quirks.vip_memory_layout = true;
+        let input = MockInput::default();
+        for _ in 0..3 {
+            chip8.emulate_cycle(&input).unwrap();
+        }
+
+        assert!(chip8.emulate_cycle(&input).unwrap().draw_on_screen);
+        let buffer = chip8.graphics.buffer();
+        assert_eq!(buffer[0][..8], [1, 1, 1, 1, 0, 0, 0, 0]);
+        assert_eq!(buffer[0][8..16], [1, 0, 0, 0, 0, 0, 0, 1]);
+        assert!(buffer[1].iter().all(|&pixel| pixel == 0));
+    }
+
+    #[test]
+    fn test_vip_display_follows_drawing() {
+        // LD I, 0x050 (the font's 0); LD V0, 8; LD V1, 1; DRW V0, V1, 5; CLS
+        let mut chip8 = create_chip8_with_program(&[0xA050, 0x6008, 0x6101, 0xD015, 0x00E0]);
+        chip8.quirks.vip_memory_layout = true;
+        let input = MockInput::default();
+        for _ in 0..4 {
+            chip8.emulate_cycle(&input).unwrap();
+        }
+
+        // Rows are 8 bytes apart, and x = 8 is the second byte of a row
+        let display = VIP_DISPLAY_ADDRESS as usize;
+        assert_eq!(chip8.memory[display..display + 9], [0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        for (row, &sprite) in HEX_DIGITS[..5].iter().enumerate() {
+            assert_eq!(chip8.memory[display + (row + 1) * 8 + 1], sprite);
+        }
+
+        chip8.emulate_cycle(&input).unwrap();
+        assert!(chip8.memory[display..].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn test_vip_stack_in_memory() {
+        // CALL 0x206; -; JP 0x204; LD V0, 0x02; LD V1, 0x04; LD I, 0xECE; LD [I], V1; RET
+        let program = [0x2206, 0x0000, 0x1204, 0x6002, 0x6104, 0xAECE, 0xF155, 0x00EE];
+        let mut chip8 = create_chip8_with_program(&program);
+        chip8.quirks.vip_memory_layout = true;
+        let input = MockInput::default();
+
+        chip8.emulate_cycle(&input).unwrap();
+        let entry = VIP_STACK_ADDRESS as usize;
+        assert_eq!(chip8.memory[entry..entry + 2], [0x02, 0x02]);
+
+        // The subroutine changes its return address to 0x204
+        for _ in 0..5 {
+            chip8.emulate_cycle(&input).unwrap();
+        }
+        assert_eq!(chip8.pc, 0x204);
+    }
+
+    #[test]
+    fn test_vip_layout_off() {
+        // CALL 0x206; -; -; LD V1, 0xFF; LD I, 0xF00; LD [I], V1
+        let mut chip8 = create_chip8_with_program(&[0x2206, 0x0000, 0x0000, 0x61FF, 0xAF00, 0xF155]);
+        let input = MockInput::default();
+        for _ in 0..3 {
+            chip8.emulate_cycle(&input).unwrap();
+        }
+
+        assert!(!chip8.emulate_cycle(&input).unwrap().draw_on_screen);
+        assert!(chip8.is_display_blank());
+        assert_eq!(chip8.memory[VIP_STACK_ADDRESS as usize], 0);
+    }
+
+    #[test]
+    fn test_memory_hotspots() {
+        // LD V0, 0x07; LD I, 0x500; LD [I], V0; JP 0x204
+        let mut chip8 = create_chip8_with_program(&[0x6007, 0xA500, 0xF055, 0x1204]);
+        chip8.dbg_options.track_memory_hotspots = true;
+        let input = MockInput::default();
+        for _ in 0..2 {
+            chip8.emulate_cycle(&input).unwrap();
+        }
+
+        // Fx55 increments I by default, so put it back every time
+        for _ in 0..10 {
+            chip8.ir = 0x500;
+            chip8.emulate_cycle(&input).unwrap();
+            chip8.emulate_cycle(&input).unwrap();
+        }
+
+        let writes = chip8.trace_memory_write_hotspots(5);
+        assert_eq!(writes, [(0x500, 10)]);
+        assert!(writes[0].1 >= 10);
+        // The loop's opcodes were fetched 10 times each; the setup's once
+        assert_eq!(
+            chip8.trace_memory_hotspots(5),
+            [(0x204, 10), (0x205, 10), (0x206, 10), (0x207, 10), (0x200, 1)]
+        );
+        assert_eq!(chip8.trace_memory_hotspots(0), []);
+    }
+
+    #[test]
+    fn test_memory_hotspots_off() {
+        let mut chip8 = create_chip8(0xF455);
+        chip8.ir = 0x500;
+        chip8.opcode_0xfyyy().unwrap();
+
+        assert_eq!(chip8.trace_memory_write_hotspots(5), []);
+    }
+
+    #[test]
+    fn test_read_high_water_mark() {
+        let mut chip8 = create_chip8(0xF465);
+        chip8.ir = 0x600;
+
+        let result = chip8.opcode_0xfyyy();
+
+        assert_eq!(result, Ok(ProgramCounter::Next));
+        assert_eq!(chip8.get_read_high_water_mark(), 0x604);
+
+        // A lower read doesn't lower the mark
+        chip8.opcode = 0xD005;
+        chip8.ir = 0x300;
+        chip8.opcode_0xdyyy().unwrap();
+        assert_eq!(chip8.get_read_high_water_mark(), 0x604);
+
+        chip8.ir = 0x700;
+        chip8.opcode_0xdyyy().unwrap();
+        assert_eq!(chip8.get_read_high_water_mark(), 0x704);
+        assert_eq!(chip8.get_write_high_water_mark(), 0);
+    }
+
+    fn test_copy_from_mem_impl(quirks: Quirks, starting_ir: u16, ending_ir: u16) {
+        let mut chip8 = create_chip8_with_quirks(0xF565, quirks);
+
+        chip8.ir = starting_ir;
+
+        for i in 0..=5 {
+            chip8.memory[chip8.ir as usize + i] = (i + 1) as u8;
+        }
+
+        let result = chip8.opcode_0xfyyy();
+
+        assert_eq!(result, Ok(ProgramCounter::Next));
+
+        assert_eq!(chip8.registers[0], 1);
+        assert_eq!(chip8.registers[1], 2);
+        assert_eq!(chip8.registers[2], 3);
+        assert_eq!(chip8.registers[3], 4);
+        assert_eq!(chip8.registers[4], 5);
+        assert_eq!(chip8.registers[5], 6);
+        assert_eq!(chip8.ir, ending_ir);
+    }
+
+    #[test]
+    fn test_copy_from_mem() {
+        let quirks = QuirksBuilder::default().increment_ir(true).build().unwrap();
+        test_copy_from_mem_impl(quirks, 0x500, 0x506);
+    }
+
+    #[test]
+    fn test_copy_from_mem_no_increment_ir() {
+        let quirks = QuirksBuilder::default().increment_ir(false).build().unwrap();
+        test_copy_from_mem_impl(quirks, 0x500, 0x500);
+    }
+
+    /// LD V0, 0x01; LD V1, 0x02; SE V0, 0x01; LD VF, 0xFF. Done after 3 cycles, since the skip
+    /// leaves VF alone.
+    const SET_AND_CHECK_ROM: [u8; 8] = [0x60, 0x01, 0x61, 0x02, 0x30, 0x01, 0x6F, 0xFF];
+
+    /// Key 0 starts being pressed on the third time the keys are read.
+    struct ScriptedInput {
+        reads: std::cell::Cell<usize>,
+    }
+
+    impl ScriptedInput {
+        fn new() -> Self {
+            ScriptedInput {
+                reads: std::cell::Cell::new(0),
+            }
+        }
+
+        fn read(&self) -> bool {
+            self.reads.set(self.reads.get() + 1);
+            self.reads.get() >= 3
+        }
+    }
+
+    impl Input for ScriptedInput {
+        fn is_pressed(&self, key: Key) -> bool {
+            self.read() && key == Key::Num0
+        }
+
+        fn pressed_mask(&self) -> u16 {
+            self.read() as u16
+        }
+    }
+
+    /// Counts in V1 how many of four `SKP V0` checks saw key 0 released, then loops forever.
+    fn key_counting_chip8(sampling: InputSampling) -> Chip8<Graphics> {
+        let quirks = QuirksBuilder::default().input_sampling(sampling).build().unwrap();
+        let mut chip8 = create_chip8_with_quirks(0x0000, quirks);
+        let program = [0xE09E, 0x7101].repeat(4);
+        let rom: Vec<u8> = program
+            .iter()
+            .chain(&[0x1210])
+            .flat_map(|op: &u16| op.to_be_bytes())
+            .collect();
+        chip8.load_rom(&rom).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn test_emulate_frame_samples_keys_once_per_frame() {
+        let mut chip8 = key_counting_chip8(InputSampling::PerFrame);
+        let input = ScriptedInput::new();
+
+        chip8.emulate_frame(8, &input).unwrap();
+
+        // The key was released when the frame started, so every check saw it released
+        assert_eq!(input.reads.get(), 1);
+        assert_eq!(chip8.registers[1], 4);
+    }
+
+    #[test]
+    fn test_emulate_frame_samples_keys_per_cycle() {
+        let mut chip8 = key_counting_chip8(InputSampling::PerCycle);
+        let input = ScriptedInput::new();
+
+        chip8.emulate_frame(8, &input).unwrap();
+
+        // The last two checks saw the key pressed partway through the frame
+        assert_eq!(input.reads.get(), 4);
+        assert_eq!(chip8.registers[1], 2);
+    }
+
+    #[test]
+    fn test_queued_tap_is_seen_by_skp() {
+        // LD V0, 0x5; SKP V0; JP 0x202; LD V1, 1; JP 0x208
+        let mut chip8 = create_chip8_with_program(&[0x6005, 0xE09E, 0x1202, 0x6101, 0x1208]);
+        chip8.emulate_cycle(&MockInput::default()).unwrap();
+
+        chip8.queue_key_event(Key::Num5, true);
+        chip8.queue_key_event(Key::Num5, false);
+        chip8.emulate_cycle(&MockInput::default()).unwrap();
+        assert_eq!(chip8.pc, 0x206);
+
+        chip8.emulate_cycle(&MockInput::default()).unwrap();
+        assert_eq!(chip8.registers[1], 1);
+        assert_eq!(chip8.queued_keys, 0);
+        assert!(chip8.key_events.is_empty());
+    }
+
+    #[test]
+    fn test_queued_tap_is_seen_by_key_wait() {
+        // LD V1, K; JP 0x202
+        let mut chip8 = create_chip8_with_program(&[0xF10A, 0x1202]);
+        chip8.emulate_frame(2, &MockInput::default()).unwrap();
+        assert!(chip8.is_waiting_for_key());
+
+        chip8.queue_key_event(Key::Num7, true);
+        chip8.queue_key_event(Key::Num7, false);
+        chip8.emulate_cycle(&MockInput::default()).unwrap();
+        assert_eq!(chip8.registers[1], 0x7);
+        assert!(chip8.is_waiting_for_key());
+
+        chip8.emulate_cycle(&MockInput::default()).unwrap();
+        assert!(!chip8.is_waiting_for_key());
+        assert_eq!(chip8.pc, 0x202);
+    }
+
+    #[test]
+    fn test_queued_key_events_keep_their_order() {
+        // JP 0x200
+        let mut chip8 = create_chip8_with_program(&[0x1200]);
+        chip8.queue_key_event(Key::Num1, true);
+        chip8.queue_key_event(Key::Num1, false);
+        chip8.queue_key_event(Key::Num2, true);
+        chip8.queue_key_event(Key::Num3, true);
+
+        chip8.emulate_cycle(&MockInput::default()).unwrap();
+        assert_eq!(chip8.queued_keys, 0b0010);
+        chip8.emulate_cycle(&MockInput::default()).unwrap();
+        assert_eq!(chip8.queued_keys, 0b1100);
+    }
+
+    #[test]
+    fn test_emulate_frame_reports_draws_from_any_cycle() {
+        // CLS; LD V0, 0x01; LD V0, 0x02
+        let mut chip8 = create_chip8_with_program(&[0x00E0, 0x6001, 0x6002]);
+
+        let output = chip8.emulate_frame(3, &MockInput::default()).unwrap();
+        assert!(output.draw_on_screen);
+        assert_eq!(chip8.registers[0], 2);
+    }
+
+    #[test]
+    fn test_pressed_mask_default() {
+        let mut input = MockInput::default();
+        input.keys[0x1] = true;
+        input.keys[0xF] = true;
+
+        assert_eq!(input.pressed_mask(), 0x8002);
+    }
+
+    #[test]
+    fn test_run_test_rom_passes() {
+        let result = Chip8::run_test_rom(&SET_AND_CHECK_ROM, &[(0x0, 1), (0x1, 2), (0xF, 0)], 3);
+        assert_eq!(
+            result,
+            TestResult {
+                passed: true,
+                failed_registers: vec![],
+                error: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_run_test_rom_reports_failed_registers() {
+        let result = Chip8::run_test_rom(&SET_AND_CHECK_ROM, &[(0x0, 1), (0x1, 3), (0x20, 1)], 3);
+        assert!(!result.passed);
+        assert_eq!(result.failed_registers, [(0x1, 3, 2), (0x20, 1, 0)]);
+    }
+
+    #[test]
+    fn test_run_test_rom_reports_errors() {
+        // LD V0, 0x01; an unsupported opcode
+        let result = Chip8::run_test_rom(&[0x60, 0x01, 0xFF, 0xFF], &[(0x0, 1)], 4);
+        assert!(!result.passed);
+        assert!(result.failed_registers.is_empty());
+        assert_eq!(result.error, Some(Chip8Error::UnsupportedOpcode(0xFFFF)));
+    }
+
+    #[test]
+    fn test_profile_run() {
+        // JP 0x200
+        let result = Chip8::profile_run(&[0x12, 0x00], Quirks::default(), 25_000);
+        assert_eq!(result.instructions, 25_000);
+        assert_eq!(result.error, None);
+        assert!(result.mips() > 0.0);
+
+        // LD V0, 0x01; an unsupported opcode
+        let result = Chip8::profile_run(&[0x60, 0x01, 0xFF, 0xFF], Quirks::default(), 25_000);
+        assert_eq!(result.error, Some(Chip8Error::UnsupportedOpcode(0xFFFF)));
+    }
+
+    #[test]
+    fn test_benchmark_cycles() {
+        // JP 0x200
+        let mut chip8 = create_chip8_with_program(&[0x1200]);
+        chip8.dbg_options.print_opcodes = true;
+
+        let result = chip8.benchmark_cycles(&MockInput::default(), 10_000).unwrap();
+        assert_eq!(result.cycles, 10_000);
+        assert!(result.mhz > 0.0);
+        assert_eq!(chip8.statistics().total_cycles, 10_000);
+        assert!(chip8.dbg_options.print_opcodes);
+    }
+
+    #[test]
+    fn test_expected_keys_while_waiting() {
+        // LD V0, K; SE V0, 0x0C; JP 0x200
+        let mut chip8 = create_chip8_with_program(&[0xF00A, 0x300C, 0x1200]);
+        assert!(!chip8.is_waiting_for_key());
+        assert_eq!(chip8.expected_keys(), None);
+
+        chip8.emulate_cycle(&MockInput::default()).unwrap();
+        assert!(chip8.is_waiting_for_key());
+        assert_eq!(chip8.expected_keys(), Some(vec![0xC]));
+    }
+
+    #[test]
+    fn test_debug() {
+        // CALL 0x204
+        let mut chip8 = create_chip8_with_program(&[0x2204]);
+        chip8.emulate_cycle(&MockInput::default()).unwrap();
+        chip8.registers[0xA] = 7;
+
+        let debug = format!("{chip8:?}");
+        assert!(debug.starts_with("Chip8 { opcode: 0x2204, pc: 0x204, ir: 0x000, sp: 1, "));
+        assert!(debug.contains("registers: [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 0, 0]"));
+        assert!(debug.contains("stack: [514]"));
+        assert!(debug.contains("wait_for_key_state: None"));
+        assert!(debug.contains("memory[..16]: [0, 0, 0, 0, 0, 0, 0, 0, "));
+        assert!(!debug.contains("timer_rx"));
+        assert!(debug.ends_with(".. }"));
+    }
+
+    #[test]
+    fn test_statistics_cycles_and_draws() {
+        // LD I, 0x050; DRW V0, V0, 5; DRW V0, V0, 5; JP 0x206
+        let mut chip8 = create_chip8_with_program(&[0xA050, 0xD005, 0xD005, 0x1206]);
+        let input = MockInput::default();
+
+        for _ in 0..6 {
+            chip8.emulate_cycle(&input).unwrap();
+        }
+
+        let stats = chip8.statistics();
+        assert_eq!(stats.total_cycles, 6);
+        assert_eq!(stats.total_draw_calls, 2);
+        // Only the second draw erased the first one
+        assert_eq!(stats.total_collisions, 1);
+    }
+
+    #[test]
+    fn test_opcode_category() {
+        let category = get_opcode_category;
+
+        assert_eq!(category(0xD125), OpcodeCategory::Graphics);
+        assert_eq!(category(0x00E0), OpcodeCategory::Graphics);
+        assert_eq!(category(0x8124), OpcodeCategory::Arithmetic);
+        assert_eq!(category(0x1234), OpcodeCategory::ControlFlow);
+        assert_eq!(category(0x00EE), OpcodeCategory::ControlFlow);
+        assert_eq!(category(0xF155), OpcodeCategory::Memory);
+        assert_eq!(category(0xF10A), OpcodeCategory::Input);
+        assert_eq!(category(0xF115), OpcodeCategory::Timer);
+        assert_eq!(category(0x8128), OpcodeCategory::Unknown);
+        assert_eq!(category(0x5121), OpcodeCategory::Unknown);
+    }
+
+    #[test]
+    fn test_self_modifying_code() {
+        // LD V0, 0x6E; LD V1, 0x42; LD I, 0x20A; LD [I], V1; LD V2, 0x00; CLS, which the Fx55
+        // overwrites with LD VE, 0x42
+        let mut chip8 = create_chip8_with_program(&[0x606E, 0x6142, 0xA20A, 0xF155, 0x6200, 0x00E0]);
+        let input = MockInput::default();
+
+        for _ in 0..5 {
+            chip8.emulate_cycle(&input).unwrap();
+        }
+        assert_eq!(chip8.pc, 0x20A);
+        assert_eq!(chip8.disassemble_current(), "LD VE, 0x42");
+
+        chip8.emulate_cycle(&input).unwrap();
+        assert_eq!(chip8.registers[0xE], 0x42);
+        let stats = chip8.statistics();
+        assert_eq!(stats.cycles_in(OpcodeCategory::Graphics), 0);
+        assert_eq!(stats.cycles_in(OpcodeCategory::Arithmetic), 4);
+    }
+
+    #[test]
+    fn test_program_entry_points() {
+        // JP 0x204; CALL 0x300; CALL 0x300; JP 0x200; LD I, 0x400
+        let rom = [0x12, 0x04, 0x23, 0x00, 0x23, 0x00, 0x12, 0x00, 0xA4, 0x00];
+
+        assert_eq!(get_program_entry_points(&rom), [0x200, 0x204, 0x300]);
+        assert_eq!(get_program_entry_points(&[]), [0x200]);
+    }
+
+    #[test]
+    fn test_statistics_cycles_by_category() {
+        // LD I, 0x050; DRW V0, V0, 5; ADD V0, 1; JP 0x202
+        let mut chip8 = create_chip8_with_program(&[0xA050, 0xD005, 0x7001, 0x1202]);
+        let input = MockInput::default();
+
+        for _ in 0..7 {
+            chip8.emulate_cycle(&input).unwrap();
+        }
+
+        let stats = chip8.statistics();
+        assert_eq!(stats.cycles_in(OpcodeCategory::Memory), 1);
+        assert_eq!(stats.cycles_in(OpcodeCategory::Graphics), 2);
+        assert_eq!(stats.cycles_in(OpcodeCategory::Arithmetic), 2);
+        assert_eq!(stats.cycles_in(OpcodeCategory::ControlFlow), 2);
+        assert_eq!(stats.cycles_in(OpcodeCategory::Timer), 0);
+        let map = stats.cycles_by_category_map();
+        assert_eq!(map.len(), 4);
+        assert_eq!(map[&OpcodeCategory::Graphics], 2);
+        assert!(!map.contains_key(&OpcodeCategory::Timer));
+        assert!(stats
+            .to_string()
+            .ends_with("\n  arithmetic:     2\n  memory:         1\n  graphics:       2"));
+    }
+
+    #[test]
+    fn test_statistics_subroutine_calls() {
+        // CALL 0x202; CALL 0x204; RET
+        let mut chip8 = create_chip8_with_program(&[0x2202, 0x2204, 0x00EE]);
+        let input = MockInput::default();
+
+        for _ in 0..3 {
+            chip8.emulate_cycle(&input).unwrap();
+        }
+
+        let stats = chip8.statistics();
+        assert_eq!(stats.total_subroutine_calls, 2);
+        assert_eq!(stats.max_stack_depth, 2);
+    }
+
+    #[test]
+    fn test_timer_set_events() {
+        // LD V0, 0x05; LD DT, V0; LD ST, V0; LD V0, 0x01; LD ST, V0
+        let mut chip8 = create_chip8_with_program(&[0x6005, 0xF015, 0xF018, 0x6001, 0xF018]);
+        let input = MockInput::default();
+
+        let output = chip8.emulate_frame(3, &input).unwrap();
+        assert_eq!(
+            output.events,
+            [
+                Chip8Event::DelayTimerSet { value: 5, cycle: 2 },
+                Chip8Event::SoundTimerSet { value: 5, cycle: 3 },
+            ]
+        );
+
+        assert!(chip8.emulate_cycle(&input).unwrap().events.is_empty());
+        let output = chip8.emulate_cycle(&input).unwrap();
+        assert_eq!(output.events, [Chip8Event::SoundTimerSet { value: 1, cycle: 5 }]);
+    }
+
+    #[test]
+    fn test_events_are_bounded_without_outputs() {
+        // LD ST, V0; JP 0x200
+        let mut chip8 = create_chip8_with_program(&[0xF018, 0x1200]);
+        let input = MockInput::default();
+
+        for _ in 0..10_000 {
+            chip8.step(&input).unwrap();
+        }
+        assert!(chip8.outcome.events.len() <= MAX_PENDING_EVENTS);
+
+        let output = chip8.emulate_cycle(&input).unwrap();
+        assert_eq!(
+            output.events.last(),
+            Some(&Chip8Event::SoundTimerSet {
+                value: 0,
+                cycle: 10_001
+            })
+        );
+    }
+
+    #[test]
+    fn test_sound_callback() {
+        let (timer_tx, timer_rx) = mpsc::channel();
+        let mut chip8 = Chip8::new(
+            Graphics::new(),
+            timer_rx,
+            Quirks::default(),
+            DebugOptions::default(),
+        );
+        // LD V0, 0x06; LD ST, V0; LD ST, V0; JP 0x206
+        chip8
+            .load_rom(&vec![0x60, 0x06, 0xF0, 0x18, 0xF0, 0x18, 0x12, 0x06])
+            .unwrap();
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        chip8.register_sound_callback({
+            let events = events.clone();
+            move |event| events.borrow_mut().push(event)
+        });
+
+        let input = MockInput::default();
+        for _ in 0..3 {
+            chip8.emulate_cycle(&input).unwrap();
+        }
+        assert_eq!(*events.borrow(), [SoundEvent::On(0.1)]);
+
+        timer_tx.send(TimerOperation::Decrement(6)).unwrap();
+        chip8.emulate_cycle(&input).unwrap();
+        assert_eq!(*events.borrow(), [SoundEvent::On(0.1), SoundEvent::Off]);
+    }
+
+    #[test]
+    fn test_sound_callback_load_state() {
+        let mut chip8 = create_chip8(0x0000);
+        chip8.sound_timer = 30;
+        let buzzing = chip8.save_state();
+        chip8.sound_timer = 0;
+        let silent = chip8.save_state();
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        chip8.register_sound_callback({
+            let events = events.clone();
+            move |event| events.borrow_mut().push(event)
+        });
+
+        chip8.load_state(buzzing).unwrap();
+        assert_eq!(*events.borrow(), [SoundEvent::On(0.5)]);
+        chip8.load_state(silent).unwrap();
+        assert_eq!(*events.borrow(), [SoundEvent::On(0.5), SoundEvent::Off]);
+    }
+
+    #[test]
+    fn test_statistics_timer_decrements() {
+        let (timer_tx, timer_rx) = mpsc::channel();
+        let mut chip8 = Chip8::new(
+            Graphics::new(),
+            timer_rx,
+            Quirks::default(),
+            DebugOptions::default(),
+        );
+        chip8.load_rom(&vec![0x12, 0x00]).unwrap();
+
+        timer_tx.send(TimerOperation::Decrement(1)).unwrap();
+        timer_tx.send(TimerOperation::Decrement(2)).unwrap();
+        chip8.emulate_cycle(&MockInput::default()).unwrap();
+
+        assert_eq!(chip8.statistics().timer_decrements, 3);
+    }
+
+    #[test]
+    fn test_drain_timer_queue() {
+        let (timer_tx, timer_rx) = mpsc::channel();
+        let mut chip8 = Chip8::new(
+            Graphics::new(),
+            timer_rx,
+            Quirks::default(),
+            DebugOptions::default(),
+        );
+        chip8.delay_timer = 10;
+
+        for _ in 0..3 {
+            timer_tx.send(TimerOperation::Decrement(1)).unwrap();
+        }
+        chip8.drain_timer_queue();
+
+        assert_eq!(chip8.delay_timer, 7);
+        assert_eq!(chip8.statistics().total_cycles, 0);
+    }
+
+    #[test]
+    fn test_timer_ticks_sent_before_disconnect() {
+        let (timer_tx, timer_rx) = mpsc::channel();
+        let mut chip8 = Chip8::new(
+            Graphics::new(),
+            timer_rx,
+            Quirks::default(),
+            DebugOptions::default(),
+        );
+        chip8.load_rom(&vec![0x12, 0x00]).unwrap();
+        chip8.delay_timer = 10;
+
+        timer_tx.send(TimerOperation::Decrement(4)).unwrap();
+        drop(timer_tx);
+        chip8.emulate_cycle(&MockInput::default()).unwrap();
+        chip8.emulate_cycle(&MockInput::default()).unwrap();
+
+        assert_eq!(chip8.delay_timer, 6);
+        assert!(!chip8.timer_connected);
+    }
+
+    /// Reads the delay timer into V1 on every cycle, with 10 ticks queued up front.
+    fn delay_timer_countdown(quirks: Quirks) -> (Vec<u8>, Chip8<Graphics>) {
+        let (timer_tx, timer_rx) = mpsc::channel();
+        let mut chip8 = Chip8::new(Graphics::new(), timer_rx, quirks, DebugOptions::default());
+        let rom: Vec<u8> = [0xF1, 0x07].repeat(10);
+        chip8.load_rom(&rom).unwrap();
+        chip8.delay_timer = 10;
+
+        for _ in 0..10 {
+            timer_tx.send(TimerOperation::Decrement(1)).unwrap();
+        }
+
+        let input = MockInput::default();
+        let observed = (0..10)
+            .map(|_| {
+                chip8.emulate_cycle(&input).unwrap();
+                chip8.registers[1]
+            })
+            .collect();
+        (observed, chip8)
+    }
+
+    #[test]
+    fn test_timer_ticks_capped_per_cycle() {
+        let quirks = QuirksBuilder::default()
+            .ticks_per_cycle_cap(Some(1))
+            .build()
+            .unwrap();
+        let (observed, chip8) = delay_timer_countdown(quirks);
+
+        assert_eq!(observed, [10, 9, 8, 7, 6, 5, 4, 3, 2, 1]);
+        assert_eq!(chip8.delay_timer, 0);
+        assert_eq!(chip8.get_owed_timer_ticks(), 0);
+    }
+
+    #[test]
+    fn test_timer_ticks_owed_under_cap() {
+        let quirks = QuirksBuilder::default()
+            .ticks_per_cycle_cap(Some(2))
+            .build()
+            .unwrap();
+        let (timer_tx, timer_rx) = mpsc::channel();
+        let mut chip8 = Chip8::new(Graphics::new(), timer_rx, quirks, DebugOptions::default());
+        chip8.load_rom(&vec![0x12, 0x00]).unwrap();
+        chip8.delay_timer = 20;
+
+        timer_tx.send(TimerOperation::Decrement(5)).unwrap();
+        chip8.emulate_cycle(&MockInput::default()).unwrap();
+
+        assert_eq!(chip8.delay_timer, 18);
+        assert_eq!(chip8.get_owed_timer_ticks(), 3);
+    }
+
+    #[test]
+    fn test_timer_ticks_uncapped() {
+        let (observed, chip8) = delay_timer_countdown(Quirks::default());
+
+        assert_eq!(observed, [10, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(chip8.get_owed_timer_ticks(), 0);
+    }
+
+    #[test]
+    fn test_validate_zero_tick_cap() {
+        let quirks = QuirksBuilder::default()
+            .ticks_per_cycle_cap(Some(0))
+            .build()
+            .unwrap();
+
+        let error = quirks.validate().unwrap_err();
+        assert_eq!(error.conflicting[0].0, "ticks_per_cycle_cap");
+    }
+
+    #[test]
+    fn test_reset_statistics() {
+        // CALL 0x202; LD V0, 0x05
+        let mut chip8 = create_chip8_with_program(&[0x2202, 0x6005]);
+        let input = MockInput::default();
+        chip8.emulate_cycle(&input).unwrap();
+
+        chip8.reset_statistics();
+        assert_eq!(chip8.statistics(), Default::default());
+        assert_eq!(chip8.sp(), 1);
+        assert_eq!(chip8.pc, 0x202);
+
+        chip8.emulate_cycle(&input).unwrap();
+        assert_eq!(chip8.statistics().total_cycles, 1);
+        assert_eq!(chip8.statistics().cycles_in(OpcodeCategory::Arithmetic), 1);
+        assert_eq!(chip8.cycles_executed(), 2);
+        assert_eq!(chip8.registers[0], 5);
+    }
+
+    #[test]
+    fn test_validate_default_quirks() {
+        assert_eq!(Quirks::default().validate(), Ok(()));
+
+        let quirks = QuirksBuilder::default()
+            .legacy_opcodes(VIP_LEGACY_OPCODES.to_vec())
+            .build()
+            .unwrap();
+        assert_eq!(quirks.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_legacy_opcode_shadowed_by_standard_instruction() {
+        let quirks = QuirksBuilder::default()
+            .legacy_opcodes(vec![(0x00E0, LegacyOpcode::Nop), (0x1234, LegacyOpcode::Nop)])
+            .build()
+            .unwrap();
+
+        let error = quirks.validate().unwrap_err();
+        assert_eq!(error.conflicting.len(), 2);
+        assert_eq!(
+            error.conflicting[0],
+            (
+                "legacy_opcodes",
+                "instruction set",
+                "`0x00e0` is a standard instruction, so it is never treated as Nop".to_string()
+            )
+        );
+        assert_eq!(
+            error.conflicting[1].2,
+            "`0x1234` is a standard instruction, so it is never treated as Nop"
+        );
+    }
+
+    #[test]
+    fn test_validate_legacy_opcode_mapped_twice() {
+        let quirks = QuirksBuilder::default()
+            .legacy_opcodes(vec![
+                (0x00E1, LegacyOpcode::DisplayOff),
+                (0x00E1, LegacyOpcode::DisplayOff),
+                (0x00E1, LegacyOpcode::Nop),
+            ])
+            .build()
+            .unwrap();
+
+        let error = quirks.validate().unwrap_err();
+        assert_eq!(
+            error.conflicting,
+            [(
+                "legacy_opcodes",
+                "legacy_opcodes",
+                "`0x00e1` is mapped to both DisplayOff and Nop".to_string()
+            )]
+        );
+        assert_eq!(
+            error.to_string(),
+            "`legacy_opcodes` conflicts with `legacy_opcodes`: `0x00e1` is mapped to both DisplayOff and Nop"
+        );
+    }
+
+    #[test]
+    fn test_validate_legacy_opcode_shadowed_by_extended_scrolling() {
+        let quirks = QuirksBuilder::default()
+            .extended_scrolling(true)
+            .legacy_opcodes(vec![(0x00C1, LegacyOpcode::Nop)])
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            quirks.validate().unwrap_err().to_string(),
+            "`legacy_opcodes` conflicts with `extended_scrolling`: `0x00c1` scrolls the screen, so it \
+             is never treated as Nop"
+        );
+    }
+
+    #[test]
+    fn test_builder_rejects_invalid_quirks() {
+        let quirks = QuirksBuilder::default()
+            .legacy_opcodes(vec![(0x00EE, LegacyOpcode::Nop)])
+            .build()
+            .unwrap();
+        let (_, timer_rx) = mpsc::channel();
+
+        let result = Chip8Builder::new(Graphics::new(), timer_rx)
+            .quirks(quirks)
+            .build();
+        assert!(matches!(result, Err(Chip8Error::InvalidQuirksConfiguration(_))));
+    }
+
+    #[test]
+    fn test_builder() {
+        let quirks = QuirksBuilder::default().use_vx_in_jump(true).build().unwrap();
+        let options = DebugOptions {
+            log_draw_calls: true,
+            ..Default::default()
+        };
+        let (_, timer_rx) = mpsc::channel();
+
+        let chip8 = Chip8Builder::new(Graphics::new(), timer_rx)
+            .quirks(quirks)
+            .debug_options(options)
+            .build()
+            .unwrap();
+        assert!(chip8.quirks.use_vx_in_jump);
+        assert!(chip8.dbg_options.log_draw_calls);
+    }
+
+    #[test]
+    fn test_load_store_behaviors() {
+        let cases = [
+            (LoadStoreBehavior::IncrementByXPlusOne, 0, 0x501),
+            (LoadStoreBehavior::IncrementByXPlusOne, 5, 0x506),
+            (LoadStoreBehavior::IncrementByX, 0, 0x500),
+            (LoadStoreBehavior::IncrementByX, 5, 0x505),
+            (LoadStoreBehavior::Unchanged, 0, 0x500),
+            (LoadStoreBehavior::Unchanged, 5, 0x500),
+        ];
+
+        for (behavior, x, ending_ir) in cases {
+            for opcode in [0xF055, 0xF065] {
+                let quirks = QuirksBuilder::default().increment_ir(behavior).build().unwrap();
+                let mut chip8 = create_chip8_with_quirks(opcode | (x << 8), quirks);
+                chip8.ir = 0x500;
+
+                assert_eq!(chip8.opcode_0xfyyy(), Ok(ProgramCounter::Next));
+                assert_eq!(
+                    chip8.ir, ending_ir,
+                    "{behavior} with opcode {:#06x}",
+                    chip8.opcode
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_load_store_behavior_from_bool() {
+        assert_eq!(
+            LoadStoreBehavior::from(true),
+            LoadStoreBehavior::IncrementByXPlusOne
+        );
+        assert_eq!(LoadStoreBehavior::from(false), LoadStoreBehavior::Unchanged);
+    }
+
+    #[test]
+    fn test_load_store_behavior_from_str() {
+        for behavior in [
+            LoadStoreBehavior::IncrementByXPlusOne,
+            LoadStoreBehavior::IncrementByX,
+            LoadStoreBehavior::Unchanged,
+        ] {
+            assert_eq!(behavior.to_string().parse(), Ok(behavior));
+        }
+        assert_eq!("true".parse(), Ok(LoadStoreBehavior::IncrementByXPlusOne));
+        assert_eq!("false".parse(), Ok(LoadStoreBehavior::Unchanged));
+        assert!("x+2".parse::<LoadStoreBehavior>().is_err());
+    }
+
+    #[test]
+    fn test_key_from_str() {
+        for key in Key::ALL {
+            assert_eq!(key.to_string().parse(), Ok(key));
+            assert_eq!(key.to_string().to_lowercase().parse(), Ok(key));
+        }
+        assert_eq!("b".parse(), Ok(Key::B));
+        assert_eq!(Key::C.to_string(), "C");
+
+        for name in ["G", "10", "", "+1"] {
+            assert!(name.parse::<Key>().is_err(), "{name:?}");
+        }
+    }
+
+    #[test]
+    fn test_key_all() {
+        for (i, key) in Key::ALL.into_iter().enumerate() {
+            assert_eq!(u8::from(key) as usize, i);
+            assert_eq!(Key::try_from(i as u8), Ok(key));
+        }
+    }
+
+    #[test]
+    fn test_draw_past_end_of_memory_is_clamped() {
+        let mut chip8 = create_chip8(0xD00F);
+        chip8.ir = 0xFFC;
+        chip8.memory[0xFFC..].fill(0xFF);
+        chip8.memory[..11].fill(0x81);
+
+        assert_eq!(chip8.opcode_0xdyyy(), Ok(ProgramCounter::Next));
 
-                self.memory[self.ir as usize] = hundreds;
-                self.memory[self.ir as usize + 1] = tens;
-                self.memory[self.ir as usize + 2] = ones;
+        let screen = chip8.graphics.buffer();
+        assert!(screen[..4].iter().all(|row| row[..8] == [1; 8]));
+        assert!(screen[4..].iter().all(|row| row[..8] == [0; 8]));
+    }
 
-                Ok(ProgramCounter::Next)
-            }
+    #[test]
+    fn test_draw_past_end_of_memory_wraps() {
+        let mut chip8 = create_chip8(0xD00F);
+        chip8.quirks.wrap_memory = true;
+        chip8.ir = 0xFFC;
+        chip8.memory[0xFFC..].fill(0xFF);
+        chip8.memory[..11].fill(0x81);
+
+        assert_eq!(chip8.opcode_0xdyyy(), Ok(ProgramCounter::Next));
+
+        // The last 11 rows come from the start of memory
+        let mut expected = Graphics::new();
+        expected.draw_sprite(Coord::new(0, 0), &[0xFF; 4], true);
+        expected.draw_sprite(Coord::new(0, 4), &[0x81; 11], true);
+        assert_eq!(chip8.graphics.buffer(), expected.buffer());
+        assert_eq!(chip8.read_high_water, 0xFFF);
+    }
 
-            // Fx55 - LD [I], Vx
-            // Store registers V0 through Vx in memory starting at location I.
-            // The interpreter copies the values of registers V0 through Vx into memory, starting at the address in I.
-            0x55 => {
-                let (x, _) = self.get_regs_x_y();
-                let mut addr = self.ir;
+    #[test]
+    fn test_fetch_past_end_of_memory() {
+        let mut chip8 = create_chip8(0x0000);
+        chip8.pc = 0x1000;
+
+        assert_eq!(
+            chip8.emulate_cycle(&MockInput::default()).err(),
+            Some(Chip8Error::InvalidMemoryAccess(0x1000))
+        );
+    }
 
-                for i in 0..=x {
-                    self.memory[addr as usize] = self.registers[i];
-                    addr += REG_SIZE;
+    #[test]
+    fn test_bcd_past_end_of_memory() {
+        let mut chip8 = create_chip8(0xF033);
+        chip8.ir = 0xFFE;
+        chip8.registers[0] = 123;
+
+        assert_eq!(
+            chip8.opcode_0xfyyy(),
+            Err(Chip8Error::InvalidMemoryAccess(0x1000))
+        );
+        assert_eq!(chip8.memory[0xFFE..], [1, 2]);
+    }
 
-                    increment_ir!();
-                }
+    #[test]
+    fn test_load_store_past_end_of_memory() {
+        let mut chip8 = create_chip8(0xF155);
+        chip8.ir = 0xFFF;
+        assert_eq!(
+            chip8.opcode_0xfyyy(),
+            Err(Chip8Error::InvalidMemoryAccess(0x1000))
+        );
+
+        let mut chip8 = create_chip8(0xF165);
+        chip8.ir = 0xFFF;
+        assert_eq!(
+            chip8.opcode_0xfyyy(),
+            Err(Chip8Error::InvalidMemoryAccess(0x1000))
+        );
+    }
 
-                Ok(ProgramCounter::Next)
-            }
+    #[test]
+    fn test_load_store_past_end_of_memory_wraps() {
+        let mut chip8 = create_chip8(0xF155);
+        chip8.quirks.wrap_memory = true;
+        chip8.ir = 0xFFF;
+        chip8.registers[..2].copy_from_slice(&[0xAB, 0xCD]);
+
+        assert_eq!(chip8.opcode_0xfyyy(), Ok(ProgramCounter::Next));
+        assert_eq!((chip8.memory[0xFFF], chip8.memory[0]), (0xAB, 0xCD));
+    }
 
-            // Fx65 - LD Vx, [I]
-            // Read registers V0 through Vx from memory starting at location I.
-            // The interpreter reads values from memory starting at location I into registers V0 through Vx.
-            0x65 => {
-                let (x, _) = self.get_regs_x_y();
-                let mut addr = self.ir;
+    #[test]
+    fn test_call_graph() {
+        // CALL 0x206; JP 0x202; -; CALL 0x20A; RET; RET
+        let mut chip8 = create_chip8_with_program(&[0x2206, 0x1202, 0x0000, 0x220A, 0x00EE, 0x00EE]);
+        chip8.dbg_options.track_call_graph = true;
+        let input = MockInput::default();
+
+        for _ in 0..5 {
+            chip8.emulate_cycle(&input).unwrap();
+        }
 
-                for i in 0..=x {
-                    self.registers[i] = self.memory[addr as usize];
-                    addr += REG_SIZE;
+        assert_eq!(
+            chip8.export_call_graph_dot(),
+            "digraph calls {\n    \"0x200\";\n    \"0x206\";\n    \"0x20a\";\n    \
+             \"0x200\" -> \"0x206\";\n    \"0x206\" -> \"0x20a\";\n}\n"
+        );
+        assert_eq!(chip8.call_frames, [0x200]);
+    }
 
-                    increment_ir!();
-                }
+    #[test]
+    fn test_call_graph_off() {
+        let mut chip8 = create_chip8_with_program(&[0x2202, 0x00EE]);
+        chip8.emulate_cycle(&MockInput::default()).unwrap();
 
-                Ok(ProgramCounter::Next)
-            }
+        assert_eq!(chip8.export_call_graph_dot(), "digraph calls {\n}\n");
+    }
 
-            _ => self.unknown_opcode(),
+    #[test]
+    fn test_draw_call_log() {
+        // LD V0, 3; LD V1, 4; LD I, 0x050;
+        // DRW V0, V1, 5; DRW V0, V1, 5; LD V0, 60; DRW V0, V1, 2
+        let program = [0x6003, 0x6104, 0xA050, 0xD015, 0xD015, 0x603C, 0xD012];
+        let mut chip8 = create_chip8_with_program(&program);
+        chip8.dbg_options.log_draw_calls = true;
+        let input = MockInput::default();
+
+        for _ in 0..program.len() {
+            chip8.emulate_cycle(&input).unwrap();
         }
+
+        let log = chip8.get_draw_call_log();
+        assert_eq!(log.len(), 3);
+        assert_eq!((log[0].cycle, log[0].x, log[0].y, log[0].num_rows), (4, 3, 4, 5));
+        assert!(!log[0].flipped);
+        assert_eq!((log[1].cycle, log[1].x, log[1].y), (5, 3, 4));
+        assert!(log[1].flipped);
+        assert_eq!((log[2].cycle, log[2].x, log[2].y, log[2].num_rows), (7, 60, 4, 2));
+        assert_eq!(log[2].ir, 0x050);
+
+        chip8.clear_draw_call_log();
+        assert!(chip8.get_draw_call_log().is_empty());
     }
 
-    // 0xFX0A requires special handling. It has to wait for the key
-    // to be released before registering the key pressed. It also
-    // needs to halt the whole emulator, except for timers.
-    // Timers need to continue to decrement.
-    fn check_and_process_0xfx0a(&mut self, input: &impl Input) -> OpcodeResult {
-        if self.wait_for_key_state != WaitForKeyState::None {
-            match self.wait_for_key_state {
-                WaitForKeyState::WaitForNoKeyPressed => {
-                    let mut key_pressed = false;
-                    for i in 0..=Key::F as u8 {
-                        if input.is_pressed(i.try_into()?) {
-                            key_pressed = true;
-                        }
-                    }
-                    if !key_pressed {
-                        self.wait_for_key_state = WaitForKeyState::CheckForKeyPressed;
-                    }
-                    Ok(ProgramCounter::Pause)
-                }
-                WaitForKeyState::CheckForKeyPressed => {
-                    for i in 0..=Key::F as u8 {
-                        if input.is_pressed(i.try_into()?) {
-                            self.registers[self.wait_for_keypress_register as usize] = i;
-                            self.wait_for_key_state = WaitForKeyState::WaitForKeyRelease;
-                            break;
-                        }
-                    }
-                    Ok(ProgramCounter::Pause)
-                }
-                WaitForKeyState::WaitForKeyRelease => {
-                    let mut key_pressed = false;
-                    for i in 0..=Key::F as u8 {
-                        if input.is_pressed(i.try_into()?) {
-                            key_pressed = true;
-                            break;
-                        }
-                    }
+    #[test]
+    fn test_draw_call_log_disabled_by_default() {
+        let mut chip8 = create_chip8_with_program(&[0xD015]);
+        chip8.emulate_cycle(&MockInput::default()).unwrap();
 
-                    if !key_pressed {
-                        self.wait_for_key_state = WaitForKeyState::None;
-                        Ok(ProgramCounter::Next)
-                    } else {
-                        Ok(ProgramCounter::Pause)
-                    }
-                }
-                WaitForKeyState::None => Ok(ProgramCounter::Next),
-            }
-        } else {
-            Ok(ProgramCounter::None)
-        }
+        assert!(chip8.get_draw_call_log().is_empty());
     }
 
-    fn dump_graphics(&self) {
-        let screen = self.graphics.buffer();
+    #[test]
+    fn test_snapshot_graphics() {
+        // LD I, 0x050; DRW V0, V0, 5
+        let mut chip8 = create_chip8_with_program(&[0xA050, 0xD005]);
+        let input = MockInput::default();
+        chip8.emulate_cycle(&input).unwrap();
 
-        for row in screen {
-            for pixel in row {
-                print!("{} ", pixel);
-            }
+        let before = chip8.snapshot_graphics();
+        assert!(compare_graphics_snapshot(&before, &chip8.snapshot_graphics()));
 
-            println!();
-        }
-    }
-}
+        chip8.emulate_cycle(&input).unwrap();
+        let after = chip8.snapshot_graphics();
 
-#[cfg(test)]
-mod tests {
-    use std::sync::mpsc;
+        assert!(!compare_graphics_snapshot(&before, &after));
+        assert_eq!(&after, chip8.graphics().buffer());
+    }
 
-    use crate::graphics::Graphics;
-    use crate::traits::GraphicsBuffer;
-    use crate::{DebugOptions, Quirks, QuirksBuilder};
+    #[test]
+    fn test_timer_remaining_ms() {
+        let mut chip8 = create_chip8(0x0000);
+        chip8.delay_timer = 60;
+        chip8.sound_timer = 30;
+
+        let (cpu, timer) = (chip8.cpu_frequency(), chip8.timer_frequency());
+        assert_eq!(chip8.delay_timer_remaining_ms(cpu, timer), 1000.0);
+        assert_eq!(chip8.sound_timer_remaining_ms(cpu, timer), 500.0);
+        assert_eq!(chip8.delay_timer_remaining_ms(cpu, 120.0), 500.0);
+    }
 
-    use super::FLAG_REGISTER;
-    use super::{Chip8, ProgramCounter};
+    #[test]
+    fn test_builder_frequencies() {
+        let (_, timer_rx) = mpsc::channel();
+        let chip8 = Chip8Builder::new(Graphics::new(), timer_rx)
+            .cpu_frequency(500.0)
+            .timer_frequency(50.0)
+            .build()
+            .unwrap();
+
+        assert_eq!(chip8.cpu_frequency(), 500.0);
+        assert_eq!(chip8.timer_frequency(), 50.0);
+    }
 
-    fn create_chip8(opcode: u16) -> Chip8<Graphics> {
-        let graphics = Graphics::new();
+    #[test]
+    fn test_builder_load_address() {
         let (_, timer_rx) = mpsc::channel();
-        let mut chip8 = Chip8::new(graphics, timer_rx, Quirks::default(), DebugOptions::default());
-        chip8.opcode = opcode;
-        chip8
+        let mut chip8 = Chip8Builder::new(Graphics::new(), timer_rx)
+            .entry_point(0x600)
+            .load_address(0x600)
+            .build()
+            .unwrap();
+        // LD V0, 0x42
+        chip8.load_rom(&vec![0x60, 0x42]).unwrap();
+
+        assert_eq!(chip8.memory[0x200..0x202], [0, 0]);
+        chip8.emulate_cycle(&MockInput::default()).unwrap();
+        assert_eq!(chip8.registers[0], 0x42);
+        assert_eq!(chip8.pc, 0x602);
+
+        let result = chip8.load_rom(&vec![0; MEMORY_SIZE - 0x600 + 1]);
+        assert_eq!(result, Err(Chip8Error::RomTooBig(MEMORY_SIZE as u16)));
     }
 
-    fn create_chip8_with_quirks(opcode: u16, quirks: Quirks) -> Chip8<Graphics> {
-        let graphics = Graphics::new();
+    #[test]
+    fn test_font_sprite_address() {
+        let build = |font_base| {
+            let (_, timer_rx) = mpsc::channel();
+            Chip8Builder::new(Graphics::new(), timer_rx)
+                .font_base(font_base)
+                .build()
+                .unwrap()
+        };
+
+        for font_base in [FONT_BASE, 0x000, 0x1B0] {
+            let mut chip8 = build(font_base);
+            assert_eq!(chip8.font_base(), font_base);
+
+            for digit in 0..16 {
+                chip8.opcode = 0xF029;
+                chip8.registers[0] = digit;
+                assert_eq!(chip8.opcode_0xfyyy(), Ok(ProgramCounter::Next));
+
+                let sprite = chip8.ir as usize..chip8.ir as usize + 5;
+                assert!(sprite.start >= font_base as usize);
+                assert!(sprite.end <= font_base as usize + HEX_DIGITS.len());
+                assert_eq!(chip8.memory[sprite], HEX_DIGITS[digit as usize * 5..][..5]);
+            }
+        }
+    }
 
+    #[test]
+    fn test_builder_moves_font() {
         let (_, timer_rx) = mpsc::channel();
-        let mut chip8 = Chip8::new(graphics, timer_rx, quirks, DebugOptions::default());
-        chip8.opcode = opcode;
-        chip8
+        let chip8 = Chip8Builder::new(Graphics::new(), timer_rx)
+            .font_base(0x000)
+            .build()
+            .unwrap();
+
+        assert_eq!(chip8.memory[..HEX_DIGITS.len()], HEX_DIGITS);
+        assert!(chip8.memory[HEX_DIGITS.len()..APP_LOCATION as usize]
+            .iter()
+            .all(|&byte| byte == 0));
     }
 
     #[test]
-    fn test_0x00e0() {
-        let mut chip8 = create_chip8(0x00e0);
-        // Draw the first sprite digit - digits are loaded starting at 0x0 and are all 5 bytes tall
+    fn test_builder_rejects_font_in_program_space() {
+        let (_, timer_rx) = mpsc::channel();
+        let result = Chip8Builder::new(Graphics::new(), timer_rx)
+            .font_base(0x1B1)
+            .build();
+
+        assert_eq!(result.err(), Some(Chip8Error::InvalidFontAddress(0x1B1)));
+    }
+
+    #[test]
+    fn test_opcode_overrides() {
+        let overrides = OpcodeOverrides::parse(
+            "[opcodes]\n\
+             0x0100 = nop\n\
+             0x0200 = halt\n\
+             0xF0FF = break\n\
+             0x8xyF = alias=0x8xy4\n",
+        )
+        .unwrap();
+        let (_, timer_rx) = mpsc::channel();
+        let mut chip8 = Chip8Builder::new(Graphics::new(), timer_rx)
+            .opcode_overrides(overrides)
+            .build()
+            .unwrap();
+        // LD V0, 2; nop; ADD V0, V1 as an alias; break; halt
         chip8
-            .graphics
-            .draw(0, 0, 5, 0, &chip8.memory, chip8.quirks.clipping);
+            .load_rom(&vec![0x60, 0x02, 0x01, 0x00, 0x80, 0x1F, 0xF0, 0xFF, 0x02, 0x00])
+            .unwrap();
+        chip8.registers[1] = 3;
+        let input = MockInput::default();
+
+        chip8.emulate_cycle(&input).unwrap();
+        chip8.emulate_cycle(&input).unwrap();
+        assert_eq!(chip8.pc, 0x204);
+        chip8.emulate_cycle(&input).unwrap();
+        assert_eq!(chip8.registers[0], 5);
+
+        assert_eq!(
+            chip8.emulate_cycle(&input).err(),
+            Some(Chip8Error::BreakpointHit(0x206))
+        );
+        assert_eq!(chip8.pc, 0x208);
+        assert_eq!(chip8.emulate_cycle(&input).err(), Some(Chip8Error::Halted(0x208)));
+        assert_eq!(chip8.emulate_cycle(&input).err(), Some(Chip8Error::Halted(0x208)));
+    }
 
-        let pc_op = chip8.opcode_0x0yyy();
+    #[test]
+    fn test_builder_validates_opcode_overrides() {
+        let build = |allow_core_override| {
+            let overrides = OpcodeOverrides::parse("[opcodes]\n0x00E0 = nop").unwrap();
+            let (_, timer_rx) = mpsc::channel();
+            Chip8Builder::new(Graphics::new(), timer_rx)
+                .opcode_overrides(overrides)
+                .allow_core_override(allow_core_override)
+                .build()
+                .err()
+        };
+
+        assert!(matches!(
+            build(false),
+            Some(Chip8Error::InvalidOpcodeOverrides(
+                OverrideError::ShadowsCoreOpcode { opcode: 0x00E0, .. }
+            ))
+        ));
+        assert_eq!(build(true), None);
+    }
 
-        assert_eq!(pc_op, Ok(ProgramCounter::Next));
+    #[test]
+    fn test_builder_rejects_reserved_addresses() {
+        let build = |entry_point, load_address| {
+            let (_, timer_rx) = mpsc::channel();
+            Chip8Builder::new(Graphics::new(), timer_rx)
+                .entry_point(entry_point)
+                .load_address(load_address)
+                .build()
+                .err()
+        };
+
+        assert_eq!(
+            build(0x200, 0x1FF),
+            Some(Chip8Error::InvalidProgramAddress(0x1FF))
+        );
+        assert_eq!(
+            build(0x050, 0x200),
+            Some(Chip8Error::InvalidProgramAddress(0x050))
+        );
+        assert_eq!(
+            build(0x200, 0x1000),
+            Some(Chip8Error::InvalidProgramAddress(0x1000))
+        );
+        assert_eq!(build(0x2C0, 0x200), None);
+    }
 
-        let screen = chip8.graphics.buffer();
+    #[test]
+    fn test_is_display_blank() {
+        // LD I, 0x050; DRW V0, V0, 5; CLS
+        let mut chip8 = create_chip8_with_program(&[0xA050, 0xD005, 0x00E0]);
+        let input = MockInput::default();
+        assert!(chip8.is_display_blank());
+
+        chip8.emulate_cycle(&input).unwrap();
+        chip8.emulate_cycle(&input).unwrap();
+        assert!(!chip8.is_display_blank());
+        assert!(!chip8.is_display_full());
+
+        chip8.emulate_cycle(&input).unwrap();
+        assert!(chip8.is_display_blank());
+    }
 
-        for i in screen {
-            for j in i {
-                assert_eq!(*j, 0);
+    #[test]
+    fn test_is_display_full() {
+        let mut chip8 = create_chip8(0x0000);
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                chip8.graphics.set_pixel(Coord::new(x, y), 1);
             }
         }
+
+        assert!(chip8.is_display_full());
+        assert!(!chip8.is_display_blank());
     }
 
     #[test]
-    fn test_regs_x_y() {
-        let chip8 = create_chip8(0x0FA0);
+    fn test_wait_for_vsync_without_vsync_returns_immediately() {
+        let mut display = NullDisplay::new();
+        let start = Instant::now();
 
-        let (x, y) = chip8.get_regs_x_y();
-        assert_eq!(x, 0xF);
-        assert_eq!(y, 0xA);
+        assert!(wait_for_vsync(&mut display, Duration::from_secs(10)));
+        assert!(start.elapsed() < Duration::from_secs(1));
     }
 
     #[test]
-    fn test_bcd() {
-        let mut chip8 = create_chip8(0xF133);
-        let (x, _) = chip8.get_regs_x_y();
-
-        chip8.registers[x] = 123;
-        chip8.ir = 0x500;
+    fn test_wait_for_vsync_times_out() {
+        let mut display = NullDisplay::new();
+        display.vsync_ready = false;
 
-        let result = chip8.opcode_0xfyyy();
+        assert!(!wait_for_vsync(&mut display, Duration::from_millis(5)));
+    }
 
-        assert_eq!(result, Ok(ProgramCounter::Next));
-        assert_eq!(chip8.memory[chip8.ir as usize], 1);
-        assert_eq!(chip8.memory[chip8.ir as usize + 1], 2);
-        assert_eq!(chip8.memory[chip8.ir as usize + 2], 3);
+    #[test]
+    fn test_present_skips_cycles_without_draw() {
+        // LD V0, 0x00; CLS; LD V0, 0x00
+        let mut chip8 = create_chip8_with_program(&[0x6000, 0x00E0, 0x6000]);
+        let input = MockInput::default();
+        let mut display = NullDisplay::new();
+
+        let drawn: Vec<_> = (0..3)
+            .map(|_| chip8.emulate_cycle(&input).unwrap().present(&mut display))
+            .collect();
+
+        assert_eq!(drawn, [false, true, false]);
+        assert_eq!(display.draws, 1);
     }
 
-    fn test_copy_to_mem_impl(quirks: Quirks, starting_ir: usize, ending_ir: u16) {
-        let mut chip8 = create_chip8_with_quirks(0xF555, quirks);
+    #[test]
+    fn test_frame_snapshot() {
+        // LD I, 0x050 (the font's 0); LD V0, 0x3C; DRW V0, V0, 5; LD ST, V0
+        let mut chip8 = create_chip8_with_program(&[0xA050, 0x603C, 0xD005, 0xF018]);
+        let input = MockInput::default();
+        for _ in 0..2 {
+            chip8.emulate_cycle(&input).unwrap();
+        }
 
-        for i in 0..=5 {
-            chip8.registers[i] = (i + 1) as u8;
+        let output = chip8.emulate_cycle(&input).unwrap();
+        let snapshot = output.to_owned_snapshot();
+        assert_eq!(&snapshot.buffer(), output.graphics.buffer());
+        assert!(snapshot.draw_on_screen);
+        // Drawn at (60, 28) once wrapped, and clipped at the right edge
+        assert_eq!(snapshot.pixel(Coord::new(60, 28)), 1);
+        assert_eq!(snapshot.pixel(Coord::new(59, 28)), 0);
+        assert_eq!(snapshot.rows[28], 0xF);
+
+        let snapshot = chip8.emulate_cycle_snapshot(&input).unwrap();
+        assert_eq!(
+            snapshot.events,
+            [Chip8Event::SoundTimerSet {
+                value: 0x3C,
+                cycle: 4
+            }]
+        );
+        assert!(snapshot.sound_on);
+        assert!(!snapshot.draw_on_screen);
+    }
+
+    #[test]
+    fn test_frame_snapshot_matches_emulation() {
+        // LD V0, 0x05; LD F, V0; DRW V0, V0, 5; ADD V0, 0x07; JP 0x204
+        let program = [0x6005, 0xF029, 0xD005, 0x7007, 0x1204];
+        let mut borrowed = create_chip8_with_program(&program);
+        let mut owned = create_chip8_with_program(&program);
+        let input = MockInput::default();
+
+        for _ in 0..40 {
+            let expected = borrowed.emulate_cycle(&input).unwrap().to_owned_snapshot();
+            assert_eq!(owned.emulate_cycle_snapshot(&input).unwrap(), expected);
         }
+        assert_eq!(owned.state(), borrowed.state());
+    }
 
-        chip8.ir = starting_ir as u16;
+    #[test]
+    fn test_import_memory_from_reader() {
+        let mut chip8 = create_chip8(0x0000);
+        let data: Vec<u8> = (0..100).collect();
+
+        let result = chip8.import_memory_from_reader(Cursor::new(data.clone()), 0x300, 100);
+        assert_eq!(result, Ok(100));
+        assert_eq!(chip8.memory[0x300..0x364], data[..]);
+        assert_eq!(chip8.memory[0x364], 0);
+    }
 
-        let result = chip8.opcode_0xfyyy();
+    #[test]
+    fn test_import_memory_from_reader_short_input() {
+        let mut chip8 = create_chip8(0x0000);
 
-        assert_eq!(result, Ok(ProgramCounter::Next));
-        assert_eq!(chip8.memory[starting_ir], 1);
-        assert_eq!(chip8.memory[starting_ir + 1], 2);
-        assert_eq!(chip8.memory[starting_ir + 2], 3);
-        assert_eq!(chip8.memory[starting_ir + 3], 4);
-        assert_eq!(chip8.memory[starting_ir + 4], 5);
-        assert_eq!(chip8.memory[starting_ir + 5], 6);
-        assert_eq!(chip8.ir, ending_ir);
+        let result = chip8.import_memory_from_reader(Cursor::new(vec![1, 2, 3]), 0x300, 10);
+        assert_eq!(result, Ok(3));
+        assert_eq!(chip8.memory[0x300..0x304], [1, 2, 3, 0]);
     }
 
     #[test]
-    fn test_copy_to_mem() {
-        let quirks = QuirksBuilder::default().increment_ir(true).build().unwrap();
-        test_copy_to_mem_impl(quirks, 0x500, 0x506);
+    fn test_import_memory_from_reader_out_of_bounds() {
+        let mut chip8 = create_chip8(0x0000);
+
+        let result = chip8.import_memory_from_reader(Cursor::new(vec![1; 16]), 0xFF8, 16);
+        assert_eq!(result, Err(Chip8Error::InvalidMemoryAccess(0x1008)));
     }
 
     #[test]
-    fn test_copy_to_mem_no_increment_ir() {
-        let quirks = QuirksBuilder::default().increment_ir(false).build().unwrap();
-        test_copy_to_mem_impl(quirks, 0x500, 0x500);
+    fn test_patch_memory_region() {
+        let mut chip8 = create_chip8(0x0000);
+        chip8.memory[0x500..0x504].copy_from_slice(&[0x00, 0x0F, 0xF0, 0xFF]);
+
+        let result = chip8.patch_memory_region(0x500, 0x504, |b| b ^ 0xFF);
+        assert_eq!(result, Ok(4));
+        assert_eq!(chip8.memory[0x500..0x504], [0xFF, 0xF0, 0x0F, 0x00]);
+        assert_eq!(chip8.memory[0x504], 0);
+
+        let result = chip8.patch_memory_region(0x500, 0x504, |b| b ^ 0xFF);
+        assert_eq!(result, Ok(4));
+        assert_eq!(chip8.memory[0x500..0x504], [0x00, 0x0F, 0xF0, 0xFF]);
     }
 
-    fn test_copy_from_mem_impl(quirks: Quirks, starting_ir: u16, ending_ir: u16) {
-        let mut chip8 = create_chip8_with_quirks(0xF565, quirks);
+    #[test]
+    fn test_patch_memory_region_counts_changed_bytes() {
+        let mut chip8 = create_chip8(0x0000);
+        chip8.memory[0x500..0x504].copy_from_slice(&[0x00, 0x01, 0x00, 0x01]);
 
-        chip8.ir = starting_ir;
+        let result = chip8.patch_memory_region(0x500, 0x504, |_| 0x01);
+        assert_eq!(result, Ok(2));
+    }
 
-        for i in 0..=5 {
-            chip8.memory[chip8.ir as usize + i] = (i + 1) as u8;
-        }
+    #[test]
+    fn test_patch_memory_region_out_of_bounds() {
+        let mut chip8 = create_chip8(0x0000);
 
-        let result = chip8.opcode_0xfyyy();
+        let result = chip8.patch_memory_region(0xFFE, 0x1001, |b| b);
+        assert_eq!(result, Err(Chip8Error::InvalidMemoryAccess(0x1001)));
+        assert_eq!(chip8.patch_memory_region(0xFFE, 0x1000, |b| b), Ok(0));
+    }
 
-        assert_eq!(result, Ok(ProgramCounter::Next));
+    #[test]
+    fn test_write_opcode_at() {
+        let mut chip8 = create_chip8(0x0000);
 
-        assert_eq!(chip8.registers[0], 1);
-        assert_eq!(chip8.registers[1], 2);
-        assert_eq!(chip8.registers[2], 3);
-        assert_eq!(chip8.registers[3], 4);
-        assert_eq!(chip8.registers[4], 5);
-        assert_eq!(chip8.registers[5], 6);
-        assert_eq!(chip8.ir, ending_ir);
+        assert_eq!(chip8.write_opcode_at(0x200, 0xA123), Ok(()));
+        assert_eq!(chip8.memory[0x200..0x202], [0xA1, 0x23]);
+        assert_eq!(chip8.read_opcode_at(0x200), Ok(0xA123));
     }
 
     #[test]
-    fn test_copy_from_mem() {
-        let quirks = QuirksBuilder::default().increment_ir(true).build().unwrap();
-        test_copy_from_mem_impl(quirks, 0x500, 0x506);
+    fn test_write_opcode_at_invalid_address() {
+        let mut chip8 = create_chip8(0x0000);
+
+        assert_eq!(
+            chip8.write_opcode_at(0x201, 0xA123),
+            Err(Chip8Error::MemoryAlignment(0x201))
+        );
+        assert_eq!(
+            chip8.write_opcode_at(0x1000, 0xA123),
+            Err(Chip8Error::InvalidMemoryAccess(0x1000))
+        );
+        assert_eq!(
+            chip8.read_opcode_at(0xFFF),
+            Err(Chip8Error::MemoryAlignment(0xFFF))
+        );
+        assert_eq!(chip8.read_opcode_at(0xFFE), Ok(0x0000));
     }
 
     #[test]
-    fn test_copy_from_mem_no_increment_ir() {
-        let quirks = QuirksBuilder::default().increment_ir(false).build().unwrap();
-        test_copy_from_mem_impl(quirks, 0x500, 0x500);
+    fn test_set_registers_from_slice() {
+        let mut chip8 = create_chip8(0x0000);
+        let values: Vec<u8> = (0..16).map(|i| i * 3 + 1).collect();
+
+        assert_eq!(chip8.set_registers_from_slice(&values), Ok(()));
+        assert_eq!(chip8.registers(), &values[..]);
+
+        // Shorter slices only overwrite the first registers
+        assert_eq!(chip8.set_registers_from_slice(&[0xAA, 0xBB]), Ok(()));
+        assert_eq!(chip8.registers()[..3], [0xAA, 0xBB, values[2]]);
+    }
+
+    #[test]
+    fn test_set_registers_from_slice_too_long() {
+        let mut chip8 = create_chip8(0x0000);
+
+        let result = chip8.set_registers_from_slice(&[1; 17]);
+        assert_eq!(result, Err(Chip8Error::InvalidRegister(16)));
+        assert_eq!(chip8.registers(), &[0; 16]);
+    }
+
+    #[test]
+    fn test_assert_register_equals() {
+        let mut chip8 = create_chip8(0x0000);
+        chip8.registers[3] = 42;
+        chip8.registers[0xF] = 1;
+
+        assert_eq!(chip8.assert_register_equals(3, 42), Ok(()));
+        assert_eq!(
+            chip8.assert_register_equals(3, 41),
+            Err(Chip8Error::AssertionFailed {
+                register: 3,
+                expected: 41,
+                actual: 42
+            })
+        );
+        assert_eq!(
+            chip8.assert_register_equals(16, 0),
+            Err(Chip8Error::InvalidRegister(16))
+        );
+
+        assert_eq!(chip8.assert_vf_equals(1), Ok(()));
+        assert_eq!(
+            chip8.assert_vf_equals(0),
+            Err(Chip8Error::AssertionFailed {
+                register: 0xF,
+                expected: 0,
+                actual: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_register_assertions_after_every_cycle() {
+        // LD V1, 0x05; LD V2, 0x07; LD V1, 0x06
+        let mut chip8 = create_chip8_with_program(&[0x6105, 0x6207, 0x6106]);
+        chip8.dbg_options.register_assertions = vec![(1, 5)];
+        let input = MockInput::default();
+
+        assert!(chip8.emulate_cycle(&input).is_ok());
+        assert!(chip8.emulate_cycle(&input).is_ok());
+        assert_eq!(
+            chip8.emulate_cycle(&input).err(),
+            Some(Chip8Error::AssertionFailed {
+                register: 1,
+                expected: 5,
+                actual: 6
+            })
+        );
     }
 
     #[test]
@@ -1023,6 +5644,26 @@ mod tests {
         assert_eq!(chip8.sp, 1);
     }
 
+    #[test]
+    fn test_stack_depth() {
+        // Five nested calls, then two returns
+        let mut chip8 = create_chip8_with_program(&[0x2202]);
+        chip8.memory[0x202..0x20A].copy_from_slice(&[0x22, 0x04, 0x22, 0x06, 0x22, 0x08, 0x22, 0x0A]);
+        chip8.memory[0x20A..0x20E].copy_from_slice(&[0x00, 0xEE, 0x00, 0xEE]);
+        let input = MockInput::default();
+
+        for _ in 0..5 {
+            chip8.emulate_cycle(&input).unwrap();
+        }
+        assert_eq!(chip8.sp(), 5);
+
+        for _ in 0..2 {
+            chip8.emulate_cycle(&input).unwrap();
+        }
+        assert_eq!(chip8.sp(), 3);
+        assert_eq!(chip8.get_max_stack_depth(), 5);
+    }
+
     macro_rules! test_skip_value_opcodes {
         ($($name:ident: ($test_fn:ident, $values:expr),)*) => {
             $(
@@ -1312,4 +5953,180 @@ mod tests {
         test_shl_3_no_shift: ((0x8ABE, 128, 0, 0, 1)),
         test_shl_4_no_shift: ((0x8ABE, 129, 0, 2, 1)),
     }
+
+    /// Holds a machine without being generic itself.
+    struct Console {
+        chip8: DefaultChip8,
+    }
+
+    /// Draws the `0` glyph at (0, 0), then sets V0: LD F, V0; DRW V0, V0, 5; LD V0, 1
+    const DRAW_ZERO: [u8; 6] = [0xF0, 0x29, 0xD0, 0x05, 0x60, 0x01];
+
+    #[test]
+    fn test_default_chip8() {
+        let (_, timer_rx) = mpsc::channel();
+        let mut console = Console {
+            chip8: Chip8Builder::new(Graphics::new(), timer_rx).build().unwrap(),
+        };
+        console.chip8.load_rom(&DRAW_ZERO.to_vec()).unwrap();
+
+        let output = console.chip8.emulate_frame(3, &MockInput::default()).unwrap();
+        assert!(output.draw_on_screen);
+        assert_eq!(output.graphics.buffer()[0][..4], [1, 1, 1, 1]);
+        assert_eq!(console.chip8.registers[0], 1);
+    }
+
+    #[test]
+    fn test_boxed_chip8() {
+        let run = |graphics: Box<dyn GraphicsBuffer>| {
+            let (_, timer_rx) = mpsc::channel();
+            let mut chip8: BoxedChip8 = Chip8Builder::new(graphics, timer_rx).build().unwrap();
+            chip8.load_rom(&DRAW_ZERO.to_vec()).unwrap();
+            chip8.emulate_frame(3, &MockInput::default()).unwrap();
+            chip8
+        };
+
+        let plain = run(Box::new(Graphics::new()));
+        let mirrored = run(Box::new(MirroredGraphics::new(Graphics::new(), Graphics::new())));
+        assert_eq!(plain.graphics().buffer()[0][..4], [1, 1, 1, 1]);
+        assert_eq!(plain.graphics().buffer(), mirrored.graphics().buffer());
+
+        let (_, timer_rx) = mpsc::channel();
+        let mut chip8 = Chip8::new_boxed(
+            Graphics::new(),
+            timer_rx,
+            Quirks::default(),
+            DebugOptions::default(),
+        );
+        chip8.load_rom(&DRAW_ZERO.to_vec()).unwrap();
+        chip8.emulate_cycle(&MockInput::default()).unwrap();
+        chip8.emulate_cycle(&MockInput::default()).unwrap();
+        assert_eq!(chip8.graphics().buffer(), plain.graphics().buffer());
+    }
+
+    /// Full runs of `2nnn` and `00EE`, through [`Chip8::emulate_cycle`].
+    mod call_return {
+        use super::{create_chip8_with_program, MockInput};
+        use crate::chip8::STACK_SIZE;
+        use crate::Chip8Error;
+
+        #[test]
+        fn test_nested_calls_return_in_order() {
+            let mut chip8 = create_chip8_with_program(&[
+                0x220A, // 0x200: CALL 0x20A
+                0x6001, // 0x202: LD V0, 1
+                0x1204, // 0x204: JP 0x204
+                0x0000, 0x0000, //
+                0x2212, // 0x20A: CALL 0x212
+                0x6101, // 0x20C: LD V1, 1
+                0x00EE, // 0x20E: RET
+                0x0000, //
+                0x221A, // 0x212: CALL 0x21A
+                0x6201, // 0x214: LD V2, 1
+                0x00EE, // 0x216: RET
+                0x0000, //
+                0x6301, // 0x21A: LD V3, 1
+                0x00EE, // 0x21C: RET
+            ]);
+            let input = MockInput::default();
+
+            let mut trace = Vec::new();
+            for _ in 0..10 {
+                chip8.emulate_cycle(&input).unwrap();
+                trace.push(chip8.pc);
+                if chip8.pc == 0x21A {
+                    assert_eq!(chip8.stack[..3], [0x202, 0x20C, 0x214]);
+                    assert_eq!(chip8.sp(), 3);
+                }
+            }
+
+            assert_eq!(
+                trace,
+                [0x20A, 0x212, 0x21A, 0x21C, 0x214, 0x216, 0x20C, 0x20E, 0x202, 0x204]
+            );
+            assert_eq!(chip8.registers[..4], [1, 1, 1, 1]);
+            assert_eq!(chip8.sp(), 0);
+            assert_eq!(chip8.get_max_stack_depth(), 3);
+        }
+
+        #[test]
+        fn test_call_skips_to_subroutine_until_return() {
+            let mut chip8 = create_chip8_with_program(&[
+                0x2206, // 0x200: CALL 0x206
+                0x60AA, // 0x202: LD V0, 0xAA
+                0x1204, // 0x204: JP 0x204
+                0x8100, // 0x206: LD V1, V0
+                0x00EE, // 0x208: RET
+            ]);
+            let input = MockInput::default();
+
+            chip8.emulate_cycle(&input).unwrap();
+            chip8.emulate_cycle(&input).unwrap();
+            // The subroutine ran before the instruction after the call
+            assert_eq!(chip8.registers[..2], [0, 0]);
+
+            chip8.emulate_cycle(&input).unwrap();
+            assert_eq!(chip8.pc, 0x202);
+            chip8.emulate_cycle(&input).unwrap();
+            assert_eq!(chip8.registers[..2], [0xAA, 0]);
+        }
+
+        #[test]
+        fn test_skipped_call_does_not_push() {
+            let program = |skip: u16| {
+                create_chip8_with_program(&[
+                    0x6001, // 0x200: LD V0, 1
+                    skip,   // 0x202: SE/SNE V0, 1
+                    0x2300, // 0x204: CALL 0x300
+                    0x6102, // 0x206: LD V1, 2
+                ])
+            };
+            let input = MockInput::default();
+
+            let mut skipped = program(0x3001);
+            for _ in 0..3 {
+                skipped.emulate_cycle(&input).unwrap();
+            }
+            assert_eq!(skipped.pc, 0x208);
+            assert_eq!(skipped.registers[1], 2);
+            assert_eq!(skipped.sp(), 0);
+            assert_eq!(skipped.statistics().total_subroutine_calls, 0);
+
+            let mut called = program(0x4001);
+            for _ in 0..3 {
+                called.emulate_cycle(&input).unwrap();
+            }
+            assert_eq!(called.pc, 0x300);
+            assert_eq!(called.stack[0], 0x206);
+            assert_eq!(called.sp(), 1);
+        }
+
+        #[test]
+        fn test_recursion_to_stack_size() {
+            // 0x200: CALL 0x200
+            let mut chip8 = create_chip8_with_program(&[0x2200]);
+            let input = MockInput::default();
+
+            for _ in 0..STACK_SIZE {
+                chip8.emulate_cycle(&input).unwrap();
+            }
+            assert_eq!(chip8.sp() as usize, STACK_SIZE);
+            assert!(chip8.stack.iter().all(|&addr| addr == 0x202));
+
+            let result = chip8.emulate_cycle(&input);
+            assert_eq!(result.err(), Some(Chip8Error::StackOverflow(0x200)));
+            assert_eq!(chip8.sp() as usize, STACK_SIZE);
+            assert_eq!(chip8.pc, 0x200);
+        }
+
+        #[test]
+        fn test_return_with_empty_stack() {
+            // 0x200: RET
+            let mut chip8 = create_chip8_with_program(&[0x00EE]);
+
+            let result = chip8.emulate_cycle(&MockInput::default());
+            assert_eq!(result.err(), Some(Chip8Error::StackUnderflow(0x200)));
+            assert_eq!(chip8.sp(), 0);
+        }
+    }
 }
@@ -0,0 +1,153 @@
+//! Everything needed to reproduce an emulation error in a bug report, gathered as text so the
+//! frontend only has to write it out.
+
+use std::fmt::Write;
+
+use crate::chip8::Chip8;
+use crate::graphics::{self, PIXEL_ON};
+use crate::traits::GraphicsBuffer;
+use crate::Chip8Error;
+
+/// The state of the emulator when it stopped with an error. See [`CrashReport::files`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrashReport {
+    /// The error, and the [`Chip8::format_call_stack`] it happened in
+    pub error: String,
+    /// The screen as a [`graphics::text_screenshot`], with the ROM name and the quirks
+    pub screen: String,
+    /// The screen as a plain PBM image, one image pixel per screen pixel
+    pub screen_pbm: String,
+    /// The registers, timers, stack, and the instruction the program counter points at
+    pub registers: String,
+    /// The [`Chip8::trace`] up to the error, one instruction per line. Empty unless
+    /// `DebugOptions::trace_depth` is set.
+    pub trace: String,
+}
+
+impl CrashReport {
+    /// Gathers the report for `chip8`, which was running `rom_name` and had drawn `frame` frames
+    /// when it stopped with `error`.
+    pub fn new<G: GraphicsBuffer>(rom_name: &str, frame: u64, chip8: &Chip8<G>, error: &Chip8Error) -> Self {
+        let buffer = chip8.graphics().buffer();
+        let state = chip8.state();
+
+        let mut registers = String::new();
+        let _ = writeln!(registers, "at {}", chip8.disassemble_with_bytes(state.pc));
+        let _ = writeln!(
+            registers,
+            "pc: {:#05x}, ir: {:#05x}, sp: {}",
+            state.pc, state.ir, state.sp
+        );
+        for (name, value) in state.into_named_iter() {
+            let _ = writeln!(registers, "{name}: {value:#04x}");
+        }
+        let _ = writeln!(
+            registers,
+            "delay timer: {}, sound timer: {}",
+            state.delay_timer, state.sound_timer
+        );
+        let stack: Vec<_> = state.stack[..state.sp as usize]
+            .iter()
+            .map(|addr| format!("{addr:#05x}"))
+            .collect();
+        let _ = writeln!(registers, "stack: [{}]", stack.join(", "));
+
+        let mut error = format!("{error}\n");
+        let call_stack = chip8.format_call_stack();
+        if !call_stack.is_empty() {
+            let _ = writeln!(error, "call stack: {call_stack}");
+        }
+
+        CrashReport {
+            error,
+            screen: graphics::text_screenshot(rom_name, frame, chip8.quirks(), buffer),
+            screen_pbm: to_pbm(buffer),
+            registers,
+            trace: chip8.trace().map(|entry| format!("{entry}\n")).collect(),
+        }
+    }
+
+    /// The report as `(file name, contents)` pairs. The trace is left out when it's empty.
+    pub fn files(&self) -> Vec<(&'static str, &str)> {
+        let mut files = vec![
+            ("error.txt", self.error.as_str()),
+            ("screen.txt", &self.screen),
+            ("screen.pbm", &self.screen_pbm),
+            ("registers.txt", &self.registers),
+        ];
+        if !self.trace.is_empty() {
+            files.push(("trace.txt", &self.trace));
+        }
+        files
+    }
+}
+
+/// Encodes `buffer` as an ASCII PBM (`P1`) image, which most image viewers can open.
+fn to_pbm(buffer: &[Vec<u8>]) -> String {
+    let width = buffer.first().map_or(0, Vec::len);
+    let mut out = format!("P1\n{width} {}\n", buffer.len());
+    for row in buffer {
+        let pixels: Vec<_> = row
+            .iter()
+            .map(|&pixel| if pixel == PIXEL_ON { "1" } else { "0" })
+            .collect();
+        out += &pixels.join(" ");
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_pbm, CrashReport};
+    use crate::chip8::NoInput;
+    use crate::test_helpers::chip8_with_program;
+    use crate::{Chip8Error, DebugOptions, DebugOptionsBuilder};
+
+    /// LD V3, 0x2A; CALL 0x206; an unsupported opcode
+    const CRASHING: [u16; 4] = [0x632A, 0x2206, 0x0000, 0xFFFF];
+
+    #[test]
+    fn test_crash_report() {
+        let mut chip8 = chip8_with_program(&CRASHING, DebugOptions::default());
+
+        let error = (0..3).find_map(|_| chip8.emulate_cycle(&NoInput).err()).unwrap();
+        assert_eq!(error, Chip8Error::UnsupportedOpcode(0xFFFF));
+        let report = CrashReport::new("crash.ch8", 7, &chip8, &error);
+
+        assert_eq!(
+            report.error,
+            "Opcode `0xffff` is not supported\ncall stack: #0 0x0204\n"
+        );
+        assert!(report.screen.starts_with("crash.ch8 | frame 7 | reset_vf=true"));
+        assert!(report.screen_pbm.starts_with("P1\n64 32\n0 0 0"));
+        assert!(report.registers.starts_with("at 0206: FFFF"));
+        assert!(report.registers.contains("\nV3: 0x2a\n"));
+        assert!(report.registers.ends_with("stack: [0x204]\n"));
+
+        let names: Vec<_> = report.files().iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, ["error.txt", "screen.txt", "screen.pbm", "registers.txt"]);
+    }
+
+    #[test]
+    fn test_crash_report_trace() {
+        let options = DebugOptionsBuilder::default().trace_depth(2).build().unwrap();
+        let mut chip8 = chip8_with_program(&CRASHING, options);
+
+        let error = (0..3).find_map(|_| chip8.emulate_cycle(&NoInput).err()).unwrap();
+        let report = CrashReport::new("crash.ch8", 7, &chip8, &error);
+
+        let lines: Vec<_> = report.trace.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("0x202: 2206  CALL 0x206"));
+        assert!(lines[1].starts_with("0x206: FFFF  DB 0xffff"));
+        assert!(lines[1].contains(" V3=2a "));
+        assert_eq!(report.files().last().unwrap().0, "trace.txt");
+    }
+
+    #[test]
+    fn test_to_pbm() {
+        let buffer = vec![vec![1, 0, 0], vec![0, 1, 1]];
+        assert_eq!(to_pbm(&buffer), "P1\n3 2\n1 0 0\n0 1 1\n");
+    }
+}
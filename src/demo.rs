@@ -0,0 +1,176 @@
+//! A small two player pong game, built into the emulator so it can show something without a ROM.
+//! It draws, reads both players' keys, and uses the delay timer to pace itself and the sound timer
+//! to beep, so playing it touches every part of the emulator.
+//!
+//! The left paddle moves with keys `1` and `4`, the right one with `C` and `D`. There's no score:
+//! the ball is served again from the middle when it gets past a paddle.
+
+/// The program, one opcode per entry, loaded from 0x200.
+///
+/// V0/V1: left/right paddle y, V2/V3: ball x/y, V4/V5: ball dx/dy, V6: scratch, VA/VB: left/right
+/// paddle x.
+const PROGRAM: [u16; 69] = [
+    0x6A02, // 0x200: LD VA, 2
+    0x6B3D, // 0x202: LD VB, 61
+    0x600D, // 0x204: LD V0, 13
+    0x610D, // 0x206: LD V1, 13
+    0x6401, // 0x208: LD V4, 1
+    0x6501, // 0x20A: LD V5, 1
+    0x226C, // 0x20C: CALL serve
+    0x2276, // 0x20E: CALL draw
+    // loop: wait 2 ticks
+    0x6602, // 0x210: LD V6, 2
+    0xF615, // 0x212: LD DT, V6
+    0xF607, // 0x214: LD V6, DT
+    0x3600, // 0x216: SE V6, 0
+    0x1214, // 0x218: JP 0x214
+    0x2276, // 0x21A: CALL draw, which erases everything again
+    // Left paddle: 1 up, 4 down
+    0x6601, // 0x21C: LD V6, 0x1
+    0xE6A1, // 0x21E: SKNP V6
+    0x70FF, // 0x220: ADD V0, -1
+    0x6604, // 0x222: LD V6, 0x4
+    0xE6A1, // 0x224: SKNP V6
+    0x7001, // 0x226: ADD V0, 1
+    // Right paddle: C up, D down
+    0x660C, // 0x228: LD V6, 0xC
+    0xE6A1, // 0x22A: SKNP V6
+    0x71FF, // 0x22C: ADD V1, -1
+    0x660D, // 0x22E: LD V6, 0xD
+    0xE6A1, // 0x230: SKNP V6
+    0x7101, // 0x232: ADD V1, 1
+    // Keep the paddles on screen: 0 <= y <= 26
+    0x40FF, // 0x234: SNE V0, -1
+    0x6000, // 0x236: LD V0, 0
+    0x401B, // 0x238: SNE V0, 27
+    0x601A, // 0x23A: LD V0, 26
+    0x41FF, // 0x23C: SNE V1, -1
+    0x6100, // 0x23E: LD V1, 0
+    0x411B, // 0x240: SNE V1, 27
+    0x611A, // 0x242: LD V1, 26
+    // Move the ball, bouncing off the top and bottom
+    0x8244, // 0x244: ADD V2, V4
+    0x8354, // 0x246: ADD V3, V5
+    0x4300, // 0x248: SNE V3, 0
+    0x6501, // 0x24A: LD V5, 1
+    0x431F, // 0x24C: SNE V3, 31
+    0x65FF, // 0x24E: LD V5, -1
+    // Serve again once the ball gets past a paddle
+    0x4200, // 0x250: SNE V2, 0
+    0x226C, // 0x252: CALL serve
+    0x423F, // 0x254: SNE V2, 63
+    0x226C, // 0x256: CALL serve
+    // Draw, and bounce off a paddle if the ball hit one
+    0x2276, // 0x258: CALL draw
+    0x4F01, // 0x25A: SNE VF, 1
+    0x2260, // 0x25C: CALL hit
+    0x1210, // 0x25E: JP loop
+    // hit: reverse dx and beep
+    0x6600, // 0x260: LD V6, 0
+    0x8645, // 0x262: SUB V6, V4
+    0x8460, // 0x264: LD V4, V6
+    0x6604, // 0x266: LD V6, 4
+    0xF618, // 0x268: LD ST, V6
+    0x00EE, // 0x26A: RET
+    // serve: put the ball in the middle and beep a little longer
+    0x6220, // 0x26C: LD V2, 32
+    0x6310, // 0x26E: LD V3, 16
+    0x6608, // 0x270: LD V6, 8
+    0xF618, // 0x272: LD ST, V6
+    0x00EE, // 0x274: RET
+    // draw: XORs the paddles and then the ball, so VF is set if the ball overlaps a paddle
+    0xA282, // 0x276: LD I, paddle
+    0xDA06, // 0x278: DRW VA, V0, 6
+    0xDB16, // 0x27A: DRW VB, V1, 6
+    0xA288, // 0x27C: LD I, ball
+    0xD231, // 0x27E: DRW V2, V3, 1
+    0x00EE, // 0x280: RET
+    // paddle: 6 rows of 1 pixel, followed by ball: 1 pixel
+    0x8080, // 0x282
+    0x8080, // 0x284
+    0x8080, // 0x286
+    0x8000, // 0x288
+];
+
+/// Returns the demo ROM.
+pub fn rom() -> Vec<u8> {
+    PROGRAM.iter().flat_map(|opcode| opcode.to_be_bytes()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use crate::chip8::{Chip8, Chip8Event};
+    use crate::graphics::Graphics;
+    use crate::timer::TimerOperation;
+    use crate::traits::{GraphicsBuffer, Input};
+    use crate::{DebugOptions, Key, Quirks};
+
+    use super::rom;
+
+    /// Holds `pressed` down the whole time.
+    struct Hold(Vec<u8>);
+
+    impl Input for Hold {
+        fn is_pressed(&self, key: Key) -> bool {
+            self.0.contains(&u8::from(key))
+        }
+    }
+
+    /// Runs the demo for `seconds` at the default 800 Hz, with the timers ticking at 60 Hz.
+    fn run(seconds: u32, input: &impl Input) -> (Chip8<Graphics>, Vec<Chip8Event>) {
+        let (timer_tx, timer_rx) = mpsc::channel();
+        let mut chip8 = Chip8::new(
+            Graphics::new(),
+            timer_rx,
+            Quirks::default(),
+            DebugOptions::default(),
+        );
+        chip8.load_rom(&rom()).unwrap();
+
+        let mut events = Vec::new();
+        for _ in 0..seconds * 60 {
+            timer_tx.send(TimerOperation::Decrement(1)).unwrap();
+            let output = chip8.emulate_frame(13, input).unwrap();
+            events.extend(output.events);
+        }
+        (chip8, events)
+    }
+
+    #[test]
+    fn test_demo_runs() {
+        let (chip8, events) = run(10, &Hold(vec![]));
+
+        let lit = chip8
+            .graphics()
+            .buffer()
+            .iter()
+            .flatten()
+            .filter(|&&pixel| pixel != 0);
+        // Two paddles of 6 pixels and the ball, unless it's on a paddle
+        assert!(lit.count() >= 12);
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, Chip8Event::DelayTimerSet { value: 2, .. })));
+        // With nobody playing, the ball bounces off a paddle now and then, and gets past one too
+        let serves = events
+            .iter()
+            .filter(|event| matches!(event, Chip8Event::SoundTimerSet { value: 8, .. }))
+            .count();
+        assert!(serves >= 2, "{serves} serves");
+        let hits = events
+            .iter()
+            .filter(|event| matches!(event, Chip8Event::SoundTimerSet { value: 4, .. }))
+            .count();
+        assert!(hits >= 1, "{hits} hits");
+    }
+
+    #[test]
+    fn test_demo_reads_both_players() {
+        let (chip8, _) = run(2, &Hold(vec![0x1, 0xD]));
+
+        let paddles = chip8.registers();
+        assert_eq!((paddles[0], paddles[1]), (0, 26));
+    }
+}
@@ -0,0 +1,684 @@
+//! Turns opcodes back into assembly mnemonics, following the syntax of
+//! <http://devernay.free.fr/hacks/chip8/C8TECH10.HTM#3.1>, which also covers the SCHIP
+//! instructions. The XO-CHIP ones follow the same style.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Write};
+use std::str::FromStr;
+
+use crate::Chip8Error;
+
+/// A set of instructions. Each one extends the one before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Platform {
+    /// The original instructions, which are the ones the emulator runs
+    #[default]
+    Chip8,
+    /// SUPER-CHIP 1.1: scrolling, the 128x64 mode, big font and the RPL flags
+    Schip,
+    /// XO-CHIP: bit planes, audio patterns, 16-bit `LD I` and register ranges
+    XoChip,
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Platform::Chip8 => "CHIP-8",
+            Platform::Schip => "SCHIP",
+            Platform::XoChip => "XO-CHIP",
+        })
+    }
+}
+
+/// Parses `chip8`, `schip` or `xochip`.
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "chip8" => Ok(Platform::Chip8),
+            "schip" => Ok(Platform::Schip),
+            "xochip" => Ok(Platform::XoChip),
+            _ => Err(format!("unknown platform `{s}`, expected chip8, schip or xochip")),
+        }
+    }
+}
+
+/// The first word of XO-CHIP's `LD I, nnnn`, whose address is in the next word.
+const LONG_LOAD: u16 = 0xF000;
+
+/// Decodes `opcode` into its mnemonic, e.g. `0x6120` becomes `LD V1, 0x20`. Returns
+/// `Chip8Error::UnsupportedOpcode` for opcodes the emulator can't execute.
+pub fn disassemble(opcode: u16) -> Result<String, Chip8Error> {
+    let x = (opcode & 0x0F00) >> 8;
+    let y = (opcode & 0x00F0) >> 4;
+    let n = opcode & 0x000F;
+    let kk = opcode & 0x00FF;
+    let nnn = opcode & 0x0FFF;
+
+    let mnemonic = match opcode & 0xF000 {
+        0x0000 => match kk {
+            0xE0 => "CLS".to_string(),
+            0xEE => "RET".to_string(),
+            _ => return Err(Chip8Error::UnsupportedOpcode(opcode)),
+        },
+        0x1000 => format!("JP {nnn:#05x}"),
+        0x2000 => format!("CALL {nnn:#05x}"),
+        0x3000 => format!("SE V{x:X}, {kk:#04x}"),
+        0x4000 => format!("SNE V{x:X}, {kk:#04x}"),
+        0x5000 => format!("SE V{x:X}, V{y:X}"),
+        0x6000 => format!("LD V{x:X}, {kk:#04x}"),
+        0x7000 => format!("ADD V{x:X}, {kk:#04x}"),
+        0x8000 => {
+            let op = match n {
+                0x0 => "LD",
+                0x1 => "OR",
+                0x2 => "AND",
+                0x3 => "XOR",
+                0x4 => "ADD",
+                0x5 => "SUB",
+                0x6 => "SHR",
+                0x7 => "SUBN",
+                0xE => "SHL",
+                _ => return Err(Chip8Error::UnsupportedOpcode(opcode)),
+            };
+            format!("{op} V{x:X}, V{y:X}")
+        }
+        0x9000 => format!("SNE V{x:X}, V{y:X}"),
+        0xA000 => format!("LD I, {nnn:#05x}"),
+        0xB000 => format!("JP V0, {nnn:#05x}"),
+        0xC000 => format!("RND V{x:X}, {kk:#04x}"),
+        0xD000 => format!("DRW V{x:X}, V{y:X}, {n}"),
+        0xE000 => match kk {
+            0x9E => format!("SKP V{x:X}"),
+            0xA1 => format!("SKNP V{x:X}"),
+            _ => return Err(Chip8Error::UnsupportedOpcode(opcode)),
+        },
+        _ => match kk {
+            0x07 => format!("LD V{x:X}, DT"),
+            0x0A => format!("LD V{x:X}, K"),
+            0x15 => format!("LD DT, V{x:X}"),
+            0x18 => format!("LD ST, V{x:X}"),
+            0x1E => format!("ADD I, V{x:X}"),
+            0x29 => format!("LD F, V{x:X}"),
+            0x33 => format!("LD B, V{x:X}"),
+            0x55 => format!("LD [I], V{x:X}"),
+            0x65 => format!("LD V{x:X}, [I]"),
+            _ => return Err(Chip8Error::UnsupportedOpcode(opcode)),
+        },
+    };
+
+    Ok(mnemonic)
+}
+
+/// Decodes `opcode` like [`disassemble`], but also knows the SCHIP and XO-CHIP instructions, and
+/// returns the first platform that has it. XO-CHIP's `F000` is decoded as `LD I, long`, as its
+/// address is the next word.
+pub fn disassemble_extended(opcode: u16) -> Result<(String, Platform), Chip8Error> {
+    let x = (opcode & 0x0F00) >> 8;
+    let y = (opcode & 0x00F0) >> 4;
+    let n = opcode & 0x000F;
+
+    let (mnemonic, platform) = match opcode {
+        0x00C0..=0x00CF => (format!("SCD {n}"), Platform::Schip),
+        0x00D0..=0x00DF => (format!("SCU {n}"), Platform::XoChip),
+        0x00FB => ("SCR".to_string(), Platform::Schip),
+        0x00FC => ("SCL".to_string(), Platform::Schip),
+        0x00FD => ("EXIT".to_string(), Platform::Schip),
+        0x00FE => ("LOW".to_string(), Platform::Schip),
+        0x00FF => ("HIGH".to_string(), Platform::Schip),
+        _ if opcode & 0xF00F == 0x5002 => (format!("SAVE V{x:X}-V{y:X}"), Platform::XoChip),
+        _ if opcode & 0xF00F == 0x5003 => (format!("LOAD V{x:X}-V{y:X}"), Platform::XoChip),
+        LONG_LOAD => ("LD I, long".to_string(), Platform::XoChip),
+        0xF002 => ("AUDIO".to_string(), Platform::XoChip),
+        _ => match opcode & 0xF0FF {
+            0xF001 => (format!("PLANE {x}"), Platform::XoChip),
+            0xF030 => (format!("LD HF, V{x:X}"), Platform::Schip),
+            0xF03A => (format!("LD PITCH, V{x:X}"), Platform::XoChip),
+            0xF075 => (format!("LD R, V{x:X}"), Platform::Schip),
+            0xF085 => (format!("LD V{x:X}, R"), Platform::Schip),
+            _ => (disassemble(opcode)?, Platform::Chip8),
+        },
+    };
+
+    Ok((mnemonic, platform))
+}
+
+/// What refers to a labelled address. Calls take precedence over jumps, and jumps over data
+/// references, when picking the label's name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Reference {
+    Data,
+    Jump,
+    Call,
+}
+
+/// Code found by following the control flow of a ROM from its entry point.
+struct Analysis {
+    /// Addresses of the instructions that can be reached
+    code: BTreeSet<u16>,
+    /// Address referred to -> how it's referred to, and the addresses referring to it
+    xrefs: BTreeMap<u16, (Reference, BTreeSet<u16>)>,
+}
+
+impl Analysis {
+    fn refer(&mut self, target: u16, kind: Reference, from: u16) {
+        let (existing, sources) = self.xrefs.entry(target).or_insert((kind, BTreeSet::new()));
+        *existing = (*existing).max(kind);
+        sources.insert(from);
+    }
+
+    fn label(&self, addr: u16) -> Option<String> {
+        let (kind, _) = self.xrefs.get(&addr)?;
+        let prefix = match kind {
+            Reference::Call => "sub",
+            Reference::Jump => "loc",
+            Reference::Data => "data",
+        };
+        Some(format!("{prefix}_{addr:03X}"))
+    }
+}
+
+/// Follows every branch from `origin`, the address `rom` is loaded at. Jumps through `Bnnn`
+/// can't be followed statically, and unsupported opcodes end the branch they're on. Instructions
+/// of every platform are followed, as reachable ones mean the ROM is for that platform.
+fn analyze(rom: &[u8], origin: u16) -> Analysis {
+    let mut analysis = Analysis {
+        code: BTreeSet::new(),
+        xrefs: BTreeMap::new(),
+    };
+    let mut pending = vec![origin];
+
+    while let Some(addr) = pending.pop() {
+        // Targets outside of the ROM, like the font, aren't analyzed
+        if addr < origin || analysis.code.contains(&addr) {
+            continue;
+        }
+        let offset = (addr - origin) as usize;
+        let Some(bytes) = rom.get(offset..offset + 2) else {
+            continue;
+        };
+        let opcode = u16::from_be_bytes([bytes[0], bytes[1]]);
+        if disassemble_extended(opcode).is_err() {
+            continue;
+        }
+        analysis.code.insert(addr);
+
+        let nnn = opcode & 0x0FFF;
+        let next = addr + 2;
+        match opcode & 0xF000 {
+            0x0000 if opcode & 0xFF == 0xEE || opcode == 0x00FD => (),
+            0xF000 if opcode == LONG_LOAD => pending.push(next + 2),
+            0x1000 => {
+                analysis.refer(nnn, Reference::Jump, addr);
+                pending.push(nnn);
+            }
+            0x2000 => {
+                analysis.refer(nnn, Reference::Call, addr);
+                pending.extend([next, nnn]);
+            }
+            0x3000 | 0x4000 | 0x5000 | 0x9000 | 0xE000 => pending.extend([next + 2, next]),
+            0xA000 => {
+                analysis.refer(nnn, Reference::Data, addr);
+                pending.push(next);
+            }
+            0xB000 => (),
+            _ => pending.push(next),
+        }
+    }
+
+    analysis
+}
+
+/// The mnemonic for `opcode`, with the address it refers to replaced by its label, and the first
+/// platform that has it.
+fn labelled_mnemonic(opcode: u16, analysis: &Analysis, platform: Platform) -> (String, Platform) {
+    let nnn = opcode & 0x0FFF;
+    let label = analysis.label(nnn).unwrap_or_else(|| format!("{nnn:#05x}"));
+
+    match opcode & 0xF000 {
+        0x1000 => (format!("JP {label}"), Platform::Chip8),
+        0x2000 => (format!("CALL {label}"), Platform::Chip8),
+        0xA000 => (format!("LD I, {label}"), Platform::Chip8),
+        // Scrolls 1 pixel left and right with `Quirks::extended_scrolling`, as the emulator runs
+        // them, rather than SCHIP's `SCD 0` and `SCD 1`
+        _ if platform == Platform::Chip8 && opcode == 0x00C0 => ("SCL 1".to_string(), Platform::Chip8),
+        _ if platform == Platform::Chip8 && opcode == 0x00C1 => ("SCR 1".to_string(), Platform::Chip8),
+        _ => disassemble_extended(opcode).unwrap_or_else(|_| (format!("DW {opcode:#06x}"), Platform::Chip8)),
+    }
+}
+
+/// Produces an annotated listing of `rom`, loaded at `origin`. Every reachable instruction is
+/// disassembled, and every other byte is listed as data. Call, jump and `LD I` targets get labels
+/// (`sub_2A4`, `loc_230`, `data_400`) with a comment listing where they're referred from.
+///
+/// ```text
+/// sub_206:                ; called from 0x200
+/// 0206: 6001      LD V0, 0x01
+/// ```
+///
+/// SCHIP and XO-CHIP instructions are disassembled too, with a comment naming the platform they
+/// need, except for `00C0` and `00C1`: they're the 1 pixel scrolls of
+/// [`Quirks::extended_scrolling`](crate::Quirks::extended_scrolling). Same as [`listing_for`]
+/// with [`Platform::Chip8`].
+pub fn listing(rom: &[u8], origin: u16) -> String {
+    listing_for(rom, origin, Platform::Chip8)
+}
+
+/// Like [`listing`], for a ROM written for `platform`: only instructions `platform` doesn't have
+/// get a comment.
+///
+/// ```text
+/// 0200: 00FF      HIGH                ; needs SCHIP
+/// ```
+pub fn listing_for(rom: &[u8], origin: u16, platform: Platform) -> String {
+    let analysis = analyze(rom, origin);
+    let mut out = String::new();
+    let mut offset = 0;
+
+    while offset < rom.len() {
+        let addr = origin + offset as u16;
+
+        if let Some((kind, sources)) = analysis.xrefs.get(&addr) {
+            let verb = match kind {
+                Reference::Call => "called",
+                Reference::Jump => "jumped to",
+                Reference::Data => "referenced",
+            };
+            let sources: Vec<_> = sources.iter().map(|from| format!("{from:#05x}")).collect();
+            let label = format!("{}:", analysis.label(addr).unwrap_or_default());
+            let _ = writeln!(out, "{label:<24}; {verb} from {}", sources.join(", "));
+        }
+
+        if analysis.code.contains(&addr) {
+            let opcode = u16::from_be_bytes([rom[offset], rom[offset + 1]]);
+            let (mut mnemonic, needs) = labelled_mnemonic(opcode, &analysis, platform);
+            let mut bytes = format!("{opcode:04X}");
+            offset += 2;
+            if opcode == LONG_LOAD {
+                if let Some(long) = rom.get(offset..offset + 2) {
+                    let long = u16::from_be_bytes([long[0], long[1]]);
+                    mnemonic = format!("LD I, {long:#06x}");
+                    bytes += &format!(" {long:04X}");
+                    offset += 2;
+                }
+            }
+            if needs > platform {
+                mnemonic = format!("{mnemonic:<20}; needs {needs}");
+            }
+            // Wide enough for the 4 bytes of `LD I, long`
+            let _ = writeln!(out, "{addr:04X}: {bytes:<9} {mnemonic}");
+        } else {
+            let byte = rom[offset];
+            let _ = writeln!(out, "{addr:04X}: {:<9} DB {byte:#04x}", format!("{byte:02X}"));
+            offset += 1;
+        }
+    }
+
+    out
+}
+
+/// Guesses which keys the program accepts after the `Fx0A` at `addr`, by looking for the
+/// common pattern of comparing `Vx` against constants right after the wait:
+///
+/// ```text
+/// LD V0, K
+/// SNE V0, 0x05
+/// JP up
+/// SNE V0, 0x08
+/// JP down
+/// ```
+///
+/// Returns `None` if there's no such pattern, i.e. any key might be accepted.
+pub fn expected_keys(memory: &[u8], addr: u16) -> Option<Vec<u8>> {
+    let read = |addr: u16| {
+        let bytes = memory.get(addr as usize..addr as usize + 2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    };
+
+    let wait = read(addr)?;
+    if wait & 0xF0FF != 0xF00A {
+        return None;
+    }
+    let x = wait & 0x0F00;
+
+    let mut keys = Vec::new();
+    // Each comparison guards the instruction after it
+    let mut pos = addr + 2;
+    while let Some(opcode) = read(pos) {
+        let is_compare = matches!(opcode & 0xF000, 0x3000 | 0x4000) && opcode & 0x0F00 == x;
+        if !is_compare || keys.len() == 16 {
+            break;
+        }
+
+        let key = (opcode & 0xFF) as u8;
+        if key <= 0xF && !keys.contains(&key) {
+            keys.push(key);
+        }
+        pos += 4;
+    }
+
+    if keys.is_empty() {
+        None
+    } else {
+        Some(keys)
+    }
+}
+
+/// Number of opcodes at the start of a ROM that [`detect_byte_swap`] looks at.
+const BYTE_SWAP_SAMPLE: usize = 16;
+
+/// Guesses whether the two bytes of every opcode in `rom` were swapped, e.g. by a tool that wrote
+/// it out little-endian: more than half of the first opcodes don't decode as they are, but more
+/// than half do once swapped.
+pub fn detect_byte_swap(rom: &[u8]) -> bool {
+    let sample: Vec<_> = rom.chunks_exact(2).take(BYTE_SWAP_SAMPLE).collect();
+    let valid =
+        |opcodes: &mut dyn Iterator<Item = u16>| opcodes.filter(|&op| disassemble(op).is_ok()).count();
+
+    let as_is = valid(&mut sample.iter().map(|pair| u16::from_be_bytes([pair[0], pair[1]])));
+    let swapped = valid(&mut sample.iter().map(|pair| u16::from_le_bytes([pair[0], pair[1]])));
+    let half = sample.len() / 2;
+    sample.len() - as_is > half && swapped > half
+}
+
+/// Swaps the two bytes of every opcode in `rom`. A trailing odd byte is kept as it is.
+pub fn swap_bytes(rom: &[u8]) -> Vec<u8> {
+    let mut swapped = rom.to_vec();
+    for pair in swapped.chunks_exact_mut(2) {
+        pair.swap(0, 1);
+    }
+    swapped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        analyze, detect_byte_swap, disassemble, disassemble_extended, expected_keys, listing, listing_for,
+        swap_bytes, Platform,
+    };
+    use crate::Chip8Error;
+
+    macro_rules! disassemble_test {
+        ($($name:ident: $value:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let (opcode, mnemonic) = $value;
+                    assert_eq!(disassemble(opcode), Ok(mnemonic.to_string()));
+                }
+            )*
+        }
+    }
+
+    disassemble_test! {
+        test_cls: (0x00E0, "CLS"),
+        test_ret: (0x00EE, "RET"),
+        test_jp: (0x1234, "JP 0x234"),
+        test_call: (0x2300, "CALL 0x300"),
+        test_se_byte: (0x3A12, "SE VA, 0x12"),
+        test_sne_byte: (0x4B05, "SNE VB, 0x05"),
+        test_se_reg: (0x5120, "SE V1, V2"),
+        test_ld_byte: (0x6120, "LD V1, 0x20"),
+        test_add_byte: (0x7FFF, "ADD VF, 0xff"),
+        test_ld_reg: (0x8AB0, "LD VA, VB"),
+        test_or: (0x8AB1, "OR VA, VB"),
+        test_and: (0x8AB2, "AND VA, VB"),
+        test_xor: (0x8AB3, "XOR VA, VB"),
+        test_add_reg: (0x8AB4, "ADD VA, VB"),
+        test_sub: (0x8AB5, "SUB VA, VB"),
+        test_shr: (0x8AB6, "SHR VA, VB"),
+        test_subn: (0x8AB7, "SUBN VA, VB"),
+        test_shl: (0x8ABE, "SHL VA, VB"),
+        test_sne_reg: (0x9120, "SNE V1, V2"),
+        test_ld_i: (0xA123, "LD I, 0x123"),
+        test_jp_v0: (0xB120, "JP V0, 0x120"),
+        test_rnd: (0xC3FF, "RND V3, 0xff"),
+        test_drw: (0xD125, "DRW V1, V2, 5"),
+        test_skp: (0xE19E, "SKP V1"),
+        test_sknp: (0xE1A1, "SKNP V1"),
+        test_ld_dt: (0xF107, "LD V1, DT"),
+        test_ld_k: (0xF10A, "LD V1, K"),
+        test_set_dt: (0xF115, "LD DT, V1"),
+        test_set_st: (0xF118, "LD ST, V1"),
+        test_add_i: (0xF11E, "ADD I, V1"),
+        test_ld_f: (0xF129, "LD F, V1"),
+        test_ld_b: (0xF133, "LD B, V1"),
+        test_store: (0xF555, "LD [I], V5"),
+        test_load: (0xF565, "LD V5, [I]"),
+    }
+
+    macro_rules! disassemble_extended_test {
+        ($($name:ident: $value:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let (opcode, mnemonic, platform) = $value;
+                    assert_eq!(disassemble_extended(opcode), Ok((mnemonic.to_string(), platform)));
+                }
+            )*
+        }
+    }
+
+    disassemble_extended_test! {
+        test_scd: (0x00C4, "SCD 4", Platform::Schip),
+        test_scu: (0x00D2, "SCU 2", Platform::XoChip),
+        test_scr: (0x00FB, "SCR", Platform::Schip),
+        test_scl: (0x00FC, "SCL", Platform::Schip),
+        test_exit: (0x00FD, "EXIT", Platform::Schip),
+        test_low: (0x00FE, "LOW", Platform::Schip),
+        test_high: (0x00FF, "HIGH", Platform::Schip),
+        test_drw_big: (0xD120, "DRW V1, V2, 0", Platform::Chip8),
+        test_ld_hf: (0xF330, "LD HF, V3", Platform::Schip),
+        test_ld_r: (0xF775, "LD R, V7", Platform::Schip),
+        test_ld_from_r: (0xF785, "LD V7, R", Platform::Schip),
+        test_save_range: (0x5142, "SAVE V1-V4", Platform::XoChip),
+        test_load_range: (0x5423, "LOAD V4-V2", Platform::XoChip),
+        test_ld_i_long: (0xF000, "LD I, long", Platform::XoChip),
+        test_plane: (0xF301, "PLANE 3", Platform::XoChip),
+        test_audio: (0xF002, "AUDIO", Platform::XoChip),
+        test_pitch: (0xF53A, "LD PITCH, V5", Platform::XoChip),
+        test_extended_chip8: (0x6120, "LD V1, 0x20", Platform::Chip8),
+    }
+
+    #[test]
+    fn test_extended_only_in_disassemble_extended() {
+        for opcode in [0x00C4, 0x00FF, 0xF330, 0xF000, 0xF002, 0xF301] {
+            assert_eq!(disassemble(opcode), Err(Chip8Error::UnsupportedOpcode(opcode)));
+        }
+        assert_eq!(
+            disassemble_extended(0xE1FF),
+            Err(Chip8Error::UnsupportedOpcode(0xE1FF))
+        );
+    }
+
+    #[test]
+    fn test_platform_from_str() {
+        assert_eq!("schip".parse(), Ok(Platform::Schip));
+        assert_eq!("XOCHIP".parse(), Ok(Platform::XoChip));
+        assert!("megachip".parse::<Platform>().is_err());
+        assert!(Platform::Chip8 < Platform::Schip && Platform::Schip < Platform::XoChip);
+    }
+
+    #[test]
+    fn test_listing_platform_annotations() {
+        let rom = [
+            0x00, 0xFF, // 0x200: HIGH
+            0xF0, 0x00, 0x23, 0x00, // 0x202: LD I, 0x2300
+            0xF3, 0x01, // 0x206: PLANE 3
+            0x00, 0xFD, // 0x208: EXIT
+            0x60, 0x01, // 0x20A: not reachable
+        ];
+
+        assert_eq!(
+            listing(&rom, 0x200),
+            "\
+0200: 00FF      HIGH                ; needs SCHIP
+0202: F000 2300 LD I, 0x2300        ; needs XO-CHIP
+0206: F301      PLANE 3             ; needs XO-CHIP
+0208: 00FD      EXIT                ; needs SCHIP
+020A: 60        DB 0x60
+020B: 01        DB 0x01
+"
+        );
+        assert_eq!(
+            listing_for(&rom, 0x200, Platform::Schip),
+            "\
+0200: 00FF      HIGH
+0202: F000 2300 LD I, 0x2300        ; needs XO-CHIP
+0206: F301      PLANE 3             ; needs XO-CHIP
+0208: 00FD      EXIT
+020A: 60        DB 0x60
+020B: 01        DB 0x01
+"
+        );
+        assert!(!listing_for(&rom, 0x200, Platform::XoChip).contains("needs"));
+    }
+
+    #[test]
+    fn test_unsupported() {
+        for opcode in [0x0123, 0x8AB8, 0xE1FF, 0xF1FF] {
+            assert_eq!(disassemble(opcode), Err(Chip8Error::UnsupportedOpcode(opcode)));
+        }
+    }
+
+    #[test]
+    fn test_listing() {
+        let rom = [
+            0x22, 0x08, // 0x200: CALL sub_208
+            0xA2, 0x0C, // 0x202: LD I, data_20C
+            0x22, 0x08, // 0x204: CALL sub_208
+            0x12, 0x06, // 0x206: JP loc_206
+            0x60, 0x01, // 0x208: LD V0, 0x01
+            0x00, 0xEE, // 0x20A: RET
+            0xF0, 0x90, // 0x20C: sprite data
+        ];
+
+        let expected = "\
+0200: 2208      CALL sub_208
+0202: A20C      LD I, data_20C
+0204: 2208      CALL sub_208
+loc_206:                ; jumped to from 0x206
+0206: 1206      JP loc_206
+sub_208:                ; called from 0x200, 0x204
+0208: 6001      LD V0, 0x01
+020A: 00EE      RET
+data_20C:               ; referenced from 0x202
+020C: F0        DB 0xf0
+020D: 90        DB 0x90
+";
+        assert_eq!(listing(&rom, 0x200), expected);
+    }
+
+    #[test]
+    fn test_analysis_follows_both_sides_of_skips() {
+        let rom = [
+            0x30, 0x01, // 0x200: SE V0, 0x01
+            0x12, 0x08, // 0x202: JP 0x208
+            0x12, 0x0A, // 0x204: JP 0x20A
+            0x21, 0x00, // 0x206: unreachable, CALL 0x100
+            0x00, 0xEE, // 0x208: RET
+            0x00, 0xEE, // 0x20A: RET
+        ];
+
+        let analysis = analyze(&rom, 0x200);
+        let code: Vec<_> = analysis.code.iter().copied().collect();
+        assert_eq!(code, [0x200, 0x202, 0x204, 0x208, 0x20A]);
+    }
+
+    #[test]
+    fn test_analysis_stops_at_unsupported_and_indirect_jumps() {
+        let rom = [
+            0xB2, 0x06, // 0x200: JP V0, 0x206
+            0x60, 0x01, // 0x202: not reachable
+        ];
+
+        let analysis = analyze(&rom, 0x200);
+        assert_eq!(analysis.code.len(), 1);
+        assert!(listing(&rom, 0x200).contains("0202: 60        DB 0x60"));
+    }
+
+    #[test]
+    fn test_analysis_ignores_targets_outside_of_rom() {
+        let rom = [
+            0x21, 0x00, // 0x200: CALL 0x100
+            0x00, 0xEE, // 0x202: RET
+        ];
+
+        let analysis = analyze(&rom, 0x200);
+        assert_eq!(analysis.code.len(), 2);
+        assert_eq!(
+            listing(&rom, 0x200),
+            "0200: 2100      CALL sub_100\n0202: 00EE      RET\n"
+        );
+    }
+
+    #[test]
+    fn test_detect_byte_swap() {
+        // LD V1, 0x05; LD V1, DT; LD V2, K; ADD V0, 0x01; LD I, 0x2E0
+        let rom = [0x61, 0x05, 0xF1, 0x07, 0xF2, 0x0A, 0x70, 0x01, 0xA2, 0xE0];
+        let swapped = swap_bytes(&rom);
+
+        assert_eq!(swapped[..4], [0x05, 0x61, 0x07, 0xF1]);
+        assert!(!detect_byte_swap(&rom));
+        assert!(detect_byte_swap(&swapped));
+        assert_eq!(swap_bytes(&swapped), rom);
+        assert!(!detect_byte_swap(&[]));
+    }
+
+    #[test]
+    fn test_swap_bytes_odd_length() {
+        assert_eq!(swap_bytes(&[0x12, 0x34, 0x56]), [0x34, 0x12, 0x56]);
+    }
+
+    #[test]
+    fn test_listing_extended_scrolling() {
+        let rom = [0x00, 0xC0, 0x00, 0xC1, 0x00, 0xC2];
+
+        assert_eq!(
+            listing(&rom, 0x200),
+            "\
+0200: 00C0      SCL 1
+0202: 00C1      SCR 1
+0204: 00C2      SCD 2               ; needs SCHIP
+"
+        );
+        assert!(listing_for(&rom, 0x200, Platform::Schip).starts_with("0200: 00C0      SCD 0\n"));
+    }
+
+    #[test]
+    fn test_listing_odd_rom_length() {
+        let rom = [0x00, 0xE0, 0x00];
+        assert_eq!(
+            listing(&rom, 0x200),
+            "0200: 00E0      CLS\n0202: 00        DB 0x00\n"
+        );
+    }
+
+    #[test]
+    fn test_expected_keys() {
+        let memory = [
+            0xF3, 0x0A, // LD V3, K
+            0x43, 0x05, // SNE V3, 0x05
+            0x12, 0x00, // JP up
+            0x33, 0x08, // SE V3, 0x08
+            0x12, 0x10, // JP not_down
+            0x60, 0x01, // LD V0, 0x01
+        ];
+
+        assert_eq!(expected_keys(&memory, 0), Some(vec![0x5, 0x8]));
+    }
+
+    #[test]
+    fn test_expected_keys_without_comparisons() {
+        let memory = [
+            0xF3, 0x0A, // LD V3, K
+            0x44, 0x05, // SNE V4, 0x05: a different register
+        ];
+
+        assert_eq!(expected_keys(&memory, 0), None);
+        // Not an Fx0A
+        assert_eq!(expected_keys(&memory, 2), None);
+        assert_eq!(expected_keys(&memory, 0x100), None);
+    }
+}
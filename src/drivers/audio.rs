@@ -1,12 +1,17 @@
 use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use wheat::chip8::Chip8Event;
+
+use crate::tone::ToneSchedule;
 
 pub struct SdlAudioDriver {
     device: AudioDevice<SquareWave>,
 }
 
 impl SdlAudioDriver {
-    pub fn new(sdl_context: &sdl2::Sdl) -> Self {
-        let audio_subsystem = sdl_context.audio().unwrap();
+    /// Opens the audio device for a program running at `cpu_hertz`, with its timers at
+    /// `timer_hertz`.
+    pub fn new(sdl_context: &sdl2::Sdl, cpu_hertz: f64, timer_hertz: f64) -> Result<Self, String> {
+        let audio_subsystem = sdl_context.audio()?;
 
         let desired_spec = AudioSpecDesired {
             freq: Some(44100),
@@ -14,26 +19,28 @@ impl SdlAudioDriver {
             samples: None,     // default sample size
         };
 
-        let device = audio_subsystem
-            .open_playback(None, &desired_spec, |spec| {
-                // initialize the audio callback
-                SquareWave {
-                    phase_inc: 440.0 / spec.freq as f32,
-                    phase: 0.0,
-                    volume: 0.25,
-                }
-            })
-            .unwrap();
-
-        SdlAudioDriver { device }
+        let device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
+            // initialize the audio callback
+            SquareWave {
+                phase_inc: 440.0 / spec.freq as f32,
+                phase: 0.0,
+                volume: 0.25,
+                tones: ToneSchedule::new(spec.freq as u32, cpu_hertz, timer_hertz),
+            }
+        })?;
+        // Silent until a tone is scheduled
+        device.resume();
+
+        Ok(SdlAudioDriver { device })
     }
 
-    pub fn start_buzzer(&self) {
-        self.device.resume();
+    /// Plays the buzzer for the sound timer writes in `events`, see [`ToneSchedule::schedule`].
+    pub fn schedule(&mut self, events: &[Chip8Event]) {
+        self.device.lock().tones.schedule(events);
     }
 
-    pub fn stop_buzzer(&self) {
-        self.device.pause();
+    pub fn stop_buzzer(&mut self) {
+        self.device.lock().tones.clear();
     }
 }
 
@@ -41,6 +48,7 @@ struct SquareWave {
     phase_inc: f32,
     phase: f32,
     volume: f32,
+    tones: ToneSchedule,
 }
 
 impl AudioCallback for SquareWave {
@@ -49,10 +57,10 @@ impl AudioCallback for SquareWave {
     fn callback(&mut self, out: &mut [f32]) {
         // Generate a square wave
         for x in out.iter_mut() {
-            *x = if self.phase <= 0.5 {
-                self.volume
-            } else {
-                -self.volume
+            *x = match (self.tones.next_sample(), self.phase <= 0.5) {
+                (false, _) => 0.0,
+                (true, true) => self.volume,
+                (true, false) => -self.volume,
             };
             self.phase = (self.phase + self.phase_inc) % 1.0;
         }
@@ -1,57 +1,358 @@
+use std::time::Duration;
+
 use sdl2::{pixels, rect::Rect, render::Canvas, video::Window};
 
+use wheat::chip8::HEX_DIGITS;
+use wheat::graphics::{Coord, Palette, SCREEN_DIMENSIONS};
 use wheat::traits::Display;
-use wheat::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+use crate::frametimes::{FrameTimes, FRAME_BUDGET, HISTORY};
 
 const SCALE_FACTOR: u16 = 20;
-const DISPLAY_WIDTH: u16 = SCREEN_WIDTH * SCALE_FACTOR;
-const DISPLAY_HEIGHT: u16 = SCREEN_HEIGHT * SCALE_FACTOR;
+const DISPLAY_WIDTH: u16 = SCREEN_DIMENSIONS.w * SCALE_FACTOR;
+const DISPLAY_HEIGHT: u16 = SCREEN_DIMENSIONS.h * SCALE_FACTOR;
 const TITLE: &str = "Chip 8";
 
+/// Window pixels per millisecond of frame time in the diagnostics graph.
+const GRAPH_PX_PER_MS: u32 = 4;
+/// Height of the diagnostics graph, which fits frames of up to 50ms.
+const GRAPH_HEIGHT: u32 = 50 * GRAPH_PX_PER_MS;
+const GRAPH_BAR_WIDTH: u32 = 4;
+/// Space between the diagnostics and the edges of the window.
+const GRAPH_MARGIN: i32 = 8;
+/// Window pixels per font pixel for the numbers in the diagnostics.
+const DIGIT_SCALE: u32 = 3;
+
+/// How each screen pixel is drawn in the window.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DisplayConfig {
+    /// Window pixels left in the background colour between screen pixels, for a visible grid.
+    pub pixel_gap: u16,
+    /// Cuts the corners off each pixel for a softer look.
+    pub rounded_pixels: bool,
+}
+
+/// Frame pacing diagnostics, drawn over the screen while they're shown.
+#[derive(Default)]
+struct Diagnostics {
+    frame_times: FrameTimes,
+    /// Timer ticks the emulator still owes, see `Chip8::get_owed_timer_ticks`.
+    backlog: u32,
+}
+
 /// The window that displays the Chip 8 buffer to the screen.
 pub struct SdlDisplayDriver {
     canvas: Canvas<Window>,
+    palette: [[u8; 3]; 16],
+    config: DisplayConfig,
+    diagnostics: Option<Diagnostics>,
 }
 
 impl SdlDisplayDriver {
     /// Creates a new display window and clears it to black.
-    pub fn new(sdl_context: &sdl2::Sdl) -> SdlDisplayDriver {
-        let video_subsystem = sdl_context.video().unwrap();
+    pub fn new(sdl_context: &sdl2::Sdl) -> Result<SdlDisplayDriver, String> {
+        let video_subsystem = sdl_context.video()?;
         let window = video_subsystem
             .window(TITLE, DISPLAY_WIDTH as u32, DISPLAY_HEIGHT as u32)
             .opengl()
             .position_centered()
             .build()
-            .unwrap();
+            .map_err(|e| e.to_string())?;
 
-        let mut canvas = window.into_canvas().build().unwrap();
+        let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
         canvas.set_draw_color(pixels::Color::RGB(0, 0, 0));
         canvas.clear();
         canvas.present();
 
-        Self { canvas }
+        Ok(Self {
+            canvas,
+            palette: Palette::default().colors(),
+            config: DisplayConfig::default(),
+            diagnostics: None,
+        })
     }
+
+    /// Leaves a gap of `gap_px` window pixels between screen pixels. The gap is capped so that
+    /// every pixel stays at least one window pixel wide.
+    pub fn with_pixel_gap(mut self, gap_px: u16) -> Self {
+        self.config.pixel_gap = gap_px.min(SCALE_FACTOR - 1);
+        self
+    }
+
+    pub fn with_palette(mut self, palette: &Palette) -> Self {
+        self.palette = palette.colors();
+        self
+    }
+
+    pub fn with_rounded_pixels(mut self, rounded: bool) -> Self {
+        self.config.rounded_pixels = rounded;
+        self
+    }
+
+    /// Replaces the contents of the system clipboard with `text`.
+    pub fn copy_to_clipboard(&self, text: &str) -> Result<(), String> {
+        self.canvas
+            .window()
+            .subsystem()
+            .clipboard()
+            .set_clipboard_text(text)
+    }
+
+    /// Shows or hides the frame pacing diagnostics. When hiding them, returns the frame times
+    /// collected while they were shown.
+    pub fn toggle_diagnostics(&mut self) -> Option<FrameTimes> {
+        match self.diagnostics.take() {
+            Some(diagnostics) => Some(diagnostics.frame_times),
+            None => {
+                self.diagnostics = Some(Diagnostics::default());
+                None
+            }
+        }
+    }
+
+    pub fn diagnostics_shown(&self) -> bool {
+        self.diagnostics.is_some()
+    }
+
+    /// Records a frame that took `time` for the diagnostics, along with the emulator's timer
+    /// backlog. Does nothing while the diagnostics are hidden.
+    pub fn record_frame(&mut self, time: Duration, backlog: u32) {
+        if let Some(diagnostics) = &mut self.diagnostics {
+            diagnostics.frame_times.push(time);
+            diagnostics.backlog = backlog;
+        }
+    }
+
+    /// Shows `name` in the window title next to the emulator's name.
+    pub fn set_title(&mut self, name: &str) {
+        let _ = self.canvas.window_mut().set_title(&format!("{TITLE} - {name}"));
+    }
+}
+
+/// Returns the area of the window covered by the screen pixel at `pos`, leaving `gap` window
+/// pixels free on its bottom and right edges.
+fn pixel_rect(pos: Coord, gap: u16) -> Rect {
+    let size = (SCALE_FACTOR - gap) as u32;
+    Rect::new(
+        (pos.x * SCALE_FACTOR) as i32,
+        (pos.y * SCALE_FACTOR) as i32,
+        size,
+        size,
+    )
+}
+
+/// Approximates a rounded pixel with two overlapping rects, one narrowed and one shortened, which
+/// leaves the corners empty.
+fn rounded_rects(rect: Rect) -> [Rect; 2] {
+    let inset = (rect.width() / 5).max(1);
+    let (x, y, w, h) = (rect.x(), rect.y(), rect.width(), rect.height());
+
+    [
+        Rect::new(x + inset as i32, y, w.saturating_sub(2 * inset).max(1), h),
+        Rect::new(x, y + inset as i32, w, h.saturating_sub(2 * inset).max(1)),
+    ]
+}
+
+/// Returns the color drawn for a pixel with value `val`.
+fn pixel_color(palette: &[[u8; 3]; 16], val: u8) -> pixels::Color {
+    let [r, g, b] = palette[(val & 0xF) as usize];
+    pixels::Color::RGB(r, g, b)
+}
+
+/// Returns the bar of the diagnostics graph for the `index`th frame, which took `time`. The
+/// graph's bottom edge is at `bottom`.
+fn graph_bar(index: usize, time: Duration, bottom: i32) -> Rect {
+    let height = (time.as_micros() as u32 * GRAPH_PX_PER_MS / 1000).clamp(1, GRAPH_HEIGHT);
+    Rect::new(
+        GRAPH_MARGIN + (index as u32 * GRAPH_BAR_WIDTH) as i32,
+        bottom - height as i32,
+        GRAPH_BAR_WIDTH - 1,
+        height,
+    )
+}
+
+/// Returns the rects that write `value` in decimal with the Chip 8 font, starting at the top left
+/// corner `(x, y)`.
+fn digit_rects(value: u64, x: i32, y: i32) -> Vec<Rect> {
+    let scale = DIGIT_SCALE as i32;
+    let mut rects = Vec::new();
+
+    for (i, digit) in value.to_string().bytes().enumerate() {
+        let glyph = &HEX_DIGITS[(digit - b'0') as usize * 5..][..5];
+        let left = x + i as i32 * 5 * scale;
+
+        for (row, bits) in glyph.iter().enumerate() {
+            for col in 0..4 {
+                if bits & (0x80 >> col) != 0 {
+                    let (x, y) = (left + col * scale, y + row as i32 * scale);
+                    rects.push(Rect::new(x, y, DIGIT_SCALE, DIGIT_SCALE));
+                }
+            }
+        }
+    }
+
+    rects
+}
+
+/// Draws the frame time graph in the bottom left corner of the window, with the missed frame
+/// count (red) and the timer backlog (yellow) above it.
+fn draw_diagnostics(canvas: &mut Canvas<Window>, diagnostics: &Diagnostics) {
+    let bottom = DISPLAY_HEIGHT as i32 - GRAPH_MARGIN;
+    let top = bottom - GRAPH_HEIGHT as i32;
+
+    canvas.set_draw_color(pixels::Color::RGB(32, 32, 32));
+    let _ = canvas.fill_rect(Rect::new(
+        GRAPH_MARGIN,
+        top,
+        HISTORY as u32 * GRAPH_BAR_WIDTH,
+        GRAPH_HEIGHT,
+    ));
+
+    for (i, time) in diagnostics.frame_times.iter().enumerate() {
+        let color = if time > FRAME_BUDGET {
+            pixels::Color::RGB(220, 50, 50)
+        } else {
+            pixels::Color::RGB(50, 200, 80)
+        };
+        canvas.set_draw_color(color);
+        let _ = canvas.fill_rect(graph_bar(i, time, bottom));
+    }
+
+    // The budget, as a line across the graph
+    let budget = graph_bar(0, FRAME_BUDGET, bottom);
+    canvas.set_draw_color(pixels::Color::RGB(255, 255, 255));
+    let _ = canvas.fill_rect(Rect::new(
+        GRAPH_MARGIN,
+        budget.y(),
+        HISTORY as u32 * GRAPH_BAR_WIDTH,
+        1,
+    ));
+
+    let digits_top = top - GRAPH_MARGIN - 5 * DIGIT_SCALE as i32;
+    canvas.set_draw_color(pixels::Color::RGB(220, 50, 50));
+    let _ = canvas.fill_rects(&digit_rects(
+        diagnostics.frame_times.missed(),
+        GRAPH_MARGIN,
+        digits_top,
+    ));
+    canvas.set_draw_color(pixels::Color::RGB(240, 200, 0));
+    let _ = canvas.fill_rects(&digit_rects(
+        diagnostics.backlog.into(),
+        GRAPH_MARGIN + HISTORY as i32 * GRAPH_BAR_WIDTH as i32 / 2,
+        digits_top,
+    ));
 }
 
 impl Display for SdlDisplayDriver {
     fn draw(&mut self, buffer: &[Vec<u8>]) {
-        for row in 0..SCREEN_HEIGHT {
-            for col in 0..SCREEN_WIDTH {
-                let x = col * SCALE_FACTOR;
-                let y = row * SCALE_FACTOR;
+        // The background shows through the gaps and rounded corners
+        self.canvas.set_draw_color(pixel_color(&self.palette, 0));
+        self.canvas.clear();
 
+        for row in 0..SCREEN_DIMENSIONS.h {
+            for col in 0..SCREEN_DIMENSIONS.w {
                 let val = buffer[row as usize][col as usize];
-                let color = pixels::Color::RGB(val * 255, val * 255, val * 255);
+                let color = pixel_color(&self.palette, val);
+                let rect = pixel_rect(Coord::new(col, row), self.config.pixel_gap);
 
                 self.canvas.set_draw_color(color);
-                let _ = self.canvas.fill_rect(Rect::new(
-                    x as i32,
-                    y as i32,
-                    SCALE_FACTOR as u32,
-                    SCALE_FACTOR as u32,
-                ));
+                let _ = if self.config.rounded_pixels {
+                    self.canvas.fill_rects(&rounded_rects(rect))
+                } else {
+                    self.canvas.fill_rect(rect)
+                };
             }
         }
+
+        if let Some(diagnostics) = &self.diagnostics {
+            draw_diagnostics(&mut self.canvas, diagnostics);
+        }
         self.canvas.present();
     }
+
+    fn set_palette(&mut self, colors: &[[u8; 3]; 16]) {
+        self.palette = *colors;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use sdl2::{pixels::Color, rect::Rect};
+    use wheat::graphics::{Coord, Palette};
+
+    use super::{digit_rects, graph_bar, pixel_color, pixel_rect, rounded_rects, GRAPH_HEIGHT};
+
+    #[test]
+    fn test_pixel_rect() {
+        assert_eq!(pixel_rect(Coord::new(1, 2), 0), Rect::new(20, 40, 20, 20));
+    }
+
+    #[test]
+    fn test_pixel_rect_with_gap() {
+        assert_eq!(pixel_rect(Coord::new(1, 2), 2), Rect::new(20, 40, 18, 18));
+    }
+
+    #[test]
+    fn test_rounded_rects() {
+        let [narrow, short] = rounded_rects(Rect::new(20, 40, 20, 20));
+        assert_eq!(narrow, Rect::new(24, 40, 12, 20));
+        assert_eq!(short, Rect::new(20, 44, 20, 12));
+    }
+
+    #[test]
+    fn test_default_palette_is_monochrome() {
+        let colors = Palette::default().colors();
+        assert_eq!(pixel_color(&colors, 0), Color::RGB(0, 0, 0));
+        assert_eq!(pixel_color(&colors, 1), Color::RGB(255, 255, 255));
+    }
+
+    #[test]
+    fn test_palette_color() {
+        let mut palette = Palette::default().colors();
+        palette[3] = [0x12, 0x34, 0x56];
+
+        assert_eq!(pixel_color(&palette, 3), Color::RGB(0x12, 0x34, 0x56));
+        assert_eq!(pixel_color(&palette, 2), Color::RGB(255, 255, 255));
+    }
+
+    #[test]
+    fn test_draws_library_palette() {
+        let palette = Palette::builtins()
+            .into_iter()
+            .find(|palette| palette.name == "okabe-ito")
+            .unwrap();
+        let colors = palette.colors();
+        let rgb = |[r, g, b]: [u8; 3]| Color::RGB(r, g, b);
+
+        assert_eq!(pixel_color(&colors, 0), rgb(palette.bg));
+        assert_eq!(pixel_color(&colors, 1), rgb(palette.fg));
+        assert_eq!(pixel_color(&colors, 3), rgb(palette.planes.unwrap()[1]));
+    }
+
+    #[test]
+    fn test_graph_bar() {
+        let bar = graph_bar(2, Duration::from_micros(16_500), 600);
+        assert_eq!(bar, Rect::new(16, 534, 3, 66));
+    }
+
+    #[test]
+    fn test_graph_bar_is_clamped() {
+        assert_eq!(graph_bar(0, Duration::ZERO, 600).height(), 1);
+        assert_eq!(graph_bar(0, Duration::from_secs(1), 600).height(), GRAPH_HEIGHT);
+    }
+
+    #[test]
+    fn test_digit_rects() {
+        // "1" is 0x20, 0x60, 0x20, 0x20, 0x70: 7 pixels
+        let rects = digit_rects(1, 10, 20);
+        assert_eq!(rects.len(), 7);
+        assert_eq!(rects[0], Rect::new(16, 20, 3, 3));
+
+        // The second digit starts one glyph (5 font pixels) further right
+        let rects = digit_rects(11, 10, 20);
+        assert_eq!(rects.len(), 14);
+        assert_eq!(rects[7], Rect::new(31, 20, 3, 3));
+    }
 }
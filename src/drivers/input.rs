@@ -1,11 +1,68 @@
-use std::{ops::Deref, sync::mpsc::Receiver};
+use std::{fmt, ops::Deref, str::FromStr, sync::mpsc::Receiver};
 
-use sdl2::{keyboard::Keycode, EventPump};
+use sdl2::{
+    keyboard::{Keycode, Scancode},
+    EventPump,
+};
 use thiserror::Error;
 use wheat::{traits::Input, Key};
 
 const NUM_KEYS: usize = 16;
 
+/// The physical key each Chip 8 key is bound to, indexed by the Chip 8 key. The inverse of the
+/// mapping in `TryFrom<Keycode> for Chip8Key`.
+const KEY_LABELS: [&str; NUM_KEYS] = [
+    "X", "1", "2", "3", "Q", "W", "E", "A", "S", "D", "Z", "C", "4", "R", "F", "V",
+];
+
+/// Returns the name of the physical key bound to the Chip 8 key `key`.
+pub fn key_label(key: u8) -> &'static str {
+    KEY_LABELS.get(key as usize).copied().unwrap_or("?")
+}
+
+/// How [`SdlInput`] keeps the key states up to date.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputMode {
+    /// Rescan the whole keyboard every time the input thread ticks, i.e. at `--freq-input`. Taps
+    /// shorter than the time between two scans can be missed.
+    Polled,
+    /// Press and release keys as the key events arrive, so that every tap is seen.
+    Events,
+}
+
+impl InputMode {
+    /// Returns the other mode.
+    pub fn toggled(self) -> Self {
+        match self {
+            InputMode::Polled => InputMode::Events,
+            InputMode::Events => InputMode::Polled,
+        }
+    }
+}
+
+impl fmt::Display for InputMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            InputMode::Polled => "polled",
+            InputMode::Events => "events",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Parses the names printed by `Display`.
+impl FromStr for InputMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "polled" => Ok(InputMode::Polled),
+            "events" => Ok(InputMode::Events),
+            _ => Err(format!("unknown input mode `{s}`, expected `polled` or `events`")),
+        }
+    }
+}
+
 /// Keeps track of the state of the keys. Chip8 uses 16 keys; this implementation
 /// defines the following:
 ///
@@ -21,50 +78,108 @@ pub struct SdlInput {
     input_impl: SdlInputImpl,
     event_pump: EventPump,
     rx: Receiver<()>,
+    mode: InputMode,
 }
 
 impl SdlInput {
     /// Creates a new `Input` with all key states set to `false`.
-    pub fn new(sdl: &sdl2::Sdl, rx: Receiver<()>) -> Self {
+    pub fn new(sdl: &sdl2::Sdl, rx: Receiver<()>, mode: InputMode) -> Self {
         let event_pump = sdl.event_pump().unwrap();
         SdlInput {
             input_impl: SdlInputImpl::new(),
             event_pump,
             rx,
+            mode,
         }
     }
 
+    /// Switches to `mode`. The keys are rescanned straight away, so that a key released while
+    /// the previous mode wasn't looking doesn't stay pressed.
+    fn set_mode(&mut self, mode: InputMode) {
+        self.mode = mode;
+        let pressed = self.pressed_keycodes();
+        self.input_impl.scan(pressed);
+    }
+
+    fn pressed_keycodes(&self) -> Vec<Keycode> {
+        self.event_pump
+            .keyboard_state()
+            .pressed_scancodes()
+            .filter_map(Keycode::from_scancode)
+            .collect()
+    }
+
     pub fn update(&mut self) -> InputUpdate {
         if let Some(event) = self.event_pump.poll_event() {
             use sdl2::event::Event;
-            if let Event::Quit { .. } = event {
-                return InputUpdate::Quit;
+            match event {
+                Event::Quit { .. } => return InputUpdate::Quit,
+                Event::KeyDown {
+                    keycode: Some(Keycode::PageDown),
+                    repeat: false,
+                    ..
+                } => return InputUpdate::NextRom,
+                Event::KeyDown {
+                    keycode: Some(Keycode::PageUp),
+                    repeat: false,
+                    ..
+                } => return InputUpdate::PrevRom,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F10),
+                    repeat: false,
+                    ..
+                } => return InputUpdate::Screenshot,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F3),
+                    repeat: false,
+                    ..
+                } => return InputUpdate::ToggleDiagnostics,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    repeat: false,
+                    ..
+                } => return InputUpdate::Reset,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F4),
+                    repeat: false,
+                    ..
+                } => {
+                    self.set_mode(self.mode.toggled());
+                    return InputUpdate::InputModeChanged(self.mode);
+                }
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    repeat: false,
+                    ..
+                } => self.input_impl.key_event(self.mode, keycode, true),
+                Event::KeyUp {
+                    keycode: Some(keycode),
+                    ..
+                } => self.input_impl.key_event(self.mode, keycode, false),
+                _ => (),
             }
         }
 
         // Don't update input every frame; otherwise input is very janky
+        let mut ticked = false;
         while let Ok(()) = self.rx.try_recv() {
-            let keys_pressed: Vec<_> = self
-                .event_pump
-                .keyboard_state()
-                .pressed_scancodes()
-                .filter_map(Keycode::from_scancode)
-                .collect();
-
-            for i in 0..self.input_impl.keys.len() {
-                self.input_impl.keys[i] = false;
-            }
-
-            for k in keys_pressed {
-                if let Ok(chip8_key) = <Keycode as TryInto<Chip8Key>>::try_into(k) {
-                    self.input_impl.keys[*chip8_key as usize] = true;
-                }
-            }
+            ticked = true;
+        }
+        if ticked {
+            let pressed = self.pressed_keycodes();
+            self.input_impl.scan_tick(self.mode, pressed);
         }
 
         InputUpdate::Continue
     }
 
+    /// Whether Backspace, which rewinds the ROM, is held.
+    pub fn is_rewinding(&self) -> bool {
+        self.event_pump
+            .keyboard_state()
+            .is_scancode_pressed(Scancode::Backspace)
+    }
+
     pub fn input(&self) -> &SdlInputImpl {
         &self.input_impl
     }
@@ -74,6 +189,18 @@ impl SdlInput {
 pub enum InputUpdate {
     Continue,
     Quit,
+    /// Switch to the next ROM in the playlist.
+    NextRom,
+    /// Switch to the previous ROM in the playlist.
+    PrevRom,
+    /// Take a text screenshot of the screen.
+    Screenshot,
+    /// Show or hide the frame pacing diagnostics.
+    ToggleDiagnostics,
+    /// Restart the current ROM.
+    Reset,
+    /// The input mode was switched to this one.
+    InputModeChanged(InputMode),
 }
 
 #[derive(Debug, Error)]
@@ -128,6 +255,34 @@ impl SdlInputImpl {
             keys: vec![false; NUM_KEYS],
         }
     }
+
+    /// Replaces every key state with the keys in `pressed`.
+    fn scan(&mut self, pressed: impl IntoIterator<Item = Keycode>) {
+        self.keys.fill(false);
+        for keycode in pressed {
+            if let Ok(chip8_key) = Chip8Key::try_from(keycode) {
+                self.keys[*chip8_key as usize] = true;
+            }
+        }
+    }
+
+    /// Called when the input thread ticks, with the keys held down at the time. Only the polled
+    /// mode uses it.
+    fn scan_tick(&mut self, mode: InputMode, pressed: impl IntoIterator<Item = Keycode>) {
+        if mode == InputMode::Polled {
+            self.scan(pressed);
+        }
+    }
+
+    /// Called for every key press and release. Only the events mode uses it.
+    fn key_event(&mut self, mode: InputMode, keycode: Keycode, down: bool) {
+        if mode != InputMode::Events {
+            return;
+        }
+        if let Ok(chip8_key) = Chip8Key::try_from(keycode) {
+            self.keys[*chip8_key as usize] = down;
+        }
+    }
 }
 
 impl Input for SdlInputImpl {
@@ -138,7 +293,7 @@ impl Input for SdlInputImpl {
 
 #[cfg(test)]
 mod tests {
-    use super::{Chip8Key, SdlInputImpl};
+    use super::{key_label, Chip8Key, InputMode, SdlInputImpl};
     use sdl2::keyboard::Keycode;
     use wheat::{traits::Input, Key};
 
@@ -174,4 +329,107 @@ mod tests {
         test_c: (Keycode::C, Key::B),
         test_v: (Keycode::V, Key::F),
     }
+
+    #[test]
+    fn test_key_labels_match_bindings() {
+        for key in 0..=0xF {
+            let keycode = Keycode::from_name(key_label(key)).unwrap();
+            let chip8_key = <Keycode as TryInto<Chip8Key>>::try_into(keycode).unwrap();
+            assert_eq!(u8::from(*chip8_key), key);
+        }
+        assert_eq!(key_label(0x10), "?");
+    }
+
+    /// What happens to the keyboard, in order.
+    enum Step {
+        Down(Keycode),
+        Up(Keycode),
+        /// The input thread ticks
+        Tick,
+        /// Switch to the other input mode
+        Switch,
+    }
+
+    /// Feeds `steps` to the input, and returns whether `X` (key `0`) is pressed after each one.
+    fn run(mode: InputMode, steps: &[Step]) -> Vec<bool> {
+        let mut input = SdlInputImpl::new();
+        let mut mode = mode;
+        let mut held = Vec::new();
+
+        steps
+            .iter()
+            .map(|step| {
+                match step {
+                    Step::Down(keycode) => {
+                        held.push(*keycode);
+                        input.key_event(mode, *keycode, true);
+                    }
+                    Step::Up(keycode) => {
+                        held.retain(|held| held != keycode);
+                        input.key_event(mode, *keycode, false);
+                    }
+                    Step::Tick => input.scan_tick(mode, held.clone()),
+                    Step::Switch => {
+                        mode = mode.toggled();
+                        input.scan(held.clone());
+                    }
+                }
+                input.is_pressed(Key::Num0)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_short_tap() {
+        let steps = [
+            Step::Tick,
+            Step::Down(Keycode::X),
+            Step::Up(Keycode::X),
+            Step::Tick,
+        ];
+
+        // The tap falls between two scans, so the polled mode never sees it
+        assert_eq!(run(InputMode::Polled, &steps), [false, false, false, false]);
+        assert_eq!(run(InputMode::Events, &steps), [false, true, false, false]);
+    }
+
+    #[test]
+    fn test_held_key() {
+        let steps = [
+            Step::Down(Keycode::X),
+            Step::Tick,
+            Step::Tick,
+            Step::Up(Keycode::X),
+            Step::Tick,
+        ];
+
+        assert_eq!(run(InputMode::Polled, &steps), [false, true, true, true, false]);
+        assert_eq!(run(InputMode::Events, &steps), [true, true, true, false, false]);
+    }
+
+    #[test]
+    fn test_switch_mode_while_held() {
+        // Switching rescans the keys, so a key held across the switch stays pressed until the new
+        // mode sees it released
+        let steps = [
+            Step::Down(Keycode::X),
+            Step::Switch,
+            Step::Up(Keycode::X),
+            Step::Tick,
+        ];
+        assert_eq!(run(InputMode::Events, &steps), [true, true, true, false]);
+        assert_eq!(run(InputMode::Polled, &steps), [false, true, false, false]);
+
+        // and a key released before the switch isn't left pressed by the old mode
+        let steps = [Step::Down(Keycode::X), Step::Up(Keycode::X), Step::Switch];
+        assert_eq!(run(InputMode::Polled, &steps), [false, false, false]);
+    }
+
+    #[test]
+    fn test_input_mode_from_str() {
+        for mode in [InputMode::Polled, InputMode::Events] {
+            assert_eq!(mode.to_string().parse(), Ok(mode));
+        }
+        assert!("scanned".parse::<InputMode>().is_err());
+    }
 }
@@ -5,5 +5,5 @@ mod rom;
 
 pub use self::audio::SdlAudioDriver;
 pub use self::display::SdlDisplayDriver;
-pub use self::input::{InputUpdate, SdlInput};
+pub use self::input::{key_label, InputMode, InputUpdate, SdlInput};
 pub use self::rom::RomDriver;
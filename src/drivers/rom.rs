@@ -1,4 +1,5 @@
 use std::fs::{self};
+use std::io;
 
 use wheat::traits::Rom;
 
@@ -7,10 +8,10 @@ pub struct RomDriver {
 }
 
 impl RomDriver {
-    pub fn new(filename: &str) -> Self {
-        let rom = fs::read(filename).unwrap();
+    pub fn new(filename: &str) -> io::Result<Self> {
+        let rom = fs::read(filename)?;
 
-        Self { rom }
+        Ok(Self { rom })
     }
 }
 
@@ -0,0 +1,143 @@
+//! A triple-buffered channel that hands frames from the emulation thread to a render thread.
+//!
+//! The channel keeps three slots: the publisher writes the next frame into its own slot, the
+//! subscriber reads from its own, and the third holds the newest frame neither of them owns. A
+//! publish swaps the publisher's slot with the middle one, and [`FrameSubscriber::latest`] swaps
+//! the subscriber's slot with it when there's a newer frame. So:
+//!
+//! - neither side ever waits for the other: a slot's lock is only taken by the side that owns the
+//!   slot at the time, so it's never contended;
+//! - the subscriber only gets whole frames, as a frame is handed over once it's published;
+//! - frames the subscriber doesn't pick up in time are replaced by newer ones, and the subscriber
+//!   only ever gets frames newer than the last one it got.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::chip8::FrameSnapshot;
+
+/// Set next to the index of the middle slot when it holds a frame the subscriber hasn't taken.
+const FRESH: usize = 0b100;
+const INDEX: usize = 0b011;
+
+struct Shared<T> {
+    slots: [Mutex<Option<T>>; 3],
+    /// The index of the middle slot, and [`FRESH`]
+    middle: AtomicUsize,
+}
+
+impl<T> Shared<T> {
+    fn put(&self, slot: usize, frame: Option<T>) {
+        *self.slots[slot].lock().unwrap_or_else(PoisonError::into_inner) = frame;
+    }
+
+    fn take(&self, slot: usize) -> Option<T> {
+        self.slots[slot]
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+    }
+}
+
+/// The emulation side of a [`frame_channel`].
+pub struct FramePublisher<T = FrameSnapshot> {
+    shared: Arc<Shared<T>>,
+    /// The slot the next frame goes in
+    back: usize,
+}
+
+/// The render side of a [`frame_channel`].
+pub struct FrameSubscriber<T = FrameSnapshot> {
+    shared: Arc<Shared<T>>,
+    /// The slot the last frame was taken from
+    front: usize,
+}
+
+/// Creates a channel for frames, usually [`FrameSnapshot`]s. See the [module docs](self) for
+/// what it guarantees.
+pub fn frame_channel<T: Send>() -> (FramePublisher<T>, FrameSubscriber<T>) {
+    let shared = Arc::new(Shared {
+        slots: [Mutex::new(None), Mutex::new(None), Mutex::new(None)],
+        middle: AtomicUsize::new(1),
+    });
+    let publisher = FramePublisher {
+        shared: Arc::clone(&shared),
+        back: 0,
+    };
+    (publisher, FrameSubscriber { shared, front: 2 })
+}
+
+impl<T: Send> FramePublisher<T> {
+    /// Makes `frame` the latest one, replacing the previous one if the subscriber hasn't taken it
+    /// yet. The emulation side only needs to publish outputs that drew something.
+    pub fn publish(&mut self, frame: T) {
+        self.shared.put(self.back, Some(frame));
+        let previous = self.shared.middle.swap(self.back | FRESH, Ordering::AcqRel);
+        self.back = previous & INDEX;
+    }
+}
+
+impl<T: Send> FrameSubscriber<T> {
+    /// Takes the latest frame, or returns `None` if nothing was published since the last call.
+    pub fn latest(&mut self) -> Option<T> {
+        if self.shared.middle.load(Ordering::Acquire) & FRESH == 0 {
+            return None;
+        }
+        let middle = self.shared.middle.swap(self.front, Ordering::AcqRel);
+        self.front = middle & INDEX;
+        self.shared.take(self.front)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::frame_channel;
+
+    #[test]
+    fn test_latest_frame_wins() {
+        let (mut publisher, mut subscriber) = frame_channel();
+        assert_eq!(subscriber.latest(), None);
+
+        publisher.publish(1);
+        assert_eq!(subscriber.latest(), Some(1));
+        assert_eq!(subscriber.latest(), None);
+
+        for frame in 2..=5 {
+            publisher.publish(frame);
+        }
+        assert_eq!(subscriber.latest(), Some(5));
+        assert_eq!(subscriber.latest(), None);
+    }
+
+    #[test]
+    fn test_frames_arrive_whole_and_in_order() {
+        const FRAMES: u64 = 20_000;
+        let (mut publisher, mut subscriber) = frame_channel();
+
+        let producer = thread::spawn(move || {
+            for frame in 1..=FRAMES {
+                // Every row holds the frame number, so a torn frame would mix numbers
+                publisher.publish([frame; 32]);
+            }
+        });
+
+        let mut last = 0;
+        let mut received = 0;
+        while last < FRAMES {
+            let Some(rows) = subscriber.latest() else {
+                thread::yield_now();
+                continue;
+            };
+            assert!(rows.iter().all(|&row| row == rows[0]), "torn frame: {rows:?}");
+            assert!(rows[0] > last, "frame {} after {last}", rows[0]);
+            last = rows[0];
+            received += 1;
+        }
+
+        producer.join().unwrap();
+        assert!(received >= 1);
+        assert_eq!(subscriber.latest(), None);
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use wheat::FRAMES_PER_SECOND;
+
+/// Time for one frame. Frames that take longer are counted as missed: they weren't ready for the
+/// next refresh.
+pub const FRAME_BUDGET: Duration = Duration::from_nanos((1e9 / FRAMES_PER_SECOND) as u64);
+
+/// Number of frames kept for the graph and the percentiles.
+pub const HISTORY: usize = 120;
+
+/// Percentiles of the frame times kept by [`FrameTimes`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameSummary {
+    pub p50: Duration,
+    pub p95: Duration,
+    pub max: Duration,
+}
+
+/// The last [`HISTORY`] frame times, oldest first, and how many frames were missed in total.
+#[derive(Debug, Default)]
+pub struct FrameTimes {
+    times: VecDeque<Duration>,
+    missed: u64,
+}
+
+impl FrameTimes {
+    /// Records a frame that took `time`, dropping the oldest one once the history is full.
+    pub fn push(&mut self, time: Duration) {
+        if self.times.len() == HISTORY {
+            self.times.pop_front();
+        }
+        self.times.push_back(time);
+
+        if time > FRAME_BUDGET {
+            self.missed += 1;
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Duration> + '_ {
+        self.times.iter().copied()
+    }
+
+    /// Frames missed since the counter was created. Unlike the history, this is never trimmed.
+    pub fn missed(&self) -> u64 {
+        self.missed
+    }
+
+    /// Returns the percentiles of the frames in the history, or `None` if there are none.
+    pub fn summary(&self) -> Option<FrameSummary> {
+        if self.times.is_empty() {
+            return None;
+        }
+
+        let mut sorted: Vec<_> = self.iter().collect();
+        sorted.sort();
+        // Nearest-rank percentile
+        let percentile = |p: usize| sorted[(sorted.len() * p).div_ceil(100).max(1) - 1];
+
+        Some(FrameSummary {
+            p50: percentile(50),
+            p95: percentile(95),
+            max: sorted[sorted.len() - 1],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{FrameSummary, FrameTimes, FRAME_BUDGET, HISTORY};
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_keeps_last_frames() {
+        let mut times = FrameTimes::default();
+        for i in 0..HISTORY as u64 + 5 {
+            times.push(ms(i));
+        }
+
+        let kept: Vec<_> = times.iter().collect();
+        assert_eq!(kept.len(), HISTORY);
+        assert_eq!(kept[0], ms(5));
+        assert_eq!(kept[HISTORY - 1], ms(HISTORY as u64 + 4));
+    }
+
+    #[test]
+    fn test_missed_frames() {
+        let mut times = FrameTimes::default();
+        times.push(ms(16));
+        times.push(ms(17));
+        times.push(ms(40));
+        times.push(ms(100));
+
+        assert_eq!(times.missed(), 3);
+    }
+
+    #[test]
+    fn test_missed_frame_boundary() {
+        let mut times = FrameTimes::default();
+        times.push(FRAME_BUDGET);
+        times.push(Duration::from_micros(16_666));
+        assert_eq!(times.missed(), 0);
+
+        times.push(Duration::from_micros(16_700));
+        assert_eq!(times.missed(), 1);
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut times = FrameTimes::default();
+        assert_eq!(times.summary(), None);
+
+        // 1..=100ms, shuffled a little
+        for i in (51..=100).chain(1..=50) {
+            times.push(ms(i));
+        }
+
+        assert_eq!(
+            times.summary(),
+            Some(FrameSummary {
+                p50: ms(50),
+                p95: ms(95),
+                max: ms(100),
+            })
+        );
+    }
+
+    #[test]
+    fn test_summary_single_frame() {
+        let mut times = FrameTimes::default();
+        times.push(ms(7));
+
+        let summary = times.summary().unwrap();
+        assert_eq!((summary.p50, summary.p95, summary.max), (ms(7), ms(7), ms(7)));
+    }
+}
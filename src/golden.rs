@@ -0,0 +1,227 @@
+//! Headless screenshots taken at exact frame numbers, compared against golden files saved from an
+//! earlier run, to catch changes to what a ROM draws while refactoring the emulator.
+//!
+//! A frame is 1/60 of a second: the timers tick once, then the instructions due by the end of the
+//! frame run. Frame 0 is the screen before the first instruction. ROMs that use `RND` can't be
+//! compared, as the random numbers differ between runs.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+
+use crate::chip8::{Chip8, NoInput};
+use crate::graphics::{self, Graphics};
+use crate::timer::TimerOperation;
+use crate::traits::GraphicsBuffer;
+use crate::{Chip8Error, DebugOptions, Quirks, FRAMES_PER_SECOND};
+
+/// What [`check`] found for one frame.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GoldenOutcome {
+    Matched,
+    /// The screen differs from the golden, with the rows that differ as returned by [`diff`].
+    Mismatched(String),
+    /// There's no golden for the frame yet.
+    Missing,
+    /// The golden was written with the current screen.
+    Updated,
+}
+
+impl GoldenOutcome {
+    pub fn passed(&self) -> bool {
+        matches!(self, GoldenOutcome::Matched | GoldenOutcome::Updated)
+    }
+}
+
+/// Runs `rom` with no keys pressed and returns the screen at each of `frames`, in ascending order
+/// and without duplicates, as [`graphics::to_ascii_1bpp`] text.
+pub fn capture(
+    rom: &[u8],
+    quirks: Quirks,
+    cpu_frequency: f64,
+    frames: &[u64],
+) -> Result<Vec<(u64, String)>, Chip8Error> {
+    let mut frames = frames.to_vec();
+    frames.sort_unstable();
+    frames.dedup();
+
+    let (timer_tx, timer_rx) = mpsc::channel();
+    let mut chip8 = Chip8::new(Graphics::new(), timer_rx, quirks, DebugOptions::default());
+    chip8.load_rom(&rom.to_vec())?;
+
+    let mut captures = Vec::with_capacity(frames.len());
+    let (mut frame, mut cycles) = (0, 0);
+    for target in frames {
+        while frame < target {
+            frame += 1;
+            // The receiver lives as long as `chip8`
+            let _ = timer_tx.send(TimerOperation::Decrement(1));
+            let due = (frame as f64 * cpu_frequency / FRAMES_PER_SECOND + 1e-6) as u64;
+            chip8.emulate_frame((due - cycles) as u32, &NoInput)?;
+            cycles = due;
+        }
+        captures.push((frame, graphics::to_ascii_1bpp(chip8.graphics().buffer())));
+    }
+
+    Ok(captures)
+}
+
+/// Returns the golden file for `frame` in `dir`, e.g. `dir/frame-00100.txt`.
+pub fn golden_path(dir: &Path, frame: u64) -> PathBuf {
+    dir.join(format!("frame-{frame:05}.txt"))
+}
+
+/// Describes the rows where `actual` differs from `expected`, as `-` lines with the expected row
+/// and `+` lines with the actual one, each prefixed with the row number. Returns `None` if they're
+/// the same.
+pub fn diff(expected: &str, actual: &str) -> Option<String> {
+    if expected == actual {
+        return None;
+    }
+
+    let (expected, actual): (Vec<_>, Vec<_>) = (expected.lines().collect(), actual.lines().collect());
+    let mut out = String::new();
+    for row in 0..expected.len().max(actual.len()) {
+        let (old, new) = (expected.get(row), actual.get(row));
+        if old == new {
+            continue;
+        }
+        if let Some(old) = old {
+            out += &format!("-{row:2} {old}\n");
+        }
+        if let Some(new) = new {
+            out += &format!("+{row:2} {new}\n");
+        }
+    }
+    Some(out)
+}
+
+/// Compares each capture against its golden in `dir`, or writes the goldens with the captures if
+/// `update` is set.
+pub fn check(dir: &Path, captures: &[(u64, String)], update: bool) -> io::Result<Vec<(u64, GoldenOutcome)>> {
+    if update {
+        fs::create_dir_all(dir)?;
+    }
+
+    captures
+        .iter()
+        .map(|(frame, screen)| {
+            let path = golden_path(dir, *frame);
+            let outcome = if update {
+                fs::write(&path, screen)?;
+                GoldenOutcome::Updated
+            } else {
+                match fs::read_to_string(&path) {
+                    Ok(golden) => {
+                        diff(&golden, screen).map_or(GoldenOutcome::Matched, GoldenOutcome::Mismatched)
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => GoldenOutcome::Missing,
+                    Err(e) => return Err(e),
+                }
+            };
+            Ok((*frame, outcome))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use crate::test_helpers::rom_bytes;
+    use crate::Quirks;
+
+    use super::{capture, check, diff, golden_path, GoldenOutcome};
+
+    /// Draws the `0` glyph one pixel further right every 3 frames: LD F, V2; LD V1, 3; LD DT, V1;
+    /// wait for DT; CLS; DRW V0, V2, 5; ADD V0, 1; JP 0x200
+    const SLIDING_ZERO: [u16; 10] = [
+        0xF229, 0x6103, 0xF115, 0xF107, 0x3100, 0x1206, 0x00E0, 0xD025, 0x7001, 0x1200,
+    ];
+
+    /// A directory of its own under the system's temporary directory.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("wheat-golden-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    /// Column of the leftmost lit pixel in the first row of `screen`.
+    fn glyph_x(screen: &str) -> Option<usize> {
+        screen.lines().next().and_then(|row| row.find('#'))
+    }
+
+    #[test]
+    fn test_capture_at_exact_frames() {
+        let captures = capture(
+            &rom_bytes(&SLIDING_ZERO),
+            Quirks::default(),
+            800.0,
+            &[9, 0, 3, 4, 9],
+        )
+        .unwrap();
+
+        let frames: Vec<_> = captures.iter().map(|(frame, _)| *frame).collect();
+        assert_eq!(frames, [0, 3, 4, 9]);
+        assert_eq!(glyph_x(&captures[0].1), None);
+        // The delay timer set in the first frame runs out on the fourth
+        assert_eq!(glyph_x(&captures[1].1), None);
+        assert_eq!(glyph_x(&captures[2].1), Some(0));
+        assert_eq!(glyph_x(&captures[3].1), Some(1));
+    }
+
+    #[test]
+    fn test_capture_is_deterministic() {
+        let first = capture(&rom_bytes(&SLIDING_ZERO), Quirks::default(), 800.0, &[50, 100]).unwrap();
+        let second = capture(&rom_bytes(&SLIDING_ZERO), Quirks::default(), 800.0, &[100, 50]).unwrap();
+
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn test_golden_path() {
+        assert_eq!(
+            golden_path(Path::new("goldens"), 100),
+            Path::new("goldens/frame-00100.txt")
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        assert_eq!(diff("..\n##\n", "..\n##\n"), None);
+        assert_eq!(
+            diff("..\n##\n..\n", "..\n#.\n..\n"),
+            Some("- 1 ##\n+ 1 #.\n".to_string())
+        );
+    }
+
+    #[test]
+    fn test_update_then_compare() {
+        let dir = temp_dir("update");
+        let captures = capture(&rom_bytes(&SLIDING_ZERO), Quirks::default(), 800.0, &[10, 20]).unwrap();
+
+        assert_eq!(
+            check(&dir, &captures, false).unwrap(),
+            [(10, GoldenOutcome::Missing), (20, GoldenOutcome::Missing)]
+        );
+        assert_eq!(
+            check(&dir, &captures, true).unwrap(),
+            [(10, GoldenOutcome::Updated), (20, GoldenOutcome::Updated)]
+        );
+        assert_eq!(
+            check(&dir, &captures, false).unwrap(),
+            [(10, GoldenOutcome::Matched), (20, GoldenOutcome::Matched)]
+        );
+
+        // Frame 20 has the glyph further right than the golden for frame 10
+        let later = [(10, captures[1].1.clone())];
+        let outcomes = check(&dir, &later, false).unwrap();
+        assert_eq!(outcomes[0].0, 10);
+        assert!(matches!(&outcomes[0].1, GoldenOutcome::Mismatched(diff) if diff.lines().count() == 10));
+        assert!(!outcomes[0].1.passed());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,7 +1,372 @@
+use std::fmt;
+
+use thiserror::Error;
+
 use crate::traits::GraphicsBuffer;
-use crate::{SCREEN_HEIGHT, SCREEN_WIDTH, SPRITE_WIDTH};
+use crate::{Quirks, SCREEN_HEIGHT, SCREEN_WIDTH, SPRITE_WIDTH};
+
+pub(crate) const PIXEL_ON: u8 = 1;
+
+/// The size of the Chip 8 screen.
+pub const SCREEN_DIMENSIONS: Dimensions = Dimensions::new(SCREEN_WIDTH, SCREEN_HEIGHT);
+
+/// A pixel position on a screen, with `(0, 0)` being the top-left corner.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Coord {
+    pub x: u16,
+    pub y: u16,
+}
+
+impl Coord {
+    pub const fn new(x: u16, y: u16) -> Self {
+        Self { x, y }
+    }
+
+    /// Wraps the coordinate around `dims`, so positions past the right/bottom edge continue on
+    /// the left/top of the screen.
+    pub fn wrapped(self, dims: Dimensions) -> Self {
+        Self {
+            x: self.x % dims.w,
+            y: self.y % dims.h,
+        }
+    }
+
+    /// Returns `true` if the coordinate lies outside of `dims`, i.e. it would be clipped.
+    pub fn clips(self, dims: Dimensions) -> bool {
+        self.x >= dims.w || self.y >= dims.h
+    }
+
+    /// Returns the coordinate moved right by `dx` and down by `dy`.
+    pub fn offset(self, dx: u16, dy: u16) -> Self {
+        Self {
+            x: self.x + dx,
+            y: self.y + dy,
+        }
+    }
+}
+
+/// The width and height of a screen, in pixels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Dimensions {
+    pub w: u16,
+    pub h: u16,
+}
+
+impl Dimensions {
+    pub const fn new(w: u16, h: u16) -> Self {
+        Self { w, h }
+    }
+}
+
+/// Colours to draw the screen with. Pixels that are off use `bg` and pixels that are on use `fg`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    pub fg: [u8; 3],
+    pub bg: [u8; 3],
+    /// Colours for XO-CHIP's four-colour mode: pixels set only on the second plane, and pixels set
+    /// on both planes. Without them those pixels use `fg`.
+    pub planes: Option<[[u8; 3]; 2]>,
+}
+
+/// A palette file that couldn't be parsed.
+#[derive(Error, Debug, PartialEq)]
+pub enum PaletteError {
+    #[error("line {line}: {message}")]
+    Syntax { line: usize, message: String },
+    #[error("line {line}: unknown key `{key}`, expected `name`, `fg`, `bg` or `planes`")]
+    UnknownKey { line: usize, key: String },
+    #[error("line {line}: `{key}` is set more than once")]
+    DuplicateKey { line: usize, key: String },
+    #[error("line {line}: invalid colour `{value}`, expected `\"#RRGGBB\"`")]
+    InvalidColor { line: usize, value: String },
+    #[error("line {line}: expected 2 plane colours, got {count}")]
+    PlaneCount { line: usize, count: usize },
+    #[error("missing `{0}`")]
+    MissingKey(&'static str),
+}
+
+impl Palette {
+    fn builtin(name: &str, fg: u32, bg: u32, planes: Option<[u32; 2]>) -> Self {
+        let rgb = |color: u32| [(color >> 16) as u8, (color >> 8) as u8, color as u8];
+        Self {
+            name: name.to_string(),
+            fg: rgb(fg),
+            bg: rgb(bg),
+            planes: planes.map(|planes| planes.map(rgb)),
+        }
+    }
+
+    /// The palettes that ship with the emulator. `okabe-ito` and `cividis` are built from colour
+    /// schemes that stay distinguishable with the common forms of colour blindness.
+    pub fn builtins() -> Vec<Palette> {
+        vec![
+            Palette::default(),
+            Palette::builtin("amber", 0xFFB000, 0x1A1000, None),
+            Palette::builtin("green", 0x33FF66, 0x001A08, None),
+            Palette::builtin("okabe-ito", 0xE69F00, 0x000000, Some([0x56B4E9, 0xF0E442])),
+            Palette::builtin("cividis", 0xFFE945, 0x00204C, Some([0x7C7B78, 0xBCAF6F])),
+        ]
+    }
+
+    /// Parses a palette file. The format is a subset of TOML with one palette per file:
+    ///
+    /// ```toml
+    /// name = "paper"
+    /// fg = "#202020"
+    /// bg = "#F0E8D8"
+    /// # Optional: second plane only, then both planes
+    /// planes = ["#A03020", "#3060A0"]
+    /// ```
+    pub fn parse(text: &str) -> Result<Palette, PaletteError> {
+        let (mut name, mut fg, mut bg, mut planes) = (None, None, None, None);
+
+        for (i, line) in text.lines().enumerate() {
+            let line_no = i + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let syntax = |message: &str| PaletteError::Syntax {
+                line: line_no,
+                message: message.to_string(),
+            };
+            if line.starts_with('[') {
+                return Err(syntax("tables aren't supported, put the keys at the top level"));
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| syntax("expected `key = value`"))?;
+            let (key, value) = (key.trim(), strip_comment(value.trim()));
+
+            let slot = match key {
+                "name" => &mut name,
+                "fg" => &mut fg,
+                "bg" => &mut bg,
+                "planes" => &mut planes,
+                _ => {
+                    return Err(PaletteError::UnknownKey {
+                        line: line_no,
+                        key: key.to_string(),
+                    })
+                }
+            };
+            if slot.is_some() {
+                return Err(PaletteError::DuplicateKey {
+                    line: line_no,
+                    key: key.to_string(),
+                });
+            }
+            *slot = Some((line_no, value));
+        }
+
+        let name = parse_string(name.ok_or(PaletteError::MissingKey("name"))?)?.to_string();
+        let fg = parse_color(fg.ok_or(PaletteError::MissingKey("fg"))?)?;
+        let bg = parse_color(bg.ok_or(PaletteError::MissingKey("bg"))?)?;
+        let planes = planes.map(parse_planes).transpose()?;
+
+        Ok(Palette { name, fg, bg, planes })
+    }
+
+    /// Returns the colour of every pixel value, in the form taken by
+    /// [`Display::set_palette`](crate::traits::Display::set_palette).
+    pub fn colors(&self) -> [[u8; 3]; 16] {
+        let mut colors = [self.fg; 16];
+        colors[0] = self.bg;
+        if let Some([second, both]) = self.planes {
+            colors[2] = second;
+            colors[3] = both;
+        }
+        colors
+    }
+}
+
+impl Default for Palette {
+    /// White pixels on a black background.
+    fn default() -> Self {
+        Palette::builtin("classic", 0xFFFFFF, 0x000000, None)
+    }
+}
+
+impl fmt::Display for Palette {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hex = |[r, g, b]: [u8; 3]| format!("#{r:02X}{g:02X}{b:02X}");
+        write!(f, "{}: fg {}, bg {}", self.name, hex(self.fg), hex(self.bg))?;
+        if let Some([second, both]) = self.planes {
+            write!(f, ", planes {} {}", hex(second), hex(both))?;
+        }
+        Ok(())
+    }
+}
+
+/// Drops a trailing `# comment` from a value, ignoring `#` inside of strings.
+fn strip_comment(value: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in value.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return value[..i].trim_end(),
+            _ => {}
+        }
+    }
+    value
+}
+
+fn parse_string((line, value): (usize, &str)) -> Result<&str, PaletteError> {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .filter(|value| !value.contains('"'))
+        .ok_or_else(|| PaletteError::Syntax {
+            line,
+            message: format!("expected a quoted string, got `{value}`"),
+        })
+}
+
+fn parse_color((line, value): (usize, &str)) -> Result<[u8; 3], PaletteError> {
+    let invalid = || PaletteError::InvalidColor {
+        line,
+        value: value.to_string(),
+    };
+    let hex = parse_string((line, value))?
+        .strip_prefix('#')
+        .ok_or_else(invalid)?;
+    if hex.len() != 6 {
+        return Err(invalid());
+    }
+    let color = u32::from_str_radix(hex, 16).map_err(|_| invalid())?;
+
+    Ok([(color >> 16) as u8, (color >> 8) as u8, color as u8])
+}
+
+fn parse_planes((line, value): (usize, &str)) -> Result<[[u8; 3]; 2], PaletteError> {
+    let colors = value
+        .strip_prefix('[')
+        .and_then(|value| value.strip_suffix(']'))
+        .ok_or_else(|| PaletteError::Syntax {
+            line,
+            message: format!("expected an array of colours, got `{value}`"),
+        })?;
+    let colors: Vec<_> = colors
+        .split(',')
+        .map(str::trim)
+        .filter(|color| !color.is_empty())
+        .map(|color| parse_color((line, color)))
+        .collect::<Result<_, _>>()?;
+
+    let count = colors.len();
+    colors
+        .try_into()
+        .map_err(|_| PaletteError::PlaneCount { line, count })
+}
+
+/// Encodes `buffer` as text, packing two vertical pixels into every character with Unicode block
+/// elements: `'▀'` (top pixel set), `'▄'` (bottom pixel set), `'█'` (both set), and `' '` (neither).
+/// Each line of the result covers two rows of the screen and ends with a newline.
+pub fn to_ascii(buffer: &[Vec<u8>]) -> String {
+    let mut out = String::new();
+
+    for rows in buffer.chunks(2) {
+        for col in 0..rows[0].len() {
+            let top = rows[0][col] == PIXEL_ON;
+            let bottom = rows.get(1).is_some_and(|row| row[col] == PIXEL_ON);
+
+            out.push(match (top, bottom) {
+                (true, true) => '█',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (false, false) => ' ',
+            });
+        }
+        out.push('\n');
+    }
+
+    out
+}
+
+/// Formats `buffer` as a text screenshot for bug reports: a one-line header naming the ROM, the
+/// frame number, and the quirks in use, followed by the [`to_ascii`] rendering of the screen in a
+/// fenced code block.
+pub fn text_screenshot(rom_name: &str, frame: u64, quirks: &Quirks, buffer: &[Vec<u8>]) -> String {
+    format!(
+        "{rom_name} | frame {frame} | {quirks}\n```\n{}```\n",
+        to_ascii(buffer)
+    )
+}
+
+/// Encodes `buffer` as text with one character per pixel: `'#'` for pixels that are on and `'.'`
+/// for pixels that are off. Each line of the result is one row of the screen.
+pub fn to_ascii_1bpp(buffer: &[Vec<u8>]) -> String {
+    let mut out = String::new();
+
+    for row in buffer {
+        for pixel in row {
+            out.push(if *pixel == PIXEL_ON { '#' } else { '.' });
+        }
+        out.push('\n');
+    }
+
+    out
+}
+
+/// Packs the `dims` pixels of `buffer` starting at `pos` into sprites, one per 8 pixel wide strip
+/// from left to right, with one byte per row and the leftmost pixel in the highest bit. Strips are
+/// padded on the right with blank pixels, as are pixels outside of `buffer`.
+pub fn pack_sprites(buffer: &[Vec<u8>], pos: Coord, dims: Dimensions) -> Vec<Vec<u8>> {
+    // Offsets from `pos`, so that rectangles reaching past `u16::MAX` are blank there
+    let pixel = |dx: u16, dy: u16| {
+        let (Some(x), Some(y)) = (pos.x.checked_add(dx), pos.y.checked_add(dy)) else {
+            return 0;
+        };
+        let on = dx < dims.w
+            && buffer
+                .get(y as usize)
+                .and_then(|row| row.get(x as usize))
+                .is_some_and(|&pixel| pixel == PIXEL_ON);
+        on as u8
+    };
 
-const PIXEL_ON: u8 = 1;
+    (0..dims.w.div_ceil(SPRITE_WIDTH as u16))
+        .map(|strip| {
+            let left = strip * SPRITE_WIDTH as u16;
+            (0..dims.h)
+                .map(|dy| (0..SPRITE_WIDTH as u16).fold(0, |row, dx| row << 1 | pixel(left + dx, dy)))
+                .collect()
+        })
+        .collect()
+}
+
+/// Formats the [`pack_sprites`] of the rectangle as raw hex bytes and as Octo source, with one
+/// `label` per sprite (numbered if there are several) ready to paste into a program.
+pub fn sprite_snippet(label: &str, buffer: &[Vec<u8>], pos: Coord, dims: Dimensions) -> String {
+    let sprites = pack_sprites(buffer, pos, dims);
+    let padding = sprites.len() * SPRITE_WIDTH as usize - dims.w as usize;
+
+    let mut out = format!("# {}x{} pixels at ({}, {})", dims.w, dims.h, pos.x, pos.y);
+    if sprites.len() > 1 {
+        out += &format!(", as {} sprites drawn 8 pixels apart", sprites.len());
+    }
+    if padding > 0 {
+        out += &format!(", padded with {padding} blank columns on the right");
+    }
+    out.push('\n');
+
+    for sprite in &sprites {
+        let bytes: Vec<_> = sprite.iter().map(|byte| format!("{byte:02X}")).collect();
+        out += &format!("# {}\n", bytes.join(" "));
+    }
+    for (i, sprite) in sprites.iter().enumerate() {
+        match sprites.len() {
+            1 => out += &format!(": {label}\n"),
+            _ => out += &format!(": {label}-{i}\n"),
+        }
+        let bytes: Vec<_> = sprite.iter().map(|byte| format!("{byte:#04X}")).collect();
+        out += &format!("    {}\n", bytes.join(" "));
+    }
+
+    out
+}
 
 /// Graphics processor for Chip8. The emulator has a screen that is `64`x`32` pixels.
 ///
@@ -40,36 +405,28 @@ impl GraphicsBuffer for Graphics {
 
     /// Draws a sprite on the screen, and returns `true` if a pixel on the screen was flipped from
     /// 1 to 0.
-    /// `opcode`: Determines position and height of sprite, with position being top left
-    /// corner of the sprite.
-    /// `ir`: The index register, which contains the area of memory to
-    /// start reading the sprite from.
-    /// `memory`: The memory from which to read the sprite.
-    fn draw(&mut self, x: u8, y: u8, num_rows: u8, ir: u16, memory: &[u8], clipping: bool) -> bool {
+    /// `pos`: Top left corner of the sprite. It is wrapped onto the screen if it lies outside of it.
+    /// `rows`: The sprite, one byte per row.
+    fn draw_sprite(&mut self, pos: Coord, rows: &[u8], clipping: bool) -> bool {
         // Assume no collisions happen
         let mut pixel_flipped = false;
-        let x = x % SCREEN_WIDTH as u8;
-        let y = y % SCREEN_HEIGHT as u8;
+        let origin = pos.wrapped(SCREEN_DIMENSIONS);
 
         // Width of each pixel is 8 bits, and height is determined by the last nibble in opcode
-        for row in 0..num_rows {
-            let sprite = memory[(ir + row as u16) as usize];
-            // println!("{:#x}, {:#010b}", sprite, sprite);
+        for (row, &sprite) in rows.iter().enumerate() {
             for bit in 0..SPRITE_WIDTH {
                 // Keep only the smallest bit, because that's what we care about
                 let pixel = (sprite >> (7 - bit)) & 0x1;
 
-                // Allow wrap-around by modulusing the result
-                let mut pos_y = (y + row) as usize;
-                let mut pos_x = (x + bit) as usize;
-
-                if clipping && (pos_y >= SCREEN_HEIGHT as usize || pos_x >= SCREEN_WIDTH as usize) {
+                let pixel_pos = origin.offset(bit.into(), row as u16);
+                if clipping && pixel_pos.clips(SCREEN_DIMENSIONS) {
                     continue;
-                } else {
-                    pos_y %= SCREEN_HEIGHT as usize;
-                    pos_x %= SCREEN_WIDTH as usize;
                 }
 
+                // Allow wrap-around when clipping is off
+                let Coord { x, y } = pixel_pos.wrapped(SCREEN_DIMENSIONS);
+                let (pos_x, pos_y) = (x as usize, y as usize);
+
                 if pixel == PIXEL_ON && self.screen[pos_y][pos_x] == PIXEL_ON {
                     self.screen[pos_y][pos_x] ^= pixel;
                     pixel_flipped = true;
@@ -82,14 +439,178 @@ impl GraphicsBuffer for Graphics {
         pixel_flipped
     }
 
+    fn set_pixel(&mut self, pos: Coord, value: u8) {
+        let Coord { x, y } = pos.wrapped(SCREEN_DIMENSIONS);
+        self.screen[y as usize][x as usize] = value;
+    }
+
+    fn scroll_left(&mut self, px: u16) {
+        for row in &mut self.screen {
+            let px = (px as usize).min(row.len());
+            row.rotate_left(px);
+            let len = row.len();
+            row[len - px..].fill(0);
+        }
+    }
+
+    fn scroll_right(&mut self, px: u16) {
+        for row in &mut self.screen {
+            let px = (px as usize).min(row.len());
+            row.rotate_right(px);
+            row[..px].fill(0);
+        }
+    }
+
     fn buffer(&self) -> &Vec<Vec<u8>> {
         &self.screen
     }
 }
 
+/// A difference between the two buffers of a [`MirroredGraphics`].
+#[derive(Debug, PartialEq)]
+pub enum Divergence {
+    /// The buffers disagreed on whether a `draw` flipped a pixel from 1 to 0.
+    Collision {
+        call: usize,
+        primary: bool,
+        secondary: bool,
+    },
+    /// The buffers' contents differed after a call, at the listed pixels.
+    Pixels { call: usize, pixels: Vec<Coord> },
+}
+
+/// A `GraphicsBuffer` that forwards every call to two buffers and records where they diverge.
+/// It's meant for checking a new `GraphicsBuffer` implementation against a known-good one by
+/// running the same instruction stream through both.
+///
+/// The primary buffer is the one whose results are returned to the emulator.
+#[derive(Debug, Default)]
+pub struct MirroredGraphics<A, B> {
+    primary: A,
+    secondary: B,
+    /// Number of calls forwarded so far
+    calls: usize,
+    divergences: Vec<Divergence>,
+}
+
+impl<A, B> MirroredGraphics<A, B>
+where
+    A: GraphicsBuffer,
+    B: GraphicsBuffer,
+{
+    pub fn new(primary: A, secondary: B) -> Self {
+        Self {
+            primary,
+            secondary,
+            calls: 0,
+            divergences: Vec::new(),
+        }
+    }
+
+    /// Every divergence seen so far, in the order they happened.
+    pub fn divergences(&self) -> &[Divergence] {
+        &self.divergences
+    }
+
+    pub fn primary(&self) -> &A {
+        &self.primary
+    }
+
+    pub fn secondary(&self) -> &B {
+        &self.secondary
+    }
+
+    /// Compares the buffers after a call, and records the pixels that differ.
+    fn check_pixels(&mut self) {
+        let mut pixels = Vec::new();
+
+        for (y, (a, b)) in self
+            .primary
+            .buffer()
+            .iter()
+            .zip(self.secondary.buffer())
+            .enumerate()
+        {
+            for (x, (a, b)) in a.iter().zip(b).enumerate() {
+                if a != b {
+                    pixels.push(Coord::new(x as u16, y as u16));
+                }
+            }
+        }
+
+        if !pixels.is_empty() {
+            self.divergences.push(Divergence::Pixels {
+                call: self.calls,
+                pixels,
+            });
+        }
+        self.calls += 1;
+    }
+}
+
+impl<A, B> GraphicsBuffer for MirroredGraphics<A, B>
+where
+    A: GraphicsBuffer,
+    B: GraphicsBuffer,
+{
+    fn clear(&mut self) {
+        self.primary.clear();
+        self.secondary.clear();
+        self.check_pixels();
+    }
+
+    fn draw_sprite(&mut self, pos: Coord, rows: &[u8], clipping: bool) -> bool {
+        let primary = self.primary.draw_sprite(pos, rows, clipping);
+        let secondary = self.secondary.draw_sprite(pos, rows, clipping);
+
+        if primary != secondary {
+            self.divergences.push(Divergence::Collision {
+                call: self.calls,
+                primary,
+                secondary,
+            });
+        }
+        self.check_pixels();
+
+        primary
+    }
+
+    fn set_pixel(&mut self, pos: Coord, value: u8) {
+        self.primary.set_pixel(pos, value);
+        self.secondary.set_pixel(pos, value);
+        self.check_pixels();
+    }
+
+    fn scroll_left(&mut self, px: u16) {
+        self.primary.scroll_left(px);
+        self.secondary.scroll_left(px);
+        self.check_pixels();
+    }
+
+    fn scroll_right(&mut self, px: u16) {
+        self.primary.scroll_right(px);
+        self.secondary.scroll_right(px);
+        self.check_pixels();
+    }
+
+    fn buffer(&self) -> &Vec<Vec<u8>> {
+        self.primary.buffer()
+    }
+
+    fn load_buffer(&mut self, buffer: &[Vec<u8>]) {
+        self.primary.load_buffer(buffer);
+        self.secondary.load_buffer(buffer);
+        self.check_pixels();
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::chip8::MEMORY_SIZE;
+    use std::sync::mpsc;
+
+    use crate::chip8::{Chip8, NoInput};
+    use crate::test_helpers::rom_bytes;
+    use crate::DebugOptions;
 
     use super::*;
 
@@ -107,20 +628,9 @@ mod tests {
 
     #[test]
     fn test_clipping_on() {
-        let mut memory = vec![0 as u8; MEMORY_SIZE];
-        memory[0] = 255 as u8;
-        memory[1] = 255 as u8;
-
         let mut graphics = Graphics::new();
 
-        graphics.draw(
-            SCREEN_WIDTH as u8 - 1,
-            SCREEN_HEIGHT as u8 - 1,
-            2,
-            0,
-            &memory,
-            true,
-        );
+        graphics.draw_sprite(Coord::new(SCREEN_WIDTH - 1, SCREEN_HEIGHT - 1), &[255, 255], true);
 
         assert_eq!(graphics.screen[0][0], 0);
         assert_eq!(
@@ -131,18 +641,11 @@ mod tests {
 
     #[test]
     fn test_clipping_off() {
-        let mut memory = vec![0 as u8; MEMORY_SIZE];
-        memory[0] = 255 as u8;
-        memory[1] = 255 as u8;
-
         let mut graphics = Graphics::new();
 
-        graphics.draw(
-            SCREEN_WIDTH as u8 - 1,
-            SCREEN_HEIGHT as u8 - 1,
-            2,
-            0,
-            &memory,
+        graphics.draw_sprite(
+            Coord::new(SCREEN_WIDTH - 1, SCREEN_HEIGHT - 1),
+            &[255, 255],
             false,
         );
 
@@ -152,4 +655,342 @@ mod tests {
             1
         );
     }
+
+    #[test]
+    fn test_draw_wraps_origin() {
+        let mut graphics = Graphics::new();
+        graphics.draw_sprite(Coord::new(SCREEN_WIDTH + 1, SCREEN_HEIGHT + 2), &[0x80], true);
+
+        assert_eq!(graphics.screen[2][1], 1);
+    }
+
+    #[test]
+    fn test_set_pixel() {
+        let mut graphics = Graphics::new();
+        graphics.set_pixel(Coord::new(3, 4), 1);
+
+        assert_eq!(graphics.screen[4][3], 1);
+    }
+
+    #[test]
+    fn test_scroll_left() {
+        let mut graphics = Graphics::new();
+        graphics.set_pixel(Coord::new(0, 1), 1);
+        graphics.set_pixel(Coord::new(5, 1), 1);
+        graphics.scroll_left(1);
+
+        assert_eq!(graphics.screen[1][4], 1);
+        assert_eq!(graphics.screen[1][5], 0);
+        // Pixels pushed off the edge don't wrap around
+        assert_eq!(graphics.screen[1][SCREEN_WIDTH as usize - 1], 0);
+    }
+
+    #[test]
+    fn test_scroll_right() {
+        let mut graphics = Graphics::new();
+        graphics.set_pixel(Coord::new(SCREEN_WIDTH - 1, 2), 1);
+        graphics.set_pixel(Coord::new(5, 2), 1);
+        graphics.scroll_right(1);
+
+        assert_eq!(graphics.screen[2][6], 1);
+        assert_eq!(graphics.screen[2][5], 0);
+        assert_eq!(graphics.screen[2][0], 0);
+    }
+
+    #[test]
+    fn test_text_screenshot() {
+        let buffer = vec![vec![1, 0, 1, 0], vec![1, 1, 0, 0], vec![1, 1, 0, 1]];
+
+        let screenshot = text_screenshot("pong.ch8", 42, &Quirks::default(), &buffer);
+
+        assert_eq!(
+            screenshot,
+            "pong.ch8 | frame 42 | reset_vf=true increment_ir=x+1 use_vy_in_shift=true \
+             use_vx_in_jump=false clipping=true\n\
+             ```\n\
+             █▄▀ \n\
+             ▀▀ ▀\n\
+             ```\n"
+        );
+    }
+
+    /// Draws `rows` side by side at `pos` on an empty screen.
+    fn screen_with(pos: Coord, rows: &[u16]) -> Vec<Vec<u8>> {
+        let mut graphics = Graphics::new();
+        for (dy, row) in (0..).zip(rows) {
+            for dx in 0..16 {
+                if row & 0x8000 >> dx != 0 {
+                    graphics.set_pixel(pos.offset(dx, dy), PIXEL_ON);
+                }
+            }
+        }
+        graphics.buffer().clone()
+    }
+
+    #[test]
+    fn test_pack_sprites() {
+        let buffer = screen_with(Coord::new(8, 2), &[0xF000, 0x9000, 0xF000]);
+
+        let sprites = pack_sprites(&buffer, Coord::new(8, 2), Dimensions::new(8, 3));
+        assert_eq!(sprites, [vec![0xF0, 0x90, 0xF0]]);
+    }
+
+    #[test]
+    fn test_pack_sprites_unaligned() {
+        let buffer = screen_with(Coord::new(13, 0), &[0xF000, 0x9000]);
+
+        // Narrower than a sprite: the rest of the byte is padding, even where the screen is on
+        let sprites = pack_sprites(&buffer, Coord::new(14, 0), Dimensions::new(3, 2));
+        assert_eq!(sprites, [vec![0xE0, 0x20]]);
+    }
+
+    #[test]
+    fn test_pack_sprites_wide() {
+        // 12 pixels wide, starting at an odd column
+        let buffer = screen_with(Coord::new(3, 0), &[0xFFF0, 0x8010]);
+
+        let sprites = pack_sprites(&buffer, Coord::new(3, 0), Dimensions::new(12, 2));
+        assert_eq!(sprites, [vec![0xFF, 0x80], vec![0xF0, 0x10]]);
+    }
+
+    #[test]
+    fn test_pack_sprites_past_screen_edge() {
+        let buffer = screen_with(Coord::new(60, 30), &[0xF000, 0xF000]);
+
+        let sprites = pack_sprites(&buffer, Coord::new(60, 30), Dimensions::new(8, 3));
+        assert_eq!(sprites, [vec![0xF0, 0xF0, 0x00]]);
+    }
+
+    #[test]
+    fn test_pack_sprites_past_u16_max() {
+        let buffer = screen_with(Coord::new(0, 0), &[0xF000]);
+
+        let sprites = pack_sprites(&buffer, Coord::new(u16::MAX, u16::MAX), Dimensions::new(9, 2));
+        assert_eq!(sprites, [vec![0x00, 0x00], vec![0x00, 0x00]]);
+    }
+
+    #[test]
+    fn test_sprite_snippet() {
+        let buffer = screen_with(Coord::new(0, 0), &[0xFFF0, 0x8010]);
+
+        assert_eq!(
+            sprite_snippet("box", &buffer, Coord::new(0, 0), Dimensions::new(12, 2)),
+            "# 12x2 pixels at (0, 0), as 2 sprites drawn 8 pixels apart, padded with 4 blank columns \
+             on the right\n\
+             # FF 80\n\
+             # F0 10\n\
+             : box-0\n    0xFF 0x80\n\
+             : box-1\n    0xF0 0x10\n"
+        );
+        assert_eq!(
+            sprite_snippet("dot", &buffer, Coord::new(0, 0), Dimensions::new(8, 1)),
+            "# 8x1 pixels at (0, 0)\n# FF\n: dot\n    0xFF\n"
+        );
+    }
+
+    #[test]
+    fn test_coord_wrapped() {
+        let dims = Dimensions::new(64, 32);
+
+        assert_eq!(Coord::new(10, 5).wrapped(dims), Coord::new(10, 5));
+        assert_eq!(Coord::new(64, 32).wrapped(dims), Coord::new(0, 0));
+        // Values that would overflow a `u8` must still wrap correctly
+        assert_eq!(Coord::new(255 + 8, 31 + 15).wrapped(dims), Coord::new(7, 14));
+        assert_eq!(
+            Coord::new(130, 5).wrapped(Dimensions::new(128, 64)),
+            Coord::new(2, 5)
+        );
+    }
+
+    #[test]
+    fn test_coord_clips() {
+        let dims = Dimensions::new(64, 32);
+
+        assert!(!Coord::new(63, 31).clips(dims));
+        assert!(Coord::new(64, 0).clips(dims));
+        assert!(Coord::new(0, 32).clips(dims));
+        assert!(Coord::new(255 + 8, 0).clips(dims));
+    }
+
+    /// A broken buffer that ignores the first row of every sprite.
+    #[derive(Debug)]
+    struct SkipFirstRow(Graphics);
+
+    impl GraphicsBuffer for SkipFirstRow {
+        fn clear(&mut self) {
+            self.0.clear();
+        }
+
+        fn draw_sprite(&mut self, pos: Coord, rows: &[u8], clipping: bool) -> bool {
+            match rows.split_first() {
+                Some((_, rest)) => self.0.draw_sprite(pos.offset(0, 1), rest, clipping),
+                None => false,
+            }
+        }
+
+        fn set_pixel(&mut self, pos: Coord, value: u8) {
+            self.0.set_pixel(pos, value);
+        }
+
+        fn scroll_left(&mut self, px: u16) {
+            self.0.scroll_left(px);
+        }
+
+        fn scroll_right(&mut self, px: u16) {
+            self.0.scroll_right(px);
+        }
+
+        fn buffer(&self) -> &Vec<Vec<u8>> {
+            self.0.buffer()
+        }
+    }
+
+    #[test]
+    fn test_mirrored_graphics_records_divergence() {
+        let sprite = [0x80, 0x80];
+
+        let mut mirror = MirroredGraphics::new(Graphics::new(), SkipFirstRow(Graphics::new()));
+        assert!(!mirror.draw_sprite(Coord::new(0, 0), &sprite, true));
+        assert_eq!(
+            mirror.divergences(),
+            &[Divergence::Pixels {
+                call: 0,
+                pixels: vec![Coord::new(0, 0)],
+            }]
+        );
+
+        // Drawing the same sprite again only collides in the primary buffer at (0, 0), but both
+        // collide at (0, 1), so the collision results agree
+        assert!(mirror.draw_sprite(Coord::new(0, 0), &sprite, true));
+        assert_eq!(mirror.divergences().len(), 1);
+
+        // Drawing a single row only collides in the primary buffer
+        mirror.draw_sprite(Coord::new(0, 0), &sprite[..1], true);
+        mirror.clear();
+        mirror.draw_sprite(Coord::new(0, 0), &sprite[..1], true);
+        assert!(mirror.draw_sprite(Coord::new(0, 0), &sprite[..1], true));
+        assert!(mirror.divergences().contains(&Divergence::Collision {
+            call: 5,
+            primary: true,
+            secondary: false,
+        }));
+    }
+
+    #[test]
+    fn test_mirrored_graphics_runs_rom_without_divergence() {
+        // Draws every hex digit in turn while walking the sprite position around the screen
+        let program: [u16; 9] = [
+            0xF229, // LD F, V2
+            0xD015, // DRW V0, V1, 5
+            0x7005, // ADD V0, 0x05
+            0x7103, // ADD V1, 0x03
+            0x7201, // ADD V2, 0x01
+            0x3210, // SE V2, 0x10
+            0x1200, // JP 0x200
+            0x6200, // LD V2, 0x00
+            0x1200, // JP 0x200
+        ];
+
+        let (_timer_tx, timer_rx) = mpsc::channel();
+        let mirror = MirroredGraphics::new(Graphics::new(), Graphics::new());
+        let mut chip8 = Chip8::new(mirror, timer_rx, Quirks::default(), DebugOptions::default());
+        chip8.load_rom(&rom_bytes(&program)).unwrap();
+
+        let mut draws = 0;
+        for _ in 0..5000 {
+            if chip8.emulate_cycle(&NoInput).unwrap().draw_on_screen {
+                draws += 1;
+            }
+        }
+
+        assert!(draws > 500);
+        assert_eq!(chip8.graphics().divergences(), &[]);
+    }
+
+    const PAPER: &str = r##"
+# A light palette
+name = "paper"
+fg = "#202020"
+bg = "#F0e8d8"  # cream
+planes = ["#A03020", "#3060A0"]
+"##;
+
+    #[test]
+    fn test_parse_palette() {
+        let palette = Palette::parse(PAPER).unwrap();
+
+        assert_eq!(
+            palette,
+            Palette {
+                name: "paper".to_string(),
+                fg: [0x20, 0x20, 0x20],
+                bg: [0xF0, 0xE8, 0xD8],
+                planes: Some([[0xA0, 0x30, 0x20], [0x30, 0x60, 0xA0]]),
+            }
+        );
+        assert_eq!(
+            palette.to_string(),
+            "paper: fg #202020, bg #F0E8D8, planes #A03020 #3060A0"
+        );
+    }
+
+    #[test]
+    fn test_parse_palette_without_planes() {
+        let palette = Palette::parse("name = \"mono\"\nfg = \"#FFFFFF\"\nbg = \"#000000\"\n").unwrap();
+        assert_eq!(palette.planes, None);
+        assert_eq!(palette.colors()[3], [0xFF; 3]);
+    }
+
+    #[test]
+    fn test_parse_palette_errors() {
+        let parse = |text: &str| Palette::parse(text).unwrap_err().to_string();
+
+        assert_eq!(parse("fg = \"#FFFFFF\"\nbg = \"#000000\""), "missing `name`");
+        assert_eq!(
+            parse("name = \"x\"\nfg = \"#FFF\"\nbg = \"#000000\""),
+            "line 2: invalid colour `\"#FFF\"`, expected `\"#RRGGBB\"`"
+        );
+        assert_eq!(
+            parse("name = \"x\"\nfg = \"#GGGGGG\""),
+            "line 2: invalid colour `\"#GGGGGG\"`, expected `\"#RRGGBB\"`"
+        );
+        assert_eq!(
+            parse("name = \"x\"\ncolour = \"#FFFFFF\""),
+            "line 2: unknown key `colour`, expected `name`, `fg`, `bg` or `planes`"
+        );
+        assert_eq!(
+            parse("name = \"x\"\nname = \"y\""),
+            "line 2: `name` is set more than once"
+        );
+        assert_eq!(
+            parse("name = \"x\"\nfg = \"#FFFFFF\"\nbg = \"#000000\"\nplanes = [\"#FFFFFF\"]"),
+            "line 4: expected 2 plane colours, got 1"
+        );
+        assert_eq!(parse("name = x"), "line 1: expected a quoted string, got `x`");
+        assert_eq!(parse("[palette]").split(':').next(), Some("line 1"));
+    }
+
+    #[test]
+    fn test_palette_colors() {
+        let palette = Palette::parse(PAPER).unwrap();
+        let colors = palette.colors();
+
+        assert_eq!(colors[0], palette.bg);
+        assert_eq!(colors[1], palette.fg);
+        assert_eq!(colors[2], [0xA0, 0x30, 0x20]);
+        assert_eq!(colors[3], [0x30, 0x60, 0xA0]);
+        assert_eq!(colors[15], palette.fg);
+    }
+
+    #[test]
+    fn test_builtin_palettes() {
+        let builtins = Palette::builtins();
+
+        assert_eq!(builtins[0], Palette::default());
+        assert!(builtins.iter().any(|palette| palette.name == "okabe-ito"));
+        assert!(builtins.iter().any(|palette| palette.name == "cividis"));
+        for palette in &builtins {
+            assert_ne!(palette.fg, palette.bg, "{}", palette.name);
+        }
+    }
 }
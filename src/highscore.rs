@@ -0,0 +1,228 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// How a ROM stores its score in memory.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScoreEncoding {
+    /// One decimal digit per byte, most significant first, as written by `Fx33`.
+    Bcd,
+    /// A big-endian unsigned integer.
+    Binary,
+}
+
+/// Where a ROM keeps its score, parsed from `ADDR:ENCODING:LEN`, e.g. `0x2F0:bcd:3`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScoreLocation {
+    pub addr: u16,
+    pub len: u8,
+    pub encoding: ScoreEncoding,
+}
+
+impl ScoreLocation {
+    /// Reads the score out of `memory`. Returns `None` if the location is outside of memory or,
+    /// for BCD scores, a byte isn't a decimal digit.
+    pub fn decode(&self, memory: &[u8]) -> Option<u32> {
+        let start = self.addr as usize;
+        let bytes = memory.get(start..start + self.len as usize)?;
+
+        bytes.iter().try_fold(0u32, |score, &byte| match self.encoding {
+            ScoreEncoding::Bcd if byte < 10 => score.checked_mul(10)?.checked_add(byte as u32),
+            ScoreEncoding::Bcd => None,
+            ScoreEncoding::Binary => score.checked_mul(256)?.checked_add(byte as u32),
+        })
+    }
+
+    /// Formats `score` the way the ROM shows it, i.e. zero-padded to one digit per byte for BCD.
+    pub fn format(&self, score: u32) -> String {
+        match self.encoding {
+            ScoreEncoding::Bcd => format!("{score:0width$}", width = self.len as usize),
+            ScoreEncoding::Binary => score.to_string(),
+        }
+    }
+}
+
+impl FromStr for ScoreLocation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<_> = s.split(':').collect();
+        let [addr, encoding, len] = parts[..] else {
+            return Err(format!("expected `ADDR:ENCODING:LEN`, got `{s}`"));
+        };
+
+        let addr = addr.trim_start_matches("0x");
+        let addr = u16::from_str_radix(addr, 16).map_err(|e| format!("invalid address `{addr}`: {e}"))?;
+        let encoding = match encoding {
+            "bcd" => ScoreEncoding::Bcd,
+            "bin" | "binary" => ScoreEncoding::Binary,
+            _ => {
+                return Err(format!(
+                    "unknown encoding `{encoding}`, expected `bcd` or `binary`"
+                ))
+            }
+        };
+        let len = len.parse().map_err(|e| format!("invalid length `{len}`: {e}"))?;
+
+        Ok(ScoreLocation { addr, len, encoding })
+    }
+}
+
+/// The best score seen for every ROM, stored as one `name<TAB>score` line per ROM.
+pub struct HighScores {
+    path: PathBuf,
+    scores: BTreeMap<String, u32>,
+}
+
+impl HighScores {
+    /// Loads the scores from `path`. A missing file starts with no scores.
+    pub fn load(path: PathBuf) -> io::Result<Self> {
+        let scores = match fs::read_to_string(&path) {
+            Ok(contents) => parse(&contents),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(HighScores { path, scores })
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        fs::write(&self.path, self.to_string())
+    }
+
+    pub fn best(&self, rom: &str) -> Option<u32> {
+        self.scores.get(rom).copied()
+    }
+
+    /// Records `score` for `rom` if it beats the best one so far. Returns whether it did.
+    pub fn update(&mut self, rom: &str, score: u32) -> bool {
+        match self.scores.get(rom) {
+            Some(&best) if best >= score => false,
+            _ => {
+                self.scores.insert(rom.to_string(), score);
+                true
+            }
+        }
+    }
+}
+
+impl fmt::Display for HighScores {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (rom, score) in &self.scores {
+            writeln!(f, "{rom}\t{score}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses the high score file, skipping lines that aren't `name<TAB>score`.
+fn parse(contents: &str) -> BTreeMap<String, u32> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (rom, score) = line.rsplit_once('\t')?;
+            Some((rom.to_string(), score.parse().ok()?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+
+    use super::{parse, HighScores, ScoreEncoding, ScoreLocation};
+
+    fn high_scores(scores: &[(&str, u32)]) -> HighScores {
+        HighScores {
+            path: PathBuf::new(),
+            scores: scores
+                .iter()
+                .map(|&(rom, score)| (rom.to_string(), score))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_parse_location() {
+        let location: ScoreLocation = "0x2F0:bcd:3".parse().unwrap();
+        assert_eq!(
+            location,
+            ScoreLocation {
+                addr: 0x2F0,
+                len: 3,
+                encoding: ScoreEncoding::Bcd
+            }
+        );
+
+        let location: ScoreLocation = "300:binary:2".parse().unwrap();
+        assert_eq!(location.addr, 0x300);
+        assert_eq!(location.encoding, ScoreEncoding::Binary);
+    }
+
+    #[test]
+    fn test_parse_invalid_location() {
+        assert!("0x2F0:bcd".parse::<ScoreLocation>().is_err());
+        assert!("0x2F0:hex:3".parse::<ScoreLocation>().is_err());
+        assert!("zz:bcd:3".parse::<ScoreLocation>().is_err());
+    }
+
+    #[test]
+    fn test_decode_bcd() {
+        let location: ScoreLocation = "0x2:bcd:3".parse().unwrap();
+        assert_eq!(location.decode(&[9, 9, 0, 5, 7]), Some(57));
+        assert_eq!(location.format(57), "057");
+    }
+
+    #[test]
+    fn test_decode_invalid_bcd() {
+        let location: ScoreLocation = "0x0:bcd:2".parse().unwrap();
+        assert_eq!(location.decode(&[1, 10]), None);
+    }
+
+    #[test]
+    fn test_decode_binary() {
+        let location: ScoreLocation = "0x1:binary:2".parse().unwrap();
+        assert_eq!(location.decode(&[0xFF, 0x01, 0x02]), Some(0x102));
+        assert_eq!(location.format(0x102), "258");
+    }
+
+    #[test]
+    fn test_decode_outside_of_memory() {
+        let location: ScoreLocation = "0x3:bcd:3".parse().unwrap();
+        assert_eq!(location.decode(&[0; 4]), None);
+    }
+
+    #[test]
+    fn test_update_keeps_best() {
+        let mut scores = high_scores(&[("pong", 5)]);
+
+        assert!(!scores.update("pong", 3));
+        assert!(!scores.update("pong", 5));
+        assert_eq!(scores.best("pong"), Some(5));
+
+        assert!(scores.update("pong", 7));
+        assert_eq!(scores.best("pong"), Some(7));
+
+        assert_eq!(scores.best("brix"), None);
+        assert!(scores.update("brix", 0));
+    }
+
+    #[test]
+    fn test_format_round_trip() {
+        let scores = high_scores(&[("roms/pong.ch8", 57), ("brix", 120)]);
+        let contents = scores.to_string();
+
+        assert_eq!(contents, "brix\t120\nroms/pong.ch8\t57\n");
+        assert_eq!(parse(&contents), scores.scores);
+    }
+
+    #[test]
+    fn test_parse_skips_invalid_lines() {
+        let expected = BTreeMap::from([("pong".to_string(), 3)]);
+        assert_eq!(parse("pong\t3\ngarbage\nbrix\tabc\n"), expected);
+    }
+}
@@ -0,0 +1,86 @@
+//! Records which keys a program asks about, and when.
+
+use std::cell::RefCell;
+
+use crate::traits::Input;
+use crate::Key;
+
+/// Wraps an [`Input`] and logs every [`Input::is_pressed`] call made through it as
+/// `(cycle, key, result)`. [`Input::pressed_mask`] asks about every key, so it logs all 16.
+pub struct Chip8InputRecorder<I: Input> {
+    inner: I,
+    log: RefCell<Vec<(u64, Key, bool)>>,
+    cycle: u64,
+}
+
+impl<I: Input> Chip8InputRecorder<I> {
+    pub fn new(inner: I) -> Self {
+        Self {
+            inner,
+            log: RefCell::new(Vec::new()),
+            cycle: 0,
+        }
+    }
+
+    /// Moves on to the next cycle. Queries are logged with the number of times this was called.
+    pub fn advance_cycle(&mut self) {
+        self.cycle += 1;
+    }
+
+    /// Every query so far, oldest first.
+    pub fn log(&self) -> Vec<(u64, Key, bool)> {
+        self.log.borrow().clone()
+    }
+
+    pub fn inner(&self) -> &I {
+        &self.inner
+    }
+}
+
+impl<I: Input> Input for Chip8InputRecorder<I> {
+    fn is_pressed(&self, key: Key) -> bool {
+        let pressed = self.inner.is_pressed(key);
+        self.log.borrow_mut().push((self.cycle, key, pressed));
+        pressed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Chip8InputRecorder;
+    use crate::test_helpers::chip8_with_program;
+    use crate::traits::Input;
+    use crate::{DebugOptions, Key};
+
+    struct OneKey(Key);
+
+    impl Input for OneKey {
+        fn is_pressed(&self, key: Key) -> bool {
+            key == self.0
+        }
+    }
+
+    #[test]
+    fn test_records_key_queries() {
+        // LD V0, 0x5; SKP V0
+        let mut chip8 = chip8_with_program(&[0x6005, 0xE09E], DebugOptions::default());
+
+        let mut input = Chip8InputRecorder::new(OneKey(Key::Num5));
+        for _ in 0..2 {
+            chip8.emulate_cycle(&input).unwrap();
+            input.advance_cycle();
+        }
+
+        assert_eq!(input.log(), [(1, Key::Num5, true)]);
+    }
+
+    #[test]
+    fn test_pressed_mask_queries_every_key() {
+        let input = Chip8InputRecorder::new(OneKey(Key::A));
+
+        assert_eq!(input.pressed_mask(), 1 << 0xA);
+        let log = input.log();
+        assert_eq!(log.len(), 16);
+        assert_eq!(log[0xA], (0, Key::A, true));
+    }
+}
@@ -1,9 +1,35 @@
+use std::fmt;
+use std::str::FromStr;
+
 use derive_builder::Builder;
 use thiserror::Error;
 
+use chip8::LegacyOpcode;
+use opcode_overrides::OverrideError;
+
+#[cfg(feature = "async")]
+pub mod async_emulator;
+pub mod breakpoint;
 pub mod chip8;
+pub mod crash_report;
+pub mod demo;
+pub mod disasm;
+pub mod frame_channel;
+pub mod golden;
 pub mod graphics;
+pub mod input_recorder;
+pub mod memsearch;
+pub mod opcode_overrides;
+pub mod profile;
+#[cfg(test)]
+mod public_api;
+pub mod rewind;
+pub mod soak;
+pub mod state;
+#[cfg(any(test, feature = "test-helpers"))]
+pub mod test_helpers;
 pub mod timer;
+pub mod trace;
 pub mod traits;
 
 /// Screen is 64 pixels wide
@@ -14,9 +40,19 @@ pub const SCREEN_SIZE: u16 = SCREEN_WIDTH * SCREEN_HEIGHT;
 /// All sprites are 8 pixels wide
 pub const SPRITE_WIDTH: u8 = 8;
 
+/// Frames per second the frontends run the emulator at: a batch of instructions, then the screen
+/// is drawn. See [`cycles_per_frame`].
+pub const FRAMES_PER_SECOND: f64 = 60.0;
+
+/// Instructions in one of the [`FRAMES_PER_SECOND`] frames at `hertz`, rounded to the nearest
+/// but at least 1, e.g. 13 at the default 800 Hz.
+pub fn cycles_per_frame(hertz: f64) -> u32 {
+    (hertz / FRAMES_PER_SECOND).round().max(1.0) as u32
+}
+
 /// The keymap that this implementation uses internally. Based off
 /// of: <http://devernay.free.fr/hacks/chip8/C8TECH10.HTM#keyboard>
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[repr(u8)]
 pub enum Key {
     Num0 = 0,
@@ -38,6 +74,7 @@ pub enum Key {
 }
 
 #[derive(Error, Debug, PartialEq)]
+#[non_exhaustive]
 pub enum Chip8Error {
     #[error("Internal error from unsupported key code: `{0}`")]
     InternalKeyError(u8),
@@ -45,6 +82,42 @@ pub enum Chip8Error {
     RomTooBig(u16),
     #[error("Opcode `{0:#06x}` is not supported")]
     UnsupportedOpcode(u16),
+    #[error("Memory access at `{0:#x}` is outside of memory")]
+    InvalidMemoryAccess(u16),
+    #[error("Stack overflow: the call at `{0:#x}` is nested more than 16 calls deep")]
+    StackOverflow(u16),
+    #[error("Stack underflow: the return at `{0:#x}` has no call to return to")]
+    StackUnderflow(u16),
+    #[error("Opcode address `{0:#x}` is not even")]
+    MemoryAlignment(u16),
+    #[error("Programs can't start at `{0:#x}`: it is reserved for the interpreter or outside of memory")]
+    InvalidProgramAddress(u16),
+    #[error("The font can't be placed at `{0:#x}`: it has to fit below the program space")]
+    InvalidFontAddress(u16),
+    #[error("Register `V{0:X}` does not exist")]
+    InvalidRegister(u8),
+    #[error("Register `V{register:X}` is `{actual:#04x}`, expected `{expected:#04x}`")]
+    AssertionFailed { register: u8, expected: u8, actual: u8 },
+    #[error("Could not read data: {0}")]
+    Io(String),
+    #[error("Could not deserialize state: {0}")]
+    DeserializationError(String),
+    #[error("Invalid save state: {0}")]
+    InvalidSaveState(String),
+    #[error("Invalid quirks configuration: {0}")]
+    InvalidQuirksConfiguration(QuirksValidationError),
+    #[error("Invalid opcode overrides: {0}")]
+    InvalidOpcodeOverrides(OverrideError),
+    #[error("Halted by an opcode override at `{0:#x}`")]
+    Halted(u16),
+    #[error("Breakpoint hit at `{0:#x}`")]
+    BreakpointHit(u16),
+}
+
+impl From<std::array::TryFromSliceError> for Chip8Error {
+    fn from(e: std::array::TryFromSliceError) -> Self {
+        Chip8Error::DeserializationError(e.to_string())
+    }
 }
 
 impl TryFrom<u8> for Key {
@@ -73,6 +146,116 @@ impl TryFrom<u8> for Key {
     }
 }
 
+impl Key {
+    /// Every key, in order.
+    pub const ALL: [Key; 16] = [
+        Key::Num0,
+        Key::Num1,
+        Key::Num2,
+        Key::Num3,
+        Key::Num4,
+        Key::Num5,
+        Key::Num6,
+        Key::Num7,
+        Key::Num8,
+        Key::Num9,
+        Key::A,
+        Key::B,
+        Key::C,
+        Key::D,
+        Key::E,
+        Key::F,
+    ];
+}
+
+impl From<Key> for u8 {
+    fn from(key: Key) -> Self {
+        key as u8
+    }
+}
+
+/// Prints the key's hex digit, e.g. `A`.
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:X}", u8::from(*self))
+    }
+}
+
+/// Parses a single hex digit, in either case.
+impl FromStr for Key {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chars = s.chars();
+        match (chars.next().and_then(|c| c.to_digit(16)), chars.next()) {
+            (Some(digit), None) => Ok(Key::ALL[digit as usize]),
+            _ => Err(format!("unknown key `{s}`, expected a hex digit from 0 to F")),
+        }
+    }
+}
+
+/// What the `Fx55` and `Fx65` opcodes do to the index register after storing or loading
+/// `V0` through `Vx`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoadStoreBehavior {
+    /// `I` is left pointing past the last register, i.e. `I += x + 1`. This is what the original
+    /// COSMAC VIP did.
+    IncrementByXPlusOne,
+    /// `I += x`, one less than the COSMAC VIP. This is what CHIP-48 did.
+    IncrementByX,
+    /// `I` is left unchanged, as in SUPER-CHIP and most modern interpreters.
+    Unchanged,
+}
+
+/// Maps the old `increment_ir: bool` quirk: `true` increments by `x + 1`, `false` leaves `I` alone.
+impl From<bool> for LoadStoreBehavior {
+    fn from(increment: bool) -> Self {
+        if increment {
+            LoadStoreBehavior::IncrementByXPlusOne
+        } else {
+            LoadStoreBehavior::Unchanged
+        }
+    }
+}
+
+impl fmt::Display for LoadStoreBehavior {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LoadStoreBehavior::IncrementByXPlusOne => "x+1",
+            LoadStoreBehavior::IncrementByX => "x",
+            LoadStoreBehavior::Unchanged => "unchanged",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Parses the names printed by `Display`, as well as `true` and `false` for the old boolean quirk.
+impl FromStr for LoadStoreBehavior {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "x+1" | "true" => Ok(LoadStoreBehavior::IncrementByXPlusOne),
+            "x" => Ok(LoadStoreBehavior::IncrementByX),
+            "unchanged" | "false" => Ok(LoadStoreBehavior::Unchanged),
+            _ => Err(format!(
+                "unknown behavior `{s}`, expected `x+1`, `x` or `unchanged`"
+            )),
+        }
+    }
+}
+
+/// When [`chip8::Chip8::emulate_frame`] reads the keys.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputSampling {
+    /// Read the keys once at the start of the frame, and use that snapshot for every key opcode
+    /// in it. This makes fewer calls into the `Input`, but a key change is seen up to a frame
+    /// late, and changes within a frame are missed.
+    PerFrame,
+    /// Read the keys every time a key opcode runs, for games that need key changes within a frame.
+    PerCycle,
+}
+
 /// Chip 8 has various quirks that differ from extension to extension.
 /// This struct contains them, and can be adjusted depending on the game
 /// being run.
@@ -80,19 +263,22 @@ impl TryFrom<u8> for Key {
 /// A `Default` implementation is provided for the original Chip 8 platform.
 #[derive(Debug, Builder)]
 #[builder(default)]
+#[non_exhaustive]
 pub struct Quirks {
     /// Should the `AND`, `OR`, and `XOR` instructions reset the `VF` register?
     ///
     /// Default: `true`.
     pub reset_vf: bool,
 
-    /// Should the `Fx55` and `Fx65` opcodes increment the index register? The
+    /// How should the `Fx55` and `Fx65` opcodes increment the index register? The
     /// original COSMAC VIP incremented the index register for these opcodes.
     /// Games from the 1970s and 1980s might rely on it being incremented.
-    /// Modern games might rely on it not being incremented.
+    /// Modern games might rely on it not being incremented. The builder also accepts a `bool`,
+    /// see [`LoadStoreBehavior::from`].
     ///
-    /// Default: `true`.
-    pub increment_ir: bool,
+    /// Default: [`LoadStoreBehavior::IncrementByXPlusOne`].
+    #[builder(setter(into))]
+    pub increment_ir: LoadStoreBehavior,
 
     /// This applies to the shift instructions, `8XY6` and `8XYE`. Should register `VX` be
     /// set to the value of register `VY` before shifting?
@@ -113,22 +299,146 @@ pub struct Quirks {
     ///
     /// Default: `true`.
     pub clipping: bool,
+
+    /// Should addresses past the end of memory wrap around to `0x000`? Otherwise sprites that
+    /// extend past the end of memory are cut short with a warning.
+    ///
+    /// Default: `false`.
+    pub wrap_memory: bool,
+
+    /// Non-standard opcodes that are tolerated instead of failing with `UnsupportedOpcode`, and
+    /// what each of them does. [`chip8::VIP_LEGACY_OPCODES`] holds the ones used by VIP-era ROMs.
+    ///
+    /// Default: empty.
+    pub legacy_opcodes: Vec<(u16, LegacyOpcode)>,
+
+    /// Many interpreters support `00C0` (scroll the screen left 1 pixel) and `00C1` (scroll right
+    /// 1 pixel) outside of SUPER-CHIP. Turning this on enables both opcodes.
+    ///
+    /// Default: `false`.
+    pub extended_scrolling: bool,
+
+    /// On the COSMAC VIP, `0nnn` called the RCA 1802 machine code routine at `nnn`. Turning this
+    /// on passes those calls to the handler set with [`chip8::Chip8::set_machine_call_handler`],
+    /// or skips them with a warning if there is none. Opcodes in `legacy_opcodes` take precedence.
+    ///
+    /// Default: `false`.
+    pub handle_0x0nnn_syscalls: bool,
+
+    /// Most timer ticks applied after a single instruction. Ticks that arrive in a burst, e.g.
+    /// after the host stalled, are carried over to later cycles instead of making the timers jump.
+    /// `None` applies every pending tick at once.
+    ///
+    /// Default: `None`.
+    pub ticks_per_cycle_cap: Option<u32>,
+
+    /// When [`chip8::Chip8::emulate_frame`] reads the keys. `emulate_cycle` always reads them
+    /// live.
+    ///
+    /// Default: [`InputSampling::PerFrame`].
+    pub input_sampling: InputSampling,
+
+    /// Keeps the stack and the display in memory where the COSMAC VIP interpreter kept them, for
+    /// programs that read or change them directly: the stack grows down from
+    /// [`chip8::VIP_STACK_ADDRESS`], and the display is stored from [`chip8::VIP_DISPLAY_ADDRESS`]
+    /// to the end of memory, one bit per pixel. Writing there with `Fx33` or `Fx55` changes the
+    /// screen, drawing changes the memory, and returns use the address in memory.
+    ///
+    /// Default: `false`.
+    pub vip_memory_layout: bool,
+}
+
+/// Quirks that contradict each other or can never take effect. See [`Quirks::validate`].
+#[derive(Error, Debug, Clone, PartialEq)]
+pub struct QuirksValidationError {
+    /// Every conflict found, as `(quirk, conflicting_with, explanation)`.
+    pub conflicting: Vec<(&'static str, &'static str, String)>,
+}
+
+impl fmt::Display for QuirksValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (quirk, other, why)) in self.conflicting.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "`{quirk}` conflicts with `{other}`: {why}")?;
+        }
+        Ok(())
+    }
 }
 
 impl Quirks {
     pub fn new(
         reset_vf: bool,
-        increment_ir: bool,
+        increment_ir: impl Into<LoadStoreBehavior>,
         use_vy_in_shift: bool,
         use_vx_in_jump: bool,
         clipping: bool,
     ) -> Self {
         Self {
             reset_vf,
-            increment_ir,
+            increment_ir: increment_ir.into(),
             use_vy_in_shift,
             use_vx_in_jump,
             clipping,
+            wrap_memory: false,
+            legacy_opcodes: Vec::new(),
+            extended_scrolling: false,
+            handle_0x0nnn_syscalls: false,
+            ticks_per_cycle_cap: None,
+            input_sampling: InputSampling::PerFrame,
+            vip_memory_layout: false,
+        }
+    }
+
+    /// Checks for quirks that contradict each other, or that can never take effect.
+    pub fn validate(&self) -> Result<(), QuirksValidationError> {
+        let mut conflicting = Vec::new();
+
+        for (i, &(opcode, legacy)) in self.legacy_opcodes.iter().enumerate() {
+            // Only the 0x0 family falls through to the legacy table, and not 00E0/00EE
+            if opcode & 0xF000 != 0 || matches!(opcode & 0xFF, 0xE0 | 0xEE) {
+                conflicting.push((
+                    "legacy_opcodes",
+                    "instruction set",
+                    format!(
+                        "`{opcode:#06x}` is a standard instruction, so it is never treated as {legacy:?}"
+                    ),
+                ));
+            }
+
+            if self.extended_scrolling && matches!(opcode, 0x00C0 | 0x00C1) {
+                conflicting.push((
+                    "legacy_opcodes",
+                    "extended_scrolling",
+                    format!("`{opcode:#06x}` scrolls the screen, so it is never treated as {legacy:?}"),
+                ));
+            }
+
+            let earlier = self.legacy_opcodes[..i]
+                .iter()
+                .find(|&&(other_opcode, other)| other_opcode == opcode && other != legacy);
+            if let Some((_, other)) = earlier {
+                conflicting.push((
+                    "legacy_opcodes",
+                    "legacy_opcodes",
+                    format!("`{opcode:#06x}` is mapped to both {other:?} and {legacy:?}"),
+                ));
+            }
+        }
+
+        if self.ticks_per_cycle_cap == Some(0) {
+            conflicting.push((
+                "ticks_per_cycle_cap",
+                "timers",
+                "a cap of 0 means the timers never count down".to_string(),
+            ));
+        }
+
+        if conflicting.is_empty() {
+            Ok(())
+        } else {
+            Err(QuirksValidationError { conflicting })
         }
     }
 }
@@ -137,20 +447,64 @@ impl Default for Quirks {
     fn default() -> Self {
         Self {
             reset_vf: true,
-            increment_ir: true,
+            increment_ir: LoadStoreBehavior::IncrementByXPlusOne,
             use_vy_in_shift: true,
             use_vx_in_jump: false,
             clipping: true,
+            wrap_memory: false,
+            legacy_opcodes: Vec::new(),
+            extended_scrolling: false,
+            handle_0x0nnn_syscalls: false,
+            ticks_per_cycle_cap: None,
+            input_sampling: InputSampling::PerFrame,
+            vip_memory_layout: false,
         }
     }
 }
 
+/// Summarizes the quirks on one line, e.g. for bug reports.
+impl fmt::Display for Quirks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "reset_vf={} increment_ir={} use_vy_in_shift={} use_vx_in_jump={} clipping={}",
+            self.reset_vf, self.increment_ir, self.use_vy_in_shift, self.use_vx_in_jump, self.clipping
+        )
+    }
+}
+
 /// Options to debug programs and emulator.
 #[derive(Debug, Builder, Default)]
+#[builder(default)]
 pub struct DebugOptions {
-    /// Prints opcodes as they're interpreted.
+    /// Logs opcodes as they're interpreted, at the info level, from the hook
+    /// [`chip8::Chip8::set_pre_execute_hook`] replaces.
     pub print_opcodes: bool,
 
     /// Dumps the graphics buffer after every draw opcode.
     pub dump_graphics: bool,
+
+    /// Records every draw opcode in [`chip8::Chip8::get_draw_call_log`].
+    pub log_draw_calls: bool,
+
+    /// Records which subroutines call which, for [`chip8::Chip8::export_call_graph_dot`].
+    pub track_call_graph: bool,
+
+    /// Counts the calls and instructions of every subroutine, for [`chip8::Chip8::call_profile`].
+    pub profile_calls: bool,
+
+    /// Counts the instructions executed at every address and in every opcode family, for
+    /// [`chip8::Chip8::profile_report`].
+    pub profile_opcodes: bool,
+
+    /// Counts the reads and writes of every address, for [`chip8::Chip8::trace_memory_hotspots`].
+    pub track_memory_hotspots: bool,
+
+    /// Keeps the last `trace_depth` instructions executed, for [`chip8::Chip8::trace`]. `0` turns
+    /// the trace off.
+    pub trace_depth: usize,
+
+    /// `(register, value)` pairs checked with [`chip8::Chip8::assert_register_equals`] after
+    /// every cycle, stopping the emulation with [`Chip8Error::AssertionFailed`] on a mismatch.
+    pub register_assertions: Vec<(u8, u8)>,
 }
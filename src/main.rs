@@ -1,20 +1,157 @@
 mod drivers;
+mod frametimes;
+mod highscore;
+mod logger;
+mod messages;
+mod pacing;
+mod palette;
+mod playlist;
+mod playtime;
+mod sdl_error;
+mod tone;
 use clap::{ArgAction, Parser};
 use measurements::Frequency;
 use wheat::{
-    chip8::Chip8, graphics::Graphics, timer::TimerOperation, traits::Display, DebugOptionsBuilder,
-    QuirksBuilder,
+    chip8::{self, Chip8, Chip8Builder, NoInput, VIP_LEGACY_OPCODES},
+    crash_report::CrashReport,
+    demo, disasm, golden,
+    graphics::{self, Coord, Dimensions, Graphics, Palette},
+    opcode_overrides::OpcodeOverrides,
+    rewind::RewindBuffer,
+    soak,
+    timer::TimerOperation,
+    traits::{Display, GraphicsBuffer, Rom},
+    DebugOptions, DebugOptionsBuilder, LoadStoreBehavior, Quirks, QuirksBuilder, SCREEN_HEIGHT, SCREEN_WIDTH,
 };
 
-use std::{process, sync::mpsc, thread, time::Duration};
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    process,
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use drivers::{key_label, InputMode, InputUpdate, RomDriver, SdlAudioDriver, SdlDisplayDriver, SdlInput};
+use frametimes::FRAME_BUDGET;
+use highscore::{HighScores, ScoreLocation};
+use messages::Catalog;
+use pacing::Throttle;
+use playlist::Playlist;
+use playtime::PlayTime;
+
+/// Longest to wait for the display to be ready before drawing a frame anyway.
+const VSYNC_TIMEOUT: Duration = Duration::from_millis(20);
+
+/// Playlist entry of the `--demo` ROM.
+const DEMO_NAME: &str = "demo";
 
-use drivers::{InputUpdate, RomDriver, SdlAudioDriver, SdlDisplayDriver, SdlInput};
+/// Addresses `--profile-opcodes` lists.
+const PROFILE_TOP_ADDRESSES: usize = 10;
+
+/// Snapshots kept for rewinding per second of play, and the time between two steps back.
+const REWIND_STEPS_PER_SECOND: u32 = 10;
+const REWIND_STEP: Duration = Duration::from_millis(100);
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
-    /// Chip 8 ROM to launch
-    rom: String,
+    /// Chip 8 ROMs to launch. The first one starts immediately; PageDown and PageUp switch to the
+    /// next and previous ROM.
+    #[arg(required_unless_present_any = ["list_palettes", "demo", "soak"], num_args = 1..)]
+    roms: Vec<String>,
+
+    /// Start with the built-in two player pong demo, before any ROMs given. The left paddle moves
+    /// with 1 and Q, the right one with 4 and R.
+    #[arg(long, default_value_t = false, action = ArgAction::Set)]
+    demo: bool,
+
+    /// Print an annotated disassembly of the first ROM instead of running it.
+    #[arg(long, default_value_t = false, action = ArgAction::Set)]
+    disassemble: bool,
+
+    /// Platform the `--disassemble` listing is for: `chip8`, `schip` or `xochip`. Instructions
+    /// it doesn't have are marked in the listing.
+    #[arg(long, default_value = "chip8")]
+    disassemble_platform: disasm::Platform,
+
+    /// File to write the `--disassemble` listing to, instead of standard output.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Run the first ROM without a window and check the registers it sets, given as
+    /// comma-separated `VX=value` pairs, e.g. `V0=1,VF=0x00`. Exits with an error if any differ.
+    #[arg(long, value_delimiter = ',', value_parser = parse_register_value)]
+    test_rom: Option<Vec<(u8, u8)>>,
+
+    /// Number of cycles to run `--test-rom` for.
+    #[arg(long, default_value_t = 10_000)]
+    test_rom_cycles: u64,
+
+    /// Run the first ROM as fast as possible without a window, output, or key presses, then print
+    /// how many instructions it ran and how fast. Meant for running under a profiler like `perf`.
+    #[arg(long, default_value_t = false, action = ArgAction::Set)]
+    profile_run: bool,
+
+    /// Number of cycles to run `--profile-run` for.
+    #[arg(long, default_value_t = 5_000_000)]
+    profile_cycles: u64,
+
+    /// How to keep track of the keys: `polled` rescans the keyboard at `--freq-input`, `events`
+    /// follows every key press and release. F4 switches between them while running.
+    #[arg(long, default_value = "polled")]
+    input_mode: InputMode,
+
+    /// Run the first ROM for this many cycles as fast as possible without a window, then print
+    /// how fast the emulator ran, in MHz.
+    #[arg(long)]
+    benchmark_cycles: Option<u64>,
+
+    /// Run the first ROM without a window and compare the screen at each of the `--frames`
+    /// against the goldens in `--golden`. Exits with an error if any differ.
+    #[arg(long, default_value_t = false, action = ArgAction::Set)]
+    compare_golden: bool,
+
+    /// Comma-separated frame numbers, counted at 60 frames a second, to compare with
+    /// `--compare-golden`, e.g. `100,250,600`.
+    #[arg(long, value_delimiter = ',', default_value = "60")]
+    frames: Vec<u64>,
+
+    /// Directory with the `--compare-golden` goldens, one `frame-NNNNN.txt` file per frame.
+    #[arg(long, default_value = "golden")]
+    golden: PathBuf,
+
+    /// Write the goldens for `--compare-golden` with the current screens instead of comparing
+    /// them.
+    #[arg(long, default_value_t = false, action = ArgAction::Set)]
+    update_golden: bool,
+
+    /// Run every ROM in this directory without a window instead, pressing all keys now and then,
+    /// and print how each run ended. A ROM that fails doesn't stop the others.
+    #[arg(long, value_name = "DIR")]
+    soak: Option<PathBuf>,
+
+    /// Number of cycles to run each `--soak` ROM for.
+    #[arg(long, default_value_t = 200_000)]
+    cycles_per_rom: u64,
+
+    /// File to write the `--soak` results to, as JSON.
+    #[arg(long, default_value = "soak-report.json")]
+    soak_report: PathBuf,
+
+    /// Swap the bytes of every opcode in ROMs that look like they were saved byte-swapped, i.e.
+    /// that mostly decode to unknown opcodes unless they're swapped.
+    #[arg(long, default_value_t = false, action = ArgAction::Set)]
+    auto_fix_byteswap: bool,
+
+    /// Address the program starts executing at, in decimal or `0x` hex.
+    #[arg(long, default_value = "0x200", value_parser = parse_address)]
+    entry: u16,
+
+    /// Address the ROM is loaded at, in decimal or `0x` hex, e.g. `0x600` for ETI-660 programs.
+    #[arg(long, default_value = "0x200", value_parser = parse_address)]
+    load_addr: u16,
 
     /// Frequency (in Hz) for the Chip 8 CPU to run at.
     #[arg(short, long, default_value_t = 800)]
@@ -33,11 +170,12 @@ struct Args {
     #[arg(long, default_value_t = true, action = ArgAction::Set)]
     q_reset_vf: bool,
 
-    /// Quirk: should the `Fx55` and `Fx65` opcodes increment the index register?
+    /// Quirk: how should the `Fx55` and `Fx65` opcodes increment the index register? `x+1` (or
+    /// `true`) like the COSMAC VIP, `x` like CHIP-48, or `unchanged` (or `false`).
     /// Games from the 1970s and 1980s might rely on it being incremented.
     /// Modern games might rely on it not being incremented.
-    #[arg(long, default_value_t = true, action = ArgAction::Set)]
-    q_increment_ir: bool,
+    #[arg(long, default_value = "x+1")]
+    q_increment_ir: LoadStoreBehavior,
 
     /// Quirk: should register `VX` be set to the value of register `VY` before shifting?
     /// Modern games might require this to be false.
@@ -55,6 +193,42 @@ struct Args {
     #[arg(long, default_value_t = true, action = ArgAction::Set)]
     q_clipping: bool,
 
+    /// Quirk: wrap addresses past the end of memory around to `0x000`? Otherwise sprites that
+    /// extend past the end of memory are cut short.
+    #[arg(long, default_value_t = false, action = ArgAction::Set)]
+    q_wrap_memory: bool,
+
+    /// Quirk: tolerate the low-level display off/on opcodes (`00E1`-`00E3`) used by some COSMAC
+    /// VIP-era ROMs instead of stopping with an unsupported opcode error.
+    #[arg(long, default_value_t = false, action = ArgAction::Set)]
+    q_vip_legacy_opcodes: bool,
+
+    /// Quirk: support `00C0` and `00C1`, which scroll the screen 1 pixel left and right, like
+    /// many interpreters do outside of SUPER-CHIP.
+    #[arg(long, default_value_t = false, action = ArgAction::Set)]
+    q_extended_scrolling: bool,
+
+    /// Quirk: most timer ticks to apply after a single instruction. Ticks that pile up, e.g. when
+    /// the host stalls, are spread over later instructions instead of making the timers jump.
+    /// Unlimited by default.
+    #[arg(long)]
+    q_ticks_per_cycle_cap: Option<u32>,
+
+    /// Quirk: keep the stack and the display in memory where the COSMAC VIP did, from `0xECE`
+    /// (growing down) and from `0xF00`, for programs that change them directly.
+    #[arg(long, default_value_t = false, action = ArgAction::Set)]
+    q_vip_memory_layout: bool,
+
+    /// Config file whose `[opcodes]` section changes how opcodes are handled, with lines like
+    /// `0x8xyF = alias=0x8xy4`. An opcode can be made a `nop`, `halt` the emulator, stop at a
+    /// `break`point, or run as another opcode.
+    #[arg(long, value_name = "FILE")]
+    opcode_overrides: Option<PathBuf>,
+
+    /// Let `--opcode-overrides` change opcodes that CHIP-8 already has a meaning for.
+    #[arg(long, default_value_t = false, action = ArgAction::Set)]
+    allow_core_override: bool,
+
     /// Print opcodes as they're interpreted.
     #[arg(long, default_value_t = false, action = ArgAction::Set)]
     print_opcodes: bool,
@@ -62,75 +236,772 @@ struct Args {
     /// Dump the graphics buffer after every draw opcode.
     #[arg(long, default_value_t = false, action = ArgAction::Set)]
     dump_graphics: bool,
+
+    /// Window pixels to leave between screen pixels, for a visible grid.
+    #[arg(long, default_value_t = 0)]
+    pixel_gap: u16,
+
+    /// Draw pixels with rounded corners.
+    #[arg(long, default_value_t = false, action = ArgAction::Set)]
+    rounded_pixels: bool,
+
+    /// JSON file with the 16 `[r, g, b]` colors to draw each pixel value with, instead of a
+    /// `--palette-name` or `--palette-file` palette. By default pixels that are off are black and
+    /// everything else is white.
+    #[arg(long, conflicts_with_all = ["palette_name", "palette_file"])]
+    palette: Option<PathBuf>,
+
+    /// Palette to draw with: a built-in one or one loaded with `--palette-file`. Defaults to the
+    /// last `--palette-file`, or `classic` if there is none.
+    #[arg(long)]
+    palette_name: Option<String>,
+
+    /// TOML file defining a palette with `name`, `fg`, `bg`, and optionally `planes`, as
+    /// `"#RRGGBB"` colours. Can be given several times.
+    #[arg(long)]
+    palette_file: Vec<PathBuf>,
+
+    /// Print the built-in palettes and the ones loaded with `--palette-file`, then exit.
+    #[arg(long, default_value_t = false, action = ArgAction::Set)]
+    list_palettes: bool,
+
+    /// Show which keys the ROM accepts in the window title while it waits for a key press.
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
+    key_hints: bool,
+
+    /// Screen rectangle `X,Y,W,H` to print as sprite data and Octo source whenever F10 takes a
+    /// screenshot, e.g. `8,4,12,5`.
+    #[arg(long, value_parser = parse_rect)]
+    export_sprites: Option<(Coord, Dimensions)>,
+
+    /// Print statistics about the emulated program on exit.
+    #[arg(long, default_value_t = false, action = ArgAction::Set)]
+    stats: bool,
+
+    /// Check these registers after every instruction, given as comma-separated `VX=value` pairs
+    /// like `--test-rom`, and stop as soon as one differs.
+    #[arg(long, value_delimiter = ',', value_parser = parse_register_value)]
+    assert_on_register_mismatch: Option<Vec<(u8, u8)>>,
+
+    /// Write the subroutine calls the ROM made to this file on exit, as a Graphviz DOT graph.
+    #[arg(long, value_name = "FILE")]
+    dump_call_graph: Option<PathBuf>,
+
+    /// Print the calls and instructions of every subroutine the ROM ran on exit.
+    #[arg(long, default_value_t = false, action = ArgAction::Set)]
+    profile_calls: bool,
+
+    /// Print the addresses the ROM spent the most instructions at, and the instructions it ran by
+    /// opcode family, on exit.
+    #[arg(long, default_value_t = false, action = ArgAction::Set)]
+    profile_opcodes: bool,
+
+    /// Keep the last N instructions executed, with the registers they saw, and add them to the
+    /// `--crash-reports`, or to the error message without them. `0` keeps none, and up to 100000
+    /// can be kept.
+    #[arg(long, value_name = "N", default_value_t = 0, value_parser = clap::value_parser!(u32).range(..=100_000))]
+    trace_depth: u32,
+
+    /// Where the ROM keeps its score, as `ADDR:ENCODING:LEN`, e.g. `0x2F0:bcd:3`. ENCODING is
+    /// `bcd` (one digit per byte) or `binary`. The best score is shown in the window title and
+    /// kept across sessions.
+    #[arg(long)]
+    high_score: Option<ScoreLocation>,
+
+    /// File the best scores are kept in.
+    #[arg(long, default_value = "wheat-high-scores.txt")]
+    high_score_file: PathBuf,
+
+    /// Seconds of play to keep for rewinding with Backspace, up to an hour. `0` turns rewinding
+    /// off.
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(..=3600))]
+    rewind_seconds: u32,
+
+    /// When the ROM stops with an error, save the screen, the registers and the quirks in a new
+    /// directory under `--crash-report-dir`, to attach to a bug report.
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
+    crash_reports: bool,
+
+    /// Directory the `--crash-reports` are saved in.
+    #[arg(long, default_value = "crash-reports")]
+    crash_report_dir: PathBuf,
+
+    /// Language file to show the emulator's messages in, with one `id = text` line per message.
+    /// Messages it leaves out are shown in English.
+    #[arg(long, value_name = "FILE")]
+    lang: Option<PathBuf>,
 }
 
-fn freq_to_time(hertz: f64) -> Duration {
-    let freq = Frequency::from_hertz(hertz);
-    freq.as_period()
+/// Parses a `VX=value` register expectation, with the value in decimal or `0x` hex.
+fn parse_register_value(s: &str) -> Result<(u8, u8), String> {
+    let (reg, value) = s.split_once('=').ok_or("expected `VX=value`")?;
+    let reg = reg.strip_prefix('V').ok_or("register must be V0-VF")?;
+    let reg = u8::from_str_radix(reg, 16)
+        .ok()
+        .filter(|reg| *reg <= 0xF)
+        .ok_or("register must be V0-VF")?;
+    let value = match value.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+    .map_err(|e| format!("invalid value `{value}`: {e}"))?;
+
+    Ok((reg, value))
 }
 
-fn main() -> Result<(), String> {
-    let args = Args::parse();
+/// Parses an `X,Y,W,H` screen rectangle.
+fn parse_rect(s: &str) -> Result<(Coord, Dimensions), String> {
+    let values: Vec<u16> = s
+        .split(',')
+        .map(|value| value.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|e| format!("invalid rectangle `{s}`: {e}"))?;
 
-    let (timer_tx, timer_rx) = mpsc::channel();
-    let (input_tx, input_rx) = mpsc::channel();
+    let fits = |start: u16, len: u16, screen: u16| start.checked_add(len).is_some_and(|end| end <= screen);
+    match values[..] {
+        [_, _, 0, _] | [_, _, _, 0] => Err("the rectangle can't be empty".to_string()),
+        [x, y, w, h] if fits(x, w, SCREEN_WIDTH) && fits(y, h, SCREEN_HEIGHT) => {
+            Ok((Coord::new(x, y), Dimensions::new(w, h)))
+        }
+        [_, _, _, _] => Err(format!(
+            "the rectangle has to fit on the {SCREEN_WIDTH}x{SCREEN_HEIGHT} screen"
+        )),
+        _ => Err("expected `X,Y,W,H`".to_string()),
+    }
+}
 
-    let sdl_context = sdl2::init()?;
-    let mut display = SdlDisplayDriver::new(&sdl_context);
-    let audio = SdlAudioDriver::new(&sdl_context);
-    let rom = RomDriver::new(&args.rom);
-    let mut input = SdlInput::new(&sdl_context, input_rx);
-    let graphics = Graphics::new();
+/// Parses a memory address in decimal or `0x` hex.
+fn parse_address(s: &str) -> Result<u16, String> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|e| format!("invalid address `{s}`: {e}"))
+}
 
-    let quirks = QuirksBuilder::default()
+fn freq_to_time(hertz: f64) -> Duration {
+    let freq = Frequency::from_hertz(hertz);
+    freq.as_period()
+}
+
+fn quirks(args: &Args) -> Quirks {
+    QuirksBuilder::default()
         .reset_vf(args.q_reset_vf)
         .increment_ir(args.q_increment_ir)
         .use_vy_in_shift(args.q_use_vy_in_shift)
         .use_vx_in_jump(args.q_use_vx_in_jump)
         .clipping(args.q_clipping)
+        .wrap_memory(args.q_wrap_memory)
+        .legacy_opcodes(if args.q_vip_legacy_opcodes {
+            VIP_LEGACY_OPCODES.to_vec()
+        } else {
+            Vec::new()
+        })
+        .extended_scrolling(args.q_extended_scrolling)
+        .ticks_per_cycle_cap(args.q_ticks_per_cycle_cap)
+        .vip_memory_layout(args.q_vip_memory_layout)
         .build()
-        .unwrap();
+        .unwrap()
+}
 
-    let options = DebugOptionsBuilder::default()
+fn debug_options(args: &Args) -> DebugOptions {
+    DebugOptionsBuilder::default()
         .print_opcodes(args.print_opcodes)
         .dump_graphics(args.dump_graphics)
+        .track_call_graph(args.dump_call_graph.is_some())
+        .profile_calls(args.profile_calls)
+        .profile_opcodes(args.profile_opcodes)
+        .trace_depth(args.trace_depth as usize)
+        .register_assertions(args.assert_on_register_mismatch.clone().unwrap_or_default())
         .build()
-        .unwrap();
+        .unwrap()
+}
 
-    let mut chip8 = Chip8::new(graphics, timer_rx, quirks, options);
+/// Starts a thread that sends a timer decrement every `sleep`. The thread stops once the returned
+/// receiver is dropped, i.e. when the `Chip8` that owns it is replaced.
+fn spawn_timer(sleep: Duration) -> Receiver<TimerOperation> {
+    let (timer_tx, timer_rx) = mpsc::channel();
 
-    let chip8_freq = Frequency::from_hertz(args.freq_cpu.into());
-    let emulation_sleep_time = chip8_freq.as_period();
+    thread::spawn(move || loop {
+        thread::sleep(sleep);
+        if timer_tx.send(TimerOperation::Decrement(1)).is_err() {
+            break;
+        }
+    });
 
-    chip8.load_rom(&rom).map_err(|e| e.to_string())?;
+    timer_rx
+}
 
-    // Setup separate threads for managing input and timer updates
-    let timer_sleep = freq_to_time(args.freq_timer.into());
-    let input_sleep = freq_to_time(args.freq_input.into());
+/// Creates a fresh `Chip8` with `rom` loaded into it.
+fn start_rom(args: &Args, rom: &RomDriver) -> Result<Chip8<Graphics>, String> {
+    let overrides = match &args.opcode_overrides {
+        Some(path) => {
+            let text = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+            OpcodeOverrides::parse(&text).map_err(|e| format!("{}: {e}", path.display()))?
+        }
+        None => OpcodeOverrides::default(),
+    };
+    let timer_rx = spawn_timer(freq_to_time(args.freq_timer.into()));
+    let mut chip8 = Chip8Builder::new(Graphics::new(), timer_rx)
+        .quirks(quirks(args))
+        .debug_options(debug_options(args))
+        .cpu_frequency(args.freq_cpu.into())
+        .timer_frequency(args.freq_timer.into())
+        .entry_point(args.entry)
+        .load_address(args.load_addr)
+        .opcode_overrides(overrides)
+        .allow_core_override(args.allow_core_override)
+        .build()
+        .map_err(|e| e.to_string())?;
 
-    thread::spawn(move || loop {
-        thread::sleep(timer_sleep);
-        timer_tx.send(TimerOperation::Decrement(1)).unwrap();
-    });
+    if args.auto_fix_byteswap && disasm::detect_byte_swap(rom.data()) {
+        println!("{}", messages::text("rom-byte-swapped", &[]));
+        chip8.load_rom(&disasm::swap_bytes(rom.data()))
+    } else {
+        chip8.load_rom(rom)
+    }
+    .map_err(|e| e.to_string())?;
+
+    Ok(chip8)
+}
+
+/// Window title for `name`, with the play time, the best score when the ROM's score is being
+/// tracked, and `key_hint` while the ROM waits for a key.
+fn window_title(
+    name: &str,
+    high_scores: &Option<(ScoreLocation, HighScores)>,
+    key_hint: Option<String>,
+    throttle: &Throttle,
+    play_time: &PlayTime,
+) -> String {
+    let mut title = name.to_string();
+    let (real, emulated) = (
+        playtime::format_clock(play_time.real()),
+        playtime::format_clock(play_time.emulated()),
+    );
+    let clocks = match play_time.drift() {
+        Some(drift) => {
+            let drift = format!("{drift:+.1}");
+            let values: [(&str, &dyn fmt::Display); 3] =
+                [("real", &real), ("emulated", &emulated), ("drift", &drift)];
+            messages::text("title-play-time-drift", &values)
+        }
+        None => messages::text("title-play-time", &[("real", &real), ("emulated", &emulated)]),
+    };
+    title += &format!(" | {clocks}");
+    if let Some((location, scores)) = high_scores {
+        let best = scores.best(name).unwrap_or(0);
+        title += &format!(
+            " | {}",
+            messages::text("title-best", &[("score", &location.format(best))])
+        );
+    }
+    if let Some(hint) = key_hint {
+        title += &format!(" | {hint}");
+    }
+    if let Some(hertz) = throttle.throttled_hz() {
+        let hertz = format!("{hertz:.0}");
+        title += &format!(" | {}", messages::text("title-throttled", &[("hertz", &hertz)]));
+    }
+    title
+}
+
+/// Tells the player which physical keys the ROM accepts while it waits for one.
+fn key_hint(chip8: &Chip8<Graphics>) -> String {
+    match chip8.expected_keys() {
+        Some(keys) => {
+            let labels: Vec<_> = keys.into_iter().map(key_label).collect();
+            messages::text("title-press", &[("keys", &labels.join(" "))])
+        }
+        None => messages::text("title-press-any", &[]),
+    }
+}
+
+/// Records the score currently in memory. Returns whether it's a new best.
+fn track_high_score(
+    name: &str,
+    chip8: &Chip8<Graphics>,
+    high_scores: &mut Option<(ScoreLocation, HighScores)>,
+) -> bool {
+    let Some((location, scores)) = high_scores else {
+        return false;
+    };
+
+    match location.decode(chip8.memory()) {
+        Some(score) => scores.update(name, score),
+        None => false,
+    }
+}
+
+fn save_high_scores(high_scores: &Option<(ScoreLocation, HighScores)>) {
+    if let Some((_, scores)) = high_scores {
+        if let Err(e) = scores.save() {
+            eprintln!("{}", messages::text("save-high-scores-failed", &[("error", &e)]));
+        }
+    }
+}
+
+/// Writes `report` to a new directory under `dir`, named after the current time, and returns the
+/// directory.
+fn write_crash_report(dir: &Path, report: &CrashReport) -> io::Result<PathBuf> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let dir = dir.join(format!("{}-{:03}", now.as_secs(), now.subsec_millis()));
+    fs::create_dir_all(&dir)?;
+    for (name, contents) in report.files() {
+        fs::write(dir.join(name), contents)?;
+    }
+    Ok(dir)
+}
+
+/// Runs `--compare-golden` on `rom`.
+fn compare_golden(args: &Args, name: &str, rom: &RomDriver) -> Result<(), String> {
+    let captures = golden::capture(rom.data(), quirks(args), args.freq_cpu.into(), &args.frames)
+        .map_err(|e| messages::text("test-stopped-early", &[("rom", &name), ("error", &e)]))?;
+    let outcomes = golden::check(&args.golden, &captures, args.update_golden)
+        .map_err(|e| format!("{}: {e}", args.golden.display()))?;
+
+    for (frame, outcome) in &outcomes {
+        let file = golden::golden_path(&args.golden, *frame);
+        let values: [(&str, &dyn fmt::Display); 2] = [("frame", frame), ("file", &file.display())];
+        match outcome {
+            golden::GoldenOutcome::Matched => println!("{}", messages::text("golden-matched", &values)),
+            golden::GoldenOutcome::Mismatched(diff) => {
+                println!("{}", messages::text("golden-mismatched", &values));
+                print!("{diff}");
+            }
+            golden::GoldenOutcome::Missing => println!("{}", messages::text("golden-missing", &values)),
+            golden::GoldenOutcome::Updated => println!("{}", messages::text("golden-updated", &values)),
+        }
+    }
+
+    let failed = outcomes.iter().filter(|(_, outcome)| !outcome.passed()).count();
+    if failed > 0 {
+        let values: [(&str, &dyn fmt::Display); 3] =
+            [("rom", &name), ("failed", &failed), ("total", &outcomes.len())];
+        return Err(messages::text("golden-failed", &values));
+    }
+    Ok(())
+}
+
+/// Runs `--soak` over the files in `dir`, in name order.
+fn soak(args: &Args, dir: &Path) -> Result<(), String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("{}: {e}", dir.display()))?;
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .collect();
+    paths.sort();
+
+    let mut results = Vec::new();
+    for path in &paths {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        match fs::read(path) {
+            Ok(rom) => results.push(soak::run(&name, &rom, quirks(args), args.cycles_per_rom)),
+            Err(e) => eprintln!(
+                "{}",
+                messages::text("skipping-rom", &[("rom", &name), ("error", &e)])
+            ),
+        }
+    }
+
+    println!("{}", soak::Summary(&results));
+    fs::write(&args.soak_report, soak::json_report(&results))
+        .map_err(|e| format!("{}: {e}", args.soak_report.display()))?;
+
+    let failed = results.iter().filter(|result| result.outcome.is_error()).count();
+    if failed > 0 {
+        let values: [(&str, &dyn fmt::Display); 2] = [("failed", &failed), ("total", &results.len())];
+        return Err(messages::text("soak-failed", &values));
+    }
+    Ok(())
+}
+
+/// Reads the `--lang` file and makes it the language of [`messages::text`].
+fn install_language(path: &Path) -> Result<(), String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let (catalog, warnings) = Catalog::parse(&text).map_err(|e| format!("{}: {e}", path.display()))?;
+    for warning in warnings {
+        eprintln!("{warning}");
+    }
+    messages::install(catalog);
+    Ok(())
+}
+
+/// Returns the built-in palettes followed by the ones in `files`.
+fn load_palettes(files: &[PathBuf]) -> Result<Vec<Palette>, String> {
+    let mut palettes = Palette::builtins();
+    for path in files {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        palettes.push(Palette::parse(&text).map_err(|e| format!("{}: {e}", path.display()))?);
+    }
+
+    Ok(palettes)
+}
+
+fn main() -> Result<(), String> {
+    logger::init();
+    let args = Args::parse();
+    if let Some(path) = &args.lang {
+        install_language(path)?;
+    }
+
+    let palettes = load_palettes(&args.palette_file)?;
+    if args.list_palettes {
+        for palette in &palettes {
+            println!("{palette}");
+        }
+        return Ok(());
+    }
+
+    if let Some(dir) = &args.soak {
+        return soak(&args, dir);
+    }
+
+    let mut names = args.roms.clone();
+    if args.demo {
+        names.insert(0, DEMO_NAME.to_string());
+    }
+    let mut playlist = Playlist::load(&names, |name| match name {
+        DEMO_NAME if args.demo => Ok(RomDriver { rom: demo::rom() }),
+        _ => RomDriver::new(name),
+    })
+    .ok_or_else(|| messages::text("no-rom-loaded", &[]))?;
+
+    let (input_tx, input_rx) = mpsc::channel();
+
+    if let Some(expected) = &args.test_rom {
+        let (name, rom) = playlist.current();
+        let result = Chip8::run_test_rom(rom.data(), expected, args.test_rom_cycles);
+        if let Some(e) = &result.error {
+            eprintln!(
+                "{}",
+                messages::text("test-stopped-early", &[("rom", &name), ("error", e)])
+            );
+        }
+        for (reg, expected, actual) in &result.failed_registers {
+            let (register, actual, expected) = (
+                format!("V{reg:X}"),
+                format!("{actual:#04x}"),
+                format!("{expected:#04x}"),
+            );
+            let values: [(&str, &dyn fmt::Display); 4] = [
+                ("rom", &name),
+                ("register", &register),
+                ("actual", &actual),
+                ("expected", &expected),
+            ];
+            eprintln!("{}", messages::text("test-register-mismatch", &values));
+        }
+        if !result.passed {
+            return Err(messages::text("test-failed", &[("rom", &name)]));
+        }
+        println!("{}", messages::text("test-passed", &[("rom", &name)]));
+        return Ok(());
+    }
+
+    if args.profile_run {
+        let (name, rom) = playlist.current();
+        let result = Chip8::profile_run(rom.data(), quirks(&args), args.profile_cycles);
+        if let Some(e) = &result.error {
+            eprintln!(
+                "{}",
+                messages::text("test-stopped-early", &[("rom", &name), ("error", e)])
+            );
+        }
+        let (seconds, mips) = (
+            format!("{:.3}", result.elapsed.as_secs_f64()),
+            format!("{:.2}", result.mips()),
+        );
+        println!(
+            "{}",
+            messages::text("profile-instructions", &[("count", &result.instructions)])
+        );
+        println!(
+            "{}",
+            messages::text("profile-wall-time", &[("seconds", &seconds)])
+        );
+        println!("{}", messages::text("profile-mips", &[("mips", &mips)]));
+        return Ok(());
+    }
+
+    if let Some(cycles) = args.benchmark_cycles {
+        let (name, rom) = playlist.current();
+        let mut chip8 = start_rom(&args, rom)?;
+        let result = chip8
+            .benchmark_cycles(&NoInput, cycles)
+            .map_err(|e| messages::text("test-stopped-early", &[("rom", &name), ("error", &e)]))?;
+        let mhz = format!("{:.2}", result.mhz);
+        println!("{}", messages::text("benchmark-result", &[("mhz", &mhz)]));
+        return Ok(());
+    }
+
+    if args.compare_golden {
+        let (name, rom) = playlist.current();
+        return compare_golden(&args, name, rom);
+    }
+
+    if args.disassemble {
+        let (_, rom) = playlist.current();
+        let listing = disasm::listing_for(rom.data(), args.load_addr, args.disassemble_platform);
+        return match &args.output {
+            Some(path) => fs::write(path, listing).map_err(|e| format!("{}: {e}", path.display())),
+            None => {
+                print!("{listing}");
+                Ok(())
+            }
+        };
+    }
+
+    let palette = match &args.palette_name {
+        // Later files shadow earlier palettes with the same name
+        Some(name) => palettes
+            .iter()
+            .rev()
+            .find(|palette| &palette.name == name)
+            .ok_or_else(|| messages::text("unknown-palette", &[("name", &name)]))?,
+        None if args.palette_file.is_empty() => &palettes[0],
+        None => palettes.last().unwrap(),
+    };
+
+    let sdl_context = sdl2::init().unwrap_or_else(|e| sdl_error::exit(&e));
+    let mut display = SdlDisplayDriver::new(&sdl_context)
+        .unwrap_or_else(|e| sdl_error::exit(&e))
+        .with_pixel_gap(args.pixel_gap)
+        .with_rounded_pixels(args.rounded_pixels)
+        .with_palette(palette);
+    if let Some(path) = &args.palette {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        let colors = palette::parse(&text).map_err(|e| format!("{}: {e}", path.display()))?;
+        display.set_palette(&colors);
+    }
+    let mut audio = SdlAudioDriver::new(&sdl_context, args.freq_cpu.into(), args.freq_timer.into())
+        .unwrap_or_else(|e| sdl_error::exit(&e));
+    let mut input = SdlInput::new(&sdl_context, input_rx, args.input_mode);
+
+    let mut high_scores = match args.high_score {
+        Some(location) => {
+            let scores = HighScores::load(args.high_score_file.clone()).map_err(|e| e.to_string())?;
+            Some((location, scores))
+        }
+        None => None,
+    };
+
+    let (name, rom) = playlist.current();
+    let (cycles_per_batch, batch_slot) = pacing::batch_for(args.freq_cpu.into());
+    let mut throttle = Throttle::new(cycles_per_batch, batch_slot);
+    let mut play_time = PlayTime::new(args.freq_cpu.into());
+    display.set_title(&window_title(name, &high_scores, None, &throttle, &play_time));
+    let mut chip8 = start_rom(&args, rom)?;
+    let mut showing_key_hint = false;
+    let mut rewind = RewindBuffer::new(
+        (args.freq_cpu / REWIND_STEPS_PER_SECOND).into(),
+        (args.rewind_seconds * REWIND_STEPS_PER_SECOND) as usize,
+    );
+
+    // Setup a separate thread for managing input updates
+    let input_sleep = freq_to_time(args.freq_input.into());
 
     thread::spawn(move || loop {
         thread::sleep(input_sleep);
         input_tx.send(()).unwrap();
     });
 
-    while let InputUpdate::Continue = input.update() {
-        let output = chip8.emulate_cycle(input.input()).map_err(|e| e.to_string())?;
+    // Number of frames drawn by the current ROM
+    let mut frame: u64 = 0;
+    let mut last_present = Instant::now();
+    let mut last_batch = Instant::now();
 
-        display.draw(output.graphics.buffer());
+    loop {
+        let switch_rom = match input.update() {
+            InputUpdate::Continue => false,
+            InputUpdate::Screenshot => {
+                let (name, _) = playlist.current();
+                let buffer = chip8.graphics().buffer();
+                let screenshot = graphics::text_screenshot(name, frame, &quirks(&args), buffer);
 
-        if output.sound_on {
-            audio.start_buzzer();
-        } else {
+                print!("{screenshot}");
+                if let Some((pos, dims)) = args.export_sprites {
+                    print!("{}", graphics::sprite_snippet("sprite", buffer, pos, dims));
+                }
+                if let Err(e) = display.copy_to_clipboard(&screenshot) {
+                    eprintln!("{}", messages::text("clipboard-failed", &[("error", &e)]));
+                }
+                false
+            }
+            InputUpdate::ToggleDiagnostics => {
+                if let Some(times) = display.toggle_diagnostics() {
+                    if let Some(summary) = times.summary() {
+                        let (p50, p95, max) = (
+                            format!("{:?}", summary.p50),
+                            format!("{:?}", summary.p95),
+                            format!("{:?}", summary.max),
+                        );
+                        let values: [(&str, &dyn fmt::Display); 4] = [
+                            ("p50", &p50),
+                            ("p95", &p95),
+                            ("max", &max),
+                            ("missed", &times.missed()),
+                        ];
+                        println!("{}", messages::text("frame-times", &values));
+                    }
+                }
+                false
+            }
+            InputUpdate::Reset => {
+                let (name, _) = playlist.current();
+                track_high_score(name, &chip8, &mut high_scores);
+                play_time = PlayTime::new(args.freq_cpu.into());
+                display.set_title(&window_title(name, &high_scores, None, &throttle, &play_time));
+                audio.stop_buzzer();
+                chip8.reset();
+                rewind.clear();
+                showing_key_hint = false;
+                frame = 0;
+                false
+            }
+            InputUpdate::InputModeChanged(mode) => {
+                println!("{}", messages::text("input-mode", &[("mode", &mode)]));
+                false
+            }
+            InputUpdate::Quit => {
+                track_high_score(playlist.current().0, &chip8, &mut high_scores);
+                break;
+            }
+            InputUpdate::NextRom => {
+                playlist.next();
+                true
+            }
+            InputUpdate::PrevRom => {
+                playlist.prev();
+                true
+            }
+        };
+
+        if switch_rom {
+            save_high_scores(&high_scores);
+            let (name, rom) = playlist.current();
+            play_time = PlayTime::new(args.freq_cpu.into());
+            display.set_title(&window_title(name, &high_scores, None, &throttle, &play_time));
+            audio.stop_buzzer();
+            chip8 = start_rom(&args, rom)?;
+            rewind.clear();
+            showing_key_hint = false;
+            frame = 0;
+            continue;
+        }
+
+        // Step back one snapshot at a time while Backspace is held
+        if input.is_rewinding() && !rewind.is_empty() {
+            if let Some(state) = rewind.rewind(1) {
+                chip8.load_state(state).map_err(|e| e.to_string())?;
+                display.draw(chip8.graphics().buffer());
+            }
             audio.stop_buzzer();
+            thread::sleep(REWIND_STEP);
+            last_batch = Instant::now();
+            continue;
         }
 
-        thread::sleep(emulation_sleep_time);
+        let batch_start = Instant::now();
+        let batch = chip8.run_cycles(throttle.cycles(), input.input());
+        let batch_time = batch_start.elapsed();
+        let shown_seconds = play_time.real().as_secs();
+        let output = match batch {
+            Ok(output) => output,
+            Err(e) if args.crash_reports => {
+                let report = CrashReport::new(playlist.current().0, frame, &chip8, &e);
+                return Err(match write_crash_report(&args.crash_report_dir, &report) {
+                    Ok(dir) => {
+                        messages::text("crash-report-saved", &[("error", &e), ("dir", &dir.display())])
+                    }
+                    Err(write_error) => {
+                        messages::text("crash-report-failed", &[("error", &e), ("reason", &write_error)])
+                    }
+                });
+            }
+            Err(e) => {
+                let stack = chip8.format_call_stack();
+                let error = match stack.as_str() {
+                    "" => e.to_string(),
+                    _ => messages::text("error-call-stack", &[("error", &e), ("stack", &stack)]),
+                };
+                // Without a crash report to put it in, the trace goes with the error
+                let trace: Vec<_> = chip8.trace().map(ToString::to_string).collect();
+                if trace.is_empty() {
+                    return Err(error);
+                }
+                return Err(messages::text(
+                    "error-trace",
+                    &[("error", &error), ("trace", &trace.join("\n"))],
+                ));
+            }
+        };
+        // The batch stops early while the program waits on a key
+        play_time.record(output.instructions_executed, last_batch.elapsed());
+        last_batch = Instant::now();
+        let drew = output.draw_on_screen;
+
+        audio.schedule(&output.events);
+
+        // Only redraw when the screen changed, or every frame while the diagnostics are shown
+        let diagnostics_due = display.diagnostics_shown() && last_present.elapsed() >= FRAME_BUDGET;
+        if drew || diagnostics_due {
+            chip8::wait_for_vsync(&mut display, VSYNC_TIMEOUT);
+            if drew {
+                frame += 1;
+                output.present(&mut display);
+            } else {
+                display.draw(chip8.graphics().buffer());
+            }
+            display.record_frame(last_present.elapsed(), chip8.get_owed_timer_ticks());
+            last_present = Instant::now();
+        }
+
+        rewind.record(&chip8);
+        let throttle_changed = throttle.record(batch_time);
+        if throttle_changed {
+            match throttle.throttled_hz() {
+                Some(hertz) => {
+                    let hertz = format!("{hertz:.0}");
+                    let values: [(&str, &dyn fmt::Display); 2] =
+                        [("hertz", &hertz), ("requested", &args.freq_cpu)];
+                    println!("{}", messages::text("throttling-on", &values));
+                }
+                None => println!(
+                    "{}",
+                    messages::text("throttling-off", &[("requested", &args.freq_cpu)])
+                ),
+            }
+        }
+
+        // Scores only change on screen once the ROM redraws them
+        let (name, _) = playlist.current();
+        let new_best = drew && track_high_score(name, &chip8, &mut high_scores);
+        let waiting_for_key = args.key_hints && chip8.is_waiting_for_key();
+        let clock_ticked = play_time.real().as_secs() != shown_seconds;
+        if new_best || waiting_for_key != showing_key_hint || throttle_changed || clock_ticked {
+            showing_key_hint = waiting_for_key;
+            let hint = waiting_for_key.then(|| key_hint(&chip8));
+            display.set_title(&window_title(name, &high_scores, hint, &throttle, &play_time));
+        }
+
+        thread::sleep(throttle.slot().saturating_sub(batch_time));
     }
 
+    save_high_scores(&high_scores);
+    if args.stats {
+        println!("{}", chip8.statistics());
+    }
+    if let Some(path) = &args.dump_call_graph {
+        if let Err(e) = fs::write(path, chip8.export_call_graph_dot()) {
+            eprintln!("{}", messages::text("call-graph-failed", &[("error", &e)]));
+        }
+    }
+    if args.profile_calls {
+        print!("{}", chip8.call_profile());
+    }
+    if args.profile_opcodes {
+        print!("{}", chip8.profile_report(PROFILE_TOP_ADDRESSES));
+    }
     process::exit(0);
 }
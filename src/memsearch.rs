@@ -0,0 +1,106 @@
+//! Narrows down which memory address holds a value, e.g. a score or lives counter, by comparing
+//! snapshots of memory taken between frames.
+
+/// A set of candidate addresses, narrowed down by comparing memory against the last snapshot.
+#[derive(Debug, Default)]
+pub struct MemorySearch {
+    snapshot: Vec<u8>,
+    candidates: Vec<u16>,
+}
+
+impl MemorySearch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts a new search with every address as a candidate.
+    pub fn start(&mut self, memory: &[u8]) {
+        self.snapshot = memory.to_vec();
+        self.candidates = (0..memory.len() as u16).collect();
+    }
+
+    /// Keeps the candidates whose byte changed since the last snapshot.
+    pub fn changed(&mut self, memory: &[u8]) {
+        self.narrow(memory, |old, new| old != new);
+    }
+
+    /// Keeps the candidates whose byte stayed the same since the last snapshot.
+    pub fn unchanged(&mut self, memory: &[u8]) {
+        self.narrow(memory, |old, new| old == new);
+    }
+
+    /// Keeps the candidates whose byte is currently `value`.
+    pub fn value(&mut self, memory: &[u8], value: u8) {
+        self.narrow(memory, |_, new| new == value);
+    }
+
+    /// The addresses that matched every comparison so far.
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+
+    fn narrow(&mut self, memory: &[u8], keep: impl Fn(u8, u8) -> bool) {
+        let snapshot = &self.snapshot;
+        self.candidates.retain(|&addr| {
+            let addr = addr as usize;
+            match (snapshot.get(addr), memory.get(addr)) {
+                (Some(&old), Some(&new)) => keep(old, new),
+                _ => false,
+            }
+        });
+        self.snapshot = memory.to_vec();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemorySearch;
+
+    #[test]
+    fn test_search_before_start_has_no_candidates() {
+        let mut search = MemorySearch::new();
+        search.changed(&[1, 2, 3]);
+        assert!(search.candidates().is_empty());
+    }
+
+    #[test]
+    fn test_find_score_in_three_iterations() {
+        let mut memory = vec![0u8; 16];
+        let mut search = MemorySearch::new();
+        search.start(&memory);
+        assert_eq!(search.candidates().len(), 16);
+
+        // Score goes up, and an unrelated timer ticks
+        memory[5] = 1;
+        memory[9] = 10;
+        search.changed(&memory);
+        assert_eq!(search.candidates(), [5, 9]);
+
+        // Nothing scores, the timer keeps ticking
+        memory[9] = 11;
+        search.unchanged(&memory);
+        assert_eq!(search.candidates(), [5]);
+
+        search.value(&memory, 1);
+        assert_eq!(search.candidates(), [5]);
+    }
+
+    #[test]
+    fn test_value_filters_by_current_byte() {
+        let memory = [3, 7, 3, 0];
+        let mut search = MemorySearch::new();
+        search.start(&memory);
+
+        search.value(&memory, 3);
+        assert_eq!(search.candidates(), [0, 2]);
+    }
+
+    #[test]
+    fn test_shorter_memory_drops_missing_addresses() {
+        let mut search = MemorySearch::new();
+        search.start(&[1, 1, 1, 1]);
+
+        search.unchanged(&[1, 1]);
+        assert_eq!(search.candidates(), [0, 1]);
+    }
+}
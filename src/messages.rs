@@ -0,0 +1,352 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::sync::OnceLock;
+
+/// Every message the frontend shows, in English, as `(id, text)`. `{name}` in the text is replaced
+/// by the argument called `name`.
+const ENGLISH: &[(&str, &str)] = &[
+    ("rom-byte-swapped", "The ROM looks byte-swapped, swapping it back"),
+    ("no-rom-loaded", "No ROM could be loaded"),
+    ("skipping-rom", "warning: skipping `{rom}`: {error}"),
+    ("unknown-palette", "unknown palette `{name}`, see --list-palettes"),
+    ("title-best", "BEST: {score}"),
+    ("title-press", "PRESS: {keys}"),
+    ("title-press-any", "PRESS ANY KEY"),
+    ("title-throttled", "THROTTLED TO {hertz} HZ"),
+    ("title-play-time", "{real} real / {emulated} emu"),
+    ("title-play-time-drift", "{real} real / {emulated} emu ({drift}%)"),
+    ("test-stopped-early", "{rom}: stopped early: {error}"),
+    (
+        "test-register-mismatch",
+        "{rom}: {register} is {actual}, expected {expected}",
+    ),
+    ("test-failed", "{rom}: test ROM failed"),
+    ("test-passed", "{rom}: passed"),
+    ("profile-instructions", "instructions: {count}"),
+    ("profile-wall-time", "wall time:    {seconds}s"),
+    ("profile-mips", "MIPS:         {mips}"),
+    ("benchmark-result", "{mhz} MHz"),
+    ("golden-matched", "frame {frame}: matches {file}"),
+    ("golden-mismatched", "frame {frame}: differs from {file}:"),
+    (
+        "golden-missing",
+        "frame {frame}: {file} is missing, see --update-golden",
+    ),
+    ("golden-updated", "frame {frame}: wrote {file}"),
+    (
+        "golden-failed",
+        "{rom}: {failed} of {total} frames don't match the goldens",
+    ),
+    ("soak-failed", "{failed} of {total} ROMs stopped with an error"),
+    (
+        "frame-times",
+        "frame times: p50 {p50}, p95 {p95}, max {max}, {missed} missed",
+    ),
+    ("input-mode", "input mode: {mode}"),
+    (
+        "throttling-on",
+        "throttling: running at {hertz} Hz instead of the requested {requested} Hz",
+    ),
+    ("throttling-off", "throttling: back to {requested} Hz"),
+    ("error-call-stack", "{error} (call stack: {stack})"),
+    ("error-trace", "{error}\nlast instructions:\n{trace}"),
+    ("crash-report-saved", "{error} (crash report saved to {dir})"),
+    (
+        "crash-report-failed",
+        "{error} (could not save a crash report: {reason})",
+    ),
+    (
+        "save-high-scores-failed",
+        "warning: could not save high scores: {error}",
+    ),
+    (
+        "clipboard-failed",
+        "warning: could not copy screenshot to clipboard: {error}",
+    ),
+    (
+        "call-graph-failed",
+        "warning: could not write the call graph: {error}",
+    ),
+    (
+        "missing-message",
+        "warning: the language file has no `{id}` message, using the English one",
+    ),
+    (
+        "unknown-message",
+        "warning: the language file has an unknown `{id}` message, ignoring it",
+    ),
+];
+
+static CATALOG: OnceLock<Catalog> = OnceLock::new();
+
+/// Why a language file was rejected.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CatalogError {
+    /// A line isn't a comment, blank, or `id = text`.
+    Syntax { line: usize },
+    /// The translation of `id` has a `{` without a `}`, or the other way around.
+    Unbalanced { id: String },
+    /// The translation of `id` uses other placeholders than the English text.
+    Placeholders {
+        id: String,
+        expected: Vec<String>,
+        found: Vec<String>,
+    },
+}
+
+impl fmt::Display for CatalogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CatalogError::Syntax { line } => write!(f, "line {line}: expected `id = text`"),
+            CatalogError::Unbalanced { id } => write!(f, "`{id}` has a `{{` or `}}` without its pair"),
+            CatalogError::Placeholders { id, expected, found } => write!(
+                f,
+                "`{id}` has the placeholders [{}], expected [{}]",
+                found.join(", "),
+                expected.join(", ")
+            ),
+        }
+    }
+}
+
+/// The texts to show for every message ID, English unless a language file replaced them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Catalog {
+    messages: HashMap<&'static str, String>,
+}
+
+impl Catalog {
+    pub fn english() -> Self {
+        Self {
+            messages: ENGLISH.iter().map(|&(id, text)| (id, text.to_string())).collect(),
+        }
+    }
+
+    /// Parses a language file with one `id = text` line per message, in the style of Fluent's
+    /// `.ftl` files. `#` starts a comment line, and the text may be quoted like a TOML string.
+    /// Messages missing from the file stay in English. Returns the catalog and the warnings
+    /// about missing and unknown messages.
+    pub fn parse(text: &str) -> Result<(Self, Vec<String>), CatalogError> {
+        let english = Self::english();
+        let mut catalog = Self::english();
+        let mut translated = BTreeSet::new();
+        let mut warnings = Vec::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (id, translation) = line.split_once('=').ok_or(CatalogError::Syntax { line: i + 1 })?;
+            let (id, translation) = (id.trim(), translation.trim());
+            let translation = translation
+                .strip_prefix('"')
+                .and_then(|quoted| quoted.strip_suffix('"'))
+                .unwrap_or(translation);
+
+            let Some((&id, original)) = english.messages.get_key_value(id) else {
+                warnings.push(english.text("unknown-message", &[("id", &id)]));
+                continue;
+            };
+            let (Some(expected), Some(found)) = (placeholders(original), placeholders(translation)) else {
+                return Err(CatalogError::Unbalanced { id: id.to_string() });
+            };
+            if expected != found {
+                return Err(CatalogError::Placeholders {
+                    id: id.to_string(),
+                    expected,
+                    found,
+                });
+            }
+
+            catalog.messages.insert(id, translation.to_string());
+            translated.insert(id);
+        }
+
+        for &(id, _) in ENGLISH {
+            if !translated.contains(id) {
+                warnings.push(english.text("missing-message", &[("id", &id)]));
+            }
+        }
+
+        Ok((catalog, warnings))
+    }
+
+    /// Returns message `id` with its placeholders replaced by `args`. Placeholders without an
+    /// argument are left as they are.
+    pub fn text(&self, id: &str, args: &[(&str, &dyn fmt::Display)]) -> String {
+        let Some(template) = self.messages.get(id) else {
+            debug_assert!(false, "no message `{id}`");
+            return id.to_string();
+        };
+
+        let mut out = String::new();
+        let mut rest = template.as_str();
+        while let Some(start) = rest.find('{') {
+            out += &rest[..start];
+            let Some(len) = rest[start..].find('}') else {
+                rest = &rest[start..];
+                break;
+            };
+            let name = &rest[start + 1..start + len];
+            match args.iter().find(|(arg, _)| *arg == name) {
+                Some((_, value)) => out += &value.to_string(),
+                None => out += &rest[start..=start + len],
+            }
+            rest = &rest[start + len + 1..];
+        }
+        out + rest
+    }
+}
+
+/// Returns the names of the `{name}` placeholders in `text`, sorted, or `None` if a `{` isn't
+/// closed before the next one, or a `}` wasn't opened.
+fn placeholders(text: &str) -> Option<Vec<String>> {
+    let mut parts = text.split('{');
+    if parts.next()?.contains('}') {
+        return None;
+    }
+    let mut names = parts
+        .map(|part| match part.split_once('}') {
+            Some((name, rest)) if !rest.contains('}') => Some(name.to_string()),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    names.sort();
+    Some(names)
+}
+
+/// Makes [`text`] use `catalog` from now on. Only the first call has an effect.
+pub fn install(catalog: Catalog) {
+    let _ = CATALOG.set(catalog);
+}
+
+/// Returns message `id` from the installed catalog, or in English if none was installed. See
+/// [`Catalog::text`].
+pub fn text(id: &str, args: &[(&str, &dyn fmt::Display)]) -> String {
+    CATALOG.get_or_init(Catalog::english).text(id, args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{placeholders, Catalog, CatalogError, ENGLISH};
+
+    #[test]
+    fn test_english() {
+        let catalog = Catalog::english();
+
+        assert_eq!(
+            catalog.text("test-passed", &[("rom", &"pong.ch8")]),
+            "pong.ch8: passed"
+        );
+        assert_eq!(
+            catalog.text("throttling-on", &[("hertz", &412), ("requested", &1_000_000)]),
+            "throttling: running at 412 Hz instead of the requested 1000000 Hz"
+        );
+        assert_eq!(catalog.text("title-press-any", &[]), "PRESS ANY KEY");
+    }
+
+    #[test]
+    fn test_every_english_message_is_balanced() {
+        for &(id, text) in ENGLISH {
+            assert!(placeholders(text).is_some(), "{id}");
+        }
+    }
+
+    #[test]
+    fn test_unbalanced_template() {
+        let catalog = Catalog {
+            messages: [("input-mode", "x {y".to_string())].into_iter().collect(),
+        };
+
+        assert_eq!(catalog.text("input-mode", &[("y", &1)]), "x {y");
+    }
+
+    #[test]
+    fn test_missing_argument_is_kept() {
+        let catalog = Catalog::english();
+
+        assert_eq!(catalog.text("input-mode", &[]), "input mode: {mode}");
+    }
+
+    #[test]
+    fn test_parse() {
+        let file = "# Deutsch\n\
+                    \n\
+                    test-passed = {rom}: bestanden\n\
+                    title-press-any = \"BELIEBIGE TASTE DRÜCKEN\"\n";
+
+        let (catalog, warnings) = Catalog::parse(file).unwrap();
+
+        assert_eq!(
+            catalog.text("test-passed", &[("rom", &"a.ch8")]),
+            "a.ch8: bestanden"
+        );
+        assert_eq!(catalog.text("title-press-any", &[]), "BELIEBIGE TASTE DRÜCKEN");
+        // Everything else falls back to English, with a warning each
+        assert_eq!(
+            catalog.text("test-failed", &[("rom", &"a.ch8")]),
+            "a.ch8: test ROM failed"
+        );
+        assert_eq!(warnings.len(), ENGLISH.len() - 2);
+        assert!(warnings.contains(
+            &"warning: the language file has no `test-failed` message, using the English one".to_string()
+        ));
+    }
+
+    #[test]
+    fn test_parse_unknown_message() {
+        let (catalog, warnings) = Catalog::parse("no-such-message = hallo").unwrap();
+
+        assert_eq!(catalog, Catalog::english());
+        assert_eq!(
+            warnings[0],
+            "warning: the language file has an unknown `no-such-message` message, ignoring it"
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_mismatched_placeholders() {
+        let result = Catalog::parse("throttling-on = gedrosselt auf {hertz} Hz");
+
+        assert_eq!(
+            result,
+            Err(CatalogError::Placeholders {
+                id: "throttling-on".to_string(),
+                expected: vec!["hertz".to_string(), "requested".to_string()],
+                found: vec!["hertz".to_string()],
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_syntax_error() {
+        assert_eq!(
+            Catalog::parse("# ok\ntest-passed: {rom}").err(),
+            Some(CatalogError::Syntax { line: 2 })
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_unbalanced_placeholders() {
+        assert_eq!(
+            Catalog::parse("input-mode = Eingabe: {mode").err(),
+            Some(CatalogError::Unbalanced {
+                id: "input-mode".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_placeholders() {
+        assert_eq!(
+            placeholders("{b} and {a}, {b}"),
+            Some(vec!["a".to_string(), "b".to_string(), "b".to_string()])
+        );
+        assert_eq!(placeholders("none"), Some(vec![]));
+        for text in ["x {y", "x {y {z}", "x }", "{y}}"] {
+            assert_eq!(placeholders(text), None, "{text:?}");
+        }
+    }
+}
@@ -0,0 +1,372 @@
+//! A table of opcodes to handle differently from the interpreter, for experimenting with homebrew
+//! extensions: each entry matches opcodes with a pattern and says what to do with them instead.
+//! See [`OpcodeOverrides::parse`] for the file format.
+
+use std::fmt;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::disasm;
+
+/// Opcodes matching an [`OpcodePattern`]: the bits set in `mask` have to be equal to `value`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpcodePattern {
+    pub value: u16,
+    pub mask: u16,
+}
+
+impl OpcodePattern {
+    pub fn matches(&self, opcode: u16) -> bool {
+        opcode & self.mask == self.value
+    }
+
+    /// Returns whether some opcode matches both patterns.
+    pub fn overlaps(&self, other: &OpcodePattern) -> bool {
+        (self.value ^ other.value) & self.mask & other.mask == 0
+    }
+
+    /// Every opcode that matches the pattern.
+    pub fn opcodes(&self) -> impl Iterator<Item = u16> + '_ {
+        (0..=u16::MAX).filter(|&opcode| self.matches(opcode))
+    }
+}
+
+/// Parses 4 digits with an optional `0x`, e.g. `0x8xyF`. Hex digits have to match exactly, and
+/// `x`, `y`, `n`, `k`, or `_` match any digit.
+impl FromStr for OpcodePattern {
+    type Err = OverrideError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || OverrideError::InvalidPattern(s.to_string());
+        let digits = s.strip_prefix("0x").unwrap_or(s);
+        if digits.chars().count() != 4 {
+            return Err(invalid());
+        }
+
+        let (mut value, mut mask) = (0, 0);
+        for c in digits.chars() {
+            value <<= 4;
+            mask <<= 4;
+            match c.to_ascii_lowercase() {
+                'x' | 'y' | 'n' | 'k' | '_' => (),
+                c => {
+                    value |= c.to_digit(16).ok_or_else(invalid)? as u16;
+                    mask |= 0xF;
+                }
+            }
+        }
+        Ok(OpcodePattern { value, mask })
+    }
+}
+
+impl fmt::Display for OpcodePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x")?;
+        for shift in [12, 8, 4, 0] {
+            match (self.mask >> shift) & 0xF {
+                0 => write!(f, "_")?,
+                _ => write!(f, "{:X}", (self.value >> shift) & 0xF)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What to do with an opcode instead of interpreting it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverrideAction {
+    /// Move on to the next instruction.
+    Nop,
+    /// Stop with [`crate::Chip8Error::Halted`], staying on the opcode.
+    Halt,
+    /// Stop with [`crate::Chip8Error::BreakpointHit`], past the opcode so the program can go on.
+    Break,
+    /// Execute another opcode instead. Its wildcard digits are taken from the original opcode, so
+    /// `8xyF = alias=8xy4` runs `8ABF` as `8AB4`.
+    Alias(OpcodePattern),
+}
+
+impl FromStr for OverrideAction {
+    type Err = OverrideError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nop" => Ok(OverrideAction::Nop),
+            "halt" => Ok(OverrideAction::Halt),
+            "break" => Ok(OverrideAction::Break),
+            _ => match s.strip_prefix("alias=") {
+                Some(target) => Ok(OverrideAction::Alias(target.trim().parse()?)),
+                None => Err(OverrideError::InvalidAction(s.to_string())),
+            },
+        }
+    }
+}
+
+/// What an opcode turns into after the overrides are applied. See [`OpcodeOverrides::resolve`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolved {
+    Execute(u16),
+    Nop,
+    Halt,
+    Break,
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum OverrideError {
+    #[error("line {0}: expected `pattern = action`")]
+    Syntax(usize),
+    #[error("`{0}` is not an opcode pattern like `0x8xyF`")]
+    InvalidPattern(String),
+    #[error("`{0}` is not one of `nop`, `halt`, `break`, or `alias=<opcode>`")]
+    InvalidAction(String),
+    #[error("the aliases of `{0}` lead back to it")]
+    AliasCycle(OpcodePattern),
+    #[error("`{pattern}` overrides the opcode `{opcode:#06x}`, pass --allow-core-override to allow it")]
+    ShadowsCoreOpcode { pattern: OpcodePattern, opcode: u16 },
+}
+
+/// The override table, consulted before an opcode is interpreted. The first matching entry wins.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OpcodeOverrides {
+    entries: Vec<(OpcodePattern, OverrideAction)>,
+}
+
+impl OpcodeOverrides {
+    pub fn new(entries: Vec<(OpcodePattern, OverrideAction)>) -> Self {
+        Self { entries }
+    }
+
+    /// Reads the `[opcodes]` section of a config file, with one `pattern = action` line per
+    /// override, e.g. `0x8xyF = alias=0x8xy4` or `0xF0FF = "break"`. `#` starts a comment line,
+    /// and the other sections are left alone.
+    pub fn parse(text: &str) -> Result<Self, OverrideError> {
+        let mut entries = Vec::new();
+        let mut in_section = false;
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                in_section = section.trim() == "opcodes";
+                continue;
+            }
+            if !in_section {
+                continue;
+            }
+
+            let (pattern, action) = line.split_once('=').ok_or(OverrideError::Syntax(i + 1))?;
+            let action = action.trim();
+            let action = action
+                .strip_prefix('"')
+                .and_then(|quoted| quoted.strip_suffix('"'))
+                .unwrap_or(action);
+            entries.push((pattern.trim().parse()?, action.parse()?));
+        }
+
+        Ok(Self { entries })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Fails if following the aliases can go around in a circle, or, unless `allow_core_override`
+    /// is set, if a pattern matches an opcode the interpreter already has a meaning for.
+    pub fn validate(&self, allow_core_override: bool) -> Result<(), OverrideError> {
+        if !allow_core_override {
+            for (pattern, _) in &self.entries {
+                if let Some(opcode) = pattern
+                    .opcodes()
+                    .find(|&opcode| disasm::disassemble(opcode).is_ok())
+                {
+                    return Err(OverrideError::ShadowsCoreOpcode {
+                        pattern: *pattern,
+                        opcode,
+                    });
+                }
+            }
+        }
+
+        for (start, _) in self.entries.iter().enumerate() {
+            if self.alias_reaches(start, start, &mut vec![false; self.entries.len()]) {
+                return Err(OverrideError::AliasCycle(self.entries[start].0));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns whether the alias of entry `from` can lead to an opcode `target` matches.
+    fn alias_reaches(&self, from: usize, target: usize, visited: &mut [bool]) -> bool {
+        let OverrideAction::Alias(alias) = self.entries[from].1 else {
+            return false;
+        };
+
+        for (next, (pattern, _)) in self.entries.iter().enumerate() {
+            if !alias.overlaps(pattern) {
+                continue;
+            }
+            if next == target {
+                return true;
+            }
+            if !visited[next] {
+                visited[next] = true;
+                if self.alias_reaches(next, target, visited) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// Applies the overrides to `opcode`, following aliases until an opcode has no override.
+    pub fn resolve(&self, mut opcode: u16) -> Resolved {
+        // Validated tables have no cycles, so this only gives up on unvalidated ones
+        for _ in 0..=self.entries.len() {
+            let Some((_, action)) = self.entries.iter().find(|(pattern, _)| pattern.matches(opcode)) else {
+                return Resolved::Execute(opcode);
+            };
+            match action {
+                OverrideAction::Nop => return Resolved::Nop,
+                OverrideAction::Halt => return Resolved::Halt,
+                OverrideAction::Break => return Resolved::Break,
+                OverrideAction::Alias(alias) => opcode = alias.value | (opcode & !alias.mask),
+            }
+        }
+        Resolved::Halt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OpcodeOverrides, OpcodePattern, OverrideAction, OverrideError, Resolved};
+
+    fn pattern(s: &str) -> OpcodePattern {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_pattern() {
+        let exact = pattern("0x00FF");
+        assert_eq!(
+            exact,
+            OpcodePattern {
+                value: 0x00FF,
+                mask: 0xFFFF
+            }
+        );
+        assert!(exact.matches(0x00FF));
+        assert!(!exact.matches(0x01FF));
+
+        let masked = pattern("5xY1");
+        assert_eq!(
+            masked,
+            OpcodePattern {
+                value: 0x5001,
+                mask: 0xF00F
+            }
+        );
+        assert!(masked.matches(0x5AB1));
+        assert!(!masked.matches(0x5AB0));
+        assert_eq!(masked.to_string(), "0x5__1");
+        assert_eq!(masked.opcodes().count(), 256);
+
+        assert!("0x5xy".parse::<OpcodePattern>().is_err());
+        assert!("0x5xyz".parse::<OpcodePattern>().is_err());
+    }
+
+    #[test]
+    fn test_parse() {
+        let file = "[palette]\n\
+                    name = \"paper\"\n\
+                    \n\
+                    [opcodes]\n\
+                    # Homebrew extensions\n\
+                    0x0100 = nop\n\
+                    0x0200 = \"halt\"\n\
+                    0xF0FF = break\n\
+                    0x5xy1 = alias=0x8xy1\n";
+
+        let overrides = OpcodeOverrides::parse(file).unwrap();
+
+        assert_eq!(
+            overrides,
+            OpcodeOverrides::new(vec![
+                (pattern("0100"), OverrideAction::Nop),
+                (pattern("0200"), OverrideAction::Halt),
+                (pattern("F0FF"), OverrideAction::Break),
+                (pattern("5xy1"), OverrideAction::Alias(pattern("8xy1"))),
+            ])
+        );
+        assert_eq!(
+            OpcodeOverrides::parse("[opcodes]\n0x0100 = skip"),
+            Err(OverrideError::InvalidAction("skip".to_string()))
+        );
+        assert_eq!(
+            OpcodeOverrides::parse("[opcodes]\n0x0100"),
+            Err(OverrideError::Syntax(2))
+        );
+    }
+
+    #[test]
+    fn test_resolve() {
+        let overrides = OpcodeOverrides::new(vec![
+            (pattern("0100"), OverrideAction::Nop),
+            (pattern("0200"), OverrideAction::Halt),
+            (pattern("F0FF"), OverrideAction::Break),
+            (pattern("5xy1"), OverrideAction::Alias(pattern("8xy1"))),
+            // Chains through the one above
+            (pattern("5xy2"), OverrideAction::Alias(pattern("5xy1"))),
+        ]);
+
+        assert_eq!(overrides.resolve(0x0100), Resolved::Nop);
+        assert_eq!(overrides.resolve(0x0200), Resolved::Halt);
+        assert_eq!(overrides.resolve(0xF0FF), Resolved::Break);
+        assert_eq!(overrides.resolve(0x5AB1), Resolved::Execute(0x8AB1));
+        assert_eq!(overrides.resolve(0x5AB2), Resolved::Execute(0x8AB1));
+        assert_eq!(overrides.resolve(0x6012), Resolved::Execute(0x6012));
+    }
+
+    #[test]
+    fn test_validate_cycles() {
+        let cycle = OpcodeOverrides::new(vec![
+            (pattern("0100"), OverrideAction::Alias(pattern("0200"))),
+            (pattern("0200"), OverrideAction::Alias(pattern("0100"))),
+        ]);
+        assert_eq!(
+            cycle.validate(true),
+            Err(OverrideError::AliasCycle(pattern("0100")))
+        );
+
+        let to_itself = OpcodeOverrides::new(vec![(pattern("01xx"), OverrideAction::Alias(pattern("01x0")))]);
+        assert_eq!(
+            to_itself.validate(true),
+            Err(OverrideError::AliasCycle(pattern("01xx")))
+        );
+
+        let chain = OpcodeOverrides::new(vec![
+            (pattern("0100"), OverrideAction::Alias(pattern("0200"))),
+            (pattern("0200"), OverrideAction::Nop),
+        ]);
+        assert_eq!(chain.validate(true), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_core_shadowing() {
+        let shadowing = OpcodeOverrides::new(vec![(pattern("8xyx"), OverrideAction::Nop)]);
+        assert_eq!(
+            shadowing.validate(false),
+            Err(OverrideError::ShadowsCoreOpcode {
+                pattern: pattern("8xyx"),
+                opcode: 0x8000,
+            })
+        );
+        assert_eq!(shadowing.validate(true), Ok(()));
+
+        // `8xyF` isn't an instruction
+        let extension = OpcodeOverrides::new(vec![(pattern("8xyF"), OverrideAction::Nop)]);
+        assert_eq!(extension.validate(false), Ok(()));
+    }
+}
@@ -0,0 +1,191 @@
+use std::time::Duration;
+
+use wheat::cycles_per_frame;
+
+/// Batches in a row that have to miss their slot before they're made smaller, or that have to
+/// leave half of it unused before they're made bigger again.
+const STREAK: u32 = 8;
+
+/// Part of the slot a resized batch is meant to take up, leaving the rest for input and drawing.
+const TARGET_LOAD: f64 = 0.8;
+
+/// Splits `hertz` into batches of about a frame: how many instructions to run every slot, and how
+/// long a slot is. The slot is stretched to a whole number of instructions, so that the batches
+/// still add up to `hertz`.
+pub fn batch_for(hertz: f64) -> (u32, Duration) {
+    let cycles = cycles_per_frame(hertz);
+    (cycles, Duration::from_secs_f64(f64::from(cycles) / hertz))
+}
+
+/// Returns how many audio samples at `sample_rate` play in the time `cycles` instructions take at
+/// `hertz`, to line sound up with the instruction that made it.
+pub fn cycle_to_sample(cycles: u64, hertz: f64, sample_rate: u32) -> u64 {
+    (cycles as f64 * sample_rate as f64 / hertz).round() as u64
+}
+
+/// Returns the number of instructions per batch that fit in `slot`, given that the last batch of
+/// `current` instructions took `elapsed`. Never more than `requested`, and never less than 1.
+pub fn effective_budget(requested: u32, current: u32, elapsed: Duration, slot: Duration) -> u32 {
+    let per_cycle = elapsed.as_secs_f64().max(f64::EPSILON) / current as f64;
+    let fits = slot.as_secs_f64() * TARGET_LOAD / per_cycle;
+    (fits as u32).clamp(1, requested)
+}
+
+/// Cuts the batches down when the host can't run them in time, e.g. for `--freq-cpu 1000000`, so
+/// that input and drawing still get their turn, and grows them back once there's room again.
+#[derive(Debug)]
+pub struct Throttle {
+    requested: u32,
+    effective: u32,
+    slot: Duration,
+    /// Batches in a row that took longer than the slot
+    missed: u32,
+    /// Batches in a row that took less than half of the slot
+    headroom: u32,
+}
+
+impl Throttle {
+    pub fn new(requested: u32, slot: Duration) -> Self {
+        Self {
+            requested,
+            effective: requested,
+            slot,
+            missed: 0,
+            headroom: 0,
+        }
+    }
+
+    /// Number of instructions to run in the next batch.
+    pub fn cycles(&self) -> u32 {
+        self.effective
+    }
+
+    pub fn slot(&self) -> Duration {
+        self.slot
+    }
+
+    /// Returns the frequency (in Hz) the batches run at while they're cut down.
+    pub fn throttled_hz(&self) -> Option<f64> {
+        (self.effective < self.requested).then(|| self.effective as f64 / self.slot.as_secs_f64())
+    }
+
+    /// Records that the last batch of [`Throttle::cycles`] instructions took `elapsed`. Returns
+    /// `true` if the size of the next batches changed.
+    pub fn record(&mut self, elapsed: Duration) -> bool {
+        if elapsed > self.slot {
+            self.missed += 1;
+            self.headroom = 0;
+        } else if elapsed < self.slot / 2 && self.effective < self.requested {
+            self.headroom += 1;
+            self.missed = 0;
+        } else {
+            self.missed = 0;
+            self.headroom = 0;
+        }
+
+        if self.missed < STREAK && self.headroom < STREAK {
+            return false;
+        }
+        self.missed = 0;
+        self.headroom = 0;
+
+        let effective = effective_budget(self.requested, self.effective, elapsed, self.slot);
+        let changed = effective != self.effective;
+        self.effective = effective;
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{batch_for, cycle_to_sample, effective_budget, Throttle, STREAK};
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_batch_for() {
+        let batch = |hertz| {
+            let (cycles, slot) = batch_for(hertz);
+            (cycles, slot.as_micros())
+        };
+        // 13 instructions every 16.25 ms is exactly 800 Hz
+        assert_eq!(batch(800.0), (13, 16_250));
+        assert_eq!(batch(60.0), (1, 16_666));
+        assert_eq!(batch(20.0), (1, 50_000));
+        assert_eq!(batch(1_000_000.0), (16_667, 16_667));
+    }
+
+    #[test]
+    fn test_cycle_to_sample() {
+        assert_eq!(cycle_to_sample(0, 800.0, 44_100), 0);
+        // One instruction at 800 Hz is 55.125 samples
+        assert_eq!(cycle_to_sample(1, 800.0, 44_100), 55);
+        assert_eq!(cycle_to_sample(8, 800.0, 44_100), 441);
+        // A second of instructions is a second of samples, whatever the frequency
+        assert_eq!(cycle_to_sample(800, 800.0, 44_100), 44_100);
+        assert_eq!(cycle_to_sample(1_000_000, 1_000_000.0, 48_000), 48_000);
+        // Past what a `u32` would hold
+        assert_eq!(cycle_to_sample(800 * 100_000, 800.0, 44_100), 4_410_000_000);
+    }
+
+    #[test]
+    fn test_effective_budget() {
+        // Took twice the slot: 80% of the slot fits 40% of the batch
+        assert_eq!(effective_budget(1_000, 1_000, ms(2), ms(1)), 400);
+        // Plenty of room, but never more than requested
+        assert_eq!(
+            effective_budget(1_000, 400, Duration::from_micros(100), ms(1)),
+            1_000
+        );
+        assert_eq!(
+            effective_budget(1_000, 400, Duration::from_micros(500), ms(1)),
+            640
+        );
+        // Hopelessly slow hosts still make progress
+        assert_eq!(effective_budget(1_000, 1, ms(50), ms(1)), 1);
+    }
+
+    #[test]
+    fn test_throttle_after_missed_streak() {
+        let mut throttle = Throttle::new(1_000, ms(1));
+
+        for _ in 0..STREAK - 1 {
+            assert!(!throttle.record(ms(2)));
+        }
+        assert_eq!(throttle.throttled_hz(), None);
+
+        assert!(throttle.record(ms(2)));
+        assert_eq!(throttle.cycles(), 400);
+        assert_eq!(throttle.throttled_hz(), Some(400_000.0));
+    }
+
+    #[test]
+    fn test_throttle_ignores_single_misses() {
+        let mut throttle = Throttle::new(1_000, ms(1));
+
+        for _ in 0..STREAK * 2 {
+            throttle.record(ms(2));
+            throttle.record(Duration::from_micros(600));
+        }
+        assert_eq!(throttle.cycles(), 1_000);
+    }
+
+    #[test]
+    fn test_throttle_recovers() {
+        let mut throttle = Throttle::new(1_000, ms(1));
+        for _ in 0..STREAK {
+            throttle.record(ms(2));
+        }
+        assert_eq!(throttle.cycles(), 400);
+
+        for _ in 0..STREAK {
+            throttle.record(Duration::from_micros(100));
+        }
+        assert_eq!(throttle.cycles(), 1_000);
+        assert_eq!(throttle.throttled_hz(), None);
+    }
+}
@@ -0,0 +1,79 @@
+/// Parses a palette file: a JSON array of 16 `[r, g, b]` colors, one per pixel value, e.g.
+/// `[[0, 0, 0], [255, 255, 255], ...]`.
+pub fn parse(text: &str) -> Result<[[u8; 3]; 16], String> {
+    let colors = text
+        .trim()
+        .strip_prefix('[')
+        .and_then(|colors| colors.strip_suffix(']'))
+        .ok_or("expected a JSON array of colors")?;
+
+    let colors: Vec<[u8; 3]> = colors
+        .split(']')
+        .map(|color| color.trim().trim_start_matches(',').trim())
+        .filter(|color| !color.is_empty())
+        .map(parse_color)
+        .collect::<Result<_, _>>()?;
+
+    let count = colors.len();
+    colors
+        .try_into()
+        .map_err(|_| format!("expected 16 colors, got {count}"))
+}
+
+/// Parses `[r, g, b` (the closing bracket has already been split off).
+fn parse_color(color: &str) -> Result<[u8; 3], String> {
+    let channels = color
+        .strip_prefix('[')
+        .ok_or_else(|| format!("expected a `[r, g, b]` color, got `{color}`"))?;
+
+    let channels: Vec<u8> = channels
+        .split(',')
+        .map(|channel| {
+            channel
+                .trim()
+                .parse()
+                .map_err(|e| format!("invalid channel `{channel}`: {e}"))
+        })
+        .collect::<Result<_, _>>()?;
+
+    channels
+        .try_into()
+        .map_err(|_| format!("expected 3 channels in `{color}]`"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+
+    fn palette_json(colors: &[[u8; 3]]) -> String {
+        let colors: Vec<_> = colors
+            .iter()
+            .map(|[r, g, b]| format!("[{r}, {g}, {b}]"))
+            .collect();
+        format!("[\n  {}\n]\n", colors.join(",\n  "))
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut colors = [[0; 3]; 16];
+        for (i, color) in colors.iter_mut().enumerate() {
+            *color = [i as u8, 0x80, 255 - i as u8];
+        }
+
+        assert_eq!(parse(&palette_json(&colors)), Ok(colors));
+    }
+
+    #[test]
+    fn test_parse_wrong_color_count() {
+        let result = parse(&palette_json(&[[0; 3]; 15]));
+        assert_eq!(result, Err("expected 16 colors, got 15".to_string()));
+    }
+
+    #[test]
+    fn test_parse_invalid_color() {
+        assert!(parse("[[0, 0]]").is_err());
+        assert!(parse("[[0, 0, 256]]").is_err());
+        assert!(parse("{\"colors\": []}").is_err());
+        assert!(parse("[0, 0, 0]").is_err());
+    }
+}
@@ -0,0 +1,105 @@
+use std::fmt::Display;
+
+/// The list of ROMs passed on the command line. One entry is current at a time, and
+/// [`Playlist::next`]/[`Playlist::prev`] cycle through the list, wrapping around at either end.
+pub struct Playlist<T> {
+    entries: Vec<(String, T)>,
+    current: usize,
+}
+
+impl<T> Playlist<T> {
+    /// Loads every entry in `names` with `load`. Entries that fail to load are skipped with a
+    /// warning instead of aborting the whole session. Returns `None` if no entry could be loaded.
+    pub fn load<E: Display>(names: &[String], load: impl Fn(&str) -> Result<T, E>) -> Option<Self> {
+        let entries: Vec<_> = names
+            .iter()
+            .filter_map(|name| match load(name) {
+                Ok(entry) => Some((name.clone(), entry)),
+                Err(e) => {
+                    eprintln!(
+                        "{}",
+                        crate::messages::text("skipping-rom", &[("rom", &name), ("error", &e)])
+                    );
+                    None
+                }
+            })
+            .collect();
+
+        if entries.is_empty() {
+            return None;
+        }
+
+        Some(Self { entries, current: 0 })
+    }
+
+    /// Returns the name and contents of the current entry.
+    pub fn current(&self) -> (&str, &T) {
+        let (name, entry) = &self.entries[self.current];
+        (name, entry)
+    }
+
+    /// Moves to the next entry, wrapping around to the first one after the last.
+    pub fn next(&mut self) {
+        self.current = (self.current + 1) % self.entries.len();
+    }
+
+    /// Moves to the previous entry, wrapping around to the last one before the first.
+    pub fn prev(&mut self) {
+        self.current = (self.current + self.entries.len() - 1) % self.entries.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Playlist;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    fn load(name: &str) -> Result<usize, String> {
+        match name.strip_prefix("bad") {
+            Some(_) => Err("not found".to_string()),
+            None => Ok(name.len()),
+        }
+    }
+
+    #[test]
+    fn test_cycle_forward() {
+        let mut playlist = Playlist::load(&names(&["a", "bb", "ccc"]), load).unwrap();
+
+        assert_eq!(playlist.current(), ("a", &1));
+        playlist.next();
+        assert_eq!(playlist.current(), ("bb", &2));
+        playlist.next();
+        assert_eq!(playlist.current(), ("ccc", &3));
+        playlist.next();
+        assert_eq!(playlist.current(), ("a", &1));
+    }
+
+    #[test]
+    fn test_cycle_backward() {
+        let mut playlist = Playlist::load(&names(&["a", "bb", "ccc"]), load).unwrap();
+
+        playlist.prev();
+        assert_eq!(playlist.current(), ("ccc", &3));
+        playlist.prev();
+        assert_eq!(playlist.current(), ("bb", &2));
+    }
+
+    #[test]
+    fn test_skip_invalid() {
+        let mut playlist = Playlist::load(&names(&["bad1", "a", "bad2", "bb"]), load).unwrap();
+
+        assert_eq!(playlist.current(), ("a", &1));
+        playlist.next();
+        assert_eq!(playlist.current(), ("bb", &2));
+        playlist.next();
+        assert_eq!(playlist.current(), ("a", &1));
+    }
+
+    #[test]
+    fn test_all_invalid() {
+        assert!(Playlist::load(&names(&["bad1", "bad2"]), load).is_none());
+    }
+}
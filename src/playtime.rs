@@ -0,0 +1,111 @@
+use std::time::Duration;
+
+use wheat::FRAMES_PER_SECOND;
+
+/// Smallest difference between the two clocks, as a fraction of the real time, that is shown.
+const DRIFT_THRESHOLD: f64 = 0.01;
+
+/// Keeps two clocks for the current ROM: the time that really passed, and the time that passed
+/// for the program, i.e. the frames its instructions make up at the requested CPU frequency. They
+/// drift apart when the emulator can't keep up with that frequency, or runs too fast.
+#[derive(Debug)]
+pub struct PlayTime {
+    cycles_per_frame: f64,
+    real: Duration,
+    cycles: u64,
+}
+
+impl PlayTime {
+    pub fn new(hertz: f64) -> Self {
+        Self {
+            cycles_per_frame: hertz / FRAMES_PER_SECOND,
+            real: Duration::ZERO,
+            cycles: 0,
+        }
+    }
+
+    /// Records that `cycles` instructions ran in the `elapsed` since the last call.
+    pub fn record(&mut self, cycles: u32, elapsed: Duration) {
+        self.cycles += u64::from(cycles);
+        self.real += elapsed;
+    }
+
+    pub fn real(&self) -> Duration {
+        self.real
+    }
+
+    pub fn emulated(&self) -> Duration {
+        let frames = self.cycles as f64 / self.cycles_per_frame;
+        Duration::from_secs_f64(frames / FRAMES_PER_SECOND)
+    }
+
+    /// Returns how far the emulated time is ahead (positive) or behind (negative) of the real
+    /// time, in percent of the real time, once they're more than 1% apart.
+    pub fn drift(&self) -> Option<f64> {
+        if self.real.is_zero() {
+            return None;
+        }
+        let drift = (self.emulated().as_secs_f64() - self.real.as_secs_f64()) / self.real.as_secs_f64();
+        (drift.abs() > DRIFT_THRESHOLD).then_some(drift * 100.0)
+    }
+}
+
+/// Formats `duration` as `mm:ss`, or as `h:mm:ss` from an hour on.
+pub fn format_clock(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (hours, minutes, seconds) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if hours > 0 {
+        format!("{hours}:{minutes:02}:{seconds:02}")
+    } else {
+        format!("{minutes:02}:{seconds:02}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{format_clock, PlayTime};
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn test_format_clock() {
+        assert_eq!(format_clock(Duration::ZERO), "00:00");
+        assert_eq!(format_clock(Duration::from_millis(312_900)), "05:12");
+        assert_eq!(format_clock(secs(3723)), "1:02:03");
+    }
+
+    #[test]
+    fn test_clocks() {
+        let mut time = PlayTime::new(800.0);
+        assert_eq!(time.drift(), None);
+
+        // A second's worth of instructions, in batches of 10
+        for _ in 0..80 {
+            time.record(10, Duration::from_micros(12_500));
+        }
+        assert_eq!(time.real(), secs(1));
+        assert_eq!(time.emulated(), secs(1));
+        assert_eq!(time.drift(), None);
+    }
+
+    #[test]
+    fn test_drift() {
+        let mut slow = PlayTime::new(1_000.0);
+        slow.record(950, secs(1));
+        let drift = slow.drift().unwrap();
+        assert!((drift + 5.0).abs() < 1e-9, "{drift}");
+
+        let mut fast = PlayTime::new(1_000.0);
+        fast.record(1_020, secs(1));
+        assert!(fast.drift().unwrap() > 0.0);
+
+        // Within 1%
+        let mut close = PlayTime::new(1_000.0);
+        close.record(995, secs(1));
+        assert_eq!(close.drift(), None);
+    }
+}
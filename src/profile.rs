@@ -0,0 +1,413 @@
+//! Where a ROM spends its time: a subroutine-level profile built from the `2nnn` calls and `00EE`
+//! returns it executes, and counts of the instructions executed at each address.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use crate::chip8::MEMORY_SIZE;
+
+/// What the profile knows about one subroutine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SubroutineProfile {
+    /// Address of the subroutine's first instruction
+    pub entry: u16,
+    /// Times the subroutine was called
+    pub calls: u64,
+    /// Instructions executed while the subroutine, or a subroutine it called, was running. A
+    /// recursive call counts towards its outermost call only.
+    pub inclusive: u64,
+    /// Instructions executed in the subroutine itself, including its `CALL`s and `RET`s
+    pub exclusive: u64,
+}
+
+/// A running call.
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    entry: u16,
+    /// Instructions executed when the call was made
+    start: u64,
+}
+
+/// The call graph of a program, with the instructions executed in each subroutine. The entry
+/// point stands for the code outside of any subroutine, and counts as called once.
+///
+/// Returns without a matching call are ignored, and calls made with a shallower stack than the
+/// profile expects, e.g. because it was enabled partway through, end the calls that are deeper.
+///
+/// The profile doesn't count instructions itself: the emulator tells it how many it executed so
+/// far whenever the running subroutine changes.
+#[derive(Debug, Clone)]
+pub struct CallProfile {
+    subroutines: HashMap<u16, SubroutineProfile>,
+    /// Times each subroutine called each other one, by `(caller, callee)`
+    edges: BTreeMap<(u16, u16), u64>,
+    /// The running calls, starting with the entry point
+    frames: Vec<Frame>,
+    /// Instructions executed when the running subroutine was last credited with its instructions
+    instructions: u64,
+}
+
+impl CallProfile {
+    /// Starts a profile of a program that starts at `entry`.
+    pub(crate) fn new(entry: u16) -> Self {
+        let mut subroutines = HashMap::new();
+        subroutines.insert(
+            entry,
+            SubroutineProfile {
+                entry,
+                calls: 1,
+                ..SubroutineProfile::default()
+            },
+        );
+        Self {
+            subroutines,
+            edges: BTreeMap::new(),
+            frames: vec![Frame { entry, start: 0 }],
+            instructions: 0,
+        }
+    }
+
+    /// Credits the running subroutine with the instructions up to `instructions`, the number the
+    /// emulator executed so far.
+    pub(crate) fn run_until(&mut self, instructions: u64) {
+        if let Some(frame) = self.frames.last() {
+            let entry = frame.entry;
+            self.subroutine(entry).exclusive += instructions - self.instructions;
+        }
+        self.instructions = instructions;
+    }
+
+    /// Records a call to `entry`, made with `depth` calls on the stack before it, once the
+    /// emulator executed `instructions` instructions, including the call.
+    pub(crate) fn call(&mut self, entry: u16, depth: usize, instructions: u64) {
+        self.run_until(instructions);
+        while self.frames.len() > depth + 1 {
+            self.end_call();
+        }
+        let caller = self.frames[self.frames.len() - 1].entry;
+        *self.edges.entry((caller, entry)).or_default() += 1;
+        self.subroutine(entry).calls += 1;
+        self.frames.push(Frame {
+            entry,
+            start: self.instructions,
+        });
+    }
+
+    /// Records a return from the running subroutine, once the emulator executed `instructions`
+    /// instructions, including the return.
+    pub(crate) fn ret(&mut self, instructions: u64) {
+        if self.frames.len() > 1 {
+            self.run_until(instructions);
+            self.end_call();
+        }
+    }
+
+    fn end_call(&mut self) {
+        let Some(frame) = self.frames.pop() else {
+            return;
+        };
+        self.credit(frame);
+    }
+
+    /// Adds the instructions since `frame` started to its subroutine, unless an outer call to the
+    /// same subroutine is still running and will count them.
+    fn credit(&mut self, frame: Frame) {
+        if self.frames.iter().all(|outer| outer.entry != frame.entry) {
+            let instructions = self.instructions - frame.start;
+            self.subroutine(frame.entry).inclusive += instructions;
+        }
+    }
+
+    fn subroutine(&mut self, entry: u16) -> &mut SubroutineProfile {
+        self.subroutines.entry(entry).or_insert(SubroutineProfile {
+            entry,
+            ..SubroutineProfile::default()
+        })
+    }
+
+    /// The profile as it would be if every running call returned now.
+    fn closed(&self) -> Self {
+        let mut profile = self.clone();
+        while profile.frames.len() > 1 {
+            profile.end_call();
+        }
+        let root = profile.frames[0];
+        profile.frames.clear();
+        profile.credit(root);
+        profile
+    }
+
+    /// Every subroutine seen so far, with the calls that are still running counted up to now,
+    /// most inclusive instructions first.
+    pub fn subroutines(&self) -> Vec<SubroutineProfile> {
+        let mut subroutines: Vec<_> = self.closed().subroutines.into_values().collect();
+        subroutines.sort_by_key(|sub| (std::cmp::Reverse(sub.inclusive), sub.entry));
+        subroutines
+    }
+
+    /// The number of times each subroutine called each other one, as `(caller, callee, calls)`.
+    pub fn edges(&self) -> impl Iterator<Item = (u16, u16, u64)> + '_ {
+        self.edges
+            .iter()
+            .map(|(&(caller, callee), &calls)| (caller, callee, calls))
+    }
+
+    /// Renders the profile as a Graphviz graph, labelling each subroutine with its calls and
+    /// instructions, and each edge with its calls.
+    pub fn to_dot(&self) -> String {
+        let mut subroutines = self.subroutines();
+        subroutines.sort_by_key(|sub| sub.entry);
+
+        let mut dot = String::from("digraph profile {\n");
+        for sub in subroutines {
+            dot += &format!(
+                "    \"{:#05x}\" [label=\"{:#05x}\\ncalls: {}\\ninclusive: {}\\nexclusive: {}\"];\n",
+                sub.entry, sub.entry, sub.calls, sub.inclusive, sub.exclusive
+            );
+        }
+        for (caller, callee, calls) in self.edges() {
+            dot += &format!("    \"{caller:#05x}\" -> \"{callee:#05x}\" [label=\"{calls}\"];\n");
+        }
+        dot += "}\n";
+        dot
+    }
+}
+
+/// A table of the subroutines, most inclusive instructions first.
+impl fmt::Display for CallProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<8}{:>10}{:>14}{:>14}",
+            "entry", "calls", "inclusive", "exclusive"
+        )?;
+        for sub in self.subroutines() {
+            writeln!(
+                f,
+                "{:<#8x}{:>10}{:>14}{:>14}",
+                sub.entry, sub.calls, sub.inclusive, sub.exclusive
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Counts the instructions executed at each address. The counts of each opcode family, i.e. by
+/// their first nibble, are worked out from them.
+#[derive(Debug, Clone)]
+pub struct OpcodeProfile {
+    by_address: Vec<u32>,
+}
+
+impl Default for OpcodeProfile {
+    fn default() -> Self {
+        Self {
+            by_address: vec![0; MEMORY_SIZE],
+        }
+    }
+}
+
+impl OpcodeProfile {
+    /// Counts an instruction executed at `pc`.
+    pub(crate) fn record(&mut self, pc: u16) {
+        let count = &mut self.by_address[pc as usize % MEMORY_SIZE];
+        *count = count.saturating_add(1);
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.by_address.fill(0);
+    }
+
+    /// The `top_n` addresses with the most instructions executed, and every opcode family that
+    /// was executed, most executed first. The family of each address is the one of the opcode at
+    /// it in `memory` now, so code that a program overwrote counts as the new opcode.
+    pub fn report(&self, top_n: usize, memory: &[u8]) -> OpcodeProfileReport {
+        let mut addresses: Vec<_> = (0..)
+            .zip(self.by_address.iter().copied())
+            .filter(|&(_, count)| count > 0)
+            .collect();
+
+        let mut by_family = [0u64; 16];
+        for &(addr, count) in &addresses {
+            let opcode = memory.get(addr as usize).copied().unwrap_or(0);
+            by_family[(opcode >> 4) as usize] += u64::from(count);
+        }
+        let mut families: Vec<_> = (0..).zip(by_family).filter(|&(_, count)| count > 0).collect();
+
+        addresses.sort_by_key(|&(addr, count)| (std::cmp::Reverse(count), addr));
+        addresses.truncate(top_n);
+        families.sort_by_key(|&(family, count)| (std::cmp::Reverse(count), family));
+
+        OpcodeProfileReport { addresses, families }
+    }
+}
+
+/// A summary of an [`OpcodeProfile`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpcodeProfileReport {
+    /// `(address, instructions executed there)`, most executed first
+    pub addresses: Vec<(u16, u32)>,
+    /// `(first nibble, instructions executed)`, most executed first
+    pub families: Vec<(u8, u64)>,
+}
+
+impl fmt::Display for OpcodeProfileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<8}{:>12}", "address", "executed")?;
+        for (addr, count) in &self.addresses {
+            writeln!(f, "{addr:<#8x}{count:>12}")?;
+        }
+        writeln!(f, "{:<8}{:>12}", "opcodes", "executed")?;
+        for (family, count) in &self.families {
+            writeln!(f, "{:<8}{count:>12}", format!("{family:X}xxx"))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chip8::NoInput;
+    use crate::test_helpers::chip8_with_program;
+    use crate::DebugOptionsBuilder;
+
+    use super::{CallProfile, OpcodeProfileReport, SubroutineProfile};
+
+    fn profile(program: &[u16], cycles: u32) -> CallProfile {
+        let options = DebugOptionsBuilder::default()
+            .profile_calls(true)
+            .build()
+            .unwrap();
+        let mut chip8 = chip8_with_program(program, options);
+        chip8.emulate_frame(cycles, &NoInput).unwrap();
+        chip8.call_profile()
+    }
+
+    fn sub(entry: u16, calls: u64, inclusive: u64, exclusive: u64) -> SubroutineProfile {
+        SubroutineProfile {
+            entry,
+            calls,
+            inclusive,
+            exclusive,
+        }
+    }
+
+    /// CALL 0x206; CALL 0x206; JP 0x204;
+    /// 0x206: LD V0, 1; CALL 0x20E; LD V1, 1; RET;
+    /// 0x20E: LD V2, 1; LD V3, 1; RET
+    const NESTED: [u16; 10] = [
+        0x2206, 0x2206, 0x1204, 0x6001, 0x220E, 0x6101, 0x00EE, 0x6201, 0x6301, 0x00EE,
+    ];
+
+    #[test]
+    fn test_nested_subroutines() {
+        let profile = profile(&NESTED, 20);
+
+        assert_eq!(
+            profile.subroutines(),
+            [sub(0x200, 1, 20, 6), sub(0x206, 2, 14, 8), sub(0x20E, 2, 6, 6)]
+        );
+        assert_eq!(
+            profile.edges().collect::<Vec<_>>(),
+            [(0x200, 0x206, 2), (0x206, 0x20E, 2)]
+        );
+    }
+
+    #[test]
+    fn test_running_calls_count_up_to_now() {
+        // Stops in the second instruction of 0x20E
+        let profile = profile(&NESTED, 5);
+
+        assert_eq!(
+            profile.subroutines(),
+            [sub(0x200, 1, 5, 1), sub(0x206, 1, 4, 2), sub(0x20E, 1, 2, 2)]
+        );
+    }
+
+    #[test]
+    fn test_recursion() {
+        // LD V0, 3; CALL 0x206; JP 0x204;
+        // 0x206: ADD V0, -1; SE V0, 0; CALL 0x206; RET
+        let profile = profile(&[0x6003, 0x2206, 0x1204, 0x70FF, 0x3000, 0x2206, 0x00EE], 15);
+
+        assert_eq!(
+            profile.subroutines(),
+            [sub(0x200, 1, 15, 4), sub(0x206, 3, 11, 11)]
+        );
+        assert_eq!(
+            profile.edges().collect::<Vec<_>>(),
+            [(0x200, 0x206, 1), (0x206, 0x206, 2)]
+        );
+    }
+
+    #[test]
+    fn test_unmatched_returns_and_shallower_calls() {
+        let mut profile = CallProfile::new(0x200);
+        profile.ret(0);
+        profile.call(0x300, 0, 0);
+        // The stack was reset under the profile
+        profile.call(0x400, 0, 1);
+        profile.run_until(2);
+
+        assert_eq!(
+            profile.subroutines(),
+            [sub(0x200, 1, 2, 0), sub(0x300, 1, 1, 1), sub(0x400, 1, 1, 1)]
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let profile = profile(&NESTED, 20);
+
+        assert_eq!(
+            profile.to_string(),
+            "entry        calls     inclusive     exclusive\n\
+             0x200            1            20             6\n\
+             0x206            2            14             8\n\
+             0x20e            2             6             6\n"
+        );
+        assert!(profile
+            .to_dot()
+            .contains("    \"0x206\" [label=\"0x206\\ncalls: 2\\ninclusive: 14\\nexclusive: 8\"];\n"));
+        assert!(profile
+            .to_dot()
+            .contains("    \"0x206\" -> \"0x20e\" [label=\"2\"];\n"));
+    }
+
+    #[test]
+    fn test_opcode_profile() {
+        let options = DebugOptionsBuilder::default()
+            .profile_opcodes(true)
+            .build()
+            .unwrap();
+        // LD V1, 5; 0x202: ADD V0, 1; SE V0, V1; JP 0x202; 0x208: JP 0x208
+        let mut chip8 = chip8_with_program(&[0x6105, 0x7001, 0x5010, 0x1202, 0x1208], options);
+        chip8.emulate_frame(1000, &NoInput).unwrap();
+
+        // 5 times around the loop, then parked on the last jump, which skipped one `JP 0x202`
+        let report = chip8.profile_report(3);
+        assert_eq!(
+            report,
+            OpcodeProfileReport {
+                addresses: vec![(0x208, 985), (0x202, 5), (0x204, 5)],
+                families: vec![(0x1, 989), (0x5, 5), (0x7, 5), (0x6, 1)],
+            }
+        );
+        assert!(report.to_string().starts_with(
+            "address     executed\n\
+             0x208            985\n"
+        ));
+        assert!(report.to_string().contains("\n1xxx             989\n"));
+
+        chip8.reset_profile();
+        assert!(chip8.profile_report(3).addresses.is_empty());
+    }
+
+    #[test]
+    fn test_profile_off() {
+        let mut chip8 = chip8_with_program(&NESTED, Default::default());
+        chip8.emulate_frame(20, &NoInput).unwrap();
+
+        assert_eq!(chip8.call_profile().subroutines(), [sub(0x200, 1, 0, 0)]);
+    }
+}
@@ -0,0 +1,105 @@
+//! Keeps the public API in `public-api.txt` at the root of the repository, so that changes to it
+//! show up in review instead of breaking downstream code by accident. When the API changes on
+//! purpose, run the tests with `WHEAT_UPDATE_PUBLIC_API=1` to rewrite the file.
+
+/// The library's source files, as `(module, source)`.
+const SOURCES: &[(&str, &str)] = &[
+    ("wheat", include_str!("lib.rs")),
+    ("async_emulator", include_str!("async_emulator.rs")),
+    ("breakpoint", include_str!("breakpoint.rs")),
+    ("chip8", include_str!("chip8.rs")),
+    ("crash_report", include_str!("crash_report.rs")),
+    ("demo", include_str!("demo.rs")),
+    ("disasm", include_str!("disasm.rs")),
+    ("frame_channel", include_str!("frame_channel.rs")),
+    ("golden", include_str!("golden.rs")),
+    ("graphics", include_str!("graphics.rs")),
+    ("input_recorder", include_str!("input_recorder.rs")),
+    ("memsearch", include_str!("memsearch.rs")),
+    ("opcode_overrides", include_str!("opcode_overrides.rs")),
+    ("profile", include_str!("profile.rs")),
+    ("rewind", include_str!("rewind.rs")),
+    ("soak", include_str!("soak.rs")),
+    ("state", include_str!("state.rs")),
+    ("test_helpers", include_str!("test_helpers.rs")),
+    ("timer", include_str!("timer.rs")),
+    ("trace", include_str!("trace.rs")),
+    ("traits", include_str!("traits.rs")),
+];
+
+const SNAPSHOT: &str = include_str!("../public-api.txt");
+
+const ITEM_KINDS: &[&str] = &[
+    "fn", "const fn", "async fn", "struct", "enum", "trait", "type", "const", "static", "mod",
+];
+
+/// Lists the `pub` items of `source` outside of its tests, one per line, with the methods under the
+/// `impl` they're in.
+fn public_items(module: &str, source: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut current_impl = None;
+
+    let mut lines = source.lines().peekable();
+    while let Some(line) = lines.next() {
+        // Everything from the test module on is private
+        if line == "#[cfg(test)]" && lines.peek() == Some(&"mod tests {") {
+            break;
+        }
+        if line.starts_with("impl") {
+            current_impl = Some(
+                line.trim_end_matches(" {")
+                    .trim_end_matches('{')
+                    .trim()
+                    .to_string(),
+            );
+            continue;
+        }
+        if line.starts_with('}') {
+            current_impl = None;
+            continue;
+        }
+
+        let trimmed = line.trim_start();
+        let Some(rest) = trimmed.strip_prefix("pub ") else {
+            continue;
+        };
+        let Some(kind) = ITEM_KINDS
+            .iter()
+            .find(|kind| rest.starts_with(&format!("{kind} ")))
+        else {
+            continue;
+        };
+        let name: String = rest[kind.len() + 1..]
+            .chars()
+            .take_while(|c| c.is_alphanumeric() || *c == '_')
+            .collect();
+
+        match (&current_impl, line.len() - trimmed.len()) {
+            (Some(header), 4) => items.push(format!("{module}: {header}: {kind} {name}")),
+            (_, 0) => items.push(format!("{module}: {kind} {name}")),
+            // Trait methods and items of nested modules
+            _ => (),
+        }
+    }
+
+    items
+}
+
+#[test]
+fn test_public_api_snapshot() {
+    let api: String = SOURCES
+        .iter()
+        .flat_map(|(module, source)| public_items(module, source))
+        .map(|item| item + "\n")
+        .collect();
+
+    if std::env::var_os("WHEAT_UPDATE_PUBLIC_API").is_some() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/public-api.txt");
+        std::fs::write(path, &api).unwrap();
+        return;
+    }
+    assert!(
+        api == SNAPSHOT,
+        "the public API changed, rerun with WHEAT_UPDATE_PUBLIC_API=1 if that's on purpose:\n{api}"
+    );
+}
@@ -0,0 +1,368 @@
+//! Keeps the recent past of a program, to step back through it.
+//!
+//! Snapshots are taken every so many cycles into a ring buffer. To keep the memory use down,
+//! each snapshot only stores the memory pages and screen rows that changed since the one before
+//! it; only the oldest one is stored whole.
+
+use std::collections::VecDeque;
+
+use crate::chip8::Chip8;
+use crate::state::Chip8State;
+use crate::traits::GraphicsBuffer;
+
+/// Bytes of memory compared and stored together.
+const PAGE_SIZE: usize = 256;
+
+/// A snapshot, without the memory and screen that are the same as in the one before it.
+#[derive(Debug)]
+struct Delta {
+    /// The state, with its memory and screen left empty
+    state: Chip8State,
+    /// The memory pages that changed, by page number
+    pages: Vec<(usize, Vec<u8>)>,
+    /// The screen rows that changed, by row number
+    rows: Vec<(usize, Vec<u8>)>,
+}
+
+/// A ring buffer of the last `capacity` snapshots of a program, taken every `interval` cycles.
+/// See [`RewindBuffer::record`] and [`RewindBuffer::rewind`].
+#[derive(Debug)]
+pub struct RewindBuffer {
+    interval: u64,
+    capacity: usize,
+    /// Cycles after which the next snapshot is due
+    next_at: u64,
+    /// Oldest first. The oldest one's memory and screen are in `base_*`
+    snapshots: VecDeque<Delta>,
+    base_memory: Vec<u8>,
+    base_screen: Vec<Vec<u8>>,
+    /// The memory and screen of the newest snapshot, to compare the next one against
+    latest_memory: Vec<u8>,
+    latest_screen: Vec<Vec<u8>>,
+    /// Whether [`RewindBuffer::rewind`] already went back to the oldest snapshot
+    at_oldest: bool,
+}
+
+impl RewindBuffer {
+    /// Keeps up to `capacity` snapshots, one every `interval` cycles.
+    pub fn new(interval: u64, capacity: usize) -> Self {
+        Self {
+            interval: interval.max(1),
+            capacity,
+            next_at: 0,
+            snapshots: VecDeque::new(),
+            base_memory: Vec::new(),
+            base_screen: Vec::new(),
+            latest_memory: Vec::new(),
+            latest_screen: Vec::new(),
+            at_oldest: false,
+        }
+    }
+
+    /// Takes a snapshot of `chip8` if `interval` cycles passed since the last one. Meant to be
+    /// called after every frame.
+    pub fn record<G: GraphicsBuffer>(&mut self, chip8: &Chip8<G>) {
+        let cycles = chip8.statistics().total_cycles;
+        if cycles >= self.next_at {
+            self.next_at = cycles + self.interval;
+            self.push(chip8.save_state());
+        }
+    }
+
+    /// Adds `state` as the newest snapshot, dropping the oldest one if the buffer is full.
+    pub fn push(&mut self, mut state: Chip8State) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let memory = std::mem::take(&mut state.memory);
+        let screen = std::mem::take(&mut state.screen);
+        let delta = if self.snapshots.is_empty() {
+            self.base_memory.clone_from(&memory);
+            self.base_screen.clone_from(&screen);
+            Delta {
+                state,
+                pages: Vec::new(),
+                rows: Vec::new(),
+            }
+        } else {
+            Delta {
+                state,
+                pages: changed(self.latest_memory.chunks(PAGE_SIZE), memory.chunks(PAGE_SIZE)),
+                rows: changed(
+                    self.latest_screen.iter().map(Vec::as_slice),
+                    screen.iter().map(Vec::as_slice),
+                ),
+            }
+        };
+        self.latest_memory = memory;
+        self.latest_screen = screen;
+        self.snapshots.push_back(delta);
+        self.at_oldest = false;
+
+        if self.snapshots.len() > self.capacity {
+            self.snapshots.pop_front();
+            if let Some(oldest) = self.snapshots.front_mut() {
+                apply(&mut self.base_memory, &mut self.base_screen, oldest);
+                oldest.pages.clear();
+                oldest.rows.clear();
+            }
+        }
+    }
+
+    /// Goes back `steps` snapshots, and returns the state to load with [`Chip8::load_state`]:
+    /// `1` is the newest snapshot. Snapshots newer than the returned one, and the returned one
+    /// itself, are dropped, so that rewinding again carries on further back. Stops at the oldest
+    /// snapshot, which is kept, and returns `None` once it was returned.
+    pub fn rewind(&mut self, steps: usize) -> Option<Chip8State> {
+        if steps == 0 || self.snapshots.is_empty() || self.at_oldest {
+            return None;
+        }
+
+        let target = self.snapshots.len().saturating_sub(steps);
+        let state = self.reconstruct(target);
+        self.at_oldest = target == 0;
+        let kept = target.max(1);
+        self.snapshots.truncate(kept);
+        let newest = self.reconstruct(kept - 1);
+        self.latest_memory = newest.memory;
+        self.latest_screen = newest.screen;
+        Some(state)
+    }
+
+    /// The snapshot at `index`, oldest first, with its memory and screen filled in.
+    fn reconstruct(&self, index: usize) -> Chip8State {
+        let mut memory = self.base_memory.clone();
+        let mut screen = self.base_screen.clone();
+        for delta in self.snapshots.range(1..=index) {
+            apply(&mut memory, &mut screen, delta);
+        }
+
+        let mut state = self.snapshots[index].state.clone();
+        state.memory = memory;
+        state.screen = screen;
+        state
+    }
+
+    /// Drops every snapshot, e.g. when another ROM is started. The next [`RewindBuffer::record`]
+    /// takes a snapshot whatever the cycle count.
+    pub fn clear(&mut self) {
+        self.next_at = 0;
+        self.snapshots.clear();
+        self.base_memory.clear();
+        self.base_screen.clear();
+        self.latest_memory.clear();
+        self.latest_screen.clear();
+        self.at_oldest = false;
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    /// Roughly how many bytes the snapshots' memory and screens take up.
+    pub fn stored_bytes(&self) -> usize {
+        let whole =
+            |memory: &[u8], screen: &[Vec<u8>]| memory.len() + screen.iter().map(Vec::len).sum::<usize>();
+        let deltas: usize = self
+            .snapshots
+            .iter()
+            .flat_map(|delta| delta.pages.iter().chain(&delta.rows))
+            .map(|(_, bytes)| bytes.len())
+            .sum();
+        whole(&self.base_memory, &self.base_screen) + whole(&self.latest_memory, &self.latest_screen) + deltas
+    }
+}
+
+/// The chunks of `new` that differ from the ones of `old`, by their index.
+fn changed<'a>(
+    old: impl Iterator<Item = &'a [u8]>,
+    new: impl Iterator<Item = &'a [u8]>,
+) -> Vec<(usize, Vec<u8>)> {
+    let mut old = old.fuse();
+    new.enumerate()
+        .filter(|(_, chunk)| old.next() != Some(*chunk))
+        .map(|(index, chunk)| (index, chunk.to_vec()))
+        .collect()
+}
+
+/// Applies the changes of `delta` to the memory and screen of the snapshot before it.
+fn apply(memory: &mut [u8], screen: &mut [Vec<u8>], delta: &Delta) {
+    for (page, bytes) in &delta.pages {
+        memory[page * PAGE_SIZE..page * PAGE_SIZE + bytes.len()].copy_from_slice(bytes);
+    }
+    for (row, pixels) in &delta.rows {
+        screen[*row].clone_from(pixels);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RewindBuffer;
+    use crate::chip8::{Chip8, NoInput};
+    use crate::graphics::Graphics;
+    use crate::test_helpers::chip8_with_program;
+    use crate::traits::{GraphicsBuffer, Input};
+    use crate::{DebugOptions, Key};
+
+    /// Key 5 is held on the first 3 of every 7 frames.
+    struct ScriptedKeys(u64);
+
+    impl Input for ScriptedKeys {
+        fn is_pressed(&self, key: Key) -> bool {
+            key == Key::Num5 && self.0 % 7 < 3
+        }
+    }
+
+    const CYCLES_PER_FRAME: u32 = 13;
+
+    /// Draws a counting digit that moves right while key 5 is held: LD F, V1; DRW V0, V3, 5;
+    /// ADD V1, 1; LD V4, 0x0F; AND V1, V4; LD V6, 5; SKNP V6; ADD V0, 1; JP 0x200
+    fn chip8() -> Chip8<Graphics> {
+        let program: [u16; 9] = [
+            0xF129, 0xD035, 0x7101, 0x640F, 0x8142, 0x6605, 0xE6A1, 0x7001, 0x1200,
+        ];
+        chip8_with_program(&program, DebugOptions::default())
+    }
+
+    /// Runs `frames` with the scripted keys, and returns the screen after each.
+    fn run(
+        chip8: &mut Chip8<Graphics>,
+        frames: std::ops::Range<u64>,
+        rewind: &mut RewindBuffer,
+    ) -> Vec<Vec<Vec<u8>>> {
+        frames
+            .map(|frame| {
+                chip8
+                    .emulate_frame(CYCLES_PER_FRAME, &ScriptedKeys(frame))
+                    .unwrap();
+                rewind.record(chip8);
+                chip8.graphics().buffer().clone()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rewind_and_replay() {
+        let mut chip8 = chip8();
+        let mut rewind = RewindBuffer::new(CYCLES_PER_FRAME.into(), 200);
+        let screens = run(&mut chip8, 0..100, &mut rewind);
+        assert_eq!(rewind.len(), 100);
+
+        // Back to the end of frame 70
+        let state = rewind.rewind(30).unwrap();
+        assert_eq!(rewind.len(), 70);
+        chip8.load_state(state).unwrap();
+        assert_eq!(chip8.graphics().buffer(), &screens[70]);
+
+        let replayed = run(&mut chip8, 71..100, &mut rewind);
+        assert_eq!(replayed, screens[71..]);
+        assert_eq!(rewind.len(), 99);
+    }
+
+    #[test]
+    fn test_rewind_one_step_at_a_time() {
+        let mut chip8 = chip8();
+        let mut rewind = RewindBuffer::new(CYCLES_PER_FRAME.into(), 200);
+        let screens = run(&mut chip8, 0..10, &mut rewind);
+
+        for frame in (0..10).rev() {
+            let state = rewind.rewind(1).unwrap();
+            assert_eq!(state.screen, screens[frame]);
+        }
+        assert!(rewind.rewind(1).is_none());
+        assert_eq!(rewind.len(), 1);
+    }
+
+    #[test]
+    fn test_capacity_keeps_the_newest() {
+        let mut chip8 = chip8();
+        let mut rewind = RewindBuffer::new(CYCLES_PER_FRAME.into(), 20);
+        let screens = run(&mut chip8, 0..50, &mut rewind);
+        assert_eq!(rewind.len(), 20);
+
+        // Past the oldest snapshot, which is the end of frame 30
+        let state = rewind.rewind(100).unwrap();
+        assert_eq!(state.screen, screens[30]);
+        assert_eq!(rewind.len(), 1);
+    }
+
+    #[test]
+    fn test_rewind_past_the_oldest() {
+        let mut chip8 = chip8();
+        let mut rewind = RewindBuffer::new(100, 10);
+        for _ in 0..30 {
+            chip8.emulate_frame(CYCLES_PER_FRAME, &NoInput).unwrap();
+            rewind.record(&chip8);
+        }
+
+        // Holding Backspace: back to the snapshot at 13, then nothing further, and it's kept
+        let oldest = rewind.rewind(10).unwrap();
+        assert_eq!(rewind.rewind(1), None);
+        assert_eq!(rewind.rewind(1), None);
+        assert_eq!(rewind.len(), 1);
+
+        // The next snapshot is still due at 429, not right away
+        chip8.load_state(oldest).unwrap();
+        chip8.emulate_frame(CYCLES_PER_FRAME, &NoInput).unwrap();
+        rewind.record(&chip8);
+        assert_eq!(rewind.len(), 1);
+        for _ in 0..2 {
+            chip8.emulate_frame(CYCLES_PER_FRAME, &NoInput).unwrap();
+            rewind.record(&chip8);
+        }
+        assert_eq!(rewind.len(), 2);
+        assert!(rewind.rewind(1).is_some());
+    }
+
+    #[test]
+    fn test_snapshots_only_store_changes() {
+        let mut chip8 = chip8();
+        let mut rewind = RewindBuffer::new(CYCLES_PER_FRAME.into(), 200);
+        run(&mut chip8, 0..100, &mut rewind);
+
+        let whole = chip8.save_state();
+        let whole = whole.memory.len() + whole.screen.iter().map(Vec::len).sum::<usize>();
+        assert!(
+            rewind.stored_bytes() < 10 * whole,
+            "{} bytes",
+            rewind.stored_bytes()
+        );
+    }
+
+    #[test]
+    fn test_record_interval() {
+        let mut chip8 = chip8();
+        let mut rewind = RewindBuffer::new(100, 10);
+        for _ in 0..30 {
+            chip8.emulate_frame(CYCLES_PER_FRAME, &NoInput).unwrap();
+            rewind.record(&chip8);
+        }
+        // 390 cycles, with a snapshot at 13, 117, 221 and 325
+        assert_eq!(rewind.len(), 4);
+    }
+
+    #[test]
+    fn test_record_after_clear() {
+        let mut chip8 = chip8();
+        let mut rewind = RewindBuffer::new(100, 10);
+        for _ in 0..30 {
+            chip8.emulate_frame(CYCLES_PER_FRAME, &NoInput).unwrap();
+            rewind.record(&chip8);
+        }
+
+        // Like switching to another ROM, which starts counting the cycles from 0 again
+        rewind.clear();
+        let mut chip8 = self::chip8();
+        for _ in 0..10 {
+            chip8.emulate_frame(CYCLES_PER_FRAME, &NoInput).unwrap();
+            rewind.record(&chip8);
+        }
+        // 130 cycles, with a snapshot at 13 and 117
+        assert_eq!(rewind.len(), 2);
+    }
+}
@@ -0,0 +1,136 @@
+use std::env;
+use std::process;
+
+/// Why SDL failed to start, guessed from the error message it returned.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SdlFailure {
+    /// SDL, or a library it loads at runtime such as a video or audio backend, isn't installed.
+    MissingLibrary,
+    /// There is no display to open a window on, e.g. when running over SSH.
+    NoDisplay,
+    /// No audio device could be opened.
+    NoAudioDevice,
+    Other,
+}
+
+/// Classifies an error message returned by `sdl2::init` or one of the subsystem calls.
+pub fn classify(error: &str) -> SdlFailure {
+    let error = error.to_lowercase();
+    let mentions = |needles: &[&str]| needles.iter().any(|needle| error.contains(needle));
+
+    if mentions(&[
+        "failed loading",
+        "could not load library",
+        ".so",
+        ".dll",
+        ".dylib",
+    ]) {
+        SdlFailure::MissingLibrary
+    } else if mentions(&["video device", "display", "x11", "wayland"]) {
+        SdlFailure::NoDisplay
+    } else if mentions(&["audio"]) {
+        SdlFailure::NoAudioDevice
+    } else {
+        SdlFailure::Other
+    }
+}
+
+/// How to install SDL2 on `os`, as named by `std::env::consts::OS`.
+fn install_hint(os: &str) -> &'static str {
+    match os {
+        "linux" => {
+            "Install it with your package manager, e.g. `sudo apt install libsdl2-2.0-0` \
+             (Debian/Ubuntu), `sudo dnf install SDL2` (Fedora) or `sudo pacman -S sdl2` (Arch)."
+        }
+        "macos" => "Install it with Homebrew: `brew install sdl2`.",
+        "windows" => {
+            "Download SDL2.dll from https://github.com/libsdl-org/SDL/releases and put it next to \
+             wheat.exe."
+        }
+        _ => "Install SDL2 from https://libsdl.org.",
+    }
+}
+
+/// Explains `error` to the user, with what to do about it on `os`.
+pub fn report(error: &str, os: &str) -> String {
+    let guidance = match classify(error) {
+        SdlFailure::MissingLibrary => format!(
+            "SDL2 or one of the libraries it needs is not installed. {}\n\
+             Alternatively, build with `cargo build --features bundled-sdl` to compile SDL2 into \
+             the emulator.",
+            install_hint(os)
+        ),
+        SdlFailure::NoDisplay => "No display was found to open a window on. Run Wheat from a desktop \
+                                  session, or enable X forwarding (`ssh -X`) when running it remotely."
+            .to_string(),
+        SdlFailure::NoAudioDevice => "No audio device could be opened. Check that a sound device is \
+                                      connected, or set `SDL_AUDIODRIVER=dummy` to run without sound."
+            .to_string(),
+        SdlFailure::Other => return format!("Could not start SDL: {error}"),
+    };
+
+    format!("Could not start SDL: {error}\n{guidance}")
+}
+
+/// Prints the [`report`] for `error` and exits.
+pub fn exit(error: &str) -> ! {
+    eprintln!("{}", report(error, env::consts::OS));
+    process::exit(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{classify, report, SdlFailure};
+
+    #[test]
+    fn test_classify() {
+        let cases = [
+            (
+                "Failed loading libasound.so.2: cannot open shared object file",
+                SdlFailure::MissingLibrary,
+            ),
+            ("Could not load library SDL2.dll", SdlFailure::MissingLibrary),
+            ("No available video device", SdlFailure::NoDisplay),
+            ("x11 not available", SdlFailure::NoDisplay),
+            (
+                "Couldn't open audio device: No available audio device",
+                SdlFailure::NoAudioDevice,
+            ),
+            (
+                "Audio target 'pulseaudio' not available",
+                SdlFailure::NoAudioDevice,
+            ),
+            ("Out of memory", SdlFailure::Other),
+        ];
+
+        for (error, failure) in cases {
+            assert_eq!(classify(error), failure, "{error}");
+        }
+    }
+
+    #[test]
+    fn test_report_missing_library() {
+        let report = report("Failed loading libSDL2-2.0.so.0", "macos");
+
+        assert!(report.starts_with("Could not start SDL: Failed loading libSDL2-2.0.so.0\n"));
+        assert!(report.contains("brew install sdl2"));
+        assert!(report.contains("--features bundled-sdl"));
+    }
+
+    #[test]
+    fn test_report_per_os() {
+        let error = "Failed loading libSDL2-2.0.so.0";
+
+        assert!(report(error, "linux").contains("apt install libsdl2-2.0-0"));
+        assert!(report(error, "windows").contains("SDL2.dll"));
+        assert!(report(error, "freebsd").contains("libsdl.org"));
+    }
+
+    #[test]
+    fn test_report_other_error() {
+        assert_eq!(
+            report("Out of memory", "linux"),
+            "Could not start SDL: Out of memory"
+        );
+    }
+}
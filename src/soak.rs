@@ -0,0 +1,364 @@
+//! Runs a collection of ROMs without a window, one after the other, to catch the ones that stop
+//! with an error after a change to the emulator. See [`run`].
+
+use std::fmt;
+use std::sync::mpsc;
+
+use crate::chip8::DEFAULT_CPU_FREQUENCY;
+use crate::chip8::{Chip8, KeySnapshot};
+use crate::disasm;
+use crate::graphics::Graphics;
+use crate::timer::TimerOperation;
+use crate::{cycles_per_frame, Chip8Error, DebugOptions, Quirks};
+
+/// Frames between two checks for a terminal loop.
+const LOOP_CHECK_FRAMES: u64 = 60;
+
+/// Presses every key at once for [`KeyMasher::HOLD`] frames, then lets go of them until
+/// [`KeyMasher::PERIOD`] frames have passed, so that ROMs waiting for a key get past their menus.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KeyMasher;
+
+impl KeyMasher {
+    pub const PERIOD: u64 = 120;
+    pub const HOLD: u64 = 6;
+
+    /// Returns the keys pressed during `frame`, as an [`crate::traits::Input::pressed_mask`].
+    pub fn pressed_mask(&self, frame: u64) -> u16 {
+        if frame % Self::PERIOD < Self::HOLD {
+            u16::MAX
+        } else {
+            0
+        }
+    }
+}
+
+/// How a ROM's run ended.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SoakOutcome {
+    /// It ran for all of its cycles.
+    Completed,
+    /// It stopped with [`Chip8Error::Halted`].
+    Halted { pc: u16 },
+    /// It stopped with any other error, at the instruction at `pc`.
+    Error { pc: u16, opcode: u16, error: String },
+    /// It ended up on a jump to itself, which programs use to stop for good.
+    TerminalLoop { pc: u16 },
+}
+
+impl SoakOutcome {
+    pub fn is_error(&self) -> bool {
+        matches!(self, SoakOutcome::Error { .. })
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            SoakOutcome::Completed => "completed",
+            SoakOutcome::Halted { .. } => "halted",
+            SoakOutcome::Error { .. } => "error",
+            SoakOutcome::TerminalLoop { .. } => "terminal-loop",
+        }
+    }
+}
+
+/// One ROM's run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SoakResult {
+    pub rom: String,
+    /// Instructions executed, including the one that failed
+    pub cycles: u64,
+    /// Whether the ROM looked byte-swapped and was swapped back before it ran
+    pub byte_swapped: bool,
+    pub outcome: SoakOutcome,
+}
+
+/// Runs `rom` for up to `cycles` instructions with `quirks`, with the timers ticking at 60 Hz and
+/// the keys pressed by a [`KeyMasher`]. ROMs that look byte-swapped are swapped back first.
+pub fn run(name: &str, rom: &[u8], quirks: Quirks, cycles: u64) -> SoakResult {
+    let byte_swapped = disasm::detect_byte_swap(rom);
+    let rom = if byte_swapped {
+        disasm::swap_bytes(rom)
+    } else {
+        rom.to_vec()
+    };
+
+    let (timer_tx, timer_rx) = mpsc::channel();
+    let mut chip8 = Chip8::new(Graphics::new(), timer_rx, quirks, DebugOptions::default());
+    let result = |outcome| SoakResult {
+        rom: name.to_string(),
+        cycles,
+        byte_swapped,
+        outcome,
+    };
+    if let Err(e) = chip8.load_rom(&rom) {
+        return SoakResult {
+            cycles: 0,
+            ..result(error_outcome(&chip8, e))
+        };
+    }
+
+    let masher = KeyMasher;
+    let per_frame = u64::from(cycles_per_frame(DEFAULT_CPU_FREQUENCY));
+    let mut frame = 0;
+    while chip8.statistics().total_cycles < cycles {
+        let batch = per_frame.min(cycles - chip8.statistics().total_cycles);
+        // The receiver lives as long as `chip8`
+        let _ = timer_tx.send(TimerOperation::Decrement(1));
+        let keys = KeySnapshot(masher.pressed_mask(frame));
+        if let Err(e) = chip8.emulate_frame(batch as u32, &keys) {
+            let outcome = match e {
+                Chip8Error::Halted(pc) => SoakOutcome::Halted { pc },
+                e => error_outcome(&chip8, e),
+            };
+            return SoakResult {
+                cycles: chip8.statistics().total_cycles,
+                ..result(outcome)
+            };
+        }
+
+        frame += 1;
+        if frame % LOOP_CHECK_FRAMES == 0 {
+            let pc = chip8.state().pc;
+            if chip8.read_opcode_at(pc) == Ok(0x1000 | pc) {
+                return SoakResult {
+                    cycles: chip8.statistics().total_cycles,
+                    ..result(SoakOutcome::TerminalLoop { pc })
+                };
+            }
+        }
+    }
+
+    result(SoakOutcome::Completed)
+}
+
+fn error_outcome(chip8: &Chip8<Graphics>, error: Chip8Error) -> SoakOutcome {
+    let pc = chip8.state().pc;
+    SoakOutcome::Error {
+        pc,
+        opcode: chip8.read_opcode_at(pc).unwrap_or(0),
+        error: error.to_string(),
+    }
+}
+
+/// A table with one line per ROM, followed by how many ended each way.
+pub struct Summary<'a>(pub &'a [SoakResult]);
+
+impl fmt::Display for Summary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self
+            .0
+            .iter()
+            .map(|result| result.rom.len())
+            .max()
+            .unwrap_or(0)
+            .max(3);
+        writeln!(f, "{:width$}  {:13}  {:>10}  details", "ROM", "outcome", "cycles")?;
+        for result in self.0 {
+            let details = match &result.outcome {
+                SoakOutcome::Completed => String::new(),
+                SoakOutcome::Halted { pc } | SoakOutcome::TerminalLoop { pc } => format!("at {pc:#05x}"),
+                SoakOutcome::Error { pc, opcode, error } => format!("{opcode:#06x} at {pc:#05x}: {error}"),
+            };
+            let swapped = if result.byte_swapped {
+                " (byte-swapped)"
+            } else {
+                ""
+            };
+            writeln!(
+                f,
+                "{:width$}  {:13}  {:>10}  {details}{swapped}",
+                result.rom,
+                result.outcome.name(),
+                result.cycles,
+            )?;
+        }
+
+        let count = |name| {
+            self.0
+                .iter()
+                .filter(|result| result.outcome.name() == name)
+                .count()
+        };
+        write!(
+            f,
+            "{} ROMs: {} completed, {} halted, {} errors, {} in a terminal loop",
+            self.0.len(),
+            count("completed"),
+            count("halted"),
+            count("error"),
+            count("terminal-loop"),
+        )
+    }
+}
+
+/// Returns `results` as a JSON array with one object per ROM, for scripts to compare between
+/// runs. Addresses and opcodes are hex strings.
+pub fn json_report(results: &[SoakResult]) -> String {
+    let mut out = String::from("[\n");
+    for (i, result) in results.iter().enumerate() {
+        out += &format!(
+            "  {{\"rom\": {}, \"outcome\": \"{}\", \"cycles\": {}, \"byte_swapped\": {}",
+            json_string(&result.rom),
+            result.outcome.name(),
+            result.cycles,
+            result.byte_swapped
+        );
+        match &result.outcome {
+            SoakOutcome::Completed => (),
+            SoakOutcome::Halted { pc } | SoakOutcome::TerminalLoop { pc } => {
+                out += &format!(", \"pc\": \"{pc:#05x}\"");
+            }
+            SoakOutcome::Error { pc, opcode, error } => {
+                out += &format!(
+                    ", \"pc\": \"{pc:#05x}\", \"opcode\": \"{opcode:#06x}\", \"error\": {}",
+                    json_string(error)
+                );
+            }
+        }
+        out += if i + 1 < results.len() { "},\n" } else { "}\n" };
+    }
+    out + "]\n"
+}
+
+/// Quotes `s` as a JSON string.
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            '\n' => out += "\\n",
+            c if (c as u32) < 0x20 => out += &format!("\\u{:04x}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out + "\""
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_helpers::rom_bytes;
+    use crate::Quirks;
+
+    use super::{json_report, run, KeyMasher, SoakOutcome, SoakResult, Summary};
+
+    #[test]
+    fn test_key_masher() {
+        let masher = KeyMasher;
+
+        assert_eq!(masher.pressed_mask(0), 0xFFFF);
+        assert_eq!(masher.pressed_mask(KeyMasher::HOLD - 1), 0xFFFF);
+        assert_eq!(masher.pressed_mask(KeyMasher::HOLD), 0);
+        assert_eq!(masher.pressed_mask(KeyMasher::PERIOD), 0xFFFF);
+    }
+
+    #[test]
+    fn test_completed() {
+        // ADD V0, 1; JP 0x200
+        let result = run("count", &rom_bytes(&[0x7001, 0x1200]), Quirks::default(), 10_000);
+
+        assert_eq!(result.outcome, SoakOutcome::Completed);
+        assert_eq!(result.cycles, 10_000);
+        assert!(!result.byte_swapped);
+    }
+
+    #[test]
+    fn test_error() {
+        // LD V0, 1; CLS; then an unknown opcode
+        let result = run(
+            "broken",
+            &rom_bytes(&[0x6001, 0x00E0, 0xFFFF]),
+            Quirks::default(),
+            10_000,
+        );
+
+        assert_eq!(
+            result.outcome,
+            SoakOutcome::Error {
+                pc: 0x204,
+                opcode: 0xFFFF,
+                error: "Opcode `0xffff` is not supported".to_string(),
+            }
+        );
+        assert_eq!(result.cycles, 3);
+    }
+
+    #[test]
+    fn test_terminal_loop() {
+        // LD V0, 1; JP 0x202
+        let result = run("done", &rom_bytes(&[0x6001, 0x1202]), Quirks::default(), 100_000);
+
+        assert_eq!(result.outcome, SoakOutcome::TerminalLoop { pc: 0x202 });
+        assert!(result.cycles < 100_000);
+    }
+
+    #[test]
+    fn test_key_wait_gets_mashed() {
+        // LD V0, K; LD V1, 1; JP 0x204
+        let result = run(
+            "menu",
+            &rom_bytes(&[0xF00A, 0x6101, 0x1204]),
+            Quirks::default(),
+            100_000,
+        );
+
+        assert_eq!(result.outcome, SoakOutcome::TerminalLoop { pc: 0x204 });
+    }
+
+    #[test]
+    fn test_byte_swapped() {
+        let swapped: Vec<u8> = rom_bytes(&[0x7001, 0x1200, 0x6001, 0x6102, 0x6203, 0x6304])
+            .chunks(2)
+            .flat_map(|pair| [pair[1], pair[0]])
+            .collect();
+
+        let result = run("swapped", &swapped, Quirks::default(), 1_000);
+        assert!(result.byte_swapped);
+        assert_eq!(result.outcome, SoakOutcome::Completed);
+    }
+
+    fn results() -> Vec<SoakResult> {
+        vec![
+            SoakResult {
+                rom: "pong.ch8".to_string(),
+                cycles: 200_000,
+                byte_swapped: false,
+                outcome: SoakOutcome::Completed,
+            },
+            SoakResult {
+                rom: "broken \"beta\".ch8".to_string(),
+                cycles: 3,
+                byte_swapped: true,
+                outcome: SoakOutcome::Error {
+                    pc: 0x204,
+                    opcode: 0xFFFF,
+                    error: "Opcode `0xffff` is not supported".to_string(),
+                },
+            },
+        ]
+    }
+
+    #[test]
+    fn test_summary() {
+        assert_eq!(
+            Summary(&results()).to_string(),
+            "ROM                outcome            cycles  details\n\
+             pong.ch8           completed          200000  \n\
+             broken \"beta\".ch8  error                   3  0xffff at 0x204: Opcode `0xffff` is not \
+             supported (byte-swapped)\n\
+             2 ROMs: 1 completed, 0 halted, 1 errors, 0 in a terminal loop"
+        );
+    }
+
+    #[test]
+    fn test_json_report() {
+        assert_eq!(
+            json_report(&results()),
+            "[\n  \
+             {\"rom\": \"pong.ch8\", \"outcome\": \"completed\", \"cycles\": 200000, \"byte_swapped\": false},\n  \
+             {\"rom\": \"broken \\\"beta\\\".ch8\", \"outcome\": \"error\", \"cycles\": 3, \"byte_swapped\": true, \
+             \"pc\": \"0x204\", \"opcode\": \"0xffff\", \"error\": \"Opcode `0xffff` is not supported\"}\n]\n"
+        );
+        assert_eq!(json_report(&[]), "[\n]\n");
+    }
+}
@@ -0,0 +1,671 @@
+//! A plain copy of the emulator's state, for saving, restoring and comparing it.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use crate::chip8::{WaitForKeyState, NUM_REGISTERS, STACK_SIZE};
+use crate::Chip8Error;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chip8State {
+    pub memory: Vec<u8>,
+    pub registers: [u8; NUM_REGISTERS],
+    pub stack: [u16; STACK_SIZE],
+    pub pc: u16,
+    pub ir: u16,
+    pub sp: u8,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    /// The graphics buffer, one `Vec` per row
+    pub screen: Vec<Vec<u8>>,
+    /// Where a running `Fx0A` is in its wait for a key
+    pub key_wait: KeyWait,
+}
+
+/// How far an `Fx0A` got in waiting for a key to be pressed and released, so that a restored
+/// state carries on with the wait. Only meant to be saved and restored.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct KeyWait {
+    pub(crate) state: WaitForKeyState,
+    /// The register the key goes in
+    pub(crate) register: u8,
+}
+
+impl KeyWait {
+    pub fn is_waiting(&self) -> bool {
+        self.state != WaitForKeyState::None
+    }
+}
+
+const REGISTER_NAMES: [&str; NUM_REGISTERS] = [
+    "V0", "V1", "V2", "V3", "V4", "V5", "V6", "V7", "V8", "V9", "VA", "VB", "VC", "VD", "VE", "VF",
+];
+
+/// Iterates over the registers of a [`Chip8State`] as `(index, value)` pairs, from `V0` to `VF`.
+#[derive(Debug, Clone)]
+pub struct RegisterIter {
+    state: [u8; NUM_REGISTERS],
+    index: usize,
+}
+
+impl Iterator for RegisterIter {
+    type Item = (u8, u8);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let value = *self.state.get(self.index)?;
+        let index = self.index as u8;
+        self.index += 1;
+        Some((index, value))
+    }
+}
+
+impl IntoIterator for &Chip8State {
+    type Item = (u8, u8);
+    type IntoIter = RegisterIter;
+
+    fn into_iter(self) -> RegisterIter {
+        RegisterIter {
+            state: self.registers,
+            index: 0,
+        }
+    }
+}
+
+/// One field that differs between two states, with the value in `self` first. See
+/// [`Chip8State::diff`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateDifference {
+    Register {
+        index: u8,
+        ours: u8,
+        theirs: u8,
+    },
+    /// The bytes from `start` to `end` (inclusive) differ, or are only present in one state.
+    Memory {
+        start: u16,
+        end: u16,
+    },
+    Stack {
+        index: u8,
+        ours: u16,
+        theirs: u16,
+    },
+    Pc {
+        ours: u16,
+        theirs: u16,
+    },
+    Ir {
+        ours: u16,
+        theirs: u16,
+    },
+    Sp {
+        ours: u8,
+        theirs: u8,
+    },
+    DelayTimer {
+        ours: u8,
+        theirs: u8,
+    },
+    SoundTimer {
+        ours: u8,
+        theirs: u8,
+    },
+    /// The screens differ. Only their hashes are kept.
+    Screen {
+        ours: u64,
+        theirs: u64,
+    },
+}
+
+impl fmt::Display for StateDifference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateDifference::Register { index, ours, theirs } => {
+                write!(f, "V{index:X}: {ours:#04x} != {theirs:#04x}")
+            }
+            StateDifference::Memory { start, end } if start == end => write!(f, "memory {start:#05x}"),
+            StateDifference::Memory { start, end } => write!(f, "memory {start:#05x}..={end:#05x}"),
+            StateDifference::Stack { index, ours, theirs } => {
+                write!(f, "stack[{index}]: {ours:#05x} != {theirs:#05x}")
+            }
+            StateDifference::Pc { ours, theirs } => write!(f, "pc: {ours:#05x} != {theirs:#05x}"),
+            StateDifference::Ir { ours, theirs } => write!(f, "ir: {ours:#05x} != {theirs:#05x}"),
+            StateDifference::Sp { ours, theirs } => write!(f, "sp: {ours} != {theirs}"),
+            StateDifference::DelayTimer { ours, theirs } => write!(f, "delay timer: {ours} != {theirs}"),
+            StateDifference::SoundTimer { ours, theirs } => write!(f, "sound timer: {ours} != {theirs}"),
+            StateDifference::Screen { ours, theirs } => {
+                write!(f, "screen: hash {ours:#018x} != {theirs:#018x}")
+            }
+        }
+    }
+}
+
+/// Every field that differs between two states, see [`Chip8State::diff`]. Displayed as one
+/// difference per line.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StateDiff {
+    pub differences: Vec<StateDifference>,
+}
+
+impl StateDiff {
+    pub fn is_empty(&self) -> bool {
+        self.differences.is_empty()
+    }
+
+    /// Returns `true` if every difference is in a field that `mask` ignores.
+    pub fn is_empty_ignoring(&self, mask: StateMask) -> bool {
+        self.differences.iter().all(|difference| mask.ignores(difference))
+    }
+}
+
+impl fmt::Display for StateDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, difference) in self.differences.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{difference}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The fields a state comparison ignores, e.g. ones that are known to differ between two
+/// emulators for benign reasons. Everything is compared by default.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct StateMask {
+    pub memory: bool,
+    pub registers: bool,
+    pub stack: bool,
+    pub pc: bool,
+    pub ir: bool,
+    pub sp: bool,
+    /// Both the delay and the sound timer
+    pub timers: bool,
+    pub screen: bool,
+}
+
+impl StateMask {
+    /// Returns `true` if `difference` is in a field this mask ignores.
+    pub fn ignores(&self, difference: &StateDifference) -> bool {
+        match difference {
+            StateDifference::Register { .. } => self.registers,
+            StateDifference::Memory { .. } => self.memory,
+            StateDifference::Stack { .. } => self.stack,
+            StateDifference::Pc { .. } => self.pc,
+            StateDifference::Ir { .. } => self.ir,
+            StateDifference::Sp { .. } => self.sp,
+            StateDifference::DelayTimer { .. } | StateDifference::SoundTimer { .. } => self.timers,
+            StateDifference::Screen { .. } => self.screen,
+        }
+    }
+}
+
+fn screen_hash(screen: &[Vec<u8>]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    screen.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Returns the ranges of addresses whose bytes differ between `a` and `b`, merging adjacent
+/// addresses. Bytes past the end of the shorter memory count as different.
+fn memory_differences(a: &[u8], b: &[u8]) -> Vec<StateDifference> {
+    let mut ranges: Vec<StateDifference> = Vec::new();
+
+    for addr in 0..a.len().max(b.len()) {
+        if a.get(addr) == b.get(addr) {
+            continue;
+        }
+        let addr = addr as u16;
+        match ranges.last_mut() {
+            Some(StateDifference::Memory { end, .. }) if *end + 1 == addr => *end = addr,
+            _ => ranges.push(StateDifference::Memory {
+                start: addr,
+                end: addr,
+            }),
+        }
+    }
+
+    ranges
+}
+
+impl Chip8State {
+    /// Builds a state from raw memory, registers and stack, with everything else zeroed. Fails
+    /// with [`Chip8Error::DeserializationError`] if `registers` or `stack` has the wrong length.
+    pub fn from_slices(memory: &[u8], registers: &[u8], stack: &[u16]) -> Result<Self, Chip8Error> {
+        Ok(Chip8State {
+            memory: memory.to_vec(),
+            registers: registers.try_into()?,
+            stack: stack.try_into()?,
+            pc: 0,
+            ir: 0,
+            sp: 0,
+            delay_timer: 0,
+            sound_timer: 0,
+            screen: Vec::new(),
+            key_wait: KeyWait::default(),
+        })
+    }
+
+    /// Iterates over the registers as `("V0", value)` to `("VF", value)`.
+    pub fn into_named_iter(&self) -> impl Iterator<Item = (&'static str, u8)> + '_ {
+        self.into_iter()
+            .map(|(index, value)| (REGISTER_NAMES[index as usize], value))
+    }
+
+    /// Lists every field that differs between `self` and `other`: registers and stack entries by
+    /// index, memory as ranges of adjacent addresses, and the screen by its hash.
+    pub fn diff(&self, other: &Chip8State) -> StateDiff {
+        let mut differences = Vec::new();
+
+        for (index, (&ours, &theirs)) in self.registers.iter().zip(&other.registers).enumerate() {
+            if ours != theirs {
+                let index = index as u8;
+                differences.push(StateDifference::Register { index, ours, theirs });
+            }
+        }
+        differences.extend(memory_differences(&self.memory, &other.memory));
+        for (index, (&ours, &theirs)) in self.stack.iter().zip(&other.stack).enumerate() {
+            if ours != theirs {
+                let index = index as u8;
+                differences.push(StateDifference::Stack { index, ours, theirs });
+            }
+        }
+
+        let (ours, theirs) = (self, other);
+        if ours.pc != theirs.pc {
+            differences.push(StateDifference::Pc {
+                ours: ours.pc,
+                theirs: theirs.pc,
+            });
+        }
+        if ours.ir != theirs.ir {
+            differences.push(StateDifference::Ir {
+                ours: ours.ir,
+                theirs: theirs.ir,
+            });
+        }
+        if ours.sp != theirs.sp {
+            differences.push(StateDifference::Sp {
+                ours: ours.sp,
+                theirs: theirs.sp,
+            });
+        }
+        if ours.delay_timer != theirs.delay_timer {
+            differences.push(StateDifference::DelayTimer {
+                ours: ours.delay_timer,
+                theirs: theirs.delay_timer,
+            });
+        }
+        if ours.sound_timer != theirs.sound_timer {
+            differences.push(StateDifference::SoundTimer {
+                ours: ours.sound_timer,
+                theirs: theirs.sound_timer,
+            });
+        }
+        if ours.screen != theirs.screen {
+            differences.push(StateDifference::Screen {
+                ours: screen_hash(&ours.screen),
+                theirs: screen_hash(&theirs.screen),
+            });
+        }
+
+        StateDiff { differences }
+    }
+
+    /// Encodes the state in the save state file format: [`SAVE_STATE_MAGIC`], a version byte
+    /// ([`SAVE_STATE_VERSION`]), then the fields in order, little-endian:
+    ///
+    /// | Field | Layout |
+    /// |-------|--------|
+    /// | memory | `u32` length, then the bytes |
+    /// | registers | 16 bytes, `V0` first |
+    /// | stack | 16 `u16`s |
+    /// | `pc`, `ir` | `u16` each |
+    /// | `sp`, delay timer, sound timer | `u8` each |
+    /// | screen | `u16` height, `u16` width, then one byte per pixel, row by row |
+    /// | key wait | `u8` stage (0 when not waiting), `u8` register |
+    ///
+    /// A new version is needed for any change to this layout.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = SAVE_STATE_MAGIC.to_vec();
+        bytes.push(SAVE_STATE_VERSION);
+
+        bytes.extend((self.memory.len() as u32).to_le_bytes());
+        bytes.extend(&self.memory);
+        bytes.extend(self.registers);
+        bytes.extend(self.stack.iter().flat_map(|addr| addr.to_le_bytes()));
+        bytes.extend(self.pc.to_le_bytes());
+        bytes.extend(self.ir.to_le_bytes());
+        bytes.extend([self.sp, self.delay_timer, self.sound_timer]);
+
+        let width = self.screen.first().map_or(0, Vec::len);
+        bytes.extend((self.screen.len() as u16).to_le_bytes());
+        bytes.extend((width as u16).to_le_bytes());
+        for row in &self.screen {
+            bytes.extend(row);
+        }
+
+        let stage = match self.key_wait.state {
+            WaitForKeyState::None => 0,
+            WaitForKeyState::WaitForNoKeyPressed => 1,
+            WaitForKeyState::CheckForKeyPressed => 2,
+            WaitForKeyState::WaitForKeyRelease => 3,
+        };
+        bytes.extend([stage, self.key_wait.register]);
+        bytes
+    }
+
+    /// Decodes a state written by [`Chip8State::to_bytes`]. Fails with
+    /// [`Chip8Error::InvalidSaveState`] if `bytes` isn't a save state, was written by a version
+    /// this one doesn't know, is cut short, or holds a key wait this emulator can't resume.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Chip8Error> {
+        let mut reader = SaveStateReader(bytes);
+        if reader.take(SAVE_STATE_MAGIC.len(), "header")? != SAVE_STATE_MAGIC {
+            return Err(Chip8Error::InvalidSaveState("not a save state".to_string()));
+        }
+        let version = reader.u8("version")?;
+        if version != SAVE_STATE_VERSION {
+            return Err(Chip8Error::InvalidSaveState(format!(
+                "version {version} isn't supported, expected {SAVE_STATE_VERSION}"
+            )));
+        }
+
+        let memory_len = u32::from_le_bytes(reader.array("memory length")?) as usize;
+        let memory = reader.take(memory_len, "memory")?.to_vec();
+        let registers = reader.array("registers")?;
+        let mut stack = [0; STACK_SIZE];
+        for addr in &mut stack {
+            *addr = u16::from_le_bytes(reader.array("stack")?);
+        }
+        let pc = u16::from_le_bytes(reader.array("pc")?);
+        let ir = u16::from_le_bytes(reader.array("ir")?);
+        let [sp, delay_timer, sound_timer] = reader.array("sp and timers")?;
+
+        let height = u16::from_le_bytes(reader.array("screen height")?) as usize;
+        let width = u16::from_le_bytes(reader.array("screen width")?) as usize;
+        let screen = (0..height)
+            .map(|_| Ok(reader.take(width, "screen")?.to_vec()))
+            .collect::<Result<_, Chip8Error>>()?;
+
+        let [stage, register] = reader.array("key wait")?;
+        let state = match stage {
+            0 => WaitForKeyState::None,
+            1 => WaitForKeyState::WaitForNoKeyPressed,
+            2 => WaitForKeyState::CheckForKeyPressed,
+            3 => WaitForKeyState::WaitForKeyRelease,
+            _ => {
+                return Err(Chip8Error::InvalidSaveState(format!(
+                    "unknown key wait stage {stage}"
+                )))
+            }
+        };
+        if register as usize >= NUM_REGISTERS {
+            return Err(Chip8Error::InvalidSaveState(format!(
+                "the key wait stores into V{register:X}, which doesn't exist"
+            )));
+        }
+        if !reader.0.is_empty() {
+            return Err(Chip8Error::InvalidSaveState(format!(
+                "{} bytes left over after the state",
+                reader.0.len()
+            )));
+        }
+
+        Ok(Chip8State {
+            memory,
+            registers,
+            stack,
+            pc,
+            ir,
+            sp,
+            delay_timer,
+            sound_timer,
+            screen,
+            key_wait: KeyWait { state, register },
+        })
+    }
+}
+
+/// The first bytes of every file from [`Chip8State::to_bytes`].
+pub const SAVE_STATE_MAGIC: &[u8; 8] = b"WHEATSAV";
+
+/// The version of the layout [`Chip8State::to_bytes`] writes.
+pub const SAVE_STATE_VERSION: u8 = 1;
+
+/// The part of a save state that hasn't been decoded yet.
+struct SaveStateReader<'a>(&'a [u8]);
+
+impl<'a> SaveStateReader<'a> {
+    /// Takes the next `len` bytes, which hold `what`.
+    fn take(&mut self, len: usize, what: &str) -> Result<&'a [u8], Chip8Error> {
+        if self.0.len() < len {
+            return Err(Chip8Error::InvalidSaveState(format!("cut short in the {what}")));
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self, what: &str) -> Result<[u8; N], Chip8Error> {
+        Ok(self.take(N, what)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self, what: &str) -> Result<u8, Chip8Error> {
+        Ok(self.take(1, what)?[0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Chip8State, KeyWait, StateDifference, StateMask, SAVE_STATE_VERSION};
+    use crate::chip8::WaitForKeyState;
+    use crate::Chip8Error;
+
+    fn state() -> Chip8State {
+        Chip8State::from_slices(&[0; 0x300], &[0; 16], &[0; 16]).unwrap()
+    }
+
+    /// The state saved in `fixtures/state-v1.bin`.
+    fn fixture_state() -> Chip8State {
+        let memory: Vec<u8> = (0..4096).map(|i| (i * 7 % 256) as u8).collect();
+        let registers: Vec<u8> = (0..16).collect();
+        let stack: Vec<u16> = (0..16).map(|i| 0x200 + 2 * i).collect();
+        let mut state = Chip8State::from_slices(&memory, &registers, &stack).unwrap();
+        state.pc = 0x234;
+        state.ir = 0x456;
+        state.sp = 3;
+        state.delay_timer = 60;
+        state.sound_timer = 5;
+        state.screen = (0..32)
+            .map(|y| (0..64).map(|x| u8::from((x + y) % 3 == 0)).collect())
+            .collect();
+        state.key_wait = KeyWait {
+            state: WaitForKeyState::CheckForKeyPressed,
+            register: 7,
+        };
+        state
+    }
+
+    const FIXTURE: &[u8] = include_bytes!("../fixtures/state-v1.bin");
+
+    #[test]
+    fn test_save_state_format_matches_fixture() {
+        assert_eq!(fixture_state().to_bytes(), FIXTURE);
+        assert_eq!(Chip8State::from_bytes(FIXTURE), Ok(fixture_state()));
+    }
+
+    #[test]
+    fn test_save_state_round_trip() {
+        let state = state();
+        assert_eq!(Chip8State::from_bytes(&state.to_bytes()), Ok(state));
+    }
+
+    #[test]
+    fn test_invalid_save_states() {
+        let invalid = |bytes: &[u8]| {
+            matches!(
+                Chip8State::from_bytes(bytes),
+                Err(Chip8Error::InvalidSaveState(_))
+            )
+        };
+
+        let mut newer = FIXTURE.to_vec();
+        newer[8] = SAVE_STATE_VERSION + 1;
+        assert_eq!(
+            Chip8State::from_bytes(&newer),
+            Err(Chip8Error::InvalidSaveState(
+                "version 2 isn't supported, expected 1".to_string()
+            ))
+        );
+
+        let mut bad_key_wait = FIXTURE.to_vec();
+        let stage = bad_key_wait.len() - 2;
+        bad_key_wait[stage] = 4;
+
+        let mut bad_key_register = FIXTURE.to_vec();
+        *bad_key_register.last_mut().unwrap() = 200;
+        assert_eq!(
+            Chip8State::from_bytes(&bad_key_register),
+            Err(Chip8Error::InvalidSaveState(
+                "the key wait stores into VC8, which doesn't exist".to_string()
+            ))
+        );
+
+        assert!(invalid(b"WHEATSAW\x01"));
+        assert!(invalid(&bad_key_wait));
+        assert!(invalid(&FIXTURE[..FIXTURE.len() - 1]));
+        assert!(invalid(&[FIXTURE, &[0]].concat()));
+        assert!(invalid(&[]));
+    }
+
+    #[test]
+    fn test_from_slices() {
+        let registers: Vec<u8> = (0..16).collect();
+        let state = Chip8State::from_slices(&[1, 2, 3], &registers, &[0x200; 16]).unwrap();
+
+        assert_eq!(state.memory, [1, 2, 3]);
+        assert_eq!(state.registers[0xF], 15);
+        assert_eq!(state.stack, [0x200; 16]);
+    }
+
+    #[test]
+    fn test_from_slices_wrong_register_count() {
+        let result = Chip8State::from_slices(&[], &[0; 15], &[0; 16]);
+        assert!(matches!(result, Err(Chip8Error::DeserializationError(_))));
+    }
+
+    #[test]
+    fn test_from_slices_wrong_stack_size() {
+        let result = Chip8State::from_slices(&[], &[0; 16], &[0; 17]);
+        assert!(matches!(result, Err(Chip8Error::DeserializationError(_))));
+    }
+
+    #[test]
+    fn test_register_iter() {
+        let registers: Vec<u8> = (0..16).map(|i| i * 2).collect();
+        let state = Chip8State::from_slices(&[], &registers, &[0; 16]).unwrap();
+
+        let pairs: Vec<_> = (&state).into_iter().collect();
+        assert_eq!(pairs.len(), 16);
+        assert_eq!(pairs[0], (0, 0));
+        assert_eq!(pairs[0xA], (0xA, 20));
+    }
+
+    #[test]
+    fn test_into_named_iter() {
+        let mut state = state();
+        state.registers[0xF] = 1;
+
+        let named: Vec<_> = state.into_named_iter().collect();
+        assert_eq!(named.len(), 16);
+        assert_eq!(named[0], ("V0", 0));
+        assert_eq!(named.last(), Some(&("VF", 1)));
+    }
+
+    #[test]
+    fn test_diff_equal_states() {
+        assert!(state().diff(&state()).is_empty());
+        assert_eq!(state().diff(&state()).to_string(), "");
+    }
+
+    #[test]
+    fn test_diff() {
+        let (ours, mut theirs) = (state(), state());
+        theirs.registers[0x3] = 0x01;
+        theirs.registers[0xF] = 0xFF;
+        theirs.stack[1] = 0x204;
+        theirs.pc = 0x202;
+        theirs.sound_timer = 4;
+        theirs.screen = vec![vec![1]];
+
+        let diff = ours.diff(&theirs);
+
+        assert_eq!(
+            diff.differences[0],
+            StateDifference::Register {
+                index: 3,
+                ours: 0,
+                theirs: 1
+            }
+        );
+        assert_eq!(
+            diff.to_string()
+                .lines()
+                .filter(|line| !line.starts_with("screen"))
+                .collect::<Vec<_>>(),
+            [
+                "V3: 0x00 != 0x01",
+                "VF: 0x00 != 0xff",
+                "stack[1]: 0x000 != 0x204",
+                "pc: 0x000 != 0x202",
+                "sound timer: 0 != 4",
+            ]
+        );
+        assert!(diff
+            .to_string()
+            .lines()
+            .last()
+            .unwrap()
+            .starts_with("screen: hash 0x"));
+    }
+
+    #[test]
+    fn test_diff_coalesces_memory_ranges() {
+        let (ours, mut theirs) = (state(), state());
+        for addr in [0x200, 0x201, 0x202, 0x204, 0x2FF] {
+            theirs.memory[addr] = 1;
+        }
+        // Shorter memory: the missing bytes differ too
+        let mut short = state();
+        short.memory.truncate(0x2FE);
+
+        assert_eq!(
+            ours.diff(&theirs).to_string(),
+            "memory 0x200..=0x202\nmemory 0x204\nmemory 0x2ff"
+        );
+        assert_eq!(ours.diff(&short).to_string(), "memory 0x2fe..=0x2ff");
+    }
+
+    #[test]
+    fn test_diff_ignoring() {
+        let (ours, mut theirs) = (state(), state());
+        theirs.delay_timer = 3;
+        theirs.memory[0x250] = 9;
+
+        let diff = ours.diff(&theirs);
+        let timers = StateMask {
+            timers: true,
+            ..Default::default()
+        };
+        let timers_and_memory = StateMask {
+            memory: true,
+            ..timers
+        };
+
+        assert!(!diff.is_empty_ignoring(StateMask::default()));
+        assert!(!diff.is_empty_ignoring(timers));
+        assert!(diff.is_empty_ignoring(timers_and_memory));
+    }
+}
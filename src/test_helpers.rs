@@ -0,0 +1,79 @@
+//! Helpers for writing tests against the emulator. Available in this crate's tests, and to
+//! downstream crates with the `test-helpers` feature.
+
+use std::sync::mpsc;
+
+use thiserror::Error;
+
+use crate::chip8::{Chip8, DefaultChip8};
+use crate::graphics::Graphics;
+use crate::{DebugOptions, Quirks};
+
+/// The pixels where a screen differs from the expected one.
+#[derive(Error, Debug, PartialEq)]
+#[error("{} pixel(s) differ from the expected screen", .diff.len())]
+pub struct DisplayMismatch {
+    /// Every discrepancy, as `(x, y, expected_pixel, actual_pixel)`.
+    pub diff: Vec<(u8, u8, u8, u8)>,
+}
+
+/// Compares `actual` against `expected` pixel by pixel. Pixels missing from either buffer are
+/// treated as off.
+pub fn compare_display(expected: &[Vec<u8>], actual: &[Vec<u8>]) -> Result<(), DisplayMismatch> {
+    let height = expected.len().max(actual.len());
+    let mut diff = Vec::new();
+
+    for y in 0..height {
+        let expected_row = expected.get(y).map(Vec::as_slice).unwrap_or_default();
+        let actual_row = actual.get(y).map(Vec::as_slice).unwrap_or_default();
+        let width = expected_row.len().max(actual_row.len());
+
+        for x in 0..width {
+            let expected_pixel = expected_row.get(x).copied().unwrap_or(0);
+            let actual_pixel = actual_row.get(x).copied().unwrap_or(0);
+
+            if expected_pixel != actual_pixel {
+                diff.push((x as u8, y as u8, expected_pixel, actual_pixel));
+            }
+        }
+    }
+
+    if diff.is_empty() {
+        Ok(())
+    } else {
+        Err(DisplayMismatch { diff })
+    }
+}
+
+/// The bytes of `program`, one big-endian word per opcode, e.g. to load as a ROM.
+pub fn rom_bytes(program: &[u16]) -> Vec<u8> {
+    program.iter().flat_map(|opcode| opcode.to_be_bytes()).collect()
+}
+
+/// An emulator with the default quirks and `program` loaded as its ROM. Its timers never tick.
+pub fn chip8_with_program(program: &[u16], options: DebugOptions) -> DefaultChip8 {
+    let (_, timer_rx) = mpsc::channel();
+    let mut chip8 = Chip8::new(Graphics::new(), timer_rx, Quirks::default(), options);
+    chip8.load_rom(&rom_bytes(program)).unwrap();
+    chip8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_display_equal() {
+        let screen = vec![vec![0, 1], vec![1, 0]];
+        assert_eq!(compare_display(&screen, &screen), Ok(()));
+    }
+
+    #[test]
+    fn test_compare_display_diff() {
+        let expected = vec![vec![0, 1], vec![1, 0]];
+        let actual = vec![vec![0, 0], vec![1, 1]];
+
+        let mismatch = compare_display(&expected, &actual).unwrap_err();
+        assert_eq!(mismatch.diff, vec![(1, 0, 1, 0), (1, 1, 0, 1)]);
+    }
+}
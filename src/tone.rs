@@ -0,0 +1,208 @@
+use std::collections::VecDeque;
+use std::ops::Range;
+
+use wheat::chip8::Chip8Event;
+use wheat::FRAMES_PER_SECOND;
+
+use crate::pacing::cycle_to_sample;
+
+/// Instructions the tone is held for after the sound timer runs out. A program that keeps the
+/// buzzer on writes the timer again once it notices, e.g. in a loop waiting on the delay timer,
+/// and the tone shouldn't stop for the few instructions that takes.
+const HOLD_INSTRUCTIONS: u64 = 4;
+
+/// Turns the sound timer writes of the program into the audio samples the buzzer is on for, so
+/// that the buzzer starts and stops on the instruction that set the timer, rather than once per
+/// batch. The audio callback plays it with [`ToneSchedule::next_sample`].
+#[derive(Debug)]
+pub struct ToneSchedule {
+    sample_rate: u32,
+    cpu_hertz: f64,
+    timer_hertz: f64,
+    /// An instruction and the sample it plays at. Set by the first write after a
+    /// [`ToneSchedule::clear`], and again whenever the audio got too far ahead or behind
+    anchor: Option<(u64, u64)>,
+    /// The samples the buzzer is on for, oldest first and not overlapping
+    tones: VecDeque<Range<u64>>,
+    /// Samples played so far
+    played: u64,
+}
+
+impl ToneSchedule {
+    pub fn new(sample_rate: u32, cpu_hertz: f64, timer_hertz: f64) -> Self {
+        Self {
+            sample_rate,
+            cpu_hertz,
+            timer_hertz,
+            anchor: None,
+            tones: VecDeque::new(),
+            played: 0,
+        }
+    }
+
+    /// Schedules the [`Chip8Event::SoundTimerSet`]s in `events`, which come from the batch that
+    /// just ran. The batch plays a frame behind, so that the writes in it are still ahead of the
+    /// audio.
+    pub fn schedule(&mut self, events: &[Chip8Event]) {
+        for event in events {
+            if let Chip8Event::SoundTimerSet { value, cycle } = *event {
+                self.set_sound_timer(value, cycle);
+            }
+        }
+    }
+
+    fn set_sound_timer(&mut self, value: u8, cycle: u64) {
+        let lead = self.sample_rate as u64 / FRAMES_PER_SECOND as u64;
+        // Throttling, or a slow host, moves the instructions away from the audio
+        let start = match self.sample_at(cycle) {
+            Some(start) if start >= self.played && start <= self.played + 4 * lead => start,
+            _ => {
+                self.anchor = Some((cycle, self.played + lead));
+                self.played + lead
+            }
+        };
+
+        // A write replaces whatever was left of the timer
+        self.tones.retain(|tone| tone.start < start);
+        if let Some(last) = self.tones.back_mut() {
+            last.end = last.end.min(start);
+        }
+        if value == 0 {
+            return;
+        }
+
+        let cycles = (value as f64 * self.cpu_hertz / self.timer_hertz).ceil() as u64 + HOLD_INSTRUCTIONS;
+        let end = self.sample_at(cycle + cycles).unwrap_or(start);
+        match self.tones.back_mut() {
+            Some(last) if last.end == start => last.end = end,
+            _ => self.tones.push_back(start..end),
+        }
+    }
+
+    /// The sample instruction `cycle` plays at, if it's after the anchor.
+    fn sample_at(&self, cycle: u64) -> Option<u64> {
+        let (at, sample) = self.anchor?;
+        let cycles = cycle.checked_sub(at)?;
+        Some(sample + cycle_to_sample(cycles, self.cpu_hertz, self.sample_rate))
+    }
+
+    /// Returns whether the buzzer is on for the next sample, and moves on to the one after.
+    pub fn next_sample(&mut self) -> bool {
+        while self.tones.front().is_some_and(|tone| tone.end <= self.played) {
+            self.tones.pop_front();
+        }
+        let on = self.tones.front().is_some_and(|tone| tone.start <= self.played);
+        self.played += 1;
+        on
+    }
+
+    /// Stops the buzzer, e.g. for a rewind or another ROM, whose instructions don't line up with
+    /// the ones so far.
+    pub fn clear(&mut self) {
+        self.anchor = None;
+        self.tones.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use wheat::chip8::{Chip8, Chip8Event, NoInput};
+    use wheat::graphics::Graphics;
+    use wheat::timer::TimerOperation;
+    use wheat::{DebugOptions, Quirks};
+
+    use super::ToneSchedule;
+
+    const SAMPLE_RATE: u32 = 44_100;
+
+    /// Plays `samples` samples, and returns where the buzzer turned on and off.
+    fn edges(tones: &mut ToneSchedule, samples: u64) -> Vec<(u64, bool)> {
+        let mut edges = Vec::new();
+        let mut was_on = false;
+        for sample in 0..samples {
+            let on = tones.next_sample();
+            if on != was_on {
+                edges.push((sample, on));
+                was_on = on;
+            }
+        }
+        edges
+    }
+
+    fn sound(value: u8, cycle: u64) -> Chip8Event {
+        Chip8Event::SoundTimerSet { value, cycle }
+    }
+
+    #[test]
+    fn test_tone_starts_on_the_instruction() {
+        let mut tones = ToneSchedule::new(SAMPLE_RATE, 800.0, 60.0);
+
+        // 2 ticks are 27 instructions, and 4 to hold it for, played a frame (735 samples) late
+        tones.schedule(&[sound(2, 100), sound(0, 108)]);
+        assert_eq!(edges(&mut tones, 2_000), [(735, true), (1_176, false)]);
+
+        tones.clear();
+        tones.schedule(&[sound(2, 500)]);
+        assert_eq!(edges(&mut tones, 3_000), [(735, true), (2_444, false)]);
+    }
+
+    #[test]
+    fn test_writes_replace_the_rest_of_the_tone() {
+        let mut tones = ToneSchedule::new(SAMPLE_RATE, 800.0, 60.0);
+
+        tones.schedule(&[sound(60, 0), sound(1, 8)]);
+        assert_eq!(edges(&mut tones, 5_000), [(735, true), (2_168, false)]);
+    }
+
+    #[test]
+    fn test_audio_behind_the_instructions() {
+        let mut tones = ToneSchedule::new(SAMPLE_RATE, 800.0, 60.0);
+        tones.schedule(&[sound(1, 0)]);
+        edges(&mut tones, 44_100);
+
+        // A second of audio played, but only a few instructions ran: start over a frame ahead
+        tones.schedule(&[sound(1, 13)]);
+        assert_eq!(edges(&mut tones, 1_000), [(735, true)]);
+    }
+
+    #[test]
+    fn test_sixty_writes_a_second_are_one_tone() {
+        // LD V0, 1; LD V1, 1; LD DT, V1; LD ST, V0; LD V2, DT; SE V2, 0; JP 0x208; JP 0x204
+        let program = [
+            0x60, 0x01, 0x61, 0x01, 0xF1, 0x15, 0xF0, 0x18, 0xF2, 0x07, 0x32, 0x00, 0x12, 0x08, 0x12, 0x04,
+        ];
+        let (timer_tx, timer_rx) = mpsc::channel();
+        let mut chip8 = Chip8::new(
+            Graphics::new(),
+            timer_rx,
+            Quirks::default(),
+            DebugOptions::default(),
+        );
+        chip8.load_rom(&program.to_vec()).unwrap();
+        let mut tones = ToneSchedule::new(SAMPLE_RATE, 800.0, 60.0);
+
+        // A second, a frame at a time, with a timer tick every frame
+        let mut samples = Vec::new();
+        let mut cycles = 0;
+        for frame in 1..=60 {
+            timer_tx.send(TimerOperation::Decrement(1)).unwrap();
+            let batch = frame * 800 / 60 - cycles;
+            cycles += batch;
+            let output = chip8.emulate_frame(batch, &NoInput).unwrap();
+            let writes = output
+                .events
+                .iter()
+                .filter(|event| matches!(event, Chip8Event::SoundTimerSet { .. }));
+            assert_eq!(writes.count(), 1, "frame {frame}");
+
+            tones.schedule(&output.events);
+            samples.extend((0..SAMPLE_RATE / 60).map(|_| tones.next_sample()));
+        }
+
+        let first = samples.iter().position(|&on| on).unwrap();
+        assert!(first < 1_000);
+        assert!(samples[first..].iter().all(|&on| on));
+    }
+}
@@ -0,0 +1,162 @@
+//! The last instructions a program executed, kept when `DebugOptions::trace_depth` is set, to see
+//! how it got to where it went wrong.
+
+use std::fmt;
+
+use crate::chip8::NUM_REGISTERS;
+use crate::disasm;
+
+/// An executed instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub pc: u16,
+    pub opcode: u16,
+    /// `V0` to `VF` before the instruction ran
+    pub registers: [u8; NUM_REGISTERS],
+}
+
+/// `0x204: 7301  ADD V3, 0x01  V0=00 V1=00 ...`
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let instruction =
+            disasm::disassemble(self.opcode).unwrap_or_else(|_| format!("DB {:#06x}", self.opcode));
+        write!(f, "{:#05x}: {:04X}  {instruction:<16}", self.pc, self.opcode)?;
+        for (register, value) in self.registers.iter().enumerate() {
+            write!(f, " V{register:X}={value:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A ring buffer of the last `depth` [`TraceEntry`]s. It's allocated once, so recording an
+/// instruction doesn't allocate.
+#[derive(Debug, Clone)]
+pub struct TraceBuffer {
+    entries: Vec<TraceEntry>,
+    depth: usize,
+    /// Where the next entry goes once the buffer is full, i.e. the oldest entry
+    next: usize,
+}
+
+impl TraceBuffer {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            entries: Vec::with_capacity(depth),
+            depth,
+            next: 0,
+        }
+    }
+
+    /// Adds `entry` as the newest one, replacing the oldest one if the buffer is full.
+    pub fn push(&mut self, entry: TraceEntry) {
+        if self.entries.len() < self.depth {
+            self.entries.push(entry);
+        } else if self.depth > 0 {
+            self.entries[self.next] = entry;
+            self.next = (self.next + 1) % self.depth;
+        }
+    }
+
+    /// The entries, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &TraceEntry> {
+        let (newer, older) = self.entries.split_at(self.next);
+        older.iter().chain(newer)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.next = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TraceBuffer, TraceEntry};
+    use crate::chip8::{DefaultChip8, NoInput};
+    use crate::test_helpers::chip8_with_program;
+    use crate::DebugOptionsBuilder;
+
+    fn chip8(trace_depth: usize) -> DefaultChip8 {
+        let options = DebugOptionsBuilder::default()
+            .trace_depth(trace_depth)
+            .build()
+            .unwrap();
+        // LD V3, 0; 0x202: ADD V3, 1; JP 0x202
+        chip8_with_program(&[0x6300, 0x7301, 0x1202], options)
+    }
+
+    #[test]
+    fn test_keeps_the_last_instructions() {
+        let mut chip8 = chip8(5);
+        chip8.emulate_frame(40, &NoInput).unwrap();
+
+        // The 40th instruction was the 20th ADD, which saw V3 at 19
+        let trace: Vec<_> = chip8
+            .trace()
+            .map(|entry| (entry.pc, entry.opcode, entry.registers[3]))
+            .collect();
+        assert_eq!(
+            trace,
+            [
+                (0x202, 0x7301, 17),
+                (0x204, 0x1202, 18),
+                (0x202, 0x7301, 18),
+                (0x204, 0x1202, 19),
+                (0x202, 0x7301, 19),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fills_up_in_order() {
+        let mut buffer = TraceBuffer::new(3);
+        let entry = |pc| TraceEntry {
+            pc,
+            opcode: 0,
+            registers: [0; 16],
+        };
+        let pcs = |buffer: &TraceBuffer| buffer.iter().map(|entry| entry.pc).collect::<Vec<_>>();
+
+        buffer.push(entry(1));
+        buffer.push(entry(2));
+        assert_eq!(pcs(&buffer), [1, 2]);
+        for pc in 3..=7 {
+            buffer.push(entry(pc));
+        }
+        assert_eq!(pcs(&buffer), [5, 6, 7]);
+
+        buffer.clear();
+        assert!(buffer.is_empty());
+        TraceBuffer::new(0).push(entry(1));
+    }
+
+    #[test]
+    fn test_off_by_default() {
+        let mut chip8 = chip8(0);
+        chip8.emulate_frame(10, &NoInput).unwrap();
+        assert_eq!(chip8.trace().count(), 0);
+    }
+
+    #[test]
+    fn test_display() {
+        let mut registers = [0; 16];
+        registers[0xF] = 1;
+        let entry = TraceEntry {
+            pc: 0x204,
+            opcode: 0x7301,
+            registers,
+        };
+        assert!(entry
+            .to_string()
+            .starts_with("0x204: 7301  ADD V3, 0x01     V0=00 V1=00"));
+        assert!(entry.to_string().ends_with(" VF=01"));
+    }
+}
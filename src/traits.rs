@@ -1,19 +1,72 @@
+use crate::graphics::Coord;
 use crate::Key;
 
+/// The screen a [`crate::chip8::Chip8`] draws on. The trait is object safe, so the screen can also
+/// be a `Box<dyn GraphicsBuffer>`.
 pub trait GraphicsBuffer {
     /// Clears the entire screen with 0s; wipes everything from the screen.
     fn clear(&mut self);
 
     /// Draws a sprite on the screen, and returns `true` if a pixel on the screen was flipped from
     /// 1 to 0.
-    /// `x`: top-left "x" coordinate on screen where to draw
-    /// `y`: top-left "y" coordinate on screen where to draw
-    /// `ir`: The index register, which contains the area of memory to
-    /// start reading the sprite from.
-    /// `memory`: The memory from which to read the sprite.
-    fn draw(&mut self, x: u8, y: u8, num_rows: u8, ir: u16, memory: &[u8], clipping: bool) -> bool;
+    /// `pos`: top-left coordinate on screen where to draw
+    /// `rows`: The sprite, one byte per row. The CPU has already read it from memory.
+    fn draw_sprite(&mut self, pos: Coord, rows: &[u8], clipping: bool) -> bool;
+
+    /// Sets the pixel at `pos` to `value`, wrapping `pos` onto the screen.
+    fn set_pixel(&mut self, pos: Coord, value: u8);
+
+    /// Moves the whole screen `px` pixels to the left. Pixels pushed off the left edge are lost,
+    /// and the columns uncovered on the right are cleared.
+    fn scroll_left(&mut self, px: u16);
+
+    /// Moves the whole screen `px` pixels to the right, clearing the columns uncovered on the left.
+    fn scroll_right(&mut self, px: u16);
 
     fn buffer(&self) -> &Vec<Vec<u8>>;
+
+    /// Replaces the screen with `buffer`, one `Vec` per row like [`GraphicsBuffer::buffer`], e.g.
+    /// to restore a saved state. The default implementation sets each pixel with
+    /// [`GraphicsBuffer::set_pixel`].
+    fn load_buffer(&mut self, buffer: &[Vec<u8>]) {
+        for (y, row) in buffer.iter().enumerate() {
+            for (x, &value) in row.iter().enumerate() {
+                self.set_pixel(Coord::new(x as u16, y as u16), value);
+            }
+        }
+    }
+}
+
+/// Lets a [`crate::chip8::Chip8`] draw on a screen picked at runtime. See
+/// [`crate::chip8::BoxedChip8`].
+impl GraphicsBuffer for Box<dyn GraphicsBuffer> {
+    fn clear(&mut self) {
+        (**self).clear()
+    }
+
+    fn draw_sprite(&mut self, pos: Coord, rows: &[u8], clipping: bool) -> bool {
+        (**self).draw_sprite(pos, rows, clipping)
+    }
+
+    fn set_pixel(&mut self, pos: Coord, value: u8) {
+        (**self).set_pixel(pos, value)
+    }
+
+    fn scroll_left(&mut self, px: u16) {
+        (**self).scroll_left(px)
+    }
+
+    fn scroll_right(&mut self, px: u16) {
+        (**self).scroll_right(px)
+    }
+
+    fn buffer(&self) -> &Vec<Vec<u8>> {
+        (**self).buffer()
+    }
+
+    fn load_buffer(&mut self, buffer: &[Vec<u8>]) {
+        (**self).load_buffer(buffer)
+    }
 }
 
 pub trait Display {
@@ -21,6 +74,16 @@ pub trait Display {
     /// made up of `1`s and `0`s. `1`s are drawn as white and `0`s
     /// are drawn as black.
     fn draw(&mut self, buffer: &[Vec<u8>]);
+
+    /// Returns `true` once the display is ready to show the next frame. Backends without vsync
+    /// are always ready.
+    fn is_vsync_ready(&mut self) -> bool {
+        true
+    }
+
+    /// Sets the RGB color drawn for each pixel value, for backends that support more than two
+    /// colors. Monochrome backends ignore it.
+    fn set_palette(&mut self, _colors: &[[u8; 3]; 16]) {}
 }
 
 /// Keeps track of the state of the keys. Chip8 uses 16 keys; this implementation
@@ -38,8 +101,24 @@ pub trait Input {
     /// Returns the state of the specified key. The hex code that the key is
     /// mapped to is used to access its state.
     fn is_pressed(&self, key: Key) -> bool;
+
+    /// Returns the state of every key at once, with bit `n` set if key `n` is pressed. The
+    /// default implementation asks [`Input::is_pressed`] for each key; implementations that keep
+    /// their state as a bitmask can return it directly.
+    fn pressed_mask(&self) -> u16 {
+        Key::ALL
+            .into_iter()
+            .filter(|&key| self.is_pressed(key))
+            .fold(0, |mask, key| mask | 1 << u8::from(key))
+    }
 }
 
 pub trait Rom {
     fn data(&self) -> &Vec<u8>;
 }
+
+impl Rom for Vec<u8> {
+    fn data(&self) -> &Vec<u8> {
+        self
+    }
+}